@@ -0,0 +1,8 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+mod markers;
+mod schedule;
+mod workload;
+
+pub use crate::markers::*;
+pub use crate::schedule::Schedule;
+pub use crate::workload::*;
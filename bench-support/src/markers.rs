@@ -0,0 +1,80 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The log entries parsed by `benchmark/benchmark/logs.py` to compute performance. Any change
+//! to these formats must be mirrored in the regexes of the analysis scripts.
+use std::fmt::{Debug, Display};
+use std::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/markers_tests.rs"]
+pub mod markers_tests;
+
+/// Logged by the client before it starts sending transactions.
+pub fn transactions_size(size: usize) -> String {
+    format!("Transactions size: {} B", size)
+}
+
+/// Logged by the client before it starts sending transactions.
+pub fn transactions_rate(rate: u64) -> String {
+    format!("Transactions rate: {} tx/s", rate)
+}
+
+/// Logged by the client when the benchmark starts.
+pub fn start_sending() -> String {
+    "Start sending transactions".to_string()
+}
+
+/// Logged by the client when it sends a sample transaction, with how long ago it was due.
+pub fn sending_sample(counter: u64, lag: Duration) -> String {
+    format!(
+        "Sending sample transaction {} due {} ms ago",
+        counter,
+        lag.as_millis()
+    )
+}
+
+/// Logged by the client when it sends a pair of conflicting transactions to two nodes.
+pub fn sending_conflict<D: Debug>(election_id: &D) -> String {
+    format!(
+        "Sending conflicting transactions for election {:?}",
+        election_id
+    )
+}
+
+/// Logged by the client when it cannot keep up with the requested rate.
+pub fn rate_too_high() -> String {
+    "Transaction rate too high for this client".to_string()
+}
+
+/// Logged by the client when it starts a burst after the next one was already due.
+pub fn behind_schedule(burst: u64, lag: Duration) -> String {
+    format!(
+        "Burst {} started {} ms behind schedule",
+        burst,
+        lag.as_millis()
+    )
+}
+
+/// Logged by a worker for every sample transaction of a batch it seals.
+pub fn batch_contains_sample<D: Debug>(batch: &D, counter: u64) -> String {
+    format!("Batch {:?} contains sample tx {}", batch, counter)
+}
+
+/// Logged by a worker for every batch it seals, with its serialized size and its transactions.
+pub fn batch_contains_bytes<D: Debug>(batch: &D, size: usize, txs: usize) -> String {
+    format!("Batch {:?} contains {} B ({} txs)", batch, size, txs)
+}
+
+/// Logged by a primary when it first sees a vote for a batch.
+pub fn created<V: Display, D: Debug>(vote: &V, batch: &D) -> String {
+    format!("Created {} -> {:?}", vote, batch)
+}
+
+/// Logged by a primary when an election is decided, with the digest of the committed batch.
+pub fn committed<V: Display, D: Debug>(vote: &V, batch: &D) -> String {
+    format!("Committed {} -> {:?}", vote, batch)
+}
+
+/// Logged by a primary when an election is decided, with the digest of the committed batch.
+pub fn decided<E: Debug, D: Debug>(election_id: &E, batch: &D) -> String {
+    format!("Decided election {:?} -> {:?}", election_id, batch)
+}
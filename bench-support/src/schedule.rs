@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::workload::BURST_DURATION;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/schedule_tests.rs"]
+pub mod schedule_tests;
+
+/// The send schedule of the benchmark client: burst `i` is due `i` burst durations after the
+/// start. Latency is measured from the time a transaction was due rather than the time it was
+/// actually sent; otherwise a client falling behind would hide the delays of its backlog
+/// (coordinated omission).
+pub struct Schedule {
+    /// When the first burst is due.
+    start: Instant,
+    /// The time between two bursts.
+    period: Duration,
+}
+
+impl Schedule {
+    pub fn new(start: Instant) -> Self {
+        Self::with_period(start, Duration::from_millis(BURST_DURATION))
+    }
+
+    pub fn with_period(start: Instant, period: Duration) -> Self {
+        Self { start, period }
+    }
+
+    /// When the specified burst is due.
+    pub fn due(&self, burst: u64) -> Instant {
+        self.start + self.period * burst as u32
+    }
+
+    /// How far behind schedule the specified burst is at time `now`.
+    pub fn lag(&self, burst: u64, now: Instant) -> Duration {
+        now.saturating_duration_since(self.due(burst))
+    }
+
+    /// Whether we are so far behind schedule that the specified burst should already have been
+    /// followed by the next one.
+    pub fn overrun(&self, burst: u64, now: Instant) -> bool {
+        self.lag(burst, now) >= self.period
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fmt;
+
+// Mimics the `Debug` output of a digest and the `Display` output of a vote.
+struct Digest;
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=")
+    }
+}
+
+struct Vote;
+
+impl fmt::Display for Vote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "B3(Ag7mUfE1b9Ed)")
+    }
+}
+
+#[test]
+fn client_markers() {
+    assert_eq!(transactions_size(512), "Transactions size: 512 B");
+    assert_eq!(transactions_rate(50_000), "Transactions rate: 50000 tx/s");
+    assert_eq!(start_sending(), "Start sending transactions");
+    assert_eq!(
+        sending_sample(42, Duration::from_millis(7)),
+        "Sending sample transaction 42 due 7 ms ago"
+    );
+    assert_eq!(
+        sending_conflict(&Digest),
+        "Sending conflicting transactions for election AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+    );
+    assert_eq!(rate_too_high(), "Transaction rate too high for this client");
+    assert_eq!(
+        behind_schedule(3, Duration::from_millis(120)),
+        "Burst 3 started 120 ms behind schedule"
+    );
+}
+
+#[test]
+fn worker_markers() {
+    assert_eq!(
+        batch_contains_sample(&Digest, 7),
+        "Batch AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA= contains sample tx 7"
+    );
+    assert_eq!(
+        batch_contains_bytes(&Digest, 500_000, 1_000),
+        "Batch AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA= contains 500000 B (1000 txs)"
+    );
+}
+
+#[test]
+fn primary_markers() {
+    assert_eq!(
+        created(&Vote, &Digest),
+        "Created B3(Ag7mUfE1b9Ed) -> AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+    );
+    assert_eq!(
+        committed(&Vote, &Digest),
+        "Committed B3(Ag7mUfE1b9Ed) -> AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+    );
+    assert_eq!(
+        decided(&Digest, &Digest),
+        "Decided election AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA= -> AQEBAQEBAQEAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+    );
+}
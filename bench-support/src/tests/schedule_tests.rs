@@ -0,0 +1,34 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::thread::sleep;
+
+#[test]
+fn due_every_period() {
+    let start = Instant::now();
+    let schedule = Schedule::with_period(start, Duration::from_millis(50));
+    assert_eq!(schedule.due(0), start);
+    assert_eq!(schedule.due(3), start + Duration::from_millis(150));
+    assert_eq!(schedule.lag(3, start), Duration::from_millis(0));
+    assert!(!schedule.overrun(1, start + Duration::from_millis(99)));
+    assert!(schedule.overrun(1, start + Duration::from_millis(100)));
+}
+
+#[test]
+fn slow_transport_accrues_lag() {
+    // Simulate a transport taking three periods to send each burst.
+    let period = Duration::from_millis(10);
+    let schedule = Schedule::with_period(Instant::now(), period);
+    let mut lags = Vec::new();
+    for burst in 0..5 {
+        let sent = Instant::now();
+        lags.push(schedule.lag(burst, sent));
+        sleep(3 * period);
+    }
+
+    // Burst `i` is sent at least `2 * i` periods after it was due: its latency includes the time
+    // it waited behind the earlier bursts, not only its own sending time.
+    for (burst, lag) in lags.iter().enumerate() {
+        assert!(*lag >= 2 * period * burst as u32);
+    }
+    assert!(schedule.overrun(4, Instant::now()));
+}
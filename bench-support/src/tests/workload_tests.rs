@@ -0,0 +1,25 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn tag_sample_tx() {
+    let id = sample_tx_id(0x0102);
+    assert_eq!(id, vec![0, 0, 0, 0, 0, 0, 0, 1, 2]);
+    assert_eq!(sample_tx_counter(&id), Some(0x0102));
+}
+
+#[test]
+fn ignore_standard_tx() {
+    let id = standard_tx_id(0x0102);
+    assert_eq!(id, vec![1, 0, 0, 0, 0, 0, 0, 1, 2]);
+    assert_eq!(sample_tx_counter(&id), None);
+    assert_eq!(sample_tx_counter(&[0; 8]), None);
+    assert_eq!(sample_tx_counter(&[]), None);
+}
+
+#[test]
+fn tag_conflict_tx() {
+    let id = conflict_tx_id(0x0102);
+    assert_eq!(id, vec![2, 0, 0, 0, 0, 0, 0, 1, 2]);
+    assert_eq!(sample_tx_counter(&id), None);
+}
@@ -0,0 +1,65 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use rand::Rng as _;
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/workload_tests.rs"]
+pub mod workload_tests;
+
+/// The number of bursts sent per second by the benchmark client (sample precision).
+pub const PRECISION: u64 = 20;
+
+/// The duration of each burst of transactions (in ms).
+pub const BURST_DURATION: u64 = 1000 / PRECISION;
+
+/// The first byte of the id of sample transactions.
+pub const SAMPLE_TX_TAG: u8 = 0;
+
+/// The first byte of the id of standard transactions.
+pub const STANDARD_TX_TAG: u8 = 1;
+
+/// The first byte of the id of conflicting transactions.
+pub const CONFLICT_TX_TAG: u8 = 2;
+
+/// The id of a sample transaction: the sample tag followed by the counter identifying it.
+pub fn sample_tx_id(counter: u64) -> Vec<u8> {
+    tagged_id(SAMPLE_TX_TAG, counter)
+}
+
+/// The id of a standard transaction: the standard tag followed by a counter ensuring all
+/// transactions are different.
+pub fn standard_tx_id(counter: u64) -> Vec<u8> {
+    tagged_id(STANDARD_TX_TAG, counter)
+}
+
+/// The id shared by a pair of conflicting transactions: the conflict tag followed by a counter
+/// identifying the pair. Both transactions of the pair compete in the same election.
+pub fn conflict_tx_id(counter: u64) -> Vec<u8> {
+    tagged_id(CONFLICT_TX_TAG, counter)
+}
+
+fn tagged_id(tag: u8, counter: u64) -> Vec<u8> {
+    let mut id = Vec::with_capacity(9);
+    id.push(tag);
+    id.extend_from_slice(&counter.to_be_bytes());
+    id
+}
+
+/// Returns the counter of a sample transaction, or `None` if the id is not a sample id.
+pub fn sample_tx_counter(id: &[u8]) -> Option<u64> {
+    match id.first() {
+        Some(&SAMPLE_TX_TAG) if id.len() > 8 => id[1..9].try_into().ok().map(u64::from_be_bytes),
+        _ => None,
+    }
+}
+
+/// The number of transactions to send in each burst to reach the target rate (in tx/s).
+pub fn burst_size(rate: u64) -> u64 {
+    rate / PRECISION
+}
+
+/// Generate a random transaction payload of the given size (in bytes).
+pub fn random_payload(size: usize) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    (0..size).map(|_| rng.gen()).collect()
+}
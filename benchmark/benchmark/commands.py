@@ -46,14 +46,17 @@ class CommandMaker:
                 f'--store {store} --parameters {parameters} worker --id {id}')
 
     @staticmethod
-    def run_client(address, size, rate, nodes):
+    def run_client(address, size, rate, nodes, fee=0, conflict_rate=0):
         assert isinstance(address, str)
         assert isinstance(size, int) and size > 0
         assert isinstance(rate, int) and rate >= 0
         assert isinstance(nodes, list)
         assert all(isinstance(x, str) for x in nodes)
+        assert isinstance(fee, int) and fee >= 0
+        assert isinstance(conflict_rate, (int, float)) and 0 <= conflict_rate <= 1
         nodes = f'--nodes {" ".join(nodes)}' if nodes else ''
-        return f'./benchmark_client {address} --size {size} --rate {rate} {nodes}'
+        return (f'./benchmark_client {address} --size {size} --rate {rate} '
+                f'--fee {fee} --conflict-rate {conflict_rate} {nodes}')
 
     @staticmethod
     def kill():
@@ -182,6 +182,11 @@ class NodeParameters:
         if not all(isinstance(x, int) for x in inputs):
             raise ConfigError('Invalid parameters type')
 
+        # The clients pay the minimum fee so that the workers accept their transactions.
+        self.minimum_fee = json.get('minimum_fee', 0)
+        if not isinstance(self.minimum_fee, int):
+            raise ConfigError('Invalid parameters type')
+
         self.json = json
 
     def print(self, filename):
@@ -220,6 +225,8 @@ class BenchParameters:
             self.duration = int(json['duration'])
 
             self.runs = int(json['runs']) if 'runs' in json else 1
+
+            self.conflict_rate = float(json.get('conflict_rate', 0))
         except KeyError as e:
             raise ConfigError(f'Malformed bench parameters: missing key {e}')
 
@@ -229,6 +236,9 @@ class BenchParameters:
         if min(self.nodes) <= self.faults:
             raise ConfigError('There should be more nodes than faults')
 
+        if not 0 <= self.conflict_rate <= 1:
+            raise ConfigError('The conflict rate should be between 0 and 1')
+
 
 class PlotParameters:
     def __init__(self, json):
@@ -86,7 +86,9 @@ class LocalBench:
                             address,
                             self.tx_size,
                             rate_share,
-                            [x for y in workers_addresses for _, x in y]
+                            [x for y in workers_addresses for _, x in y],
+                            self.node_parameters.minimum_fee,
+                            self.conflict_rate
                         )
                         log_file = PathMaker.client_log_file(i, id)
                         self._background_run(cmd, log_file)
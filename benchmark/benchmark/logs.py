@@ -15,6 +15,9 @@ class ParseError(Exception):
 
 
 class LogParser:
+    # Runs whose clients fell further behind their schedule are flagged as overloaded (in ms).
+    MAX_BACKLOG = 1_000
+
     def __init__(self, clients, primaries, workers, faults=0):
         inputs = [clients, primaries, workers]
         assert all(isinstance(x, list) for x in inputs)
@@ -35,9 +38,12 @@ class LogParser:
                 results = p.map(self._parse_clients, clients)
         except (ValueError, IndexError, AttributeError) as e:
             raise ParseError(f'Failed to parse clients\' logs: {e}')
-        self.size, self.rate, self.start, misses, self.sent_samples \
-            = zip(*results)
+        self.size, self.rate, self.start, misses, self.sent_samples, \
+            conflicts, backlogs = zip(*results)
         self.misses = sum(misses)
+        self.late_bursts = sum(len(x) for x in backlogs)
+        self.max_backlog = max((x for y in backlogs for x in y), default=0)
+        self.conflicts = set().union(*conflicts)
 
         # Parse the primaries logs.
         try:
@@ -45,10 +51,18 @@ class LogParser:
                 results = p.map(self._parse_primaries, primaries)
         except (ValueError, IndexError, AttributeError) as e:
             raise ParseError(f'Failed to parse nodes\' logs: {e}')
-        proposals, commits, self.configs, primary_ips = zip(*results)
+        proposals, commits, decisions, self.configs, primary_ips = zip(*results)
         self.proposals = self._merge_results([x.items() for x in proposals])
         self.commits = self._merge_results([x.items() for x in commits])
 
+        # Gather the batches committed in each election of a conflicting pair.
+        self.decisions = {}
+        for x in decisions:
+            for election, batch in x.items():
+                if election in self.conflicts:
+                    self.decisions.setdefault(election, set()).add(batch)
+        self.split = [e for e, x in self.decisions.items() if len(x) > 1]
+
         # Parse the workers logs.
         try:
             with Pool() as p:
@@ -72,6 +86,20 @@ class LogParser:
                 f'Clients missed their target rate {self.misses:,} time(s)'
             )
 
+        # Check whether clients fell too far behind their schedule.
+        if self.overloaded():
+            Print.warn(
+                f'Clients fell up to {self.max_backlog:,} ms behind their '
+                f'schedule: the run is overloaded'
+            )
+
+        # Check whether both transactions of a conflicting pair committed.
+        if self.split:
+            Print.warn(
+                f'Both transactions of {len(self.split):,} conflicting '
+                f'pair(s) committed'
+            )
+
     def _merge_results(self, input):
         # Keep the earliest timestamp.
         merged = {}
@@ -93,10 +121,21 @@ class LogParser:
 
         misses = len(findall(r'rate too high', log))
 
-        tmp = findall(r'\[(.*Z) .* sample transaction (\d+)', log)
-        samples = {int(s): self._to_posix(t) for t, s in tmp}
+        # Samples are timed from when they were due, not from when they were sent.
+        tmp = findall(r'\[(.*Z) .* sample transaction (\d+) due (\d+) ms', log)
+        samples = {
+            int(s): self._to_posix(t) - int(lag) / 1_000 for t, s, lag in tmp
+        }
+
+        backlog = [
+            int(x) for x in findall(r'started (\d+) ms behind schedule', log)
+        ]
+
+        conflicts = set(
+            findall(r'conflicting transactions for election ([^ ]+=)', log)
+        )
 
-        return size, rate, start, misses, samples
+        return size, rate, start, misses, samples, conflicts, backlog
 
     def _parse_primaries(self, log):
         if search(r'(?:panicked|Error)', log) is not None:
@@ -110,6 +149,9 @@ class LogParser:
         tmp = [(d, self._to_posix(t)) for t, d in tmp]
         commits = self._merge_results([tmp])
 
+        tmp = findall(r'Decided election ([^ ]+=) -> ([^ ]+=)', log)
+        decisions = {e: d for e, d in tmp}
+
         configs = {
             'header_size': int(
                 search(r'Header size .* (\d+)', log).group(1)
@@ -136,7 +178,7 @@ class LogParser:
 
         ip = search(r'booted on (\d+.\d+.\d+.\d+)', log).group(1)
         
-        return proposals, commits, configs, ip
+        return proposals, commits, decisions, configs, ip
 
     def _parse_workers(self, log):
         if search(r'(?:panic|Error)', log) is not None:
@@ -154,6 +196,9 @@ class LogParser:
 
         return sizes, samples, ip
 
+    def overloaded(self):
+        return self.max_backlog > self.MAX_BACKLOG
+
     def _to_posix(self, string):
         x = datetime.fromisoformat(string.replace('Z', '+00:00'))
         return datetime.timestamp(x)
@@ -210,6 +255,11 @@ class LogParser:
         consensus_tps, consensus_bps, _ = self._consensus_throughput()
         end_to_end_tps, end_to_end_bps, duration = self._end_to_end_throughput()
         end_to_end_latency = self._end_to_end_latency() * 1_000
+        overloaded = (
+            ' WARNING: OVERLOADED RUN, clients fell more than '
+            f'{self.MAX_BACKLOG:,} ms behind their schedule\n'
+            if self.overloaded() else ''
+        )
 
         return (
             '\n'
@@ -225,7 +275,7 @@ class LogParser:
             f' Transaction size: {self.size[0]:,} B\n'
             f' Execution time: {round(duration):,} s\n'
             '\n'
-            f' Header size: {header_size:,} B\n'
+            f' Header size: {header_size:,} votes\n'
             f' Max header delay: {max_header_delay:,} ms\n'
             f' GC depth: {gc_depth:,} round(s)\n'
             f' Sync retry delay: {sync_retry_delay:,} ms\n'
@@ -241,6 +291,12 @@ class LogParser:
             f' End-to-end TPS: {round(end_to_end_tps):,} tx/s\n'
             f' End-to-end BPS: {round(end_to_end_bps):,} B/s\n'
             f' End-to-end latency: {round(end_to_end_latency):,} ms\n'
+            f' Client backlog: {self.late_bursts:,} late burst(s), '
+            f'up to {self.max_backlog:,} ms\n'
+            f'{overloaded}'
+            '\n'
+            f' Conflicting pairs: {len(self.conflicts):,} '
+            f'({len(self.decisions):,} decided, {len(self.split):,} split)\n'
             '-----------------------------------------\n'
         )
 
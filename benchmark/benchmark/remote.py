@@ -177,7 +177,7 @@ class Bench:
 
         return committee
 
-    def _run_single(self, rate, committee, bench_parameters, debug=False):
+    def _run_single(self, rate, committee, bench_parameters, node_parameters, debug=False):
         faults = bench_parameters.faults
 
         # Kill any potentially unfinished run and delete logs.
@@ -198,7 +198,9 @@ class Bench:
                     address,
                     bench_parameters.tx_size,
                     rate_share,
-                    [x for y in workers_addresses for _, x in y]
+                    [x for y in workers_addresses for _, x in y],
+                    node_parameters.minimum_fee,
+                    bench_parameters.conflict_rate
                 )
                 log_file = PathMaker.client_log_file(i, id)
                 self._background_run(hosts[i], cmd, log_file)
@@ -322,7 +324,7 @@ class Bench:
                     Print.heading(f'Run {i+1}/{bench_parameters.runs}')
                     try:
                         self._run_single(
-                            r, committee_copy, bench_parameters, debug
+                            r, committee_copy, bench_parameters, node_parameters, debug
                         )
 
                         faults = bench_parameters.faults
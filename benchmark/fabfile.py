@@ -10,16 +10,17 @@ from benchmark.remote import Bench, BenchError
 
 
 @task
-def local(ctx, debug=True):
-    ''' Run benchmarks on localhost '''
+def local(ctx, debug=True, workers=1):
+    ''' Run benchmarks on localhost (e.g. "fab local --workers 2" for two workers per node) '''
     bench_params = {
         'faults': 0,
         'nodes': [4],
-        'workers': 1,
+        'workers': int(workers),
         'rate': 50000,
         'tx_size': 500,
         'duration': 5,
         'runs': 1,
+        'conflict_rate': 0,  # fraction of txs sent with a conflicting twin
     }
     node_params = {
         'header_size': 1000,  # bytes
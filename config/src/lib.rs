@@ -3,13 +3,19 @@ use crypto::{generate_production_keypair, PublicKey, SecretKey};
 use log::info;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{self, OpenOptions};
 use std::io::BufWriter;
 use std::io::Write as _;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
 use thiserror::Error;
 
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -23,6 +29,12 @@ pub enum ConfigError {
 
     #[error("Failed to write config file '{file}': {message}")]
     ExportError { file: String, message: String },
+
+    #[error("Invalid parameters: {0}")]
+    InvalidParameters(String),
+
+    #[error("Invalid committee: {0}")]
+    InvalidCommittee(String),
 }
 
 pub trait Import: DeserializeOwned {
@@ -41,7 +53,11 @@ pub trait Import: DeserializeOwned {
 pub trait Export: Serialize {
     fn export(&self, path: &str) -> Result<(), ConfigError> {
         let writer = || -> Result<(), std::io::Error> {
-            let file = OpenOptions::new().create(true).write(true).open(path)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)?;
             let mut writer = BufWriter::new(file);
             let data = serde_json::to_string_pretty(self).unwrap();
             writer.write_all(data.as_ref())?;
@@ -57,17 +73,32 @@ pub trait Export: Serialize {
 
 pub type Stake = u32;
 pub type WorkerId = u32;
+/// The number of reconfigurations the committee went through.
+pub type Epoch = u64;
+
+/// The prefix of the environment variables overriding parameters (eg. `NARWHAL_HEADER_SIZE`).
+pub const PARAMETERS_ENV_PREFIX: &str = "NARWHAL_";
 
+/// Missing fields take their default value.
 #[derive(Deserialize, Clone)]
+#[serde(default)]
 pub struct Parameters {
     /// The preferred header size. The primary creates a new header when it has enough parents and
-    /// enough batches' digests to reach `header_size`. Denominated in bytes.
+    /// enough proposals to reach `header_size`, and no header of its carries more votes: the others
+    /// wait for the next one. Denominated in votes.
     pub header_size: usize,
+    /// The most votes the primary accepts in the headers of others. Larger headers are rejected.
+    pub max_header_entries: usize,
     /// The maximum delay that the primary waits between generating two headers, even if the header
     /// did not reach `max_header_size`. Denominated in ms.
     pub max_header_delay: u64,
-    /// The depth of the garbage collection (Denominated in number of rounds).
+    /// The depth of the garbage collection (Denominated in number of rounds). The primary also uses
+    /// it as the number of decided elections whose tallies it keeps in memory.
     pub gc_depth: u64,
+    /// How many rounds ahead of the primary a header (or a vote, ahead of the latest voting round
+    /// of its election holding a quorum) may be before it is dropped. Headers more than `gc_depth`
+    /// rounds behind are dropped as well.
+    pub round_window: u64,
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
     pub sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request. These nodes
@@ -76,21 +107,117 @@ pub struct Parameters {
     /// The preferred batch size. The workers seal a batch of transactions when it reaches this size.
     /// Denominated in bytes.
     pub batch_size: usize,
+    /// The hard limit on the size of a batch: a transaction that would take a batch past it goes
+    /// to the next one (unless the batch is empty). Denominated in bytes.
+    pub max_batch_bytes: usize,
     /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
+    /// The maximum number of batch digests a worker sends to its primary in a single message.
+    /// Digests are sent one by one if 1.
+    pub digests_batch_size: usize,
+    /// The delay after which a worker sends the digests it holds to its primary, even if
+    /// `digests_batch_size` is not reached. Denominated in ms.
+    pub max_digests_delay: u64,
+    /// The delay after which a voting round that holds a quorum of votes (but no quorum for a
+    /// single tx hash) moves on to the next round. Denominated in ms.
+    pub vote_timeout: u64,
+    /// The number of voting rounds an election gets to decide. A primary that would vote in a later
+    /// round gives up on the election instead, and reports it as failed. Unbounded if zero.
+    pub max_election_rounds: u64,
+    /// How long the batch of another authority held by our workers may wait for its election to
+    /// start (ie. for a vote in it to reach us) before the primary proposes it. This way the batches
+    /// of a proposer that crashed before voting for them still get decided. Denominated in ms.
+    /// Disabled if zero.
+    pub reproposal_delay: u64,
+    /// The maximum number of (non-commit) votes a primary sends at once. Larger flushes are
+    /// spread over several headers, `vote_pacing_interval` apart. Pacing is disabled if zero.
+    pub vote_pacing_chunk: usize,
+    /// The delay between two paced chunks of votes. Denominated in ms.
+    pub vote_pacing_interval: u64,
+    /// The maximum number of decisions the node publishes to its decision sink at once.
+    pub sink_batch_size: usize,
+    /// The delay before retrying a failed publication to the decision sink. It doubles after each
+    /// consecutive failure. Denominated in ms.
+    pub sink_retry_delay: u64,
+    /// The maximum number of messages the primary queues for a single peer. Past this limit (or
+    /// `outbound_buffer_bytes`), the oldest queued messages are dropped.
+    pub outbound_buffer_messages: usize,
+    /// The maximum size of the messages the primary queues for a single peer. Denominated in bytes.
+    pub outbound_buffer_bytes: usize,
+    /// The delay the primary waits for a quorum of peers to acknowledge a header carrying commit
+    /// votes before sending it once more to the others. Denominated in ms. Headers with commits are
+    /// sent like all others (without acknowledgements) if zero.
+    pub dissemination_timeout: u64,
+    /// The smallest fee a worker accepts for a transaction.
+    pub minimum_fee: u64,
+    /// The maximum size of the transactions a worker buffers until it batches them. Past this
+    /// limit, the lowest-fee transactions are evicted. Denominated in bytes.
+    pub mempool_size: usize,
+    /// How long a connection of the primary to a peer may go without hearing from it before
+    /// pinging it. Denominated in ms.
+    pub keepalive_interval: u64,
+    /// How long the primary waits for a peer to answer a ping (or read a message) before it tears
+    /// down the connection and reconnects. Denominated in ms.
+    pub dead_peer_timeout: u64,
+    /// The minimum delay between two compactions of the store scheduled by the primary. They only
+    /// start once the primary has been idle for `compaction_idle_delay`. Denominated in ms.
+    /// Scheduled compactions are disabled if zero (the store still compacts in the background).
+    pub compaction_interval: u64,
+    /// How long the primary must go without processing headers (and without elections in their
+    /// final rounds) before it compacts the store. Denominated in ms.
+    pub compaction_idle_delay: u64,
+    /// The bandwidth of the background compactions and flushes of the store. Denominated in B/s.
+    /// Unlimited if zero.
+    pub compaction_rate_limit: u64,
+    /// The size up to which the workers hand transactions to the primary as they are, to be
+    /// proposed inline in its headers rather than batched. Denominated in bytes. Disabled if zero.
+    pub inline_size: usize,
+    /// The maximum size of the inline transactions of a single header. Denominated in bytes.
+    pub max_inline_bytes: usize,
+    /// Whether the primary commits at once to all the uncontested votes of a header, rather than
+    /// to each of them (see `BatchCommit`). The contested ones are still committed to one by one.
+    pub batch_commits: bool,
+    /// Which primaries propose the transactions of their workers.
+    pub proposal_policy: ProposalPolicy,
 }
 
 impl Default for Parameters {
     fn default() -> Self {
         Self {
             header_size: 1_000,
+            max_header_entries: 10_000,
             max_header_delay: 100,
             gc_depth: 50,
+            round_window: 50,
             sync_retry_delay: 5_000,
             sync_retry_nodes: 3,
             batch_size: 500_000,
+            max_batch_bytes: 1_000_000,
             max_batch_delay: 100,
+            digests_batch_size: 1,
+            max_digests_delay: 10,
+            vote_timeout: 0,
+            max_election_rounds: 0,
+            reproposal_delay: 0,
+            vote_pacing_chunk: 0,
+            vote_pacing_interval: 2,
+            sink_batch_size: 100,
+            sink_retry_delay: 100,
+            outbound_buffer_messages: 1_000,
+            outbound_buffer_bytes: 32 * 1024 * 1024,
+            dissemination_timeout: 0,
+            minimum_fee: 0,
+            mempool_size: 100 * 1024 * 1024,
+            keepalive_interval: 1_000,
+            dead_peer_timeout: 10_000,
+            compaction_interval: 0,
+            compaction_idle_delay: 1_000,
+            compaction_rate_limit: 0,
+            inline_size: 0,
+            max_inline_bytes: 64 * 1024,
+            batch_commits: false,
+            proposal_policy: ProposalPolicy::AllPropose,
         }
     }
 }
@@ -98,18 +225,222 @@ impl Default for Parameters {
 impl Import for Parameters {}
 
 impl Parameters {
+    /// Override a single parameter, named as in the parameters file.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+            value.parse().map_err(|_| {
+                ConfigError::InvalidParameters(format!("Cannot parse '{}' as {}", value, key))
+            })
+        }
+
+        match key {
+            "header_size" => self.header_size = parse(key, value)?,
+            "max_header_entries" => self.max_header_entries = parse(key, value)?,
+            "max_header_delay" => self.max_header_delay = parse(key, value)?,
+            "gc_depth" => self.gc_depth = parse(key, value)?,
+            "round_window" => self.round_window = parse(key, value)?,
+            "sync_retry_delay" => self.sync_retry_delay = parse(key, value)?,
+            "sync_retry_nodes" => self.sync_retry_nodes = parse(key, value)?,
+            "batch_size" => self.batch_size = parse(key, value)?,
+            "max_batch_bytes" => self.max_batch_bytes = parse(key, value)?,
+            "max_batch_delay" => self.max_batch_delay = parse(key, value)?,
+            "digests_batch_size" => self.digests_batch_size = parse(key, value)?,
+            "max_digests_delay" => self.max_digests_delay = parse(key, value)?,
+            "vote_timeout" => self.vote_timeout = parse(key, value)?,
+            "max_election_rounds" => self.max_election_rounds = parse(key, value)?,
+            "reproposal_delay" => self.reproposal_delay = parse(key, value)?,
+            "vote_pacing_chunk" => self.vote_pacing_chunk = parse(key, value)?,
+            "vote_pacing_interval" => self.vote_pacing_interval = parse(key, value)?,
+            "sink_batch_size" => self.sink_batch_size = parse(key, value)?,
+            "sink_retry_delay" => self.sink_retry_delay = parse(key, value)?,
+            "outbound_buffer_messages" => self.outbound_buffer_messages = parse(key, value)?,
+            "outbound_buffer_bytes" => self.outbound_buffer_bytes = parse(key, value)?,
+            "dissemination_timeout" => self.dissemination_timeout = parse(key, value)?,
+            "minimum_fee" => self.minimum_fee = parse(key, value)?,
+            "mempool_size" => self.mempool_size = parse(key, value)?,
+            "keepalive_interval" => self.keepalive_interval = parse(key, value)?,
+            "dead_peer_timeout" => self.dead_peer_timeout = parse(key, value)?,
+            "compaction_interval" => self.compaction_interval = parse(key, value)?,
+            "compaction_idle_delay" => self.compaction_idle_delay = parse(key, value)?,
+            "compaction_rate_limit" => self.compaction_rate_limit = parse(key, value)?,
+            "inline_size" => self.inline_size = parse(key, value)?,
+            "max_inline_bytes" => self.max_inline_bytes = parse(key, value)?,
+            "batch_commits" => self.batch_commits = parse(key, value)?,
+            "proposal_policy" => self.proposal_policy = parse(key, value)?,
+            _ => {
+                return Err(ConfigError::InvalidParameters(format!(
+                    "Unknown parameter {}",
+                    key
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the overrides found in the environment variables starting with `PARAMETERS_ENV_PREFIX`.
+    pub fn override_from_env<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(PARAMETERS_ENV_PREFIX) {
+                self.set(&key.to_lowercase(), &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the parameters are usable and consistent with each other.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let ensure = |condition: bool, message: &str| match condition {
+            true => Ok(()),
+            false => Err(ConfigError::InvalidParameters(message.to_string())),
+        };
+        ensure(self.header_size > 0, "header_size must be positive")?;
+        // Otherwise the peers reject the largest of our headers.
+        ensure(
+            self.header_size <= self.max_header_entries,
+            "header_size must not exceed max_header_entries",
+        )?;
+        ensure(self.batch_size > 0, "batch_size must be positive")?;
+        ensure(
+            self.batch_size <= self.max_batch_bytes,
+            "batch_size must not exceed max_batch_bytes",
+        )?;
+        // Otherwise no election can decide: commits are cast from round 1 on.
+        ensure(
+            self.max_election_rounds != 1,
+            "max_election_rounds must be zero or at least 2",
+        )?;
+        // Otherwise headers are dropped as soon as their author is a round ahead of us.
+        ensure(self.round_window > 0, "round_window must be positive")?;
+        ensure(
+            self.sync_retry_nodes > 0,
+            "sync_retry_nodes must be positive",
+        )?;
+        ensure(
+            self.max_header_delay > 0 && self.max_batch_delay > 0,
+            "max_header_delay and max_batch_delay must be positive",
+        )?;
+        // Otherwise headers regularly leave before the batches they should carry are sealed.
+        ensure(
+            self.max_batch_delay <= self.max_header_delay,
+            "max_batch_delay must not exceed max_header_delay",
+        )?;
+        ensure(
+            self.digests_batch_size > 0 && self.max_digests_delay > 0,
+            "digests_batch_size and max_digests_delay must be positive",
+        )?;
+        ensure(
+            self.vote_pacing_chunk == 0 || self.vote_pacing_interval > 0,
+            "vote_pacing_interval must be positive when pacing is enabled",
+        )?;
+        ensure(
+            self.sink_batch_size > 0 && self.sink_retry_delay > 0,
+            "sink_batch_size and sink_retry_delay must be positive",
+        )?;
+        ensure(
+            self.outbound_buffer_messages > 0 && self.outbound_buffer_bytes > 0,
+            "outbound_buffer_messages and outbound_buffer_bytes must be positive",
+        )?;
+        // Otherwise the workers evict transactions before they can fill a batch.
+        ensure(
+            self.mempool_size >= self.batch_size,
+            "mempool_size must not be smaller than batch_size",
+        )?;
+        // Otherwise a peer is declared dead before it had a chance to answer.
+        ensure(
+            self.keepalive_interval > 0 && self.keepalive_interval < self.dead_peer_timeout,
+            "keepalive_interval must be positive and smaller than dead_peer_timeout",
+        )?;
+        // Otherwise some inline transactions fit in no header.
+        ensure(
+            self.inline_size <= self.max_inline_bytes,
+            "inline_size must not exceed max_inline_bytes",
+        )
+    }
+
     pub fn log(&self) {
-        info!("Header size set to {} B", self.header_size);
+        info!("Header size set to {} votes", self.header_size);
+        info!("Max header entries set to {} votes", self.max_header_entries);
         info!("Max header delay set to {} ms", self.max_header_delay);
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
+        info!("Round window set to {} rounds", self.round_window);
         info!("Sync retry delay set to {} ms", self.sync_retry_delay);
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
         info!("Batch size set to {} B", self.batch_size);
+        info!("Max batch bytes set to {} B", self.max_batch_bytes);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        info!("Digests batch size set to {} digests", self.digests_batch_size);
+        info!("Max digests delay set to {} ms", self.max_digests_delay);
+        info!("Vote timeout set to {} ms", self.vote_timeout);
+        info!("Max election rounds set to {}", self.max_election_rounds);
+        info!("Reproposal delay set to {} ms", self.reproposal_delay);
+        info!("Vote pacing chunk set to {} votes", self.vote_pacing_chunk);
+        info!(
+            "Vote pacing interval set to {} ms",
+            self.vote_pacing_interval
+        );
+        info!("Sink batch size set to {} decisions", self.sink_batch_size);
+        info!("Sink retry delay set to {} ms", self.sink_retry_delay);
+        info!(
+            "Outbound buffer set to {} messages",
+            self.outbound_buffer_messages
+        );
+        info!("Outbound buffer set to {} B", self.outbound_buffer_bytes);
+        info!(
+            "Dissemination timeout set to {} ms",
+            self.dissemination_timeout
+        );
+        info!("Minimum fee set to {}", self.minimum_fee);
+        info!("Mempool size set to {} B", self.mempool_size);
+        info!("Keepalive interval set to {} ms", self.keepalive_interval);
+        info!("Dead peer timeout set to {} ms", self.dead_peer_timeout);
+        info!("Compaction interval set to {} ms", self.compaction_interval);
+        info!(
+            "Compaction idle delay set to {} ms",
+            self.compaction_idle_delay
+        );
+        info!("Compaction rate limit set to {} B/s", self.compaction_rate_limit);
+        info!("Inline size set to {} B", self.inline_size);
+        info!("Max inline bytes set to {} B", self.max_inline_bytes);
+        info!("Batch commits set to {}", self.batch_commits);
+        info!("Proposal policy set to {:?}", self.proposal_policy);
+    }
+}
+
+/// Which primaries propose the transactions of their workers in a round.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProposalPolicy {
+    /// Every primary proposes its own.
+    AllPropose,
+    /// Only the leader of the round proposes (see `Committee::leader`): the others forward their
+    /// proposals to it.
+    Leader,
+    /// As `Leader`, but each authority leads a share of the rounds matching its share of the stake
+    /// (see `Committee::weighted_leader`).
+    WeightedLeader,
+}
+
+impl FromStr for ProposalPolicy {
+    type Err = ConfigError;
+
+    /// Parse `all-propose`, `leader` or `weighted-leader`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all-propose" => Ok(Self::AllPropose),
+            "leader" => Ok(Self::Leader),
+            "weighted-leader" => Ok(Self::WeightedLeader),
+            _ => Err(ConfigError::InvalidParameters(format!(
+                "Unknown proposal policy {}",
+                s
+            ))),
+        }
     }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PrimaryAddresses {
     /// Address to receive messages from other primaries (WAN).
     pub primary_to_primary: SocketAddr,
@@ -117,7 +448,7 @@ pub struct PrimaryAddresses {
     pub worker_to_primary: SocketAddr,
 }
 
-#[derive(Clone, Deserialize, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, Hash, PartialEq)]
 pub struct WorkerAddresses {
     /// Address to receive client transactions (WAN).
     pub transactions: SocketAddr,
@@ -127,7 +458,7 @@ pub struct WorkerAddresses {
     pub primary_to_worker: SocketAddr,
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Authority {
     /// The voting power of this authority.
     pub stake: Stake,
@@ -135,17 +466,164 @@ pub struct Authority {
     pub primary: PrimaryAddresses,
     /// Map of workers' id and their network addresses.
     pub workers: HashMap<WorkerId, WorkerAddresses>,
+    /// Whether this authority is byzantine. It then runs `ByzantineStrategy::Silent` unless the
+    /// node is started with another strategy.
     pub byzantine: bool,
 }
 
-#[derive(Clone, Deserialize)]
+/// How a byzantine authority misbehaves.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ByzantineStrategy {
+    /// Stop voting (a crash fault).
+    Silent,
+    /// Send conflicting votes to different peers.
+    Equivocate,
+    /// Always vote for the minority tx hash of the round.
+    VoteFlip,
+    /// Hold back every outbound header for the given duration.
+    DelayVotes(Duration),
+    /// Send every outbound header many times.
+    SpamHeaders,
+}
+
+impl FromStr for ByzantineStrategy {
+    type Err = ConfigError;
+
+    /// Parse `silent`, `equivocate`, `vote-flip`, `delay-votes=<ms>` or `spam-headers`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || ConfigError::InvalidParameters(format!("Unknown byzantine strategy {}", s));
+        match s.split_once('=') {
+            Some(("delay-votes", delay)) => delay
+                .parse()
+                .map(|x| Self::DelayVotes(Duration::from_millis(x)))
+                .map_err(|_| invalid()),
+            Some(_) => Err(invalid()),
+            None => match s {
+                "silent" => Ok(Self::Silent),
+                "equivocate" => Ok(Self::Equivocate),
+                "vote-flip" => Ok(Self::VoteFlip),
+                "spam-headers" => Ok(Self::SpamHeaders),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Committee {
+    /// Committees without an epoch are the first one.
+    #[serde(default)]
+    pub epoch: Epoch,
     pub authorities: BTreeMap<PublicKey, Authority>,
 }
 
 impl Import for Committee {}
+impl Export for Committee {}
 
 impl Committee {
+    /// Make a committee whose authorities all run on `host`, with `workers` workers each. The
+    /// ports are handed out in sequence from `base_port`, in the order of `authorities`: two for
+    /// the primary, then three for each worker.
+    pub fn local(
+        authorities: &[(PublicKey, Stake)],
+        host: IpAddr,
+        base_port: u16,
+        workers: WorkerId,
+    ) -> Result<Self, ConfigError> {
+        let mut ports = base_port..=u16::MAX;
+        let mut next = || {
+            ports.next().map(|x| SocketAddr::new(host, x)).ok_or_else(|| {
+                ConfigError::InvalidCommittee(format!("Not enough ports from {}", base_port))
+            })
+        };
+        let mut committee = Self {
+            epoch: 0,
+            authorities: BTreeMap::new(),
+        };
+        for (name, stake) in authorities {
+            let primary = PrimaryAddresses {
+                primary_to_primary: next()?,
+                worker_to_primary: next()?,
+            };
+            let mut addresses = HashMap::new();
+            for id in 0..workers {
+                addresses.insert(
+                    id,
+                    WorkerAddresses {
+                        primary_to_worker: next()?,
+                        transactions: next()?,
+                        worker_to_worker: next()?,
+                    },
+                );
+            }
+            let authority = Authority {
+                stake: *stake,
+                primary,
+                workers: addresses,
+                byzantine: false,
+            };
+            if committee.authorities.insert(*name, authority).is_some() {
+                return Err(ConfigError::InvalidCommittee(format!(
+                    "Authority {} listed twice",
+                    name
+                )));
+            }
+        }
+        Ok(committee)
+    }
+
+    /// Check that the committee can run: it has authorities, all with some stake and the same
+    /// worker ids, and no two of its addresses share a port on the same host.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::InvalidCommittee(message));
+        let mut worker_ids = self
+            .authorities
+            .values()
+            .map(|x| x.workers.keys().copied().collect::<BTreeSet<_>>());
+        let ids = match worker_ids.next() {
+            Some(ids) => ids,
+            None => return invalid("No authorities".to_string()),
+        };
+        if worker_ids.any(|x| x != ids) {
+            return invalid("The authorities do not all have the same worker ids".to_string());
+        }
+
+        let mut used = HashMap::new();
+        for (name, authority) in &self.authorities {
+            if authority.stake == 0 {
+                return invalid(format!("Authority {} has no stake", name));
+            }
+            let primary = vec![
+                ("primary_to_primary", authority.primary.primary_to_primary),
+                ("worker_to_primary", authority.primary.worker_to_primary),
+            ];
+            let workers = authority.workers.iter().flat_map(|(id, x)| {
+                vec![
+                    ("primary_to_worker", x.primary_to_worker),
+                    ("transactions", x.transactions),
+                    ("worker_to_worker", x.worker_to_worker),
+                ]
+                .into_iter()
+                .map(move |(field, address)| (format!("worker {} {}", id, field), address))
+            });
+            let addresses = primary
+                .into_iter()
+                .map(|(field, address)| (format!("primary {}", field), address))
+                .chain(workers);
+            for (what, address) in addresses {
+                if address.port() == 0 {
+                    return invalid(format!("Port 0 for the {} of {}", what, name));
+                }
+                let user = format!("the {} of {}", what, name);
+                if let Some(other) = used.insert(address, user.clone()) {
+                    return invalid(format!("{} is used by {} and {}", address, other, user));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the number of authorities.
     pub fn size(&self) -> usize {
         self.authorities.len()
@@ -181,11 +659,33 @@ impl Committee {
         (total_votes + 2) / 3
     }
 
-    /// Returns a leader node in a round-robin fashion.
-    pub fn leader(&self, seed: usize) -> PublicKey {
-        let mut keys: Vec<_> = self.authorities.keys().cloned().collect();
-        keys.sort();
-        keys[seed % self.size()]
+    /// Returns the leader of a round: the authorities (sorted by key) take turns.
+    pub fn leader(&self, round: u64) -> PublicKey {
+        let keys: Vec<_> = self.authorities.keys().collect();
+        *keys[(round % keys.len() as u64) as usize]
+    }
+
+    /// Returns the leader of a round, weighted by stake: out of any run of consecutive rounds as long
+    /// as the total stake, each authority leads as many rounds as it has stake.
+    pub fn weighted_leader(&self, round: u64) -> PublicKey {
+        let total: u64 = self.authorities.values().map(|x| x.stake as u64).sum();
+        let mut slot = round % total.max(1);
+        for (name, authority) in &self.authorities {
+            if slot < authority.stake as u64 {
+                return *name;
+            }
+            slot -= authority.stake as u64;
+        }
+        self.leader(round)
+    }
+
+    /// Returns the primary proposing in a round under a policy, or `None` if all of them propose.
+    pub fn proposer(&self, policy: ProposalPolicy, round: u64) -> Option<PublicKey> {
+        match policy {
+            ProposalPolicy::AllPropose => None,
+            ProposalPolicy::Leader => Some(self.leader(round)),
+            ProposalPolicy::WeightedLeader => Some(self.weighted_leader(round)),
+        }
     }
 
     /// Returns the primary addresses of the target primary.
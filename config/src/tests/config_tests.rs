@@ -0,0 +1,250 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn missing_parameters_take_defaults() {
+    let defaults = Parameters::default();
+    let parameters: Parameters = serde_json::from_str(r#"{ "header_size": 7 }"#).unwrap();
+    assert_eq!(parameters.header_size, 7);
+    assert_eq!(parameters.max_header_delay, defaults.max_header_delay);
+    assert_eq!(parameters.gc_depth, defaults.gc_depth);
+    assert_eq!(parameters.batch_size, defaults.batch_size);
+    assert!(parameters.validate().is_ok());
+}
+
+#[test]
+fn reject_invalid_parameters() {
+    let invalid = vec![
+        ("header_size", "0"),
+        ("max_header_entries", "10"), // Smaller than the default header size.
+        ("batch_size", "0"),
+        ("max_batch_bytes", "1000"), // Smaller than the default batch size.
+        ("round_window", "0"),
+        ("max_election_rounds", "1"),
+        ("sync_retry_nodes", "0"),
+        ("max_header_delay", "0"),
+        ("max_batch_delay", "0"),
+        ("max_batch_delay", "1000"), // Larger than the default header delay.
+        ("digests_batch_size", "0"),
+        ("max_digests_delay", "0"),
+        ("sink_batch_size", "0"),
+        ("sink_retry_delay", "0"),
+        ("outbound_buffer_messages", "0"),
+        ("outbound_buffer_bytes", "0"),
+        ("mempool_size", "1000"), // Smaller than the default batch size.
+        ("keepalive_interval", "0"),
+        ("keepalive_interval", "10000"), // Not smaller than the default dead peer timeout.
+        ("inline_size", "100000"),       // Larger than the default max inline bytes.
+    ];
+    for (key, value) in invalid {
+        let mut parameters = Parameters::default();
+        parameters.set(key, value).unwrap();
+        assert!(
+            parameters.validate().is_err(),
+            "{} = {} accepted",
+            key,
+            value
+        );
+    }
+
+    // Pacing needs a positive interval.
+    let mut parameters = Parameters::default();
+    parameters.set("vote_pacing_chunk", "10").unwrap();
+    parameters.set("vote_pacing_interval", "0").unwrap();
+    assert!(parameters.validate().is_err());
+}
+
+#[test]
+fn reject_malformed_overrides() {
+    let mut parameters = Parameters::default();
+    assert!(parameters.set("header_size", "many").is_err());
+    assert!(parameters.set("unknown", "1").is_err());
+    assert!(parameters.set("batch_commits", "yes").is_err());
+    parameters.set("batch_commits", "true").unwrap();
+    assert!(parameters.batch_commits);
+    let vars = env(&[("NARWHAL_UNKNOWN", "1")]);
+    assert!(parameters.override_from_env(vars).is_err());
+}
+
+#[test]
+fn override_precedence() {
+    // The file sets three parameters.
+    let file = r#"{ "header_size": 1, "gc_depth": 2, "batch_size": 3 }"#;
+    let mut parameters: Parameters = serde_json::from_str(file).unwrap();
+
+    // The environment overrides two of them (and ignores unrelated variables).
+    let vars = env(&[
+        ("NARWHAL_GC_DEPTH", "20"),
+        ("NARWHAL_BATCH_SIZE", "30"),
+        ("HOME", "/root"),
+    ]);
+    parameters.override_from_env(vars).unwrap();
+
+    // The command line overrides one of those again.
+    parameters.set("batch_size", "300").unwrap();
+
+    assert_eq!(parameters.header_size, 1);
+    assert_eq!(parameters.gc_depth, 20);
+    assert_eq!(parameters.batch_size, 300);
+}
+
+#[test]
+fn parse_byzantine_strategies() {
+    let parse = |x: &str| x.parse::<ByzantineStrategy>().ok();
+    assert_eq!(parse("silent"), Some(ByzantineStrategy::Silent));
+    assert_eq!(parse("vote-flip"), Some(ByzantineStrategy::VoteFlip));
+    assert_eq!(
+        parse("delay-votes=50"),
+        Some(ByzantineStrategy::DelayVotes(Duration::from_millis(50)))
+    );
+    assert_eq!(parse("delay-votes=soon"), None);
+    assert_eq!(parse("honest"), None);
+}
+
+#[test]
+fn committee_without_epoch() {
+    // Committee files from before reconfigurations are of the first epoch.
+    let json = format!(
+        r#"{{ "authorities": {{ "{}": {{
+            "stake": 1,
+            "primary": {{ "primary_to_primary": "127.0.0.1:0", "worker_to_primary": "127.0.0.1:0" }},
+            "workers": {{}},
+            "byzantine": false
+        }} }} }}"#,
+        PublicKey::default().encode_base64()
+    );
+    let committee: Committee = serde_json::from_str(&json).unwrap();
+    assert_eq!(committee.epoch, 0);
+    assert_eq!(committee.size(), 1);
+}
+
+#[test]
+fn parse_proposal_policies() {
+    let mut parameters = Parameters::default();
+    assert_eq!(parameters.proposal_policy, ProposalPolicy::AllPropose);
+    parameters.set("proposal_policy", "weighted-leader").unwrap();
+    assert_eq!(parameters.proposal_policy, ProposalPolicy::WeightedLeader);
+    assert!(parameters.set("proposal_policy", "dictator").is_err());
+    let parameters: Parameters = serde_json::from_str(r#"{ "proposal_policy": "leader" }"#).unwrap();
+    assert_eq!(parameters.proposal_policy, ProposalPolicy::Leader);
+}
+
+fn local_committee(stakes: &[Stake]) -> Committee {
+    let authorities: Vec<_> = stakes.iter().map(|x| (KeyPair::new().name, *x)).collect();
+    Committee::local(&authorities, [127, 0, 0, 1].into(), 3000, 2).unwrap()
+}
+
+#[test]
+fn generate_local_committee() {
+    let committee = local_committee(&[1, 1, 2, 1]);
+    assert!(committee.validate().is_ok());
+    assert_eq!(committee.quorum_threshold(), 4);
+
+    // The committee survives a round trip through its file format.
+    let json = serde_json::to_string_pretty(&committee).unwrap();
+    let imported: Committee = serde_json::from_str(&json).unwrap();
+    assert!(imported.validate().is_ok());
+    for (name, authority) in &committee.authorities {
+        assert_eq!(imported.stake(name), authority.stake);
+        assert_eq!(
+            imported.our_workers(name).unwrap().len(),
+            authority.workers.len()
+        );
+    }
+
+    // 4 authorities with one primary (2 ports) and 2 workers (3 ports each).
+    let mut ports: Vec<_> = committee
+        .authorities
+        .values()
+        .flat_map(|x| {
+            let workers = x
+                .workers
+                .values()
+                .flat_map(|x| vec![x.primary_to_worker, x.transactions, x.worker_to_worker]);
+            vec![x.primary.primary_to_primary, x.primary.worker_to_primary]
+                .into_iter()
+                .chain(workers)
+                .map(|x| x.port())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    ports.sort_unstable();
+    assert_eq!(ports, (3000..3032).collect::<Vec<_>>());
+}
+
+#[test]
+fn schedule_leaders() {
+    let committee = local_committee(&[1, 1, 2, 1]);
+    let names: Vec<_> = committee.authorities.keys().cloned().collect();
+
+    // Round-robin: every authority leads once every 4 rounds.
+    let leaders: Vec<_> = (0..8).map(|x| committee.leader(x)).collect();
+    assert_eq!(leaders[..4], names[..]);
+    assert_eq!(leaders[4..], names[..]);
+
+    // Weighted: the authority with twice the stake leads twice as many rounds.
+    let leaders: Vec<_> = (0..10).map(|x| committee.weighted_leader(x)).collect();
+    for (name, authority) in &committee.authorities {
+        let led = leaders.iter().filter(|x| *x == name).count();
+        assert_eq!(led, 2 * authority.stake as usize);
+    }
+    assert_eq!(committee.proposer(ProposalPolicy::AllPropose, 3), None);
+    assert_eq!(committee.proposer(ProposalPolicy::Leader, 3), Some(names[3]));
+}
+
+#[test]
+fn reject_invalid_committees() {
+    let authorities: Vec<_> = (0..4).map(|_| (KeyPair::new().name, 1)).collect();
+    assert!(Committee::local(&authorities, [127, 0, 0, 1].into(), u16::MAX - 10, 1).is_err());
+
+    let mut committee = local_committee(&[1, 1, 1, 1]);
+    let (first, second) = {
+        let mut names = committee.authorities.keys().copied();
+        (names.next().unwrap(), names.next().unwrap())
+    };
+    let address = committee.authorities[&first].primary.primary_to_primary;
+    committee
+        .authorities
+        .get_mut(&second)
+        .unwrap()
+        .workers
+        .get_mut(&1)
+        .unwrap()
+        .transactions = address;
+    assert!(committee.validate().is_err());
+
+    let mut committee = local_committee(&[1, 1, 1, 1]);
+    committee.authorities.values_mut().next().unwrap().stake = 0;
+    assert!(committee.validate().is_err());
+
+    let mut committee = local_committee(&[1, 1, 1, 1]);
+    committee
+        .authorities
+        .values_mut()
+        .next()
+        .unwrap()
+        .workers
+        .remove(&1);
+    assert!(committee.validate().is_err());
+
+    let empty = Committee {
+        epoch: 0,
+        authorities: BTreeMap::new(),
+    };
+    assert!(empty.validate().is_err());
+}
+
+#[test]
+fn reject_malformed_keys() {
+    let committee = local_committee(&[1]);
+    let json = serde_json::to_string(&committee).unwrap();
+    let name = committee.authorities.keys().next().unwrap().encode_base64();
+    let malformed = json.replace(&name, &name[..20]);
+    assert!(serde_json::from_str::<Committee>(&malformed).is_err());
+}
@@ -9,8 +9,7 @@ use serde::{de, ser, Deserialize, Serialize};
 use std::array::TryFromSliceError;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
-use tokio::sync::mpsc::{channel, Sender};
-use tokio::sync::oneshot;
+use std::sync::Arc;
 
 #[cfg(test)]
 #[path = "tests/crypto_tests.rs"]
@@ -37,6 +36,15 @@ impl Digest {
     pub fn size(&self) -> usize {
         self.0.len()
     }
+
+    /// Parse a digest printed in full (see `Debug`).
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
 }
 
 impl fmt::Debug for Digest {
@@ -80,7 +88,7 @@ impl PublicKey {
 
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..32]
+        let array = bytes[..]
             .try_into()
             .map_err(|_| base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
@@ -135,7 +143,7 @@ impl SecretKey {
 
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..64]
+        let array = bytes[..]
             .try_into()
             .map_err(|_| base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
@@ -183,7 +191,7 @@ where
 }
 
 /// Represents an ed25519 signature.
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
 pub struct Signature {
     part1: [u8; 32],
     part2: [u8; 32],
@@ -192,6 +200,16 @@ pub struct Signature {
 impl Signature {
     pub fn new(digest: &Digest, secret: &SecretKey) -> Self {
         let keypair = dalek::Keypair::from_bytes(&secret.0).expect("Unable to load secret key");
+        Self::with_keypair(digest, &keypair)
+    }
+
+    /// Sign each digest with the same key.
+    pub fn new_batch(digests: &[Digest], secret: &SecretKey) -> Vec<Self> {
+        let keypair = dalek::Keypair::from_bytes(&secret.0).expect("Unable to load secret key");
+        digests.iter().map(|x| Self::with_keypair(x, &keypair)).collect()
+    }
+
+    fn with_keypair(digest: &Digest, keypair: &dalek::Keypair) -> Self {
         let sig = keypair.sign(&digest.0).to_bytes();
         let part1 = sig[..32].try_into().expect("Unexpected signature length");
         let part2 = sig[32..64].try_into().expect("Unexpected signature length");
@@ -227,32 +245,36 @@ impl Signature {
     }
 }
 
-/// This service holds the node's private key. It takes digests as input and returns a signature
-/// over the digest (through a oneshot channel).
+/// This service holds the node's private key. It takes digests as input and returns signatures
+/// over them. Clones share the key, and each signs on behalf of its own caller: requests never
+/// queue behind each other. Large batches are signed on a blocking thread.
 #[derive(Clone)]
 pub struct SignatureService {
-    channel: Sender<(Digest, oneshot::Sender<Signature>)>,
+    secret: Arc<SecretKey>,
 }
 
 impl SignatureService {
+    /// Batches of more digests than this are signed on a blocking thread.
+    const BLOCKING_BATCH: usize = 64;
+
     pub fn new(secret: SecretKey) -> Self {
-        let (tx, mut rx): (Sender<(_, oneshot::Sender<_>)>, _) = channel(100);
-        tokio::spawn(async move {
-            while let Some((digest, sender)) = rx.recv().await {
-                let signature = Signature::new(&digest, &secret);
-                let _ = sender.send(signature);
-            }
-        });
-        Self { channel: tx }
+        Self {
+            secret: Arc::new(secret),
+        }
     }
 
     pub async fn request_signature(&mut self, digest: Digest) -> Signature {
-        let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
-        if let Err(e) = self.channel.send((digest, sender)).await {
-            panic!("Failed to send message Signature Service: {}", e);
+        Signature::new(&digest, &self.secret)
+    }
+
+    /// Sign many digests at once, in order. The key is only loaded once for the whole batch.
+    pub async fn sign_batch(&mut self, digests: Vec<Digest>) -> Vec<Signature> {
+        if digests.len() <= Self::BLOCKING_BATCH {
+            return Signature::new_batch(&digests, &self.secret);
         }
-        receiver
+        let secret = self.secret.clone();
+        tokio::task::spawn_blocking(move || Signature::new_batch(&digests, &secret))
             .await
-            .expect("Failed to receive signature from Signature Service")
+            .expect("Failed to sign a batch of digests")
     }
 }
@@ -4,6 +4,8 @@ use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
+use tokio::sync::mpsc::channel;
+use tokio::sync::oneshot;
 
 impl Hash for &[u8] {
     fn digest(&self) -> Digest {
@@ -46,6 +48,23 @@ fn import_export_secret_key() {
     assert_eq!(import.unwrap(), secret_key);
 }
 
+#[test]
+fn reject_malformed_keys() {
+    let (public_key, secret_key) = keys().pop().unwrap();
+    let short = base64::encode(&public_key.0[..31]);
+    assert!(PublicKey::decode_base64(&short).is_err());
+    assert!(PublicKey::decode_base64(&secret_key.encode_base64()).is_err());
+    assert!(SecretKey::decode_base64(&public_key.encode_base64()).is_err());
+    assert!(PublicKey::decode_base64("not base64!").is_err());
+}
+
+#[test]
+fn import_export_digest() {
+    let digest = Digest([7; 32]);
+    assert_eq!(Digest::decode_base64(&format!("{:?}", digest)), Ok(digest));
+    assert!(Digest::decode_base64(&format!("{}", Digest([7; 32]))).is_err());
+}
+
 #[test]
 fn verify_valid_signature() {
     // Get a keypair.
@@ -130,3 +149,58 @@ async fn signature_service() {
     // Verify the signature we received.
     assert!(signature.verify(&digest, &public_key).is_ok());
 }
+
+#[tokio::test]
+async fn sign_batch() {
+    let (public_key, secret_key) = keys().pop().unwrap();
+    let mut service = SignatureService::new(secret_key);
+
+    // Small batches are signed right away, large ones on a blocking thread: both in order.
+    for size in [3, 100] {
+        let digests: Vec<_> = (0..size as u32).map(|i| i.to_be_bytes().as_ref().digest()).collect();
+        let signatures = service.sign_batch(digests.clone()).await;
+        assert_eq!(signatures.len(), size);
+        for (digest, signature) in digests.iter().zip(&signatures) {
+            assert!(signature.verify(digest, &public_key).is_ok());
+        }
+    }
+    assert!(service.sign_batch(Vec::new()).await.is_empty());
+}
+
+// Run with `cargo test --release -p crypto -- --ignored --nocapture`.
+#[tokio::test]
+#[ignore]
+async fn bench_sign_batch() {
+    let (_, secret_key) = keys().pop().unwrap();
+    let digests: Vec<_> = (0..1_000u32).map(|i| i.to_be_bytes().as_ref().digest()).collect();
+
+    // The signing task every request used to queue behind, one round trip per digest.
+    let (tx, mut rx) = channel::<(Digest, oneshot::Sender<Signature>)>(100);
+    let secret = SecretKey(secret_key.0);
+    tokio::spawn(async move {
+        while let Some((digest, sender)) = rx.recv().await {
+            let _ = sender.send(Signature::new(&digest, &secret));
+        }
+    });
+    let start = std::time::Instant::now();
+    for digest in &digests {
+        let (sender, receiver) = oneshot::channel();
+        tx.send((digest.clone(), sender)).await.unwrap();
+        receiver.await.unwrap();
+    }
+    let queued = start.elapsed();
+
+    let mut service = SignatureService::new(secret_key);
+    let start = std::time::Instant::now();
+    for digest in &digests {
+        service.request_signature(digest.clone()).await;
+    }
+    let looped = start.elapsed();
+    let start = std::time::Instant::now();
+    service.sign_batch(digests).await;
+    let batched = start.elapsed();
+    println!(
+        "Signed 1,000 digests in {:?} through a signing task, {:?} one by one, {:?} at once",
+        queued, looped, batched
+    );
+}
@@ -0,0 +1,37 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bincode::Options as _;
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+#[path = "tests/codec_tests.rs"]
+pub mod codec_tests;
+
+/// The maximum size of an encoded message (in bytes). It matches the default maximum frame length
+/// of the `LengthDelimitedCodec` used by all our connections.
+pub const MAX_MESSAGE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The bincode configuration shared by all crates. Every option is pinned explicitly so that a change
+/// of bincode's defaults cannot silently alter digests, signatures, or the wire format.
+fn options() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+        .with_limit(MAX_MESSAGE_SIZE)
+        .with_little_endian()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+/// Serialize a message (or any value that is hashed or signed) with the canonical configuration.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> bincode::Result<Vec<u8>> {
+    options().serialize(value)
+}
+
+/// The size of the encoding of a value, without encoding it.
+pub fn encoded_size<T: Serialize + ?Sized>(value: &T) -> bincode::Result<u64> {
+    options().serialized_size(value)
+}
+
+/// Deserialize a value produced by `encode`. It rejects inputs with trailing bytes and inputs
+/// larger than `MAX_MESSAGE_SIZE`.
+pub fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> bincode::Result<T> {
+    options().deserialize(bytes)
+}
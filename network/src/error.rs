@@ -22,4 +22,10 @@ pub enum NetworkError {
 
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Dropping connection with {0} after {1} faulty messages")]
+    TooManyFaults(SocketAddr, usize),
+
+    #[error("Dropping connection with {0}: no answer for {1} ms")]
+    DeadPeer(SocketAddr, u64),
 }
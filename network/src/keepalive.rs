@@ -0,0 +1,98 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use crate::receiver::Writer;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use std::net::SocketAddr;
+use tokio::time::{timeout, Duration, Instant};
+
+/// The ping frame. Messages are never empty, so an empty frame is a ping (from a sender) or the
+/// reply to a ping (from a receiver). Receivers answer pings without handing them to their handler.
+pub fn ping() -> Bytes {
+    Bytes::new()
+}
+
+pub fn is_ping(frame: &[u8]) -> bool {
+    frame.is_empty()
+}
+
+/// The application-level keepalive of outgoing connections: an idle connection pings its peer,
+/// and one whose peer does not answer (or does not read) for too long is torn down.
+#[derive(Clone, Copy, Debug)]
+pub struct KeepAlive {
+    /// How long a connection may go without hearing from its peer before pinging it (in ms).
+    pub interval: u64,
+    /// How long we wait for the reply to a ping, or for the peer to read a message, before we
+    /// consider the connection dead (in ms).
+    pub timeout: u64,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            interval: 1_000,
+            timeout: 10_000,
+        }
+    }
+}
+
+/// Tracks the liveness of a single connection.
+pub struct Heartbeat {
+    address: SocketAddr,
+    keepalive: KeepAlive,
+    /// When we last received a frame from the peer.
+    last_received: Instant,
+    /// When we sent the ping we are waiting a reply for.
+    ping_sent: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(address: SocketAddr, keepalive: KeepAlive) -> Self {
+        Self {
+            address,
+            keepalive,
+            last_received: Instant::now(),
+            ping_sent: None,
+        }
+    }
+
+    /// How often `check` should be called.
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.keepalive.interval)
+    }
+
+    /// Note a frame from the peer. Returns the round-trip time if it answers our ping.
+    pub fn received(&mut self, frame: &[u8]) -> Option<Duration> {
+        self.last_received = Instant::now();
+        match is_ping(frame) {
+            true => self.ping_sent.take().map(|x| x.elapsed()),
+            false => None,
+        }
+    }
+
+    /// Ping the peer if we did not hear from it for a while. Fails if the peer did not answer our
+    /// last ping in time.
+    pub async fn check(&mut self, writer: &mut Writer) -> Result<(), NetworkError> {
+        match self.ping_sent {
+            Some(sent) if sent.elapsed() >= Duration::from_millis(self.keepalive.timeout) => {
+                Err(NetworkError::DeadPeer(self.address, self.keepalive.timeout))
+            }
+            Some(_) => Ok(()),
+            None if self.last_received.elapsed() >= self.period() => {
+                self.ping_sent = Some(Instant::now());
+                self.send(writer, ping()).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Send a frame to the peer. Fails if the peer does not read it in time, rather than waiting
+    /// for the OS to notice the connection is gone.
+    pub async fn send(&self, writer: &mut Writer, data: Bytes) -> Result<(), NetworkError> {
+        let limit = Duration::from_millis(self.keepalive.timeout);
+        match timeout(limit, writer.send(data)).await {
+            Ok(result) => result.map_err(|e| NetworkError::FailedToSendMessage(self.address, e)),
+            Err(_) => Err(NetworkError::DeadPeer(self.address, self.keepalive.timeout)),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod codec;
 mod error;
+mod keepalive;
 mod receiver;
 mod reliable_sender;
 mod simple_sender;
@@ -8,6 +10,8 @@ mod simple_sender;
 #[path = "tests/common.rs"]
 pub mod common;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
+pub use crate::codec::{decode, encode, encoded_size, MAX_MESSAGE_SIZE};
+pub use crate::keepalive::KeepAlive;
+pub use crate::receiver::{MessageHandler, Receiver, Writer, MAX_FAULTS};
 pub use crate::reliable_sender::{CancelHandler, ReliableSender};
-pub use crate::simple_sender::SimpleSender;
+pub use crate::simple_sender::{DropPolicy, PeerStats, SenderLimits, SimpleSender};
@@ -1,19 +1,25 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::keepalive::{is_ping, ping};
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::sink::SinkExt as _;
 use futures::stream::SplitSink;
 use futures::stream::StreamExt as _;
 use log::{debug, info, warn};
 use std::error::Error;
 use std::net::SocketAddr;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
 #[path = "tests/receiver_tests.rs"]
 pub mod receiver_tests;
 
+/// The number of messages a peer may fail to get dispatched before we drop its connection.
+pub const MAX_FAULTS: usize = 10;
+
 /// Convenient alias for the writer end of the TCP channel.
 pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
 
@@ -22,7 +28,8 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// Defines how to handle an incoming message. A typical usage is to define a `MessageHandler` with a
     /// number of `Sender<T>` channels. Then implement `dispatch` to deserialize incoming messages and
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
-    /// responses or acknowledgements to the sender machine (see unit tests for examples).
+    /// responses or acknowledgements to the sender machine (see unit tests for examples). Replies must
+    /// not be empty: empty frames are the pings of the keepalive.
     async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
 }
 
@@ -35,12 +42,24 @@ pub struct Receiver<Handler: MessageHandler> {
     handler: Handler,
 }
 
+/// The runners of the connections accepted by a receiver. They are stopped along with the receiver.
+struct Runners(Vec<JoinHandle<()>>);
+
+impl Drop for Runners {
+    fn drop(&mut self) {
+        for runner in &self.0 {
+            runner.abort();
+        }
+    }
+}
+
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
-    pub fn spawn(address: SocketAddr, handler: Handler) {
+    /// Spawn a new network receiver handling connections from any incoming peer. Cancelling the
+    /// returned task closes the listener and all the connections it accepted.
+    pub fn spawn(address: SocketAddr, handler: Handler) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self { address, handler }.run().await;
-        });
+        })
     }
 
     /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
@@ -50,6 +69,7 @@ impl<Handler: MessageHandler> Receiver<Handler> {
             .expect("Failed to bind TCP port");
 
         //debug!("Listening on {}", self.address);
+        let mut runners = Runners(Vec::new());
         loop {
             let (socket, peer) = match listener.accept().await {
                 Ok(value) => value,
@@ -59,22 +79,36 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                 }
             };
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            runners.0.retain(|x| !x.is_finished());
+            runners
+                .0
+                .push(Self::spawn_runner(socket, peer, self.handler.clone()));
         }
     }
 
     /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// using the provided handler. Messages the handler fails to dispatch are counted against the peer,
+    /// and the connection is dropped once the peer reaches `MAX_FAULTS`. Pings are answered directly.
+    fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) -> JoinHandle<()> {
         tokio::spawn(async move {
             let transport = Framed::new(socket, LengthDelimitedCodec::new());
             let (mut writer, mut reader) = transport.split();
+            let mut faults = 0;
             while let Some(frame) = reader.next().await {
                 match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
+                    Ok(message) if is_ping(&message) => {
+                        if writer.send(ping()).await.is_err() {
+                            return;
+                        }
+                    }
                     Ok(message) => {
                         if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
-                            warn!("{}", e);
-                            return;
+                            faults += 1;
+                            warn!("Failed to dispatch message from {}: {}", peer, e);
+                            if faults >= MAX_FAULTS {
+                                warn!("{}", NetworkError::TooManyFaults(peer, faults));
+                                return;
+                            }
                         }
                     }
                     Err(e) => {
@@ -84,6 +118,6 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                 }
             }
             warn!("Connection closed by peer {}", peer);
-        });
+        })
     }
 }
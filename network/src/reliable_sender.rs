@@ -1,7 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::keepalive::{is_ping, Heartbeat, KeepAlive};
 use bytes::Bytes;
-use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use log::{info, warn};
 use rand::prelude::SliceRandom as _;
@@ -15,7 +15,7 @@ use std::thread;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
-use tokio::time::{sleep, Duration};
+use tokio::time::{interval, sleep, Duration};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
@@ -28,10 +28,13 @@ pub type CancelHandler = oneshot::Receiver<Bytes>;
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
-/// receive an ACK back (until they succeed or are canceled).
+/// receive an ACK back (until they succeed or are canceled). It never drops messages: when a connection falls
+/// behind, `send` waits for room in its channel (backpressure) instead.
 pub struct ReliableSender {
     /// A map holding the channels to our connections.
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
+    /// The keepalive of each connection.
+    keepalive: KeepAlive,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
 }
@@ -46,23 +49,30 @@ impl ReliableSender {
     pub fn new() -> Self {
         Self {
             connections: HashMap::new(),
+            keepalive: KeepAlive::default(),
             rng: SmallRng::from_entropy(),
         }
     }
 
+    pub fn with_keepalive(mut self, keepalive: KeepAlive) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(address: SocketAddr, keepalive: KeepAlive) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, rx, keepalive);
         tx
     }
 
     /// Reliably send a message to a specific address.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
+        let keepalive = self.keepalive;
         self.connections
             .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+            .or_insert_with(|| Self::spawn_connection(address, keepalive))
             .send(InnerMessage {
                 data,
                 cancel_handler: sender,
@@ -132,27 +142,40 @@ struct Connection {
     retry_delay: u64,
     /// Buffer keeping all messages that need to be re-transmitted.
     buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    /// Whether the `ReliableSender` was dropped. The connection then stops once it has no message
+    /// left that a caller still waits on.
+    closed: bool,
+    /// When to ping the peer, and when to give up on it (and reconnect).
+    keepalive: KeepAlive,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>, keepalive: KeepAlive) {
         tokio::spawn(async move {
             Self {
                 address,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
+                closed: false,
+                keepalive,
             }
             .run()
             .await;
         });
     }
 
+    /// Whether the connection has nothing left to do.
+    fn done(&mut self) -> bool {
+        self.buffer.retain(|(_, handler)| !handler.is_closed());
+        self.closed && self.buffer.is_empty()
+    }
+
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
         let mut delay = self.retry_delay;
         let mut retry = 0;
-        loop {
+        while !self.done() {
             match TcpStream::connect(self.address).await {
                 Ok(stream) => {
                     info!("Outgoing connection established with {}", self.address);
@@ -162,9 +185,11 @@ impl Connection {
                     retry = 0;
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
-                    // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
-                    warn!("{}", error);
+                    // The following function only returns if there is an error or nothing left to send.
+                    match self.keep_alive(stream).await {
+                        Some(error) => warn!("{}", error),
+                        None => return,
+                    }
                 }
                 Err(e) => {
                     warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
@@ -182,9 +207,12 @@ impl Connection {
 
                             // Drain the channel into the buffer to not saturate the channel and block the caller task.
                             // The caller is responsible to cleanup the buffer through the cancel handlers.
-                            Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
+                            message = self.receiver.recv(), if !self.closed => match message {
+                                Some(InnerMessage{data, cancel_handler}) => {
+                                    self.buffer.push_back((data, cancel_handler));
+                                    self.buffer.retain(|(_, handler)| !handler.is_closed());
+                                }
+                                None => self.closed = true,
                             }
                         }
                     }
@@ -193,13 +221,16 @@ impl Connection {
         }
     }
 
-    /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    /// Transmit messages once we have established a connection. Returns `None` if the sender was
+    /// dropped and all messages were acknowledged (or cancelled).
+    async fn keep_alive(&mut self, stream: TcpStream) -> Option<NetworkError> {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
         let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        let mut heartbeat = Heartbeat::new(self.address, self.keepalive);
+        let mut timer = interval(heartbeat.period());
         let error = 'connection: loop {
             // Try to send all messages of the buffer.
             while let Some((data, handler)) = self.buffer.pop_front() {
@@ -209,7 +240,7 @@ impl Connection {
                 }
 
                 // Try to send the message.
-                match writer.send(data.clone()).await {
+                match heartbeat.send(&mut writer, data.clone()).await {
                     Ok(()) => {
                         // The message has been sent, we remove it from the buffer and add it to
                         // `pending_replies` while we wait for an ACK.
@@ -218,33 +249,44 @@ impl Connection {
                     Err(e) => {
                         // We failed to send the message, we put it back into the buffer.
                         self.buffer.push_front((data, handler));
-                        break 'connection NetworkError::FailedToSendMessage(self.address, e);
+                        break 'connection e;
                     }
                 }
             }
 
+            // Once dropped, the sender only waits for the ACKs someone still waits on. Acknowledged messages
+            // are matched in order, so the other pending replies stay in the queue.
+            let waited_on = |(_, handler): &(Bytes, oneshot::Sender<Bytes>)| !handler.is_closed();
+            if self.closed && !pending_replies.iter().any(waited_on) {
+                return None;
+            }
+
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
-                Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
+                message = self.receiver.recv(), if !self.closed => match message {
                     // Add the message to the buffer of messages to send.
-                    self.buffer.push_back((data, cancel_handler));
+                    Some(InnerMessage{data, cancel_handler}) => self.buffer.push_back((data, cancel_handler)),
+                    None => self.closed = true,
                 },
                 response = reader.next() => {
-                    let (data, handler) = match pending_replies.pop_front() {
-                        Some(message) => message,
-                        None => break 'connection NetworkError::UnexpectedAck(self.address)
+                    let bytes = match response {
+                        Some(Ok(bytes)) => bytes,
+                        // Something has gone wrong (either the channel dropped or we failed to read from it).
+                        _ => break 'connection NetworkError::FailedToReceiveAck(self.address),
                     };
-                    match response {
-                        Some(Ok(bytes)) => {
-                            // Notify the handler that the message has been successfully sent.
-                            let _ = handler.send(bytes.freeze());
-                        },
-                        _ => {
-                            // Something has gone wrong (either the channel dropped or we failed to read from it).
-                            // Put the message back in the buffer, we will try to send it again.
-                            pending_replies.push_front((data, handler));
-                            break 'connection NetworkError::FailedToReceiveAck(self.address);
-                        }
+                    heartbeat.received(&bytes);
+                    if is_ping(&bytes) {
+                        continue 'connection;
+                    }
+                    match pending_replies.pop_front() {
+                        // Notify the handler that the message has been successfully sent.
+                        Some((_, handler)) => { let _ = handler.send(bytes.freeze()); },
+                        None => break 'connection NetworkError::UnexpectedAck(self.address),
+                    }
+                },
+                _ = timer.tick() => {
+                    if let Err(e) = heartbeat.check(&mut writer).await {
+                        break 'connection e;
                     }
                 },
             }
@@ -255,6 +297,6 @@ impl Connection {
         while let Some(message) = pending_replies.pop_back() {
             self.buffer.push_front(message);
         }
-        error
+        Some(error)
     }
 }
@@ -1,29 +1,198 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::keepalive::{Heartbeat, KeepAlive};
 use bytes::Bytes;
-use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use log::{info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::{SmallRng, OsRng};
 use rand::{SeedableRng as _, Rng};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Notify;
+use tokio::time::{interval, Instant};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
 #[path = "tests/simple_sender_tests.rs"]
 pub mod simple_sender_tests;
 
+/// Which message a connection gives up when its outbound buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the oldest queued message. This suits consensus messages, where a newer message
+    /// usually supersedes the older ones.
+    DropOldest,
+    /// Drop the message being sent and keep the queue as it is.
+    DropNewest,
+}
+
+/// The bounds of the outbound buffer of each connection.
+#[derive(Clone, Copy, Debug)]
+pub struct SenderLimits {
+    /// The maximum number of queued messages per peer.
+    pub max_messages: usize,
+    /// The maximum total size of the queued messages per peer (in bytes).
+    pub max_bytes: usize,
+    /// What to drop once either bound is reached.
+    pub policy: DropPolicy,
+}
+
+impl Default for SenderLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: 1_000,
+            max_bytes: 32 * 1024 * 1024,
+            policy: DropPolicy::DropOldest,
+        }
+    }
+}
+
+/// The accounting of the messages sent to a single peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// The number of messages waiting in the outbound buffer.
+    pub queued_messages: usize,
+    /// The total size of the messages waiting in the outbound buffer (in bytes).
+    pub queued_bytes: usize,
+    /// The number of messages handed to the socket.
+    pub sent: u64,
+    /// The number of messages dropped, because the buffer was full or the connection failed.
+    pub dropped: u64,
+    /// How long the current connection has been established, if any.
+    pub connection_age: Option<Duration>,
+    /// The round-trip time of the last ping answered on the current connection.
+    pub round_trip: Option<Duration>,
+}
+
+/// The bounded outbound buffer of a connection.
+struct Outbox {
+    address: SocketAddr,
+    limits: SenderLimits,
+    queue: VecDeque<Bytes>,
+    stats: PeerStats,
+    /// When the current connection was established.
+    established: Option<Instant>,
+    /// Set when the connection gives up (the next message then spawns a new connection) or when
+    /// the sender is dropped.
+    closed: bool,
+}
+
+impl Outbox {
+    fn new(address: SocketAddr, limits: SenderLimits, stats: PeerStats) -> Self {
+        Self {
+            address,
+            limits,
+            queue: VecDeque::new(),
+            stats: PeerStats {
+                connection_age: None,
+                round_trip: None,
+                ..stats
+            },
+            established: None,
+            closed: false,
+        }
+    }
+
+    /// Note that the connection (re-)connected to the peer.
+    fn connected(&mut self) {
+        self.established = Some(Instant::now());
+        self.stats.round_trip = None;
+    }
+
+    fn is_full(&self, size: usize) -> bool {
+        self.queue.len() >= self.limits.max_messages
+            || self.stats.queued_bytes + size > self.limits.max_bytes
+    }
+
+    /// Queue a message, dropping a message per the drop policy if the buffer is full.
+    fn push(&mut self, data: Bytes) {
+        if data.len() > self.limits.max_bytes {
+            return self.drop_messages(1);
+        }
+        match self.limits.policy {
+            DropPolicy::DropNewest if self.is_full(data.len()) => return self.drop_messages(1),
+            DropPolicy::DropNewest => (),
+            DropPolicy::DropOldest => {
+                while self.is_full(data.len()) {
+                    match self.queue.pop_front() {
+                        Some(old) => self.stats.queued_bytes -= old.len(),
+                        None => break,
+                    }
+                    self.drop_messages(1);
+                }
+                if self.is_full(data.len()) {
+                    return self.drop_messages(1);
+                }
+            }
+        }
+        self.stats.queued_bytes += data.len();
+        self.queue.push_back(data);
+    }
+
+    fn pop(&mut self) -> Option<Bytes> {
+        let data = self.queue.pop_front()?;
+        self.stats.queued_bytes -= data.len();
+        self.stats.sent += 1;
+        Some(data)
+    }
+
+    /// Give up on the connection: whatever is still queued is dropped.
+    fn close(&mut self) {
+        self.closed = true;
+        self.established = None;
+        let queued = self.queue.len();
+        self.queue.clear();
+        self.stats.queued_bytes = 0;
+        self.drop_messages(queued);
+    }
+
+    fn drop_messages(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        // Log the first drop and then every thousand drops to avoid flooding the logs.
+        let before = self.stats.dropped;
+        self.stats.dropped += count as u64;
+        if before == 0 || before / 1_000 != self.stats.dropped / 1_000 {
+            warn!(
+                "Dropped {} outbound messages to {} so far",
+                self.stats.dropped, self.address
+            );
+        }
+    }
+
+    fn stats(&self) -> PeerStats {
+        PeerStats {
+            queued_messages: self.queue.len(),
+            connection_age: self.established.map(|x| x.elapsed()),
+            ..self.stats
+        }
+    }
+}
+
+/// The outbound buffer shared between a `SimpleSender` and one of its connections.
+struct Shared {
+    outbox: Mutex<Outbox>,
+    /// Wakes up the connection when a message is queued.
+    notify: Notify,
+}
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
-/// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
+/// We hand messages to our 'connections' through bounded outbound buffers kept by the HashMap called `connections`.
+/// Sending never waits: when a slow peer lets its buffer fill up, messages are dropped according to the
+/// `DropPolicy` and accounted for in the `PeerStats` of the peer.
 pub struct SimpleSender {
-    /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<Bytes>>,
+    /// A map holding the outbound buffers of our connections.
+    connections: HashMap<SocketAddr, Arc<Shared>>,
+    /// The bounds of the outbound buffer of each connection.
+    limits: SenderLimits,
+    /// The keepalive of each connection.
+    keepalive: KeepAlive,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
 }
@@ -34,36 +203,67 @@ impl std::default::Default for SimpleSender {
     }
 }
 
+impl Drop for SimpleSender {
+    /// Let the connections send what is still queued and stop.
+    fn drop(&mut self) {
+        for shared in self.connections.values() {
+            shared.outbox.lock().unwrap().closed = true;
+            shared.notify.notify_one();
+        }
+    }
+}
+
 impl SimpleSender {
     pub fn new() -> Self {
+        Self::with_limits(SenderLimits::default())
+    }
+
+    pub fn with_limits(limits: SenderLimits) -> Self {
         Self {
             connections: HashMap::new(),
+            limits,
+            keepalive: KeepAlive::default(),
             rng: SmallRng::from_entropy(),
         }
     }
 
-    /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
-        let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
-        tx
+    pub fn with_keepalive(mut self, keepalive: KeepAlive) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Helper function to spawn a new connection. It carries over the accounting of the previous
+    /// connection to the same peer.
+    fn spawn_connection(&self, address: SocketAddr, stats: PeerStats) -> Arc<Shared> {
+        let shared = Arc::new(Shared {
+            outbox: Mutex::new(Outbox::new(address, self.limits, stats)),
+            notify: Notify::new(),
+        });
+        Connection::spawn(address, shared.clone(), self.keepalive);
+        shared
     }
 
     /// Try (best-effort) to send a message to a specific address.
     /// This is useful to answer sync requests.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) {
-        // Try to re-use an existing connection if possible.
-        if let Some(tx) = self.connections.get(&address) {
-            if tx.send(data.clone()).await.is_ok() {
-                return;
+        // Try to re-use an existing connection if possible. Otherwise make a new connection.
+        let stats = match self.connections.get(&address) {
+            Some(shared) => {
+                let mut outbox = shared.outbox.lock().unwrap();
+                if !outbox.closed {
+                    outbox.push(data);
+                    drop(outbox);
+                    shared.notify.notify_one();
+                    return;
+                }
+                outbox.stats()
             }
-        }
-
-        // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
-        if tx.send(data).await.is_ok() {
-            self.connections.insert(address, tx);
-        }
+            None => PeerStats::default(),
+        };
+        let shared = self.spawn_connection(address, stats);
+        shared.outbox.lock().unwrap().push(data);
+        shared.notify.notify_one();
+        self.connections.insert(address, shared);
     }
 
     /// Try (best-effort) to broadcast the message to all specified addresses.
@@ -98,60 +298,118 @@ impl SimpleSender {
         addresses.truncate(nodes);
         self.broadcast(addresses, data).await
     }
+
+    /// The accounting of the messages sent to each peer.
+    pub fn stats(&self) -> HashMap<SocketAddr, PeerStats> {
+        self.connections
+            .iter()
+            .map(|(address, shared)| (*address, shared.outbox.lock().unwrap().stats()))
+            .collect()
+    }
 }
 
 /// A connection is responsible to establish and keep alive (if possible) a connection with a single peer.
 struct Connection {
     /// The destination address.
     address: SocketAddr,
-    /// Channel from which the connection receives its commands.
-    receiver: Receiver<Bytes>,
+    /// The outbound buffer from which the connection takes the messages to send.
+    shared: Arc<Shared>,
+    /// When to ping the peer, and when to give up on it.
+    keepalive: KeepAlive,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    fn spawn(address: SocketAddr, shared: Arc<Shared>, keepalive: KeepAlive) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            let connection = Self {
+                address,
+                shared,
+                keepalive,
+            };
+            connection.run().await;
+            connection.shared.outbox.lock().unwrap().close();
         });
     }
 
-    /// Main loop trying to connect to the peer and transmit messages.
-    async fn run(&mut self) {
-        // Try to connect to the peer.
-        let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
-            Err(e) => {
-                warn!(
-                    "{}",
-                    NetworkError::FailedToConnect(self.address, /* retry */ 0, e)
-                );
-                return;
+    /// Wait for the next queued message. Returns `None` once the buffer is empty and closed.
+    async fn next(&self) -> Option<Bytes> {
+        loop {
+            {
+                let mut outbox = self.shared.outbox.lock().unwrap();
+                if let Some(data) = outbox.pop() {
+                    return Some(data);
+                }
+                if outbox.closed {
+                    return None;
+                }
             }
-        };
-        info!("Outgoing connection established with {}", self.address);
+            self.shared.notify.notified().await;
+        }
+    }
 
-        // Transmit messages once we have established a connection.
+    /// Main loop trying to connect to the peer and transmit messages. If the peer stops answering,
+    /// we reconnect right away and keep the queued messages.
+    async fn run(&self) {
+        loop {
+            // Try to connect to the peer.
+            let stream = match TcpStream::connect(self.address).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(
+                        "{}",
+                        NetworkError::FailedToConnect(self.address, /* retry */ 0, e)
+                    );
+                    return;
+                }
+            };
+            info!("Outgoing connection established with {}", self.address);
+            self.shared.outbox.lock().unwrap().connected();
+
+            match self.keep_alive(stream).await {
+                Some(e @ NetworkError::DeadPeer(..)) => warn!("{}", e),
+                Some(e) => {
+                    warn!("{}", e);
+                    return;
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Transmit messages once we have established a connection. Returns `None` once the buffer is
+    /// empty and closed.
+    async fn keep_alive(&self, stream: TcpStream) -> Option<NetworkError> {
+        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        let mut heartbeat = Heartbeat::new(self.address, self.keepalive);
+        let mut timer = interval(heartbeat.period());
         loop {
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
-                Some(data) = self.receiver.recv() => {
-                    if let Err(e) = writer.send(data).await {
-                        warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
-                        return;
+                data = self.next() => {
+                    let data = data?;
+                    if let Err(e) = heartbeat.send(&mut writer, data).await {
+                        return Some(e);
                     }
                 },
                 response = reader.next() => {
                     match response {
-                        Some(Ok(_)) => {
-                            // Sink the reply.
+                        Some(Ok(frame)) => {
+                            // Sink the reply, but keep the round-trip time of our pings.
+                            if let Some(round_trip) = heartbeat.received(&frame) {
+                                self.shared.outbox.lock().unwrap().stats.round_trip = Some(round_trip);
+                            }
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
-                            warn!("{}", NetworkError::FailedToReceiveAck(self.address));
-                            return;
+                            return Some(NetworkError::FailedToReceiveAck(self.address));
                         }
                     }
                 },
+                _ = timer.tick() => {
+                    if let Err(e) = heartbeat.check(&mut writer).await {
+                        return Some(e);
+                    }
+                },
             }
         }
     }
@@ -0,0 +1,48 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Message {
+    Ping,
+    Data(u64, Vec<u8>, bool),
+}
+
+#[test]
+fn pinned_encoding() {
+    // Fixed-size little-endian integers, u32 variant tags, and u64 length prefixes.
+    let message = Message::Data(1, vec![7, 8], true);
+    let expected = vec![
+        1, 0, 0, 0, // Variant tag.
+        1, 0, 0, 0, 0, 0, 0, 0, // u64.
+        2, 0, 0, 0, 0, 0, 0, 0, 7, 8, // Vec<u8>.
+        1, // bool.
+    ];
+    assert_eq!(encode(&message).unwrap(), expected);
+    assert_eq!(encode(&Message::Ping).unwrap(), vec![0, 0, 0, 0]);
+    assert_eq!(encoded_size(&message).unwrap(), expected.len() as u64);
+}
+
+#[test]
+fn round_trip() {
+    let message = Message::Data(u64::MAX, vec![0; 100], false);
+    let bytes = encode(&message).unwrap();
+    assert_eq!(decode::<Message>(&bytes).unwrap(), message);
+}
+
+#[test]
+fn reject_trailing_bytes() {
+    let mut bytes = encode(&Message::Ping).unwrap();
+    bytes.push(0);
+    assert!(decode::<Message>(&bytes).is_err());
+}
+
+#[test]
+fn reject_oversized_message() {
+    let message = Message::Data(0, vec![0; MAX_MESSAGE_SIZE as usize], false);
+    assert!(encode(&message).is_err());
+
+    // A length prefix above the limit is rejected before allocating.
+    let mut bytes = vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+    assert!(decode::<Message>(&bytes).is_err());
+}
@@ -1,9 +1,9 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use futures::sink::SinkExt as _;
+use crate::{decode, encode};
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Sender;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout, Duration};
 
 #[derive(Clone)]
 struct TestHandler {
@@ -17,7 +17,7 @@ impl MessageHandler for TestHandler {
         let _ = writer.send(Bytes::from("Ack")).await;
 
         // Deserialize the message.
-        let message = bincode::deserialize(&message).unwrap();
+        let message = decode(&message)?;
 
         // Deliver the message to the application.
         self.deliver.send(message).await.unwrap();
@@ -35,7 +35,7 @@ async fn receive() {
 
     // Send a message.
     let sent = "Hello, world!";
-    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let bytes = Bytes::from(encode(sent).unwrap());
     let stream = TcpStream::connect(address).await.unwrap();
     let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
     transport.send(bytes.clone()).await.unwrap();
@@ -46,3 +46,54 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[tokio::test]
+async fn disconnect_faulty_peer() {
+    // Make the network receiver.
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(address, TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // A few malformed messages do not tear down the connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    for _ in 0..MAX_FAULTS - 1 {
+        transport.send(Bytes::from(vec![0xff; 3])).await.unwrap();
+    }
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(encode(sent).unwrap());
+    transport.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), sent);
+
+    // Reaching the limit does: the acks stop and the stream ends.
+    transport.send(Bytes::from(vec![0xff; 3])).await.unwrap();
+    let closed = timeout(Duration::from_millis(1_000), async {
+        while let Some(Ok(_)) = transport.next().await {}
+    });
+    assert!(closed.await.is_ok());
+}
+
+#[tokio::test]
+async fn answer_pings() {
+    // Make the network receiver.
+    let address = "127.0.0.1:4200".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(address, TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // A ping gets an empty reply and never reaches the handler.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Bytes::new()).await.unwrap();
+    assert!(transport.next().await.unwrap().unwrap().is_empty());
+
+    // Messages are still dispatched.
+    let sent = "Hello, world!";
+    transport
+        .send(Bytes::from(encode(sent).unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(transport.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx.recv().await.unwrap(), sent);
+}
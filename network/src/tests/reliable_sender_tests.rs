@@ -2,6 +2,9 @@
 use super::*;
 use crate::common::listener;
 use futures::future::try_join_all;
+use futures::sink::SinkExt as _;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn send() {
@@ -65,3 +68,36 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn reconnect_after_silent_drop() {
+    // Run a TCP server that reads the message without acknowledging it and then stops reading
+    // (without closing), and that serves the next connection normally.
+    let address = "127.0.0.1:5400".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let listener = TcpListener::bind(&address).await.unwrap();
+    let server = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut silent = Framed::new(socket, LengthDelimitedCodec::new());
+        assert_eq!(silent.next().await.unwrap().unwrap(), message);
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        assert_eq!(transport.next().await.unwrap().unwrap(), message);
+        transport.send(Bytes::from("Ack")).await.unwrap();
+        silent
+    });
+
+    // Make the network sender and send the message.
+    let keepalive = KeepAlive {
+        interval: 50,
+        timeout: 200,
+    };
+    let mut sender = ReliableSender::new().with_keepalive(keepalive);
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+
+    // The sender notices the peer stopped answering, reconnects, and sends the message again.
+    let ack = timeout(Duration::from_millis(1_000), cancel_handler).await;
+    assert!(ack.unwrap().is_ok());
+    assert!(server.await.is_ok());
+}
@@ -2,6 +2,10 @@
 use super::*;
 use crate::common::listener;
 use futures::future::try_join_all;
+use futures::sink::SinkExt as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::TcpListener;
+use tokio::time::{sleep, timeout};
 
 #[tokio::test]
 async fn simple_send() {
@@ -40,3 +44,155 @@ async fn broadcast() {
     // Ensure all servers received the broadcast.
     assert!(try_join_all(handles).await.is_ok());
 }
+
+#[test]
+fn drop_policies() {
+    let address = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let limits = |policy| SenderLimits {
+        max_messages: 2,
+        max_bytes: 1_000,
+        policy,
+    };
+    let queued = |outbox: &Outbox| outbox.queue.iter().cloned().collect::<Vec<_>>();
+
+    // Too many messages.
+    let mut oldest = Outbox::new(
+        address,
+        limits(DropPolicy::DropOldest),
+        PeerStats::default(),
+    );
+    let mut newest = Outbox::new(
+        address,
+        limits(DropPolicy::DropNewest),
+        PeerStats::default(),
+    );
+    for x in &["1", "2", "3"] {
+        oldest.push(Bytes::from(*x));
+        newest.push(Bytes::from(*x));
+    }
+    assert_eq!(queued(&oldest), vec![Bytes::from("2"), Bytes::from("3")]);
+    assert_eq!(queued(&newest), vec![Bytes::from("1"), Bytes::from("2")]);
+    assert_eq!(oldest.stats().dropped, 1);
+    assert_eq!(newest.stats().dropped, 1);
+
+    // Too many bytes.
+    let mut outbox = Outbox::new(
+        address,
+        limits(DropPolicy::DropOldest),
+        PeerStats::default(),
+    );
+    outbox.push(Bytes::from(vec![0; 600]));
+    outbox.push(Bytes::from(vec![1; 600]));
+    outbox.push(Bytes::from(vec![2; 1_001]));
+    assert_eq!(queued(&outbox), vec![Bytes::from(vec![1; 600])]);
+    assert_eq!(outbox.stats().queued_bytes, 600);
+    assert_eq!(outbox.stats().dropped, 2);
+}
+
+#[tokio::test]
+async fn bound_buffer_of_wedged_peer() {
+    const MESSAGES: usize = 400;
+    const SIZE: usize = 64 * 1024;
+
+    // Run a TCP server reading all messages.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let healthy = listener.local_addr().unwrap();
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+    let _healthy = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(Ok(message)) = transport.next().await {
+            assert_eq!(message.len(), SIZE);
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    // Run a TCP server that never reads.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let wedged = listener.local_addr().unwrap();
+    let _wedged = tokio::spawn(async move {
+        let (_socket, _) = listener.accept().await.unwrap();
+        sleep(Duration::from_secs(60)).await;
+    });
+
+    // Broadcast more than the wedged peer's buffer and socket can hold.
+    let limits = SenderLimits {
+        max_messages: 1_000,
+        max_bytes: 1024 * 1024,
+        policy: DropPolicy::DropOldest,
+    };
+    let mut sender = SimpleSender::with_limits(limits);
+    for _ in 0..MESSAGES {
+        let addresses = vec![healthy, wedged];
+        sender
+            .broadcast(addresses, Bytes::from(vec![0; SIZE]))
+            .await;
+    }
+
+    // The healthy peer receives every message that was not dropped on its way.
+    let delivered = async {
+        while received.load(Ordering::SeqCst) as u64 + sender.stats()[&healthy].dropped
+            < MESSAGES as u64
+        {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(5), delivered).await.is_ok());
+    let stats = sender.stats();
+    assert_eq!(stats[&healthy].sent, received.load(Ordering::SeqCst) as u64);
+
+    // The buffer of the wedged peer stays bounded, and every message is accounted for.
+    let stats = stats[&wedged];
+    assert!(stats.queued_bytes <= limits.max_bytes);
+    assert!(stats.dropped > 0);
+    let total = stats.sent + stats.queued_messages as u64 + stats.dropped;
+    assert_eq!(total, MESSAGES as u64);
+}
+
+#[tokio::test]
+async fn reconnect_after_silent_drop() {
+    // Run a TCP server that reads one message and then stops reading (without closing), and that
+    // serves the next connection normally.
+    let address = "127.0.0.1:6400".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&address).await.unwrap();
+    let (tx_received, mut rx_received) = tokio::sync::mpsc::channel(2);
+    let _server = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut silent = Framed::new(socket, LengthDelimitedCodec::new());
+        let first = silent.next().await.unwrap().unwrap();
+        tx_received.send(first.freeze()).await.unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(Ok(frame)) = transport.next().await {
+            match frame.is_empty() {
+                true => transport.send(Bytes::new()).await.unwrap(),
+                false => tx_received.send(frame.freeze()).await.unwrap(),
+            }
+        }
+        drop(silent);
+    });
+
+    // Make the network sender and send a first message.
+    let keepalive = KeepAlive {
+        interval: 50,
+        timeout: 200,
+    };
+    let mut sender = SimpleSender::new().with_keepalive(keepalive);
+    sender.send(address, Bytes::from("first")).await;
+    assert_eq!(rx_received.recv().await.unwrap(), "first");
+
+    // The sender notices the peer stopped answering its pings and reconnects by itself.
+    sleep(Duration::from_millis(500)).await;
+    sender.send(address, Bytes::from("second")).await;
+    let received = timeout(Duration::from_millis(500), rx_received.recv()).await;
+    assert_eq!(received.unwrap().unwrap(), "second");
+
+    // The new connection answers our pings.
+    sleep(Duration::from_millis(150)).await;
+    let stats = sender.stats()[&address];
+    assert!(stats.connection_age.unwrap() < Duration::from_millis(500));
+    assert!(stats.round_trip.is_some());
+    assert_eq!(stats.dropped, 0);
+}
@@ -1,19 +1,32 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod distribution;
+mod event;
+mod stats;
+mod trace;
+
+use crate::distribution::{Distribution, Distributor};
+use crate::event::CommittedEvent;
+use crate::stats::Stats;
+use crate::trace::{Outgoing, Random, Trace, TraceHeader, TraceWriter, TxSource};
 use anyhow::{Context, Result};
-use bytes::BufMut as _;
-use bytes::BytesMut;
-use clap::{crate_name, crate_version, App, AppSettings};
+use bench_support::{sample_tx_counter, BURST_DURATION};
+use clap::{crate_name, crate_version, App, AppSettings, Arg};
 use env_logger::Env;
-use futures::future::join_all;
+use futures::future::{join_all, pending};
 use futures::sink::SinkExt as _;
+use futures::stream::{SplitSink, StreamExt as _};
 use log::{info, warn};
-use primary::Transaction;
-use rand::Rng;
-use rand::thread_rng;
-use std::convert::TryInto;
+use network::{decode, encode};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpStream;
-use tokio::time::{interval, sleep, Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use bytes::Bytes;
 use primary::Hash;
@@ -23,10 +36,19 @@ async fn main() -> Result<()> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .about("Benchmark client for Narwhal and Tusk.")
-        .args_from_usage("<ADDR> 'The network address of the node where to send txs'")
-        .args_from_usage("--size=<INT> 'The size of each transaction in bytes'")
-        .args_from_usage("--rate=<INT> 'The rate (txs/s) at which to send the transactions'")
-        .args_from_usage("--nodes=[ADDR]... 'Network addresses that must be reachable before starting the benchmark.'")
+        .args_from_usage("[ADDR] 'The network address of the node where to send txs'")
+        .arg(Arg::from_usage("--targets=[ADDR]... 'More nodes where to send txs (along with ADDR), over one connection each'").use_delimiter(true))
+        .args_from_usage("--distribution=[NAME] 'How txs are spread over the targets: round-robin (default), random or single (the first only)'")
+        .args_from_usage("--size=[INT] 'The size of each transaction in bytes (required unless replaying a trace)'")
+        .args_from_usage("--rate=<INT> 'The rate (txs/s) at which to send the transactions. A trace is replayed this much faster than the rate it was recorded at.'")
+        .args_from_usage("--fee=[INT] 'The fee paid by each transaction (default 0)'")
+        .args_from_usage("--conflict-rate=[FLOAT] 'The fraction of transactions sent along with a conflicting twin to another node (default 0)'")
+        .args_from_usage("--nodes=[ADDR]... 'Network addresses that must be reachable before starting the benchmark. Conflicting twins are sent to the nodes after the target of their transaction.'")
+        .args_from_usage("--trace-out=[FILE] 'Record the transactions sent to a trace file'")
+        .args_from_usage("--trace-in=[FILE] 'Replay the transactions of a trace file instead of generating them'")
+        .args_from_usage("--feed=[ADDR] 'The notifications address of a node, to measure the latency of our transactions from its decisions'")
+        .args_from_usage("--sample-every=[INT] 'Measure the latency of one transaction out of INT (default 100)'")
+        .args_from_usage("--duration=[SECS] 'Stop sending after this many seconds (default: until interrupted or the trace ends)'")
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
 
@@ -34,21 +56,39 @@ async fn main() -> Result<()> {
         .format_timestamp_millis()
         .init();
 
-    let target = matches
+    let targets = matches
         .value_of("ADDR")
-        .unwrap()
-        .parse::<SocketAddr>()
+        .into_iter()
+        .chain(matches.values_of("targets").unwrap_or_default())
+        .map(|x| x.parse::<SocketAddr>())
+        .collect::<Result<Vec<_>, _>>()
         .context("Invalid socket address format")?;
-    let size = matches
-        .value_of("size")
-        .unwrap()
-        .parse::<usize>()
-        .context("The size of transactions must be a non-negative integer")?;
+    if targets.is_empty() {
+        return Err(anyhow::Error::msg(
+            "No node to send transactions to (ADDR or --targets)",
+        ));
+    }
+    let distribution = matches
+        .value_of("distribution")
+        .unwrap_or("round-robin")
+        .parse::<Distribution>()?;
     let rate = matches
         .value_of("rate")
         .unwrap()
         .parse::<u64>()
         .context("The rate of transactions must be a non-negative integer")?;
+    let fee = matches
+        .value_of("fee")
+        .unwrap_or("0")
+        .parse::<u64>()
+        .context("The fee of transactions must be a non-negative integer")?;
+    let conflict_rate = matches
+        .value_of("conflict-rate")
+        .unwrap_or("0")
+        .parse::<f64>()
+        .ok()
+        .filter(|x| (0.0..=1.0).contains(x))
+        .context("The conflict rate must be a number between 0 and 1")?;
     let nodes = matches
         .values_of("nodes")
         .unwrap_or_default()
@@ -56,18 +96,72 @@ async fn main() -> Result<()> {
         .map(|x| x.parse::<SocketAddr>())
         .collect::<Result<Vec<_>, _>>()
         .context("Invalid socket address format")?;
+    let feed = matches
+        .value_of("feed")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid socket address format")?;
+    let sample_every = matches
+        .value_of("sample-every")
+        .unwrap_or("100")
+        .parse::<u64>()
+        .ok()
+        .filter(|x| *x > 0)
+        .context("The sampling interval must be a positive integer")?;
+    let duration = matches
+        .value_of("duration")
+        .map(|x| x.parse::<u64>())
+        .transpose()
+        .context("The duration must be a non-negative integer")?
+        .map(Duration::from_secs);
+    let trace_out = match matches.value_of("trace-out") {
+        Some(path) => Some(BufWriter::new(
+            File::create(path).context(format!("Failed to create trace {}", path))?,
+        )),
+        None => None,
+    };
+
+    let mut source: Box<dyn TxSource> = match matches.value_of("trace-in") {
+        Some(path) => {
+            let file = File::open(path).context(format!("Failed to open trace {}", path))?;
+            Box::new(Trace::new(BufReader::new(file))?)
+        }
+        None => {
+            let size = matches
+                .value_of("size")
+                .context("The size of transactions is required")?
+                .parse::<usize>()
+                .context("The size of transactions must be a non-negative integer")?;
+            // The transaction size must be at least 9 bytes to ensure all txs are different.
+            if size < 9 {
+                return Err(anyhow::Error::msg(
+                    "Transaction size must be at least 9 bytes",
+                ));
+            }
+            let others = nodes.iter().filter(|x| !targets.contains(x)).count();
+            let rivals = targets.len() - 1 + others;
+            if conflict_rate > 0.0 && rivals == 0 {
+                return Err(anyhow::Error::msg(
+                    "Conflicting transactions need at least one other node (--nodes)",
+                ));
+            }
+            Box::new(Random::new(size, fee, rate, conflict_rate, rivals))
+        }
+    };
 
-    info!("Node address: {}", target);
+    for target in &targets {
+        info!("Node address: {}", target);
+    }
 
     // NOTE: This log entry is used to compute performance.
-    info!("Transactions size: {} B", size);
+    info!("{}", bench_support::transactions_size(source.size()));
 
     // NOTE: This log entry is used to compute performance.
-    info!("Transactions rate: {} tx/s", rate);
+    info!("{}", bench_support::transactions_rate(rate));
 
     let client = Client {
-        target,
-        size,
+        targets,
+        distribution,
         rate,
         nodes,
     };
@@ -75,107 +169,316 @@ async fn main() -> Result<()> {
     // Wait for all nodes to be online and synchronized.
     client.wait().await;
 
-    // Start the benchmark.
-    client.send().await.context("Failed to submit transactions")
+    // Follow the decisions of a node before sending anything, not to miss any.
+    let stats = Arc::new(Mutex::new(Stats::new(sample_every)));
+    if let Some(address) = feed {
+        let transport = Client::subscribe(address).await;
+        tokio::spawn(Client::follow(transport, stats.clone()));
+    }
+
+    // Start the benchmark, until the workload ends, the duration elapses, or we are interrupted.
+    let mut connections = client.connect_all();
+    let start = Instant::now();
+    let stop = async {
+        match duration {
+            Some(duration) => sleep(duration).await,
+            None => pending().await,
+        }
+    };
+    let interrupted = tokio::select! {
+        result = client.send(source.as_mut(), trace_out, &stats, &mut connections) => {
+            result.context("Failed to submit transactions")?;
+            false
+        }
+        () = stop => false,
+        _ = tokio::signal::ctrl_c() => true,
+    };
+    let elapsed = start.elapsed();
+
+    // Give the last samples some time to be decided.
+    if feed.is_some() && !interrupted {
+        let deadline = Instant::now() + Duration::from_millis(DRAIN_TIMEOUT);
+        while stats.lock().unwrap().pending() > 0 && Instant::now() < deadline {
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+    for connection in &connections {
+        info!("{}", connection);
+    }
+    let report = stats.lock().unwrap().report(elapsed);
+    info!("{}", report);
+    Ok(())
 }
 
+/// How long to wait for the samples still undecided once we stopped sending (in ms), and for the
+/// connections to write the transactions they queued.
+const DRAIN_TIMEOUT: u64 = 5_000;
+
+/// The number of transactions queued for a connection past which we drop them.
+const CONNECTION_CAPACITY: usize = 10_000;
+
+/// The initial delay before reconnecting to a node, doubled on each failure up to
+/// `MAX_RETRY_DELAY` (in ms).
+const RETRY_DELAY: u64 = 200;
+const MAX_RETRY_DELAY: u64 = 10_000;
+
+/// The connection to the notifications endpoint of a node (see `TcpSink`).
+type Feed = Framed<TcpStream, LengthDelimitedCodec>;
+
 struct Client {
-    target: SocketAddr,
-    size: usize,
+    /// The nodes under benchmark.
+    targets: Vec<SocketAddr>,
+    distribution: Distribution,
     rate: u64,
     nodes: Vec<SocketAddr>,
 }
 
-impl Client {
-    pub async fn send(&self) -> Result<()> {
-        const PRECISION: u64 = 20; // Sample precision.
-        const BURST_DURATION: u64 = 1000 / PRECISION;
-
-        // The transaction size must be at least 16 bytes to ensure all txs are different.
-        if self.size < 9 {
-            return Err(anyhow::Error::msg(
-                "Transaction size must be at least 9 bytes",
-            ));
-        }
-
-        let size = 13;
+/// The connection to a node, written by a task of its own so that a slow or unreachable node does
+/// not hold back the others.
+struct Connection {
+    address: SocketAddr,
+    tx: Sender<Bytes>,
+    /// The transactions queued for the connection, and those dropped because it fell behind.
+    queued: u64,
+    dropped: u64,
+    /// The transactions its task wrote.
+    written: Arc<AtomicU64>,
+}
 
-        // Connect to the mempool.
-        let stream = TcpStream::connect(self.target)
-            .await
-            .context(format!("failed to connect to {}", self.target))?;
+impl Connection {
+    fn spawn(address: SocketAddr) -> Self {
+        let (tx, rx) = channel(CONNECTION_CAPACITY);
+        let written = Arc::new(AtomicU64::new(0));
+        tokio::spawn(Self::run(address, rx, written.clone()));
+        Self {
+            address,
+            tx,
+            queued: 0,
+            dropped: 0,
+            written,
+        }
+    }
 
-        // Submit all transactions.
-        let burst = self.rate / PRECISION;
-        //let burst = 20;
-        let mut data: Vec<u8> = Vec::new();
-        for _ in 0..self.size {
-            data.push(rand::thread_rng().gen());
-            //data.push(0);
+    /// Queue a transaction, unless the connection fell too far behind.
+    fn push(&mut self, message: Bytes) {
+        match self.tx.try_send(message) {
+            Ok(()) => self.queued += 1,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => self.dropped += 1,
         }
-        let mut id: BytesMut = BytesMut::with_capacity(size);
-        let mut tx = Transaction::new();
-        tx.data = data;
-        let mut counter = 0;
-        let mut counter2 = 0;
-        let mut r: u64 = thread_rng().gen();
-        let mut r2: u32 = thread_rng().gen();
-        let mut r: u64 = 0;
-        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
-        let interval = interval(Duration::from_millis(BURST_DURATION));
-        tokio::pin!(interval);
+    }
 
-        // NOTE: This log entry is used to compute performance.
-        info!("Start sending transactions");
+    /// Wait for the queued transactions to be written, until the deadline.
+    async fn flush(&self, deadline: Instant) {
+        while self.written.load(Ordering::Relaxed) < self.queued && Instant::now() < deadline {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
 
-        //'main: loop {
-        for _ in 0..10 {
-            interval.as_mut().tick().await;
-            let now = Instant::now();
+    /// Write the queued transactions, reconnecting (with backoff) whenever the connection fails. A
+    /// transaction that failed to go out is sent again on the next connection.
+    async fn run(address: SocketAddr, mut rx: Receiver<Bytes>, written: Arc<AtomicU64>) {
+        let mut delay = RETRY_DELAY;
+        let mut unsent = None;
+        loop {
+            let mut transport = match TcpStream::connect(address).await {
+                Ok(stream) => {
+                    delay = RETRY_DELAY;
+                    Client::connect(stream)
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to connect to {} (retrying in {} ms): {}",
+                        address, delay, e
+                    );
+                    sleep(Duration::from_millis(delay)).await;
+                    delay = (2 * delay).min(MAX_RETRY_DELAY);
+                    continue;
+                }
+            };
+            loop {
+                let message = match unsent.take() {
+                    Some(message) => message,
+                    None => match rx.recv().await {
+                        Some(message) => message,
+                        None => return,
+                    },
+                };
+                if let Err(e) = transport.send(Bytes::clone(&message)).await {
+                    warn!("Failed to send transaction to {}: {}", address, e);
+                    unsent = Some(message);
+                    break;
+                }
+                written.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
 
-            for x in 0..burst {
-                if x == counter % burst {
-                    //r += 1;
-                    id.put_u8(0u8); // Sample txs start with 0.
-                    //id.put_u64(r);
-                    id.put_u64(counter); // This counter identifies the tx.
-                    //id.put_u32(r2);
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Sent {} of {} txs queued to {} ({} dropped)",
+            self.written.load(Ordering::Relaxed),
+            self.queued,
+            self.address,
+            self.dropped
+        )
+    }
+}
 
-                    // NOTE: This log entry is used to compute performance.
-                    info!("Sending sample transaction {}", counter); 
-                } else {
-                    r += 1;
-                    id.put_u8(1u8); // Standard txs start with 1.
-                    id.put_u64(r); // Ensures all clients send different txs.
+impl Client {
+    /// Send the transactions of a workload on its schedule, sped up by the ratio of our rate to
+    /// the one it assumes, and record them to a trace if asked to. Latency is measured from the
+    /// time a transaction was due rather than the time it was actually sent (see `Schedule`).
+    pub async fn send(
+        &self,
+        source: &mut dyn TxSource,
+        trace_out: Option<BufWriter<File>>,
+        stats: &Mutex<Stats>,
+        connections: &mut [Connection],
+    ) -> Result<()> {
+        let mut trace = match trace_out {
+            Some(writer) => {
+                let header = TraceHeader {
+                    rate: source.rate(),
+                    size: source.size(),
+                    rivals: source.rivals(),
                 };
+                Some(TraceWriter::new(writer, &header)?)
+            }
+            None => None,
+        };
 
-                tx.id = id.to_vec();
-                    info!("Sending transaction with id {:?} and digest {:?}", tx.id, tx.digest());
-                    let message = bincode::serialize(&tx.clone()).unwrap();
-                    //if counter == 0 {
-                        //info!("TX SIZE: {:?}", message.len());
-                    //}   
-                    id.resize(size, 0u8);
-                    id.split();
+        // Conflicting twins go to the nodes after the target of their transaction.
+        if connections.len() <= source.rivals() {
+            return Err(anyhow::Error::msg(format!(
+                "The workload sends conflicting transactions to {} other nodes (--nodes)",
+                source.rivals()
+            )));
+        }
+        let mut distributor =
+            Distributor::new(self.distribution, self.targets.len(), connections.len());
+
+        // Replays keep the schedule of the trace, scaled to our rate.
+        let speed = self.rate.max(1) as f64 / source.rate().max(1) as f64;
+        let period = Duration::from_millis(BURST_DURATION);
 
-                    let bytes = Bytes::from(message);
+        // NOTE: This log entry is used to compute performance.
+        info!("{}", bench_support::start_sending());
+        let start = Instant::now();
+        let (mut sent, mut bytes_sent, mut bursts) = (0u64, 0usize, 0u64);
+        let mut burst: Option<(Duration, Instant)> = None;
+        while let Some(Outgoing {
+            due,
+            to,
+            transaction: tx,
+        }) = source.next()?
+        {
+            let due_at = start + due.div_f64(speed);
 
-                if let Err(e) = transport.send(bytes.clone()).await {
-                    warn!("Failed to send transaction: {}", e);
-                    //break 'main;
+            // A new burst starts once the previous one is done and it is due.
+            if burst.map(|(x, _)| x) != Some(due) {
+                if burst.is_some_and(|(_, started)| started.elapsed() > period) {
+                    // NOTE: This log entry is used to compute performance.
+                    warn!("{}", bench_support::rate_too_high());
+                }
+                sleep_until(due_at).await;
+                let lag = Instant::now().saturating_duration_since(due_at);
+                if lag >= period {
+                    // NOTE: This log entry is used to compute performance.
+                    warn!("{}", bench_support::behind_schedule(bursts, lag));
                 }
-                counter2 += 1;
+                burst = Some((due, Instant::now()));
+                bursts += 1;
             }
-            if now.elapsed().as_millis() > BURST_DURATION as u128 {
+
+            if let Some(counter) = sample_tx_counter(&tx.id) {
                 // NOTE: This log entry is used to compute performance.
-                warn!("Transaction rate too high for this client");
+                let lag = Instant::now().saturating_duration_since(due_at);
+                info!("{}", bench_support::sending_sample(counter, lag));
+            }
+            if to > 0 {
+                // NOTE: This log entry is used to check conflicting transactions.
+                info!("{}", bench_support::sending_conflict(&tx.election_id()));
+            }
+
+            stats.lock().unwrap().sent(tx.election_id(), due_at);
+            info!("Sending transaction with id {:?} and digest {:?}", tx.id, tx.digest());
+            let message = Bytes::from(encode(&tx).unwrap());
+            bytes_sent += message.len();
+            connections[distributor.route(to)].push(message);
+            sent += 1;
+
+            if let Some(trace) = trace.as_mut() {
+                trace.record(&Outgoing {
+                    due,
+                    to,
+                    transaction: tx,
+                })?;
             }
-            counter += 1;
         }
-        info!("Sent {} txs", counter2);
+        if let Some(trace) = trace.as_mut() {
+            trace.flush()?;
+        }
+        let deadline = Instant::now() + Duration::from_millis(DRAIN_TIMEOUT);
+        join_all(connections.iter().map(|x| x.flush(deadline))).await;
+        info!(
+            "Sent {} txs ({} B) in {} ms",
+            sent,
+            bytes_sent,
+            start.elapsed().as_millis()
+        );
         Ok(())
     }
 
+    /// Spawn the connections to the targets, followed by those to the other nodes.
+    fn connect_all(&self) -> Vec<Connection> {
+        let others = self.nodes.iter().filter(|x| !self.targets.contains(x));
+        self.targets
+            .iter()
+            .chain(others)
+            .map(|x| Connection::spawn(*x))
+            .collect()
+    }
+
+    /// Frame the connection to a worker. The worker acknowledges every transaction; we do not wait
+    /// for these acks.
+    fn connect(stream: TcpStream) -> SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes> {
+        let (transport, mut replies) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        tokio::spawn(async move { while let Some(Ok(_)) = replies.next().await {} });
+        transport
+    }
+
+    /// Connect to the notifications endpoint of a node, once it is up.
+    async fn subscribe(address: SocketAddr) -> Feed {
+        loop {
+            match TcpStream::connect(address).await {
+                Ok(stream) => return Framed::new(stream, LengthDelimitedCodec::new()),
+                Err(_) => sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    /// Record the decisions a node streams until it stops.
+    async fn follow(mut feed: Feed, stats: Arc<Mutex<Stats>>) {
+        while let Some(frame) = feed.next().await {
+            match frame
+                .map_err(anyhow::Error::from)
+                .and_then(|x| Ok(decode::<CommittedEvent>(&x)?))
+            {
+                Ok(event) => stats
+                    .lock()
+                    .unwrap()
+                    .decided(&event.election_id, Instant::now()),
+                Err(e) => {
+                    warn!("Failed to follow the decisions: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
     pub async fn wait(&self) {
         // Wait for all nodes to be online.
         info!("Waiting for all nodes to be online...");
@@ -0,0 +1,228 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! Finds the elections two nodes decided differently from their decision files (see `FileSink`).
+//! The elections are compared as a trie on their ids: the digests of the subtrees on both sides
+//! are compared first, so that identical ranges are skipped without looking at their elections.
+use anyhow::{bail, Result};
+use ed25519_dalek::{Digest as _, Sha512};
+use primary::Round;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(test)]
+#[path = "tests/diff_tests.rs"]
+pub mod diff_tests;
+
+/// Subtrees holding at most this many elections (on both sides) are compared election by election.
+const LEAF_SIZE: usize = 8;
+
+/// The decision of a node on an election.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decided {
+    pub tx_hash: String,
+    pub round: Round,
+}
+
+impl fmt::Display for Decided {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (round {})", self.tx_hash, self.round)
+    }
+}
+
+/// The decisions of a node, by election id.
+pub type Decisions = BTreeMap<String, Decided>;
+
+/// An election the two nodes did not decide alike.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    /// Only we decided the election.
+    MissingTheirs { election_id: String, mine: Decided },
+    /// Only they decided the election.
+    MissingMine {
+        election_id: String,
+        theirs: Decided,
+    },
+    /// Both decided the election, for different tx hashes: a safety violation.
+    Conflict {
+        election_id: String,
+        mine: Decided,
+        theirs: Decided,
+    },
+}
+
+impl Difference {
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Self::Conflict { .. })
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingTheirs { election_id, mine } => {
+                write!(f, "missing theirs {}: mine {}", election_id, mine)
+            }
+            Self::MissingMine {
+                election_id,
+                theirs,
+            } => write!(f, "missing mine {}: theirs {}", election_id, theirs),
+            Self::Conflict {
+                election_id,
+                mine,
+                theirs,
+            } => write!(
+                f,
+                "conflict {}: mine {}, theirs {}",
+                election_id, mine, theirs
+            ),
+        }
+    }
+}
+
+/// The outcome of a comparison.
+#[derive(Debug, Default)]
+pub struct DecisionDiff {
+    /// The differing elections, by election id.
+    pub differences: Vec<Difference>,
+    /// The comparisons of subtree digests and of elections it took.
+    pub comparisons: usize,
+}
+
+/// Parse a decision file: one `id election_id tx_hash round` line per decision. An election may
+/// appear more than once (the file sink delivers at least once), but always with the same decision.
+pub fn parse_decisions(contents: &str) -> Result<Decisions> {
+    let mut decisions = Decisions::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (election_id, decided) = match fields.as_slice() {
+            [id, election_id, tx_hash, round] if id.parse::<u64>().is_ok() => match round.parse() {
+                Ok(round) => (
+                    election_id.to_string(),
+                    Decided {
+                        tx_hash: tx_hash.to_string(),
+                        round,
+                    },
+                ),
+                Err(_) => bail!("Malformed decision on line {}", i + 1),
+            },
+            _ => bail!("Malformed decision on line {}", i + 1),
+        };
+        match decisions.get(&election_id) {
+            Some(x) if *x != decided => {
+                bail!(
+                    "Election {} decided twice differently (line {})",
+                    election_id,
+                    i + 1
+                )
+            }
+            Some(_) => (),
+            None => {
+                decisions.insert(election_id, decided);
+            }
+        }
+    }
+    Ok(decisions)
+}
+
+type Entry<'a> = (&'a String, &'a Decided);
+
+/// Compare the decisions of two nodes.
+pub fn diff(mine: &Decisions, theirs: &Decisions) -> DecisionDiff {
+    let mine: Vec<_> = mine.iter().collect();
+    let theirs: Vec<_> = theirs.iter().collect();
+    let mut diff = DecisionDiff::default();
+    diff_subtree(&mine, &theirs, 0, &mut diff);
+    diff
+}
+
+/// Compare the elections whose ids share a prefix of `depth` bytes (sorted on both sides).
+fn diff_subtree(mine: &[Entry], theirs: &[Entry], depth: usize, diff: &mut DecisionDiff) {
+    diff.comparisons += 1;
+    if digest(mine) == digest(theirs) {
+        return;
+    }
+    if mine.len() + theirs.len() <= LEAF_SIZE {
+        return diff_leaves(mine, theirs, diff);
+    }
+
+    // Split both sides by the next byte of the election ids. Ids ending here come first.
+    let (mut mine, mut theirs) = (mine, theirs);
+    while !mine.is_empty() || !theirs.is_empty() {
+        let next = |x: &[Entry]| x.first().map(|(id, _)| id.as_bytes().get(depth).copied());
+        let byte = match (next(mine), next(theirs)) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b).unwrap(),
+        };
+        let split = |x: &[Entry]| {
+            x.iter()
+                .take_while(|(id, _)| id.as_bytes().get(depth).copied() == byte)
+                .count()
+        };
+        let (a, b) = (split(mine), split(theirs));
+        if byte.is_none() {
+            diff_leaves(&mine[..a], &theirs[..b], diff);
+        } else {
+            diff_subtree(&mine[..a], &theirs[..b], depth + 1, diff);
+        }
+        mine = &mine[a..];
+        theirs = &theirs[b..];
+    }
+}
+
+/// Compare two sorted lists of elections one by one.
+fn diff_leaves(mine: &[Entry], theirs: &[Entry], diff: &mut DecisionDiff) {
+    let (mut i, mut j) = (0, 0);
+    while i < mine.len() || j < theirs.len() {
+        diff.comparisons += 1;
+        let ordering = match (mine.get(i), theirs.get(j)) {
+            (Some(a), Some(b)) => a.0.cmp(b.0),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Greater,
+        };
+        match ordering {
+            std::cmp::Ordering::Less => {
+                let (election_id, mine) = mine[i];
+                diff.differences.push(Difference::MissingTheirs {
+                    election_id: election_id.clone(),
+                    mine: mine.clone(),
+                });
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                let (election_id, theirs) = theirs[j];
+                diff.differences.push(Difference::MissingMine {
+                    election_id: election_id.clone(),
+                    theirs: theirs.clone(),
+                });
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                let ((election_id, a), (_, b)) = (mine[i], theirs[j]);
+                // Nodes may see the quorum of commits in different rounds: only the tx hashes
+                // have to agree.
+                if a.tx_hash != b.tx_hash {
+                    diff.differences.push(Difference::Conflict {
+                        election_id: election_id.clone(),
+                        mine: a.clone(),
+                        theirs: b.clone(),
+                    });
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+}
+
+fn digest(entries: &[Entry]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for (election_id, decided) in entries {
+        hasher.update(format!(
+            "{} {} {}\n",
+            election_id, decided.tx_hash, decided.round
+        ));
+    }
+    hasher.finalize().to_vec()
+}
@@ -0,0 +1,83 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! How the benchmark client spreads its transactions over the nodes it sends to. The client holds
+//! one connection per target (the nodes under benchmark) followed by one per other node; the
+//! conflicting twins of a transaction go to the nodes after the one it went to.
+use anyhow::{bail, Error};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::str::FromStr;
+
+#[cfg(test)]
+#[path = "tests/distribution_tests.rs"]
+pub mod distribution_tests;
+
+/// Which target each transaction goes to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    /// Each target in turn.
+    RoundRobin,
+    /// A target picked at random.
+    Random,
+    /// The first target only.
+    Single,
+}
+
+impl FromStr for Distribution {
+    type Err = Error;
+
+    /// Parse `round-robin`, `random` or `single`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Self::RoundRobin),
+            "random" => Ok(Self::Random),
+            "single" => Ok(Self::Single),
+            _ => bail!("Unknown distribution {}", s),
+        }
+    }
+}
+
+/// Picks the connection of each transaction.
+pub struct Distributor {
+    distribution: Distribution,
+    /// The number of targets, which come first among the connections.
+    targets: usize,
+    /// The number of connections.
+    connections: usize,
+    /// The target of the last transaction (but the twins).
+    current: usize,
+    /// The target of the next transaction, in round robin.
+    next: usize,
+    rng: StdRng,
+}
+
+impl Distributor {
+    pub fn new(distribution: Distribution, targets: usize, connections: usize) -> Self {
+        assert!(targets > 0 && targets <= connections);
+        Self {
+            distribution,
+            targets,
+            connections,
+            current: 0,
+            next: 0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// The connection of a transaction sent to the `to`-th node of its workload (see `Outgoing`):
+    /// a target for the transaction itself (`to` is 0), the `to`-th connection after it for its
+    /// twins.
+    pub fn route(&mut self, to: usize) -> usize {
+        if to == 0 {
+            self.current = match self.distribution {
+                Distribution::RoundRobin => {
+                    let target = self.next;
+                    self.next = (self.next + 1) % self.targets;
+                    target
+                }
+                Distribution::Random => self.rng.gen_range(0, self.targets),
+                Distribution::Single => 0,
+            };
+        }
+        (self.current + to) % self.connections
+    }
+}
@@ -0,0 +1,28 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The decided elections as a node publishes them (see `sink`), shared with the benchmark client
+//! that subscribes to them.
+use crypto::Digest;
+use primary::Round;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A decided election, as published to downstream consumers. Delivery is at-least-once: a
+/// consumer may see the same event (with the same id) more than once.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommittedEvent {
+    /// The position of the decision in the output of this node.
+    pub id: u64,
+    pub election_id: Digest,
+    pub tx_hash: Digest,
+    pub round: Round,
+}
+
+impl fmt::Display for CommittedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.id, self.election_id, self.tx_hash, self.round
+        )
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+mod verifier;
+
+use crate::verifier::Verifier;
+use anyhow::{bail, Context, Result};
+use clap::{crate_name, crate_version, App, AppSettings, ArgMatches};
+use std::fs;
+
+fn main() -> Result<()> {
+    let matches = App::new(crate_name!())
+        .version(crate_version!())
+        .about("Checks the outputs of a run for violations of the protocol invariants.")
+        .args_from_usage("--decisions=[FILE]... 'The decision files of the primaries'")
+        .args_from_usage("--primaries=[FILE]... 'The logs of the primaries'")
+        .args_from_usage("--workers=[FILE]... 'The logs of the workers'")
+        .args_from_usage("--clients=[FILE]... 'The logs of the clients'")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .get_matches();
+
+    let mut verifier = Verifier::default();
+    for (path, contents) in read_all(&matches, "decisions")? {
+        verifier.add_decisions(&path, &contents);
+    }
+    for (path, log) in read_all(&matches, "primaries")? {
+        verifier.add_primary_log(&path, &log);
+    }
+    for (_, log) in read_all(&matches, "workers")? {
+        verifier.add_worker_log(&log);
+    }
+    for (_, log) in read_all(&matches, "clients")? {
+        verifier.add_client_log(&log);
+    }
+
+    // Print one finding per line, for the analysis scripts.
+    let findings = verifier.findings();
+    for finding in &findings {
+        println!("{}", serde_json::to_string(finding)?);
+    }
+    if !findings.is_empty() {
+        bail!("Found {} invariant violations", findings.len());
+    }
+    Ok(())
+}
+
+/// Read the files passed to the specified argument.
+fn read_all(matches: &ArgMatches<'_>, arg: &str) -> Result<Vec<(String, String)>> {
+    matches
+        .values_of(arg)
+        .unwrap_or_default()
+        .map(|path| {
+            let contents =
+                fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+            Ok((path.to_string(), contents))
+        })
+        .collect()
+}
@@ -1,19 +1,36 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
+mod diff;
+mod event;
+mod parameters;
+mod sink;
+#[cfg(test)]
+mod testkit;
+
+use crate::diff::parse_decisions;
+use crate::parameters::{flag, load_parameters, parse_override, PARAMETER_FLAGS};
+use crate::sink::{FileSink, Publisher, TcpSink};
+use anyhow::{anyhow, Context, Result};
 use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
 use config::Export as _;
 use config::Import as _;
-use config::{Committee, KeyPair, Parameters, WorkerId};
+use config::{ByzantineStrategy, Committee, KeyPair, WorkerId};
+use crypto::{Digest, SignatureService};
 use env_logger::Env;
 use primary::Header;
-use primary::Primary;
-use store::Store;
+use log::info;
+use primary::{ElectionOutcome, NodeHandle, Primary, ShutdownStage};
+use std::net::SocketAddr;
+use store::{Store, StoreOptions};
 use tokio::sync::mpsc::{channel, Receiver};
-use worker::Worker;
+use tokio::time::{timeout, Duration};
+use worker::{ReceiptSigner, Worker};
 
 /// The default channel capacity.
 pub const CHANNEL_CAPACITY: usize = 100_000;
 
+/// How long `inspect` waits for the primary to answer.
+const INSPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() -> Result<()> {    
     let matches = App::new(crate_name!())
@@ -25,21 +42,76 @@ async fn main() -> Result<()> {
                 .about("Print a fresh key pair to file")
                 .args_from_usage("--filename=<FILE> 'The file where to print the new key pair'"),
         )
+        .subcommand(
+            SubCommand::with_name("keys")
+                .about("Manage key pairs")
+                .subcommand(
+                    SubCommand::with_name("generate")
+                        .about("Print a fresh key pair to file")
+                        .args_from_usage("--filename=<FILE> 'The file where to print the new key pair'"),
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp),
+        )
+        .subcommand(
+            SubCommand::with_name("committee")
+                .about("Manage committee files")
+                .subcommand(
+                    SubCommand::with_name("generate")
+                        .about("Print the key pairs of fresh authorities and their committee, all running on one host")
+                        .args_from_usage("--nodes=<INT> 'The number of authorities'")
+                        .args_from_usage("--base-port=<PORT> 'The first port of the committee; each authority takes 2 + 3 per worker'")
+                        .args_from_usage("--workers=[INT] 'The number of workers per authority (default 1)'")
+                        .args_from_usage("--stake=[INT]... 'The stake of every authority, or of each one in turn (default 1)'")
+                        .args_from_usage("--host=[IP] 'The address of the host (default 127.0.0.1)'")
+                        .args_from_usage("--keys=<PREFIX> 'Print the key pair of authority i to <PREFIX>i.json'")
+                        .args_from_usage("--filename=<FILE> 'The file where to print the committee'"),
+                )
+                .subcommand(
+                    SubCommand::with_name("validate")
+                        .about("Check a committee file for malformed keys and port collisions")
+                        .args_from_usage("<FILE> 'The committee file'"),
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp),
+        )
         .subcommand(
             SubCommand::with_name("run")
                 .about("Run a node")
                 .args_from_usage("--keys=<FILE> 'The file containing the node keys'")
                 .args_from_usage("--committee=<FILE> 'The file containing committee information'")
                 .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
+                .args_from_usage("--header-size=[INT] 'Override header_size, as --set would'")
+                .args_from_usage("--max-header-delay=[MS] 'Override max_header_delay, as --set would'")
+                .args_from_usage("--batch-size=[BYTES] 'Override batch_size, as --set would'")
+                .args_from_usage("--max-batch-delay=[MS] 'Override max_batch_delay, as --set would'")
+                .args_from_usage("--set=[KEY=VALUE]... 'Override a parameter (after the file, the NARWHAL_* environment variables and the flags above)'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
+                .args_from_usage("--decisions=[FILE] 'The file where to publish the decided elections'")
+                .args_from_usage("--notifications=[ADDR] 'The address where to stream the decided elections to subscribers, such as the benchmark client (unless --decisions)'")
+                .args_from_usage("--byzantine=[STRATEGY] 'Misbehave: silent, equivocate, vote-flip, delay-votes=<ms> or spam-headers'")
                 .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
                 .subcommand(
                     SubCommand::with_name("worker")
                         .about("Run a single worker")
                         .args_from_usage("--id=<INT> 'The worker id'"),
                 )
+                .subcommand(
+                    SubCommand::with_name("workers")
+                        .about("Run all the workers of this authority listed in the committee"),
+                )
                 .setting(AppSettings::SubcommandRequiredElseHelp),
         )
+        .subcommand(
+            SubCommand::with_name("diff-decisions")
+                .about("Print the elections two nodes did not decide alike. Exits with 2 on a conflict, 1 if only some are missing")
+                .args_from_usage("--mine=<FILE> 'Our decision file'")
+                .args_from_usage("--theirs=<FILE> 'Their decision file'"),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Print the state of an election at a primary, to debug a stuck one")
+                .args_from_usage("--election=<ID> 'The election id (in full base64)'")
+                .args_from_usage("--target=<ADDR> 'The address the primary listens to other primaries on'"),
+        )
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .get_matches();
 
@@ -59,12 +131,161 @@ async fn main() -> Result<()> {
         ("generate_keys", Some(sub_matches)) => KeyPair::new()
             .export(sub_matches.value_of("filename").unwrap())
             .context("Failed to generate key pair")?,
+        ("keys", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("generate", Some(sub_matches)) => KeyPair::new()
+                .export(sub_matches.value_of("filename").unwrap())
+                .context("Failed to generate key pair")?,
+            _ => unreachable!(),
+        },
+        ("committee", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("generate", Some(sub_matches)) => generate_committee(sub_matches)?,
+            ("validate", Some(sub_matches)) => {
+                let filename = sub_matches.value_of("FILE").unwrap();
+                Committee::import(filename)
+                    .context("Failed to load the committee information")?
+                    .validate()?;
+                println!("{} is a valid committee", filename);
+            }
+            _ => unreachable!(),
+        },
         ("run", Some(sub_matches)) => run(sub_matches).await?,
+        ("diff-decisions", Some(sub_matches)) => diff_decisions(sub_matches)?,
+        ("inspect", Some(sub_matches)) => inspect(sub_matches).await?,
         _ => unreachable!(),
     }
     Ok(())
 }
 
+// Prints the key pairs and the committee of a local testbed.
+fn generate_committee(matches: &ArgMatches<'_>) -> Result<()> {
+    let nodes: usize = matches
+        .value_of("nodes")
+        .unwrap()
+        .parse()
+        .context("The number of nodes must be a positive integer")?;
+    let base_port = matches
+        .value_of("base-port")
+        .unwrap()
+        .parse()
+        .context("The base port must be a port number")?;
+    let workers = matches
+        .value_of("workers")
+        .map_or(Ok(1), |x| x.parse())
+        .context("The number of workers must be a positive integer")?;
+    let host = matches
+        .value_of("host")
+        .map_or(Ok([127, 0, 0, 1].into()), |x| x.parse())
+        .context("The host must be an IP address")?;
+    let stakes = matches
+        .values_of("stake")
+        .map_or(Ok(vec![1]), |x| x.map(|x| x.parse()).collect())
+        .context("Stakes must be positive integers")?;
+    let stake = |i: usize| match stakes.as_slice() {
+        [stake] => Ok(*stake),
+        stakes if stakes.len() == nodes => Ok(stakes[i]),
+        _ => Err(anyhow!("Give either one stake for all nodes or one per node")),
+    };
+    let keys_prefix = matches.value_of("keys").unwrap();
+
+    let mut authorities = Vec::new();
+    for i in 0..nodes {
+        let keypair = KeyPair::new();
+        authorities.push((keypair.name, stake(i)?));
+        keypair
+            .export(&format!("{}{}.json", keys_prefix, i))
+            .context("Failed to generate key pair")?;
+    }
+    let committee = Committee::local(&authorities, host, base_port, workers)?;
+    committee.validate()?;
+    committee
+        .export(matches.value_of("filename").unwrap())
+        .context("Failed to print the committee")?;
+    Ok(())
+}
+
+// Compares the decision files of two nodes.
+fn diff_decisions(matches: &ArgMatches<'_>) -> Result<()> {
+    let read = |name| -> Result<_> {
+        let path = matches.value_of(name).unwrap();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the decision file {}", path))?;
+        parse_decisions(&contents).with_context(|| format!("Invalid decision file {}", path))
+    };
+    let outcome = diff::diff(&read("mine")?, &read("theirs")?);
+
+    for difference in &outcome.differences {
+        println!("{}", difference);
+    }
+    let conflicts = outcome.differences.iter().filter(|x| x.is_conflict()).count();
+    println!(
+        "{} conflicting and {} missing elections ({} comparisons)",
+        conflicts,
+        outcome.differences.len() - conflicts,
+        outcome.comparisons
+    );
+    if conflicts > 0 {
+        std::process::exit(2);
+    } else if !outcome.differences.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Queries a primary for its state of an election.
+async fn inspect(matches: &ArgMatches<'_>) -> Result<()> {
+    let election_id = Digest::decode_base64(matches.value_of("election").unwrap())
+        .context("The election id must be a digest in base64")?;
+    let target: SocketAddr = matches
+        .value_of("target")
+        .unwrap()
+        .parse()
+        .context("Invalid socket address format")?;
+    let state = timeout(INSPECT_TIMEOUT, primary::inspect(target, election_id.clone()))
+        .await
+        .with_context(|| format!("{} did not answer", target))?
+        .map_err(|e| anyhow!("Failed to query {}: {}", target, e))?;
+
+    let state = match state {
+        Some(state) => state,
+        None => {
+            println!("{} knows nothing of election {:?}", target, election_id);
+            return Ok(());
+        }
+    };
+    let print = |x: Option<&Digest>| x.map_or("-".to_string(), |x| format!("{:?}", x));
+    println!("Election {:?} at {}", election_id, target);
+    println!(
+        "  round {}, decided {}, highest {}, commit {}",
+        state.round,
+        state.decided,
+        print(state.highest.as_ref()),
+        print(state.commit.as_ref())
+    );
+    if !state.equivocators.is_empty() {
+        let authors: Vec<_> = state.equivocators.iter().map(|x| x.to_string()).collect();
+        println!("  equivocators: {}", authors.join(", "));
+    }
+    for (round, tally) in &state.tallies {
+        println!("  round {}:", round);
+        let entries = tally
+            .votes
+            .iter()
+            .map(|x| ("vote", x))
+            .chain(tally.commits.iter().map(|x| ("commit", x)));
+        for (kind, (tx_hash, authors)) in entries {
+            let authors: Vec<_> = authors.iter().map(|x| x.to_string()).collect();
+            println!(
+                "    {} {:?}: {} ({})",
+                kind,
+                tx_hash,
+                authors.len(),
+                authors.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
 // Runs either a worker or a primary.
 async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     //sleep(std::time::Duration::from_millis(1500));
@@ -73,41 +294,78 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     let committee_file = matches.value_of("committee").unwrap();
     let parameters_file = matches.value_of("parameters");
     let store_path = matches.value_of("store").unwrap();
+    let decisions_file = matches.value_of("decisions");
+    let notifications = matches
+        .value_of("notifications")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid notifications address")?;
 
     // Read the committee and node's keypair from file.
-    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair").unwrap();
+    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
     let committee =
         Committee::import(committee_file).context("Failed to load the committee information")?;
 
-    // Load default parameters if none are specified.
-    let parameters = match parameters_file {
-        Some(filename) => {
-            Parameters::import(filename).context("Failed to load the node's parameters")?
+    // Apply the overrides of the environment and then of the command line, flags first.
+    let mut overrides = Vec::new();
+    for key in PARAMETER_FLAGS {
+        if let Some(value) = matches.value_of(flag(key)) {
+            overrides.push((key.to_string(), value.to_string()));
         }
-        None => Parameters::default(),
+    }
+    for assignment in matches.values_of("set").into_iter().flatten() {
+        overrides.push(parse_override(assignment)?);
+    }
+    let parameters = load_parameters(parameters_file, std::env::vars(), &overrides)?;
+    let (sink_batch_size, sink_retry_delay) =
+        (parameters.sink_batch_size, parameters.sink_retry_delay);
+
+    // The committee file can only mark us as (silent) byzantine; the command line picks the strategy.
+    let byzantine = match matches.value_of("byzantine") {
+        Some(strategy) => Some(
+            strategy
+                .parse::<ByzantineStrategy>()
+                .context("Invalid byzantine strategy")?,
+        ),
+        None => committee
+            .authorities
+            .get(&keypair.name)
+            .filter(|x| x.byzantine)
+            .map(|_| ByzantineStrategy::Silent),
     };
 
     // Make the data store.
-    let store = Store::new(store_path).context("Failed to create a store")?;
+    let options = StoreOptions {
+        rate_limit: parameters.compaction_rate_limit,
+    };
+    let make_store = |path: &str| {
+        Store::with_options(path, options.clone()).context("Failed to create a store")
+    };
 
-    // Channels the sequence of certificates.
+    // Channels the sequence of decided elections.
     let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
 
     // Check whether to run a primary, a worker, or an entire authority.
+    let mut node = NodeHandle::default();
+    let mut primary_store = None;
     match matches.subcommand() {
         // Spawn the primary and consensus core.
         ("primary", _) => {
             //let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
             //let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
-            Primary::spawn(
+            let store = make_store(store_path)?;
+            primary_store = Some(store.clone());
+            node.extend(Primary::spawn(
                 keypair.name,
                 keypair.secret,
                 committee.clone(),
                 parameters.clone(),
                 store,
+                tx_output,
+                byzantine,
                 /* tx_consensus */ //tx_new_certificates,
                 /* rx_consensus */ //rx_feedback,
-            );
+            ));
         }
 
         // Spawn a single worker.
@@ -117,21 +375,97 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 .unwrap()
                 .parse::<WorkerId>()
                 .context("The worker id must be a positive integer")?;
-            Worker::spawn(keypair.name, id, committee, parameters, store);
+            let signature_service = SignatureService::new(keypair.secret);
+            let signer = ReceiptSigner::new(keypair.name, signature_service.clone());
+            let store = make_store(store_path)?;
+            node.extend(Worker::spawn(
+                keypair.name,
+                id,
+                committee,
+                parameters,
+                store,
+                signature_service,
+                Some(signer),
+            ));
+            drop(tx_output);
+        }
+
+        // Spawn all our workers, each with its own store (suffixed by its id).
+        ("workers", _) => {
+            let mut ids: Vec<WorkerId> = committee
+                .authorities
+                .get(&keypair.name)
+                .context("Our public key is not in the committee")?
+                .workers
+                .keys()
+                .copied()
+                .collect();
+            ids.sort_unstable();
+            let signature_service = SignatureService::new(keypair.secret);
+            let signer = ReceiptSigner::new(keypair.name, signature_service.clone());
+            for id in ids {
+                let store = make_store(&format!("{}-{}", store_path, id))?;
+                node.extend(Worker::spawn(
+                    keypair.name,
+                    id,
+                    committee.clone(),
+                    parameters.clone(),
+                    store,
+                    signature_service.clone(),
+                    Some(signer.clone()),
+                ));
+            }
+            drop(tx_output);
         }
         _ => unreachable!(),
     }
 
-    // Analyze the consensus' output.
-    analyze(rx_output).await;
+    // Publish the consensus' output, or analyze it.
+    match decisions_file {
+        Some(path) => {
+            let sink = FileSink::new(path)
+                .await
+                .context("Failed to open the decisions file")?;
+            let (join, tx_stop) = Publisher::spawn(
+                sink,
+                rx_output,
+                sink_batch_size,
+                sink_retry_delay,
+                primary_store,
+            );
+            node.push_drain(join, tx_stop);
+        }
+        None => match notifications {
+            Some(address) => {
+                let sink = TcpSink::new(address)
+                    .await
+                    .context("Failed to listen for subscribers to the decisions")?;
+                let (join, tx_stop) = Publisher::spawn(
+                    sink,
+                    rx_output,
+                    sink_batch_size,
+                    sink_retry_delay,
+                    primary_store,
+                );
+                node.push_drain(join, tx_stop);
+            }
+            None => node.push(ShutdownStage::Drain, tokio::spawn(analyze(rx_output))),
+        },
+    }
 
-    // If this expression is reached, the program ends and all other tasks terminate.
-    unreachable!();
+    // Run until interrupted, then stop the node one stage after the other.
+    tokio::signal::ctrl_c()
+        .await
+        .context("Failed to listen for the interrupt signal")?;
+    info!("Shutting down");
+    node.shutdown().await.context("A task panicked")?;
+    Ok(())
 }
 
-/// Receives an ordered list of certificates and apply any application-specific logic.
-async fn analyze(mut rx_output: Receiver<()>) {
-    while let Some(_certificate) = rx_output.recv().await {
+/// Receives the ordered stream of decided (and failed) elections and apply any application-specific
+/// logic.
+async fn analyze(mut rx_output: Receiver<ElectionOutcome>) {
+    while let Some(_outcome) = rx_output.recv().await {
         // NOTE: Here goes the application logic.
     }
 }
@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The parameters of `node run`: those of the parameters file (or the defaults), overridden by the
+//! `NARWHAL_*` environment variables, and then by the command line.
+use anyhow::{Context, Result};
+use config::{Import as _, Parameters};
+
+#[cfg(test)]
+#[path = "tests/parameters_tests.rs"]
+pub mod parameters_tests;
+
+/// The parameters with a flag of their own on the command line, `--header-size` for `header_size`.
+pub const PARAMETER_FLAGS: [&str; 4] = [
+    "header_size",
+    "max_header_delay",
+    "batch_size",
+    "max_batch_delay",
+];
+
+/// The flag of a parameter listed in `PARAMETER_FLAGS`.
+pub fn flag(key: &str) -> String {
+    key.replace('_', "-")
+}
+
+/// Split a parameter override of the form KEY=VALUE.
+pub fn parse_override(assignment: &str) -> Result<(String, String)> {
+    let (key, value) = assignment
+        .split_once('=')
+        .context("Parameter overrides must have the form KEY=VALUE")?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Load the parameters and check them. The command line `overrides` apply in order, the last
+/// override of a parameter winning.
+pub fn load_parameters<E>(
+    file: Option<&str>,
+    env: E,
+    overrides: &[(String, String)],
+) -> Result<Parameters>
+where
+    E: IntoIterator<Item = (String, String)>,
+{
+    // Missing fields (or a missing file) take their default value.
+    let mut parameters = match file {
+        Some(filename) => {
+            Parameters::import(filename).context("Failed to load the node's parameters")?
+        }
+        None => Parameters::default(),
+    };
+    parameters
+        .override_from_env(env)
+        .context("Invalid parameter in the environment")?;
+    for (key, value) in overrides {
+        parameters
+            .set(key, value)
+            .context("Invalid parameter on the command line")?;
+    }
+    parameters.validate().context("Invalid parameters")?;
+    Ok(parameters)
+}
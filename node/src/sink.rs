@@ -0,0 +1,304 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+pub use crate::event::CommittedEvent;
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use log::{info, warn};
+use network::{decode, encode};
+use primary::ElectionOutcome;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use store::Store;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt as _;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{FramedWrite, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/sink_tests.rs"]
+pub mod sink_tests;
+
+/// The retry delay stops doubling once it reaches this multiple of the initial delay.
+const MAX_BACKOFF: u64 = 64;
+
+/// The store key of the point where the publisher stopped.
+const CUT_KEY: &[u8] = b"publisher_cut";
+
+/// Where the publisher stopped: the id of the next event, and the events it received but did not
+/// publish (oldest first). Both are persisted in a single write.
+type Cut = (u64, VecDeque<CommittedEvent>);
+
+/// A downstream consumer of the decided elections.
+#[async_trait]
+pub trait DecisionSink: Send + Sync {
+    async fn publish(&self, event: CommittedEvent) -> Result<()>;
+}
+
+/// Appends one line per event to a local file.
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub async fn new(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl DecisionSink for FileSink {
+    async fn publish(&self, event: CommittedEvent) -> Result<()> {
+        let mut file = self.file.lock().await;
+        file.write_all(format!("{}\n", event).as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// The notifications endpoint of a node: streams the events to the subscribers connected to an
+/// address, one frame (the bincode encoding of the event) each. A subscriber only gets the events
+/// published while it is connected, and is dropped on its first failed delivery.
+pub struct TcpSink {
+    subscribers: Arc<Mutex<Vec<FramedWrite<TcpStream, LengthDelimitedCodec>>>>,
+}
+
+impl TcpSink {
+    /// Listen for subscribers on an address.
+    pub async fn new(address: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let accepted = subscribers.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, peer)) => {
+                        info!("Subscriber {} connected to the decisions", peer);
+                        let transport = FramedWrite::new(socket, LengthDelimitedCodec::new());
+                        accepted.lock().await.push(transport);
+                    }
+                    Err(e) => warn!("Failed to accept a subscriber: {}", e),
+                }
+            }
+        });
+        info!("Streaming the decisions to subscribers on {}", address);
+        Ok(Self { subscribers })
+    }
+}
+
+#[async_trait]
+impl DecisionSink for TcpSink {
+    async fn publish(&self, event: CommittedEvent) -> Result<()> {
+        let frame = Bytes::from(encode(&event)?);
+        let mut subscribers = self.subscribers.lock().await;
+        let mut connected = Vec::with_capacity(subscribers.len());
+        for mut subscriber in subscribers.drain(..) {
+            match subscriber.send(frame.clone()).await {
+                Ok(()) => connected.push(subscriber),
+                Err(e) => warn!("Dropping a subscriber to the decisions: {}", e),
+            }
+        }
+        *subscribers = connected;
+        Ok(())
+    }
+}
+
+/// Publishes the decisions of the node to a sink, in order (failed elections decided nothing: they
+/// are left out). Decisions keep arriving while the sink fails: they are spilled to the pending
+/// queue so that consensus never waits on the sink. When it stops, the publisher persists its cut
+/// point (see `Cut`) and resumes from it on the next boot, so that no event is published twice or
+/// skipped across a clean shutdown.
+pub struct Publisher<S> {
+    sink: S,
+    /// Receives the outcomes of the elections of the primary.
+    rx_decision: Receiver<ElectionOutcome>,
+    /// The maximum number of events published before checking for new decisions.
+    batch_size: usize,
+    /// The initial delay before retrying a failed publication (in ms).
+    retry_delay: u64,
+    /// The events not yet published (oldest first).
+    pending: VecDeque<CommittedEvent>,
+    /// The id of the next event.
+    next_id: u64,
+    /// Where to persist the cut point; without it, every boot starts from scratch.
+    store: Option<Store>,
+    /// Asks the publisher to stop at the next clean point.
+    rx_stop: Receiver<()>,
+    /// Whether we were asked to stop.
+    stopped: bool,
+}
+
+impl<S: DecisionSink + 'static> Publisher<S> {
+    /// Spawn a publisher, which returns once the decisions channel closed and all events are
+    /// published, or once asked to stop through the returned sender.
+    pub fn spawn(
+        sink: S,
+        rx_decision: Receiver<ElectionOutcome>,
+        batch_size: usize,
+        retry_delay: u64,
+        store: Option<Store>,
+    ) -> (JoinHandle<()>, Sender<()>) {
+        let (tx_stop, rx_stop) = channel(1);
+        let join = tokio::spawn(async move {
+            let mut publisher = Self {
+                sink,
+                rx_decision,
+                batch_size,
+                retry_delay,
+                pending: VecDeque::new(),
+                next_id: 0,
+                store,
+                rx_stop,
+                stopped: false,
+            };
+            publisher.resume().await;
+            publisher.run().await;
+
+            // Keep the decisions delivered to us for the next boot.
+            while let Ok(decision) = publisher.rx_decision.try_recv() {
+                publisher.spill(decision);
+            }
+            publisher.persist().await;
+        });
+        (join, tx_stop)
+    }
+
+    /// Start from the cut point of the previous boot, if any.
+    async fn resume(&mut self) {
+        let store = match &mut self.store {
+            Some(store) => store,
+            None => return,
+        };
+        let cut = match store.read(CUT_KEY.to_vec()).await {
+            Ok(Some(bytes)) => decode::<Cut>(&bytes),
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Failed to read the publisher cut point: {}", e);
+                return;
+            }
+        };
+        match cut {
+            Ok((next_id, pending)) => {
+                info!(
+                    "Resuming publication at event {} ({} pending)",
+                    next_id - pending.len() as u64,
+                    pending.len()
+                );
+                self.next_id = next_id;
+                self.pending = pending;
+            }
+            Err(e) => warn!("Failed to decode the publisher cut point: {}", e),
+        }
+    }
+
+    /// Persist the cut point.
+    async fn persist(&mut self) {
+        let store = match &mut self.store {
+            Some(store) => store,
+            None => return,
+        };
+        let cut: Cut = (self.next_id, self.pending.clone());
+        let bytes = encode(&cut).expect("Failed to serialize the publisher cut point");
+        store.write(CUT_KEY.to_vec(), bytes).await;
+        info!(
+            "Publisher stopped before event {} ({} pending)",
+            self.next_id - self.pending.len() as u64,
+            self.pending.len()
+        );
+    }
+
+    /// Whether we were asked to stop.
+    fn stopping(&mut self) -> bool {
+        if let Ok(()) = self.rx_stop.try_recv() {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    fn spill(&mut self, outcome: ElectionOutcome) {
+        let (election_id, tx_hash, round) = match outcome {
+            ElectionOutcome::Decided(decision) => decision,
+            ElectionOutcome::Failed(_) => return,
+        };
+        self.pending.push_back(CommittedEvent {
+            id: self.next_id,
+            election_id,
+            tx_hash,
+            round,
+        });
+        self.next_id += 1;
+    }
+
+    /// Publish up to `batch_size` pending events, unless asked to stop. An event leaves the queue
+    /// only once published.
+    async fn flush(&mut self) -> Result<()> {
+        for _ in 0..self.batch_size {
+            if self.stopping() {
+                break;
+            }
+            let event = match self.pending.front() {
+                Some(event) => event.clone(),
+                None => break,
+            };
+            self.sink.publish(event).await?;
+            self.pending.pop_front();
+        }
+        Ok(())
+    }
+
+    async fn run(&mut self) {
+        let mut backoff = self.retry_delay;
+        loop {
+            while let Ok(decision) = self.rx_decision.try_recv() {
+                self.spill(decision);
+            }
+            if self.stopping() {
+                return;
+            }
+            if self.pending.is_empty() {
+                tokio::select! {
+                    decision = self.rx_decision.recv() => match decision {
+                        Some(decision) => self.spill(decision),
+                        None => return,
+                    },
+                    Some(()) = self.rx_stop.recv() => self.stopped = true,
+                }
+                continue;
+            }
+
+            match self.flush().await {
+                Ok(()) => backoff = self.retry_delay,
+                Err(e) => {
+                    warn!("Failed to publish decision: {}", e);
+
+                    // Keep receiving decisions while we back off.
+                    let timer = sleep(Duration::from_millis(backoff));
+                    tokio::pin!(timer);
+                    loop {
+                        tokio::select! {
+                            Some(decision) = self.rx_decision.recv() => self.spill(decision),
+                            Some(()) = self.rx_stop.recv() => {
+                                self.stopped = true;
+                                break;
+                            },
+                            () = &mut timer => break,
+                        }
+                    }
+                    backoff = (2 * backoff).min(MAX_BACKOFF * self.retry_delay);
+                }
+            }
+        }
+    }
+}
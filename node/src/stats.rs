@@ -0,0 +1,121 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The statistics of a benchmark run, measured by the client itself: it samples one transaction
+//! out of every few it sends, records when it was due, and matches it with the decision of its
+//! election streamed by a node (see `TcpSink`).
+use crypto::Digest;
+use std::collections::HashMap;
+use std::fmt;
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/stats_tests.rs"]
+pub mod stats_tests;
+
+pub struct Stats {
+    /// One transaction out of `every` is sampled.
+    every: u64,
+    /// The transactions sent so far.
+    sent: u64,
+    /// The decisions observed so far, sampled or not.
+    decided: u64,
+    /// When the sampled transactions waiting for a decision were due, by election.
+    pending: HashMap<Digest, Instant>,
+    /// The end-to-end latency of the sampled transactions decided so far.
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    pub fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            sent: 0,
+            decided: 0,
+            pending: HashMap::new(),
+            latencies: Vec::new(),
+        }
+    }
+
+    /// Record a transaction of an election, due at `due`. Every `every`-th one is sampled; conflicting
+    /// twins share their election, which keeps the time of the first one.
+    pub fn sent(&mut self, election_id: Digest, due: Instant) {
+        if self.sent.is_multiple_of(self.every) {
+            self.pending.entry(election_id).or_insert(due);
+        }
+        self.sent += 1;
+    }
+
+    /// Record the decision of an election, observed at `at`.
+    pub fn decided(&mut self, election_id: &Digest, at: Instant) {
+        self.decided += 1;
+        if let Some(due) = self.pending.remove(election_id) {
+            self.latencies.push(at.saturating_duration_since(due));
+        }
+    }
+
+    /// The sampled transactions still waiting for a decision.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The statistics of a run of `elapsed` so far.
+    pub fn report(&self, elapsed: Duration) -> Report {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let seconds = elapsed.as_secs_f64();
+        Report {
+            sent: self.sent,
+            decided: self.decided,
+            throughput: if seconds > 0.0 {
+                self.decided as f64 / seconds
+            } else {
+                0.0
+            },
+            samples: latencies.len(),
+            undecided: self.pending.len(),
+            p50: percentile(&latencies, 50),
+            p95: percentile(&latencies, 95),
+            p99: percentile(&latencies, 99),
+        }
+    }
+}
+
+/// The nearest-rank percentile of sorted latencies, if there are any.
+fn percentile(sorted: &[Duration], p: usize) -> Option<Duration> {
+    let rank = (p * sorted.len()).div_ceil(100);
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub sent: u64,
+    pub decided: u64,
+    /// Decisions per second.
+    pub throughput: f64,
+    /// The sampled transactions decided, and those that were not.
+    pub samples: usize,
+    pub undecided: usize,
+    pub p50: Option<Duration>,
+    pub p95: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |x: Option<Duration>| match x {
+            Some(x) => format!("{} ms", x.as_millis()),
+            None => "-".to_string(),
+        };
+        write!(
+            f,
+            "Sent {} txs, {} decisions ({:.1} decisions/s); latency of {} samples ({} undecided): p50 {}, p95 {}, p99 {}",
+            self.sent,
+            self.decided,
+            self.throughput,
+            self.samples,
+            self.undecided,
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.p99)
+        )
+    }
+}
@@ -0,0 +1,368 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! In-process clusters for end-to-end tests: every authority runs its primary and one worker in
+//! the test runtime, on ephemeral ports of the local host.
+use bytes::Bytes;
+use config::{
+    Authority, ByzantineStrategy, Committee, Parameters, PrimaryAddresses, WorkerAddresses,
+};
+use crypto::{generate_keypair, Digest, PublicKey, SecretKey, Signature, SignatureService};
+use futures::sink::SinkExt as _;
+use futures::stream::{SplitSink, StreamExt as _};
+use network::encode;
+use primary::{ElectionOutcome, Hash as _, NodeHandle, Primary, Reconfiguration, Transaction};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Mutex, MutexGuard};
+use store::Store;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use worker::{ReceiptSigner, Worker};
+
+#[cfg(test)]
+#[path = "tests/testkit_tests.rs"]
+pub mod testkit_tests;
+
+/// The capacity of the channel of decisions of each node.
+const DECISIONS_CAPACITY: usize = 10_000;
+
+/// Only one cluster runs at a time: the clusters of the tests running in parallel would slow each
+/// other down until they miss the patience of these tests.
+static RUNNING: Mutex<()> = Mutex::new(());
+
+/// An authority of the cluster. Its decisions keep flowing into the same channel across restarts.
+struct Node {
+    name: PublicKey,
+    byzantine: Option<ByzantineStrategy>,
+    /// The tasks of the primary and of the worker of the node, unless they are down.
+    primary: Option<NodeHandle>,
+    worker: Option<NodeHandle>,
+    tx_output: Sender<ElectionOutcome>,
+    rx_output: Receiver<ElectionOutcome>,
+}
+
+/// A committee of authorities running in the current runtime.
+pub struct Cluster {
+    pub committee: Committee,
+    pub parameters: Parameters,
+    nodes: Vec<Node>,
+    /// Distinguishes the stores of this cluster from those of the others.
+    prefix: String,
+    /// Held until the cluster is dropped (see `RUNNING`).
+    _running: MutexGuard<'static, ()>,
+}
+
+impl Cluster {
+    /// Start `n` authorities of equal stake; those listed in `byzantine` stay silent.
+    pub async fn spawn(n: usize, byzantine: Vec<usize>) -> Self {
+        Self::with_parameters(n, byzantine, parameters()).await
+    }
+
+    /// As `spawn`, but the authorities run with the given parameters.
+    pub async fn with_parameters(n: usize, byzantine: Vec<usize>, parameters: Parameters) -> Self {
+        // A test that panicked leaves the lock poisoned, but it dropped its cluster all the same.
+        let running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        let keys = keys(n);
+        let ports = ports(n);
+        let committee = Committee {
+            epoch: 0,
+            authorities: keys
+                .iter()
+                .zip(ports.chunks(5))
+                .enumerate()
+                .map(|(i, ((name, _), ports))| (*name, authority(ports, byzantine.contains(&i))))
+                .collect(),
+        };
+        let nodes = keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, _))| Node::new(name, byzantine.contains(&i)))
+            .collect();
+        let mut cluster = Self {
+            committee,
+            parameters,
+            nodes,
+            prefix: format!(".db_test_cluster_{}", ports[0].port()),
+            _running: running,
+        };
+        for i in 0..n {
+            let _ = std::fs::remove_dir_all(cluster.store_path(i, "primary"));
+            let _ = std::fs::remove_dir_all(cluster.store_path(i, "worker"));
+            cluster.start(i);
+        }
+        cluster.wait().await;
+        cluster
+    }
+
+    /// The public key of a node.
+    pub fn name(&self, i: usize) -> PublicKey {
+        self.nodes[i].name
+    }
+
+    /// Whether a node is running (if only in part).
+    pub fn is_up(&self, i: usize) -> bool {
+        self.nodes[i].primary.is_some() || self.nodes[i].worker.is_some()
+    }
+
+    /// Stop a node, as a crash would (but for flushing its stores).
+    pub async fn kill(&mut self, i: usize) {
+        self.kill_primary(i).await;
+        if let Some(handle) = self.nodes[i].worker.take() {
+            handle
+                .shutdown()
+                .await
+                .expect("A task of the worker panicked");
+        }
+    }
+
+    /// Stop the primary of a node, leaving its worker running.
+    pub async fn kill_primary(&mut self, i: usize) {
+        if let Some(handle) = self.nodes[i].primary.take() {
+            handle
+                .shutdown()
+                .await
+                .expect("A task of the primary panicked");
+        }
+    }
+
+    /// Move the cluster to the next epoch, whose committee drops the nodes in `leaving` and takes
+    /// `joining` new ones. Every authority of the current committee signs it; the new nodes start
+    /// on the new committee, then the running primaries get it. Returns the new nodes.
+    pub async fn reconfigure(&mut self, leaving: &[usize], joining: usize) -> Vec<usize> {
+        let n = self.nodes.len();
+        let mut committee = self.committee.clone();
+        committee.epoch += 1;
+        for i in leaving {
+            committee.authorities.remove(&self.nodes[*i].name);
+        }
+        let ports = ports(joining);
+        for ((name, _), ports) in keys(n + joining).into_iter().skip(n).zip(ports.chunks(5)) {
+            committee.authorities.insert(name, authority(ports, false));
+            self.nodes.push(Node::new(name, false));
+        }
+        let mut reconfiguration = Reconfiguration {
+            committee,
+            signatures: Vec::new(),
+        };
+        let digest = reconfiguration.digest();
+        let signers = (0..n).filter(|i| self.committee.authorities.contains_key(&self.name(*i)));
+        reconfiguration.signatures = signers
+            .map(|i| (self.name(i), Signature::new(&digest, &secret(i))))
+            .collect();
+
+        let previous = std::mem::replace(&mut self.committee, reconfiguration.committee.clone());
+        let joined: Vec<_> = (n..n + joining).collect();
+        for i in &joined {
+            let _ = std::fs::remove_dir_all(self.store_path(*i, "primary"));
+            let _ = std::fs::remove_dir_all(self.store_path(*i, "worker"));
+            self.start(*i);
+        }
+        for i in (0..n).filter(|i| self.nodes[*i].primary.is_some()) {
+            let address = previous
+                .primary(&self.name(i))
+                .expect("The node has no primary")
+                .primary_to_primary;
+            primary::reconfigure(address, reconfiguration.clone())
+                .await
+                .expect("Failed to reconfigure a primary");
+        }
+        self.wait().await;
+        joined
+    }
+
+    /// Start a stopped node again, over the stores it had.
+    pub async fn restart(&mut self, i: usize) {
+        if !self.is_up(i) {
+            self.start(i);
+            self.wait().await;
+        }
+    }
+
+    /// A client submitting transactions to the worker of a node.
+    pub async fn client(&self, i: usize) -> Client {
+        let address = self.worker_address(i);
+        let stream = TcpStream::connect(address)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to connect to {}: {}", address, e));
+        Client::new(stream)
+    }
+
+    /// The next election a node settles, or `None` if it settles none within `patience`.
+    pub async fn next_outcome(&mut self, i: usize, patience: Duration) -> Option<ElectionOutcome> {
+        tokio::time::timeout(patience, self.nodes[i].rx_output.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Wait until a node decided all the elections, and return its decision on each. Returns
+    /// `None` if the node settles no election for `patience`.
+    pub async fn decide(
+        &mut self,
+        i: usize,
+        elections: &HashSet<Digest>,
+        patience: Duration,
+    ) -> Option<HashMap<Digest, Digest>> {
+        let mut decided = HashMap::new();
+        while elections.iter().any(|x| !decided.contains_key(x)) {
+            if let ElectionOutcome::Decided((election_id, tx_hash, _)) =
+                self.next_outcome(i, patience).await?
+            {
+                decided.insert(election_id, tx_hash);
+            }
+        }
+        Some(decided)
+    }
+
+    fn start(&mut self, i: usize) {
+        let (primary_store, worker_store) = (
+            Store::new(&self.store_path(i, "primary")).unwrap(),
+            Store::new(&self.store_path(i, "worker")).unwrap(),
+        );
+        let node = &self.nodes[i];
+        let primary = Primary::spawn(
+            node.name,
+            secret(i),
+            self.committee.clone(),
+            self.parameters.clone(),
+            primary_store,
+            node.tx_output.clone(),
+            node.byzantine.clone(),
+        );
+        let signature_service = SignatureService::new(secret(i));
+        let signer = ReceiptSigner::new(node.name, signature_service.clone());
+        let worker = Worker::spawn(
+            node.name,
+            /* id */ 0,
+            self.committee.clone(),
+            self.parameters.clone(),
+            worker_store,
+            signature_service,
+            Some(signer),
+        );
+        self.nodes[i].primary = Some(primary);
+        self.nodes[i].worker = Some(worker);
+    }
+
+    /// Wait until every running node of the committee accepts transactions.
+    async fn wait(&self) {
+        let running = |i: &usize| {
+            self.nodes[*i].worker.is_some()
+                && self.committee.authorities.contains_key(&self.name(*i))
+        };
+        for i in (0..self.nodes.len()).filter(running) {
+            while TcpStream::connect(self.worker_address(i)).await.is_err() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    fn worker_address(&self, i: usize) -> SocketAddr {
+        self.committee
+            .worker(&self.nodes[i].name, &0)
+            .expect("The node has no worker")
+            .transactions
+    }
+
+    fn store_path(&self, i: usize, role: &str) -> String {
+        format!("{}_{}_{}", self.prefix, i, role)
+    }
+}
+
+impl Node {
+    fn new(name: PublicKey, byzantine: bool) -> Self {
+        let (tx_output, rx_output) = channel(DECISIONS_CAPACITY);
+        Self {
+            name,
+            byzantine: byzantine.then_some(ByzantineStrategy::Silent),
+            primary: None,
+            worker: None,
+            tx_output,
+            rx_output,
+        }
+    }
+}
+
+/// Five free ports of the local host for each of `n` authorities. We hold all listeners until
+/// every port is picked, so that no two addresses collide.
+fn ports(n: usize) -> Vec<SocketAddr> {
+    let listeners: Vec<_> = (0..5 * n)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    listeners.iter().map(|x| x.local_addr().unwrap()).collect()
+}
+
+/// An authority of stake 1 with a single worker, on five ports.
+fn authority(ports: &[SocketAddr], byzantine: bool) -> Authority {
+    let primary = PrimaryAddresses {
+        primary_to_primary: ports[0],
+        worker_to_primary: ports[1],
+    };
+    let worker = WorkerAddresses {
+        primary_to_worker: ports[2],
+        transactions: ports[3],
+        worker_to_worker: ports[4],
+    };
+    Authority {
+        stake: 1,
+        primary,
+        workers: vec![(0, worker)].into_iter().collect(),
+        byzantine,
+    }
+}
+
+/// Submits transactions to a worker. The worker acknowledges every transaction; we do not wait
+/// for these acks.
+pub struct Client {
+    transport: SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> Self {
+        let (transport, mut replies) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        tokio::spawn(async move { while let Some(Ok(_)) = replies.next().await {} });
+        Self { transport }
+    }
+
+    pub async fn submit(&mut self, transaction: &Transaction) {
+        let message = Bytes::from(encode(transaction).unwrap());
+        self.transport
+            .send(message)
+            .await
+            .expect("Failed to submit a transaction");
+    }
+}
+
+/// The parameters of the clusters made by `Cluster::spawn`.
+pub fn parameters() -> Parameters {
+    // The transactions are proposed inline, so that each is an election of its own. Debug builds
+    // are slow to verify the votes: the lagging nodes should not drop the rounds of the others as
+    // too old.
+    Parameters {
+        gc_depth: 1_000,
+        max_batch_delay: 10,
+        inline_size: 512,
+        ..Parameters::default()
+    }
+}
+
+/// `count` transactions of distinct elections, numbered from `first`.
+pub fn transactions(first: u64, count: u64) -> Vec<Transaction> {
+    (first..first + count)
+        .map(|x| Transaction::random(16, x.to_be_bytes().to_vec()))
+        .collect()
+}
+
+/// The key pairs of the authorities of a cluster of `n`. Secret keys cannot be copied: we derive
+/// them again whenever a node needs one.
+fn keys(n: usize) -> Vec<(PublicKey, SecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..n).map(|_| generate_keypair(&mut rng)).collect()
+}
+
+fn secret(i: usize) -> SecretKey {
+    keys(i + 1).pop().unwrap().1
+}
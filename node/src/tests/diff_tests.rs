@@ -0,0 +1,95 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// A decision file of `n` elections with ids spread like digests.
+fn decision_file(n: u64) -> String {
+    (0..n)
+        .map(|i| {
+            let election_id = format!("{:016x}", i.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            format!("{} {} TX{} {}\n", i, election_id, i, i / 4)
+        })
+        .collect()
+}
+
+#[test]
+fn parse() {
+    let decisions = parse_decisions("0 AAAA BBBB 3\n\n1 CCCC DDDD 4\n1 CCCC DDDD 4\n").unwrap();
+    assert_eq!(decisions.len(), 2);
+    assert_eq!(
+        decisions["CCCC"],
+        Decided {
+            tx_hash: "DDDD".to_string(),
+            round: 4
+        }
+    );
+}
+
+#[test]
+fn parse_malformed() {
+    assert!(parse_decisions("0 AAAA BBBB\n").is_err());
+    assert!(parse_decisions("0 AAAA BBBB three\n").is_err());
+    assert!(parse_decisions("0 AAAA BBBB 3\n1 AAAA CCCC 3\n").is_err());
+}
+
+#[test]
+fn identical() {
+    let mine = parse_decisions(&decision_file(1_000)).unwrap();
+    let theirs = mine.clone();
+    let outcome = diff(&mine, &theirs);
+    assert!(outcome.differences.is_empty());
+    assert_eq!(outcome.comparisons, 1);
+}
+
+#[test]
+fn classify_omission_and_conflict() {
+    let mine = parse_decisions(&decision_file(1_000)).unwrap();
+    let mut theirs = mine.clone();
+    let mut ids = mine.keys().cloned();
+    let omitted = ids.nth(10).unwrap();
+    let conflicting = ids.nth(500).unwrap();
+    theirs.remove(&omitted);
+    theirs.get_mut(&conflicting).unwrap().tx_hash = "OTHER".to_string();
+
+    let outcome = diff(&mine, &theirs);
+    assert_eq!(
+        outcome.differences,
+        vec![
+            Difference::MissingTheirs {
+                election_id: omitted.clone(),
+                mine: mine[&omitted].clone(),
+            },
+            Difference::Conflict {
+                election_id: conflicting.clone(),
+                mine: mine[&conflicting].clone(),
+                theirs: theirs[&conflicting].clone(),
+            },
+        ]
+    );
+    assert!(!outcome.differences[0].is_conflict());
+    assert!(outcome.differences[1].is_conflict());
+
+    // The identical ranges are skipped: far fewer comparisons than elections.
+    assert!(
+        outcome.comparisons < 100,
+        "{} comparisons",
+        outcome.comparisons
+    );
+}
+
+#[test]
+fn missing_mine() {
+    let theirs = parse_decisions(&decision_file(100)).unwrap();
+    let outcome = diff(&Decisions::new(), &theirs);
+    assert_eq!(outcome.differences.len(), 100);
+    assert!(outcome
+        .differences
+        .iter()
+        .all(|x| matches!(x, Difference::MissingMine { .. })));
+}
+
+#[test]
+fn different_rounds_agree() {
+    let mine = parse_decisions("0 AAAA BBBB 3\n").unwrap();
+    let theirs = parse_decisions("0 AAAA BBBB 4\n").unwrap();
+    assert!(diff(&mine, &theirs).differences.is_empty());
+}
@@ -0,0 +1,66 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn parse_distributions() {
+    assert_eq!(
+        "round-robin".parse::<Distribution>().unwrap(),
+        Distribution::RoundRobin
+    );
+    assert_eq!(
+        "random".parse::<Distribution>().unwrap(),
+        Distribution::Random
+    );
+    assert_eq!(
+        "single".parse::<Distribution>().unwrap(),
+        Distribution::Single
+    );
+    assert!("sticky".parse::<Distribution>().is_err());
+}
+
+#[test]
+fn round_robin() {
+    // Three targets and one other node: the targets take turns.
+    let mut distributor = Distributor::new(Distribution::RoundRobin, 3, 4);
+    let routes: Vec<_> = (0..7).map(|_| distributor.route(0)).collect();
+    assert_eq!(routes, vec![0, 1, 2, 0, 1, 2, 0]);
+}
+
+#[test]
+fn single() {
+    let mut distributor = Distributor::new(Distribution::Single, 3, 3);
+    assert!((0..10).all(|_| distributor.route(0) == 0));
+}
+
+#[test]
+fn random() {
+    // Every target gets some transactions, and only the targets do.
+    let mut distributor = Distributor::new(Distribution::Random, 3, 5);
+    let mut counts = [0; 5];
+    for _ in 0..1_000 {
+        counts[distributor.route(0)] += 1;
+    }
+    assert!(counts[..3].iter().all(|x| *x > 0));
+    assert_eq!(counts[3..], [0, 0]);
+}
+
+#[test]
+fn twins_follow_their_transaction() {
+    // A single target sends the twins to the other nodes, as with one connection per node.
+    let mut distributor = Distributor::new(Distribution::Single, 1, 3);
+    assert_eq!(
+        [0, 1, 2]
+            .iter()
+            .map(|x| distributor.route(*x))
+            .collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+
+    // Otherwise they go to the connections after the target of their transaction, wrapping around.
+    let mut distributor = Distributor::new(Distribution::RoundRobin, 2, 3);
+    let routes: Vec<_> = [0, 1, 0, 1, 2]
+        .iter()
+        .map(|x| distributor.route(*x))
+        .collect();
+    assert_eq!(routes, vec![0, 1, 1, 2, 0]);
+}
@@ -0,0 +1,72 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fs;
+
+// Fixture. A parameters file holding only some of the parameters.
+fn parameters_file(path: &str, json: &str) -> String {
+    fs::write(path, json).unwrap();
+    path.to_string()
+}
+
+fn overrides(assignments: &[&str]) -> Vec<(String, String)> {
+    assignments
+        .iter()
+        .map(|x| parse_override(x).unwrap())
+        .collect()
+}
+
+#[test]
+fn command_line_takes_precedence() {
+    let file = parameters_file(
+        ".test_parameters_precedence.json",
+        r#"{"header_size": 500, "max_header_delay": 200, "batch_size": 1000}"#,
+    );
+    let env = vec![
+        ("NARWHAL_HEADER_SIZE".to_string(), "600".to_string()),
+        ("NARWHAL_BATCH_SIZE".to_string(), "2000".to_string()),
+    ];
+    let overrides = overrides(&["header_size=700", "max_batch_delay=50", "header_size=800"]);
+    let parameters = load_parameters(Some(&file), env, &overrides).unwrap();
+
+    // The command line beats the environment, which beats the file, which beats the defaults.
+    assert_eq!(parameters.header_size, 800);
+    assert_eq!(parameters.batch_size, 2000);
+    assert_eq!(parameters.max_header_delay, 200);
+    assert_eq!(parameters.max_batch_delay, 50);
+    assert_eq!(parameters.gc_depth, Parameters::default().gc_depth);
+}
+
+#[test]
+fn defaults_without_file() {
+    let parameters = load_parameters(None, Vec::new(), &[]).unwrap();
+    assert_eq!(parameters.header_size, Parameters::default().header_size);
+    for key in PARAMETER_FLAGS {
+        assert!(
+            Parameters::default().set(key, "1").is_ok(),
+            "{} has no flag",
+            key
+        );
+    }
+    assert_eq!(flag("max_header_delay"), "max-header-delay");
+}
+
+#[test]
+fn reject_invalid_parameters() {
+    let error = |overrides: &[(String, String)]| {
+        format!(
+            "{:#}",
+            load_parameters(None, Vec::new(), overrides).err().unwrap()
+        )
+    };
+    assert!(error(&overrides(&["max_header_delay=0"]))
+        .contains("max_header_delay and max_batch_delay must be positive"));
+    assert!(error(&overrides(&["header_size=0"])).contains("header_size must be positive"));
+    assert!(error(&overrides(&["header_size=many"])).contains("Cannot parse 'many'"));
+    assert!(error(&overrides(&["no_such_parameter=1"])).contains("Unknown parameter"));
+    assert!(parse_override("header_size").is_err());
+
+    // The file is checked as well.
+    let file = parameters_file(".test_parameters_invalid.json", r#"{"batch_size": 0}"#);
+    assert!(load_parameters(Some(&file), Vec::new(), &[]).is_err());
+    assert!(load_parameters(Some(".test_parameters_missing.json"), Vec::new(), &[]).is_err());
+}
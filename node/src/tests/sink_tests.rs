@@ -0,0 +1,244 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crypto::Digest;
+use futures::stream::StreamExt as _;
+use primary::{Decision, NodeHandle, ShutdownStage};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
+use tokio_util::codec::FramedRead;
+
+// A sink recording the events it publishes. It fails the first `failures` publications, and
+// takes `delay` ms for each.
+#[derive(Clone, Default)]
+struct MockSink {
+    published: Arc<std::sync::Mutex<Vec<CommittedEvent>>>,
+    failures: Arc<AtomicUsize>,
+    attempts: Arc<AtomicUsize>,
+    delay: u64,
+}
+
+impl MockSink {
+    fn failing(failures: usize) -> Self {
+        let sink = Self::default();
+        sink.failures.store(failures, Ordering::SeqCst);
+        sink
+    }
+
+    fn published(&self) -> Vec<u64> {
+        self.published
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|x| x.id)
+            .collect()
+    }
+
+    async fn wait_for(&self, count: usize) {
+        while self.published.lock().unwrap().len() < count {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl DecisionSink for MockSink {
+    async fn publish(&self, event: CommittedEvent) -> Result<()> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(self.delay)).await;
+        let remaining = self.failures.load(Ordering::SeqCst);
+        if remaining > 0 {
+            self.failures.store(remaining - 1, Ordering::SeqCst);
+            anyhow::bail!("Injected failure");
+        }
+        self.published.lock().unwrap().push(event);
+        Ok(())
+    }
+}
+
+fn decision(seed: u8) -> Decision {
+    (Digest([seed; 32]), Digest([1; 32]), 1)
+}
+
+fn decided(seed: u8) -> ElectionOutcome {
+    ElectionOutcome::Decided(decision(seed))
+}
+
+#[tokio::test]
+async fn publish_in_order() {
+    let sink = MockSink::default();
+    let (tx_decision, rx_decision) = channel(10);
+    Publisher::spawn(
+        sink.clone(),
+        rx_decision,
+        /* batch_size */ 2,
+        /* retry_delay */ 10,
+        /* store */ None,
+    );
+
+    // Failed elections decided nothing: they are not published.
+    for seed in 0..5 {
+        tx_decision.send(decided(seed)).await.unwrap();
+        let failed = ElectionOutcome::Failed(Digest([100 + seed; 32]));
+        tx_decision.send(failed).await.unwrap();
+    }
+    sink.wait_for(5).await;
+    assert_eq!(sink.published(), vec![0, 1, 2, 3, 4]);
+    let events = sink.published.lock().unwrap().clone();
+    assert_eq!(events[3].election_id, Digest([3; 32]));
+}
+
+#[tokio::test]
+async fn retry_failed_publications() {
+    let sink = MockSink::failing(3);
+    let (tx_decision, rx_decision) = channel(10);
+    Publisher::spawn(
+        sink.clone(),
+        rx_decision,
+        /* batch_size */ 10,
+        /* retry_delay */ 10,
+        /* store */ None,
+    );
+
+    for seed in 0..3 {
+        tx_decision.send(decided(seed)).await.unwrap();
+    }
+    sink.wait_for(3).await;
+
+    // The failed event is retried before the next ones, which keeps them in order.
+    assert_eq!(sink.published(), vec![0, 1, 2]);
+    assert_eq!(sink.attempts.load(Ordering::SeqCst), 6);
+}
+
+#[tokio::test]
+async fn spill_while_sink_fails() {
+    let sink = MockSink::failing(5);
+    let (tx_decision, rx_decision) = channel(1);
+    Publisher::spawn(
+        sink.clone(),
+        rx_decision,
+        /* batch_size */ 10,
+        /* retry_delay */ 50,
+        /* store */ None,
+    );
+
+    // The primary never waits on the failing sink, even through a small channel.
+    let send = async {
+        for seed in 0..100 {
+            tx_decision.send(decided(seed)).await.unwrap();
+        }
+    };
+    assert!(timeout(Duration::from_millis(200), send).await.is_ok());
+    assert!(sink.published().is_empty());
+
+    sink.wait_for(100).await;
+    assert_eq!(sink.published(), (0..100).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn append_to_file() {
+    let path = ".test_decisions";
+    let _ = fs::remove_file(path);
+    let sink = FileSink::new(path).await.unwrap();
+    for seed in 0..2 {
+        let (election_id, tx_hash, round) = decision(seed);
+        let event = CommittedEvent {
+            id: seed as u64,
+            election_id,
+            tx_hash,
+            round,
+        };
+        sink.publish(event).await.unwrap();
+    }
+
+    let lines: Vec<_> = fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|x| x.split(' ').next().unwrap().to_string())
+        .collect();
+    assert_eq!(lines, vec!["0", "1"]);
+}
+
+#[tokio::test]
+async fn resume_at_cut_point() {
+    let path = ".db_test_resume_at_cut_point";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let sink = MockSink {
+        delay: 2,
+        ..MockSink::default()
+    };
+
+    // Deliver a backlog far too large for the sink to keep up with.
+    let (tx_decision, rx_decision) = channel(200);
+    let (join, tx_stop) = Publisher::spawn(
+        sink.clone(),
+        rx_decision,
+        /* batch_size */ 10,
+        /* retry_delay */ 10,
+        Some(store.clone()),
+    );
+    for seed in 0..200 {
+        tx_decision.send(decided(seed)).await.unwrap();
+    }
+
+    // Consensus stops, and the publisher gets little time to drain its backlog.
+    drop(tx_decision);
+    let mut node = NodeHandle::default();
+    node.push_drain(join, tx_stop);
+    node.push_store(store.clone());
+    node.set_timeout(ShutdownStage::Drain, Duration::from_millis(100));
+    assert!(node.shutdown().await.is_ok());
+    let published = sink.published().len();
+    assert!(published > 0 && published < 200);
+
+    // The next boot publishes the rest of the backlog, then the new decisions.
+    let (tx_decision, rx_decision) = channel(1);
+    Publisher::spawn(
+        sink.clone(),
+        rx_decision,
+        /* batch_size */ 10,
+        /* retry_delay */ 10,
+        Some(store),
+    );
+    tx_decision.send(decided(200)).await.unwrap();
+    sink.wait_for(201).await;
+    assert_eq!(sink.published(), (0..201).collect::<Vec<_>>());
+    let events = sink.published.lock().unwrap().clone();
+    assert_eq!(events[200].election_id, Digest([200; 32]));
+}
+
+#[tokio::test]
+async fn stream_to_subscribers() {
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let sink = TcpSink::new(address).await.unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut subscriber = FramedRead::new(stream, LengthDelimitedCodec::new());
+    while sink.subscribers.lock().await.is_empty() {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    let (tx_decision, rx_decision) = channel(10);
+    Publisher::spawn(
+        sink,
+        rx_decision,
+        /* batch_size */ 10,
+        /* retry_delay */ 10,
+        /* store */ None,
+    );
+    for seed in 0..3 {
+        tx_decision.send(decided(seed)).await.unwrap();
+    }
+
+    // The subscriber gets every event, in order.
+    for seed in 0..3 {
+        let frame = subscriber.next().await.unwrap().unwrap();
+        let event: CommittedEvent = decode(&frame).unwrap();
+        assert_eq!((event.id, event.election_id), (seed as u64, Digest([seed; 32])));
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn election(i: u64) -> Digest {
+    let mut id = [0; 32];
+    id[..8].copy_from_slice(&i.to_be_bytes());
+    Digest(id)
+}
+
+#[test]
+fn sample_every_kth_transaction() {
+    let start = Instant::now();
+    let mut stats = Stats::new(3);
+    for i in 0..9 {
+        stats.sent(election(i), start);
+    }
+    assert_eq!(stats.pending(), 3);
+
+    // Only the decisions of sampled transactions give a latency.
+    for i in 0..9 {
+        stats.decided(&election(i), start + Duration::from_millis(10));
+    }
+    let report = stats.report(Duration::from_secs(1));
+    assert_eq!((report.sent, report.decided), (9, 9));
+    assert_eq!((report.samples, report.undecided), (3, 0));
+    assert_eq!(report.p50, Some(Duration::from_millis(10)));
+}
+
+#[test]
+fn latency_percentiles() {
+    let start = Instant::now();
+    let mut stats = Stats::new(1);
+    for i in 0..100 {
+        stats.sent(election(i), start);
+    }
+
+    // Decided in reverse order, each a millisecond later than the previous one.
+    for i in (0..100).rev() {
+        stats.decided(&election(i), start + Duration::from_millis(100 - i));
+    }
+    let report = stats.report(Duration::from_secs(4));
+    assert_eq!(report.p50, Some(Duration::from_millis(50)));
+    assert_eq!(report.p95, Some(Duration::from_millis(95)));
+    assert_eq!(report.p99, Some(Duration::from_millis(99)));
+    assert!((report.throughput - 25.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn report_undecided_samples() {
+    let start = Instant::now();
+    let mut stats = Stats::new(1);
+
+    // A conflicting twin shares the election of the first transaction: it keeps its due time.
+    stats.sent(election(0), start);
+    stats.sent(election(0), start + Duration::from_millis(5));
+    stats.sent(election(1), start);
+
+    // Decisions of elections we did not sample (or sent to another node) are counted only.
+    stats.decided(&election(0), start + Duration::from_millis(20));
+    stats.decided(&election(0), start + Duration::from_millis(30));
+    stats.decided(&election(7), start + Duration::from_millis(30));
+    let report = stats.report(Duration::from_secs(1));
+    assert_eq!(
+        (report.decided, report.samples, report.undecided),
+        (3, 1, 1)
+    );
+    assert_eq!(report.p99, Some(Duration::from_millis(20)));
+
+    // Without samples there is no latency.
+    let report = Stats::new(1).report(Duration::ZERO);
+    assert_eq!((report.p50, report.throughput), (None, 0.0));
+}
@@ -0,0 +1,120 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::ProposalPolicy;
+
+/// How long a node may go without deciding anything before a test gives up on it.
+const PATIENCE: Duration = Duration::from_secs(20);
+
+// Submit the transactions to the worker of a node, and return the tx hash of each election.
+async fn submit(cluster: &Cluster, i: usize, txs: &[Transaction]) -> HashMap<Digest, Digest> {
+    let mut client = cluster.client(i).await;
+    for tx in txs {
+        client.submit(tx).await;
+    }
+    txs.iter().map(|x| (x.election_id(), x.digest())).collect()
+}
+
+// Check that the nodes all decided every transaction as submitted.
+async fn check_decided(cluster: &mut Cluster, nodes: &[usize], expected: &HashMap<Digest, Digest>) {
+    let elections: HashSet<_> = expected.keys().cloned().collect();
+    for i in nodes {
+        let decided = cluster
+            .decide(*i, &elections, PATIENCE)
+            .await
+            .unwrap_or_else(|| panic!("Node {} stopped deciding", i));
+        for (election_id, tx_hash) in expected {
+            assert_eq!(decided.get(election_id), Some(tx_hash));
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commit_transactions() {
+    let mut cluster = Cluster::spawn(4, Vec::new()).await;
+    let expected = submit(&cluster, 0, &transactions(0, 1_000)).await;
+    check_decided(&mut cluster, &[0, 1, 2, 3], &expected).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commit_with_crashed_node() {
+    let mut cluster = Cluster::spawn(4, Vec::new()).await;
+    cluster.kill(3).await;
+    assert!(!cluster.is_up(3));
+    let expected = submit(&cluster, 0, &transactions(0, 100)).await;
+    check_decided(&mut cluster, &[0, 1, 2], &expected).await;
+
+    // Once back, the node decides the new transactions along with the others.
+    cluster.restart(3).await;
+    let expected = submit(&cluster, 1, &transactions(100, 100)).await;
+    check_decided(&mut cluster, &[0, 1, 2, 3], &expected).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commit_with_silent_node() {
+    let mut cluster = Cluster::spawn(4, vec![3]).await;
+    assert!(cluster.committee.authorities[&cluster.name(3)].byzantine);
+    let expected = submit(&cluster, 0, &transactions(0, 100)).await;
+    check_decided(&mut cluster, &[0, 1, 2], &expected).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commit_under_leader_policy() {
+    let parameters = Parameters {
+        proposal_policy: ProposalPolicy::Leader,
+        ..parameters()
+    };
+    let mut cluster = Cluster::with_parameters(4, Vec::new(), parameters).await;
+
+    // The node does not lead the first round: its primary forwards the transactions to the leaders.
+    let leader = cluster.committee.leader(1);
+    let i = (0..4).find(|i| cluster.name(*i) != leader).unwrap();
+    let expected = submit(&cluster, i, &transactions(0, 100)).await;
+    check_decided(&mut cluster, &[0, 1, 2, 3], &expected).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repropose_batch_of_crashed_primary() {
+    // Batches, unlike inline transactions, reach the workers of the others before any vote.
+    let parameters = Parameters {
+        inline_size: 0,
+        reproposal_delay: 500,
+        ..parameters()
+    };
+    let mut cluster = Cluster::with_parameters(4, Vec::new(), parameters).await;
+
+    // The primary of node 0 is down: no header votes for the batch of its worker.
+    cluster.kill_primary(0).await;
+    let transaction = transactions(0, 1).pop().unwrap();
+    let mut client = cluster.client(0).await;
+    client.submit(&transaction).await;
+
+    // The others propose the batch themselves, and all decide it alike.
+    let election: HashSet<_> = vec![transaction.election_id()].into_iter().collect();
+    let mut tx_hashes = HashSet::new();
+    for i in 1..4 {
+        let decided = cluster
+            .decide(i, &election, PATIENCE)
+            .await
+            .unwrap_or_else(|| panic!("Node {} stopped deciding", i));
+        tx_hashes.insert(decided[&transaction.election_id()].clone());
+    }
+    assert_eq!(tx_hashes.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commit_across_reconfiguration() {
+    let mut cluster = Cluster::spawn(4, Vec::new()).await;
+    let expected = submit(&cluster, 0, &transactions(0, 100)).await;
+    check_decided(&mut cluster, &[0, 1, 2, 3], &expected).await;
+
+    // Node 3 leaves the committee to a new node 4.
+    let joined = cluster.reconfigure(&[3], 1).await;
+    assert_eq!(joined, vec![4]);
+    assert_eq!(cluster.committee.epoch, 1);
+    assert!(!cluster.committee.authorities.contains_key(&cluster.name(3)));
+
+    // Node 3 is out: without node 2, the quorum of the new committee needs node 4.
+    cluster.kill(2).await;
+    let expected = submit(&cluster, 0, &transactions(100, 100)).await;
+    check_decided(&mut cluster, &[0, 1, 4], &expected).await;
+}
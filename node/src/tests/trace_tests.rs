@@ -0,0 +1,89 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use primary::Hash as _;
+
+fn drain(source: &mut dyn TxSource) -> Vec<Outgoing> {
+    let mut outgoing = Vec::new();
+    while let Some(x) = source.next().unwrap() {
+        outgoing.push(x);
+    }
+    outgoing
+}
+
+fn record(outgoing: &[Outgoing], header: &TraceHeader) -> Vec<u8> {
+    let mut trace = TraceWriter::new(Vec::new(), header).unwrap();
+    for x in outgoing {
+        trace.record(x).unwrap();
+    }
+    trace.writer
+}
+
+#[test]
+fn generate_bursts() {
+    // Each burst holds one sample transaction; conflicting twins follow their transaction.
+    let mut random = Random::new(16, 3, /* rate */ 100, /* conflict_rate */ 0.5, 2);
+    let outgoing = drain(&mut random);
+    let originals: Vec<_> = outgoing.iter().filter(|x| x.to == 0).collect();
+    assert_eq!(originals.len() as u64, BURSTS * burst_size(100));
+    for (burst, x) in originals.chunks(burst_size(100) as usize).enumerate() {
+        let due = Duration::from_millis(BURST_DURATION * burst as u64);
+        assert!(x.iter().all(|x| x.due == due && x.transaction.fee == 3));
+        let samples = x.iter().filter(|x| x.transaction.id[0] == 0).count();
+        assert_eq!(samples, 1);
+    }
+    for pair in outgoing.windows(2).filter(|x| x[1].to > 0) {
+        assert!(pair[1].to <= 2);
+        assert_eq!(pair[0].transaction.id, pair[1].transaction.id);
+        assert_ne!(pair[0].transaction.digest(), pair[1].transaction.digest());
+    }
+}
+
+#[test]
+fn replay_recorded_trace() {
+    let mut random = Random::new(16, 0, /* rate */ 100, /* conflict_rate */ 0.5, 1);
+    let outgoing = drain(&mut random);
+    let header = TraceHeader {
+        rate: 100,
+        size: 16,
+        rivals: 1,
+    };
+    let bytes = record(&outgoing, &header);
+
+    // The replay sends the same transactions, to the same nodes, on the same schedule.
+    let mut trace = Trace::new(bytes.as_slice()).unwrap();
+    assert_eq!(trace.header, header);
+    let replayed = drain(&mut trace);
+    assert_eq!(replayed.len(), outgoing.len());
+    for (a, b) in outgoing.iter().zip(&replayed) {
+        assert_eq!((a.due, a.to), (b.due, b.to));
+        assert_eq!(a.transaction.digest(), b.transaction.digest());
+    }
+}
+
+#[test]
+fn reject_malformed_trace() {
+    assert!(Trace::new(&[][..]).is_err());
+
+    let header = TraceHeader {
+        rate: 100,
+        size: 16,
+        rivals: 0,
+    };
+    let outgoing = drain(&mut Random::new(16, 0, 100, 0.0, 0));
+    let bytes = record(&outgoing[..1], &header);
+
+    // A trace cut in the middle of a record, or of its length.
+    let start = record(&[], &header).len();
+    for cut in [bytes.len() - 1, start + 2] {
+        let mut trace = Trace::new(&bytes[..cut]).unwrap();
+        assert!(trace.next().is_err());
+    }
+
+    // A trace sending to more nodes than it declares.
+    let twin = Outgoing {
+        to: 1,
+        ..drain(&mut Random::new(16, 0, 100, 0.0, 0)).remove(0)
+    };
+    let bytes = record(&[twin], &header);
+    assert!(Trace::new(bytes.as_slice()).unwrap().next().is_err());
+}
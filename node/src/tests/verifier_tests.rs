@@ -0,0 +1,120 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// The outputs of a clean run: two nodes decide the same two elections.
+fn clean_run() -> Verifier {
+    let mut verifier = Verifier::default();
+    verifier.add_decisions("node-0", "0 AAAA BBBB 3\n1 CCCC DDDD 4\n");
+    verifier.add_decisions("node-1", "0 CCCC DDDD 4\n1 AAAA BBBB 3\n1 AAAA BBBB 3\n");
+    verifier.add_client_log(concat!(
+        "[2026-01-01T00:00:00.000Z INFO  benchmark_client] Start sending transactions\n",
+        "[2026-01-01T00:00:00.010Z INFO  benchmark_client] Sending sample transaction 0 due 2 ms ago\n",
+        "[2026-01-01T00:00:00.060Z INFO  benchmark_client] Sending sample transaction 1 due 0 ms ago\n",
+    ));
+    verifier.add_worker_log(concat!(
+        "[2026-01-01T00:00:00.020Z INFO  worker::batch_maker] Batch BBBB= contains sample tx 0\n",
+        "[2026-01-01T00:00:00.070Z INFO  worker::batch_maker] Batch DDDD= contains sample tx 1\n",
+    ));
+    verifier.add_primary_log(
+        "primary-0",
+        concat!(
+            "[2026-01-01T00:00:00.100Z INFO  primary::core] Committed B3(BBBB) -> BBBB=\n",
+            "[2026-01-01T00:00:00.100Z INFO  primary::core] Decided election AAAA= -> BBBB=\n",
+            "[2026-01-01T00:00:00.150Z INFO  primary::core] Committed B4(DDDD) -> DDDD=\n",
+            "[2026-01-01T00:00:00.150Z INFO  primary::core] Decided election CCCC= -> DDDD=\n",
+        ),
+    );
+    verifier
+}
+
+#[test]
+fn accept_clean_run() {
+    assert_eq!(clean_run().findings(), Vec::new());
+}
+
+#[test]
+fn detect_disagreement() {
+    let mut verifier = clean_run();
+    verifier.add_decisions("node-2", "0 AAAA EEEE 3\n");
+    let expected = Finding::Disagreement {
+        election_id: "AAAA".to_string(),
+        decisions: vec![
+            (
+                "BBBB".to_string(),
+                ["node-0", "node-1"].iter().map(|x| x.to_string()).collect(),
+            ),
+            (
+                "EEEE".to_string(),
+                ["node-2"].iter().map(|x| x.to_string()).collect(),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    assert_eq!(verifier.findings(), vec![expected]);
+}
+
+#[test]
+fn detect_disagreement_in_logs() {
+    let mut verifier = clean_run();
+    verifier.add_primary_log(
+        "primary-1",
+        "[2026-01-01T00:00:00.160Z INFO  primary::core] Decided election CCCC= -> EEEE=\n",
+    );
+    match verifier.findings().as_slice() {
+        [Finding::Disagreement { election_id, .. }] => assert_eq!(election_id, "CCCC="),
+        x => panic!("Unexpected findings {:?}", x),
+    }
+}
+
+#[test]
+fn detect_equivocation() {
+    let mut verifier = clean_run();
+    verifier.add_primary_log(
+        "primary-0",
+        "[2026-01-01T00:00:00.120Z WARN  primary::core] Equivocation by KKKK in round 3 of election AAAA: BBBB and EEEE\n",
+    );
+    let expected = Finding::Equivocation {
+        election_id: "AAAA".to_string(),
+        author: "KKKK".to_string(),
+        round: 3,
+        tx_hashes: ["BBBB", "EEEE"].iter().map(|x| x.to_string()).collect(),
+    };
+    assert_eq!(verifier.findings(), vec![expected]);
+}
+
+#[test]
+fn detect_unknown_samples() {
+    let mut verifier = clean_run();
+    verifier.add_worker_log(
+        "[2026-01-01T00:00:00.080Z INFO  worker::batch_maker] Batch DDDD= contains sample tx 7\n",
+    );
+    let expected = Finding::UnknownSample {
+        batch: "DDDD=".to_string(),
+        sample: 7,
+    };
+    assert_eq!(verifier.findings(), vec![expected]);
+}
+
+#[test]
+fn detect_malformed_decisions() {
+    let mut verifier = clean_run();
+    verifier.add_decisions("node-2", "0 AAAA BBBB 3\n1 CCCC\n");
+    let expected = Finding::Malformed {
+        file: "node-2".to_string(),
+        line: 2,
+    };
+    assert_eq!(verifier.findings(), vec![expected]);
+}
+
+#[test]
+fn report_findings_as_json() {
+    let finding = Finding::UnknownSample {
+        batch: "DDDD=".to_string(),
+        sample: 7,
+    };
+    assert_eq!(
+        serde_json::to_string(&finding).unwrap(),
+        r#"{"kind":"unknown_sample","batch":"DDDD=","sample":7}"#
+    );
+}
@@ -0,0 +1,260 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The workloads of the benchmark client. A workload is either generated on the fly (`Random`) or
+//! replayed from a trace recorded by an earlier run (`Trace`), so that two runs can send the exact
+//! same transactions on the exact same schedule.
+//!
+//! A trace is a sequence of length-prefixed (4 bytes, big endian) bincode records: a `TraceHeader`
+//! followed by one `TraceEntry` per transaction, in the order they were sent.
+use anyhow::{bail, Context, Result};
+use bench_support::{
+    burst_size, conflict_tx_id, random_payload, sample_tx_id, standard_tx_id, BURST_DURATION,
+};
+use network::{decode, encode};
+use primary::Transaction;
+use rand::Rng as _;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/trace_tests.rs"]
+pub mod trace_tests;
+
+/// The number of bursts of a generated workload.
+const BURSTS: u64 = 10;
+
+/// A transaction to send.
+pub struct Outgoing {
+    /// When the transaction is due, from the start of the workload.
+    pub due: Duration,
+    /// The node it goes to: 0 for the node under benchmark, `i` for the i-th other node (which
+    /// receive the conflicting twins).
+    pub to: usize,
+    pub transaction: Transaction,
+}
+
+/// A workload.
+pub trait TxSource {
+    /// The rate (tx/s) the due times of the workload assume.
+    fn rate(&self) -> u64;
+
+    /// The size of the transactions (in bytes).
+    fn size(&self) -> usize;
+
+    /// The number of other nodes the workload sends to.
+    fn rivals(&self) -> usize;
+
+    /// The next transaction to send, or `None` once the workload is over.
+    fn next(&mut self) -> Result<Option<Outgoing>>;
+}
+
+/// A generated workload: bursts of standard transactions each holding one sample transaction,
+/// some of them sent along with a conflicting twin to another node.
+pub struct Random {
+    size: usize,
+    fee: u64,
+    rate: u64,
+    /// The probability that a standard transaction is sent along with a conflicting twin.
+    conflict_rate: f64,
+    rivals: usize,
+    /// The payload shared by all transactions (but the twins).
+    data: Vec<u8>,
+    /// The current burst, and the position in it of the next transaction.
+    burst: u64,
+    position: u64,
+    standard: u64,
+    conflicts: u64,
+    /// The twin of the last transaction, sent right after it.
+    twin: Option<Outgoing>,
+}
+
+impl Random {
+    pub fn new(size: usize, fee: u64, rate: u64, conflict_rate: f64, rivals: usize) -> Self {
+        Self {
+            size,
+            fee,
+            rate,
+            conflict_rate,
+            rivals,
+            data: random_payload(size),
+            burst: 0,
+            position: 0,
+            standard: 0,
+            conflicts: 0,
+            twin: None,
+        }
+    }
+}
+
+impl TxSource for Random {
+    fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn rivals(&self) -> usize {
+        self.rivals
+    }
+
+    fn next(&mut self) -> Result<Option<Outgoing>> {
+        if let Some(twin) = self.twin.take() {
+            return Ok(Some(twin));
+        }
+        let burst = burst_size(self.rate);
+        if burst == 0 || self.burst == BURSTS {
+            return Ok(None);
+        }
+        let due = Duration::from_millis(BURST_DURATION * self.burst);
+
+        let mut transaction = Transaction::new();
+        transaction.data = self.data.clone();
+        transaction.fee = self.fee;
+        transaction.id = if self.position == self.burst % burst {
+            sample_tx_id(self.burst) // This counter identifies the tx.
+        } else if self.rivals > 0 && rand::thread_rng().gen_bool(self.conflict_rate) {
+            // Spend the same id with different outputs through another node.
+            self.conflicts += 1;
+            let id = conflict_tx_id(self.conflicts);
+            let mut twin = Transaction::random(self.size, id.clone());
+            twin.fee = self.fee;
+            let to = 1 + self.conflicts as usize % self.rivals;
+            self.twin = Some(Outgoing {
+                due,
+                to,
+                transaction: twin,
+            });
+            id
+        } else {
+            self.standard += 1;
+            standard_tx_id(self.standard) // Ensures all clients send different txs.
+        };
+
+        self.position += 1;
+        if self.position == burst {
+            self.position = 0;
+            self.burst += 1;
+        }
+        Ok(Some(Outgoing {
+            due,
+            to: 0,
+            transaction,
+        }))
+    }
+}
+
+/// The first record of a trace: the workload it was recorded from.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TraceHeader {
+    pub rate: u64,
+    pub size: usize,
+    pub rivals: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TraceEntry {
+    /// When the transaction was due (in µs). We record the schedule rather than the actual send
+    /// times, so that a client falling behind does not carry its delays over to the replays.
+    due: u64,
+    to: usize,
+    transaction: Transaction,
+}
+
+/// Records the transactions sent to a trace.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut writer: W, header: &TraceHeader) -> Result<Self> {
+        write_record(&mut writer, header)?;
+        Ok(Self { writer })
+    }
+
+    pub fn record(&mut self, outgoing: &Outgoing) -> Result<()> {
+        let entry = TraceEntry {
+            due: outgoing.due.as_micros() as u64,
+            to: outgoing.to,
+            transaction: outgoing.transaction.clone(),
+        };
+        write_record(&mut self.writer, &entry)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Failed to write the trace")
+    }
+}
+
+/// A workload replayed from a trace.
+pub struct Trace<R: Read> {
+    reader: R,
+    header: TraceHeader,
+}
+
+impl<R: Read> Trace<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let header = read_record(&mut reader)?.context("The trace is empty")?;
+        Ok(Self { reader, header })
+    }
+}
+
+impl<R: Read> TxSource for Trace<R> {
+    fn rate(&self) -> u64 {
+        self.header.rate
+    }
+
+    fn size(&self) -> usize {
+        self.header.size
+    }
+
+    fn rivals(&self) -> usize {
+        self.header.rivals
+    }
+
+    fn next(&mut self) -> Result<Option<Outgoing>> {
+        let entry: TraceEntry = match read_record(&mut self.reader)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if entry.to > self.header.rivals {
+            bail!("The trace sends to more nodes than its header says");
+        }
+        Ok(Some(Outgoing {
+            due: Duration::from_micros(entry.due),
+            to: entry.to,
+            transaction: entry.transaction,
+        }))
+    }
+}
+
+fn write_record<W: Write, T: Serialize>(writer: &mut W, record: &T) -> Result<()> {
+    let bytes = encode(record).context("Failed to serialize a trace record")?;
+    let length: u32 = bytes.len().try_into().context("Trace record too large")?;
+    writer
+        .write_all(&length.to_be_bytes())
+        .and_then(|()| writer.write_all(&bytes))
+        .context("Failed to write the trace")
+}
+
+/// Read the next record, or `None` at the end of the trace.
+fn read_record<R: Read, T: for<'a> Deserialize<'a>>(reader: &mut R) -> Result<Option<T>> {
+    let mut length = [0u8; 4];
+    let mut read = 0;
+    while read < length.len() {
+        match reader.read(&mut length[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => bail!("The trace ends in the middle of a record"),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e).context("Failed to read the trace"),
+        }
+    }
+    let mut bytes = vec![0u8; u32::from_be_bytes(length) as usize];
+    reader
+        .read_exact(&mut bytes)
+        .context("The trace ends in the middle of a record")?;
+    decode(&bytes).map(Some).context("Malformed trace record")
+}
@@ -0,0 +1,191 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! Cross-checks the decision files and logs of all the nodes of a run for violations of the
+//! protocol invariants. The log entries parsed here are those of `bench_support` (and the
+//! equivocation warning of the core): any change to their formats must be mirrored here.
+use primary::Round;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+#[cfg(test)]
+#[path = "tests/verifier_tests.rs"]
+pub mod verifier_tests;
+
+/// A violation of a protocol invariant, with the ids of the offending objects.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Finding {
+    /// An election was decided for different tx hashes, by different nodes or by the same one.
+    Disagreement {
+        election_id: String,
+        /// The nodes that decided each tx hash.
+        decisions: BTreeMap<String, BTreeSet<String>>,
+    },
+    /// An authority voted for different tx hashes in the same round of an election.
+    Equivocation {
+        election_id: String,
+        author: String,
+        round: Round,
+        tx_hashes: BTreeSet<String>,
+    },
+    /// A committed batch contains a sample transaction that no client sent.
+    UnknownSample { batch: String, sample: u64 },
+    /// A line of a decision file that is not a decision.
+    Malformed { file: String, line: usize },
+}
+
+/// Accumulates the outputs of the nodes of a run and reports the invariants they violate.
+#[derive(Default)]
+pub struct Verifier {
+    /// The tx hashes decided for each election, with the nodes that decided them.
+    decisions: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+    /// The tx hashes claimed by each (election, author, round).
+    claims: BTreeMap<(String, String, Round), BTreeSet<String>>,
+    /// The sample transactions sent by the clients.
+    sent: HashSet<u64>,
+    /// The sample transactions of each batch sealed by the workers.
+    samples: BTreeMap<String, BTreeSet<u64>>,
+    /// The batches committed by the primaries.
+    committed: BTreeSet<String>,
+    /// The lines we could not parse.
+    malformed: Vec<Finding>,
+}
+
+impl Verifier {
+    /// Add the decision file of a node: one `id election_id tx_hash round` line per decision.
+    pub fn add_decisions(&mut self, node: &str, contents: &str) {
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let parsed = match fields.as_slice() {
+                [id, election_id, tx_hash, round] => id
+                    .parse::<u64>()
+                    .and(round.parse::<Round>())
+                    .map(|_| (election_id.to_string(), tx_hash.to_string()))
+                    .ok(),
+                _ => None,
+            };
+            match parsed {
+                Some((election_id, tx_hash)) => self.decide(node, &election_id, &tx_hash),
+                None => self.malformed.push(Finding::Malformed {
+                    file: node.to_string(),
+                    line: i + 1,
+                }),
+            }
+        }
+    }
+
+    /// Add the log of a primary: its decisions, its committed batches and the equivocations it
+    /// saw.
+    pub fn add_primary_log(&mut self, node: &str, log: &str) {
+        for line in log.lines() {
+            if let Some((_, rest)) = line.split_once("Decided election ") {
+                if let Some((election_id, batch)) = rest.split_once(" -> ") {
+                    self.decide(node, election_id, batch.trim());
+                }
+            }
+            if let Some((_, rest)) = line.split_once("Committed ") {
+                if let Some((_, batch)) = rest.rsplit_once(" -> ") {
+                    self.committed.insert(batch.trim().to_string());
+                }
+            }
+            if let Some((_, rest)) = line.split_once("Equivocation by ") {
+                if let Some((key, tx_hashes)) = parse_equivocation(rest) {
+                    self.claims.entry(key).or_default().extend(tx_hashes);
+                }
+            }
+        }
+    }
+
+    fn decide(&mut self, node: &str, election_id: &str, tx_hash: &str) {
+        self.decisions
+            .entry(election_id.to_string())
+            .or_default()
+            .entry(tx_hash.to_string())
+            .or_default()
+            .insert(node.to_string());
+    }
+
+    /// Add the log of a worker: the sample transactions of its batches.
+    pub fn add_worker_log(&mut self, log: &str) {
+        for line in log.lines() {
+            if let Some((_, rest)) = line.split_once("Batch ") {
+                if let Some((batch, sample)) = rest.split_once(" contains sample tx ") {
+                    if let Ok(sample) = sample.trim().parse::<u64>() {
+                        self.samples
+                            .entry(batch.to_string())
+                            .or_default()
+                            .insert(sample);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add the log of a client: the sample transactions it sent.
+    pub fn add_client_log(&mut self, log: &str) {
+        for line in log.lines() {
+            if let Some((_, rest)) = line.split_once("Sending sample transaction ") {
+                if let Some(Ok(sample)) = rest.split_whitespace().next().map(str::parse::<u64>) {
+                    self.sent.insert(sample);
+                }
+            }
+        }
+    }
+
+    /// The violations of the invariants, by class.
+    pub fn findings(&self) -> Vec<Finding> {
+        let mut findings = self.malformed.clone();
+
+        // Every election is decided for at most one tx hash across all nodes.
+        for (election_id, decisions) in &self.decisions {
+            if decisions.len() > 1 {
+                findings.push(Finding::Disagreement {
+                    election_id: election_id.clone(),
+                    decisions: decisions.clone(),
+                });
+            }
+        }
+
+        // No authority votes for two tx hashes in the same round of an election.
+        for ((election_id, author, round), tx_hashes) in &self.claims {
+            if tx_hashes.len() > 1 {
+                findings.push(Finding::Equivocation {
+                    election_id: election_id.clone(),
+                    author: author.clone(),
+                    round: *round,
+                    tx_hashes: tx_hashes.clone(),
+                });
+            }
+        }
+
+        // Every committed sample transaction was sent by a client.
+        for batch in &self.committed {
+            for sample in self.samples.get(batch).into_iter().flatten() {
+                if !self.sent.contains(sample) {
+                    findings.push(Finding::UnknownSample {
+                        batch: batch.clone(),
+                        sample: *sample,
+                    });
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// Parse an equivocation warning: `A in round R of election E: T and T'`.
+#[allow(clippy::type_complexity)]
+fn parse_equivocation(entry: &str) -> Option<((String, String, Round), Vec<String>)> {
+    let (author, rest) = entry.split_once(" in round ")?;
+    let (round, rest) = rest.split_once(" of election ")?;
+    let (election_id, tx_hashes) = rest.split_once(": ")?;
+    let (first, second) = tx_hashes.split_once(" and ")?;
+    let key = (
+        election_id.to_string(),
+        author.to_string(),
+        round.parse().ok()?,
+    );
+    Some((key, vec![first.to_string(), second.trim().to_string()]))
+}
@@ -0,0 +1,182 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::core::TxHash;
+use crate::election::ElectionId;
+use crate::messages::BatchCommit;
+use crypto::Digest;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+#[cfg(test)]
+#[path = "tests/batching_tests.rs"]
+pub mod batching_tests;
+
+/// A header whose round-0 votes we hold our commits to.
+struct Pending {
+    /// The tx hash voted for in each election we hold.
+    members: BTreeMap<ElectionId, TxHash>,
+    /// The members that fell back to committing on their own.
+    excluded: BTreeSet<ElectionId>,
+    /// The members neither ready (see `ready`) nor excluded yet. The header is done once empty.
+    waiting: BTreeSet<ElectionId>,
+}
+
+/// The headers whose votes we commit to at once with a `BatchCommit`. Each election is held for
+/// at most one header (the first we saw voting for it) until it holds a quorum of votes for the
+/// tx hash of that header; every other member then gets our commit through the batch commit, and
+/// the members that fell back (see `exclude`) get an individual one. Only the `capacity` most
+/// recently held headers are kept: the members of an evicted header fall back.
+///
+/// The caller reports each election as it gets ready, so that the work per vote does not grow
+/// with the size (or the number) of the held headers.
+pub struct PendingBatches {
+    capacity: usize,
+    batches: HashMap<Digest, Pending>,
+    /// The held headers, oldest first.
+    order: VecDeque<Digest>,
+    /// The header holding each election.
+    holders: HashMap<ElectionId, Digest>,
+    /// The held headers with no member waiting anymore, in the order they got done.
+    done: Vec<Digest>,
+    /// The number of batch commits we made.
+    pub committed: u64,
+    /// The number of commits they stand for.
+    pub covered: u64,
+    /// The number of elections that fell back.
+    pub fallbacks: u64,
+}
+
+impl PendingBatches {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            batches: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            holders: HashMap::new(),
+            done: Vec::new(),
+            committed: 0,
+            covered: 0,
+            fallbacks: 0,
+        }
+    }
+
+    /// Hold the elections of a header that no other header holds yet, evicting the oldest header if
+    /// we hold too many. Returns the held elections of the evicted header: they fall back.
+    pub fn insert(
+        &mut self,
+        header_id: Digest,
+        members: BTreeMap<ElectionId, TxHash>,
+    ) -> Vec<ElectionId> {
+        let members: BTreeMap<_, _> = members
+            .into_iter()
+            .filter(|(x, _)| !self.holders.contains_key(x))
+            .collect();
+        if self.capacity == 0 || members.is_empty() || self.batches.contains_key(&header_id) {
+            return Vec::new();
+        }
+        let mut evicted = Vec::new();
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                let excluded = self.batches[&oldest].excluded.clone();
+                evicted = self
+                    .remove(&oldest)
+                    .into_keys()
+                    .filter(|x| !excluded.contains(x))
+                    .collect();
+                self.fallbacks += evicted.len() as u64;
+            }
+        }
+        for election_id in members.keys() {
+            self.holders.insert(election_id.clone(), header_id.clone());
+        }
+        self.order.push_back(header_id.clone());
+        let excluded = BTreeSet::new();
+        let waiting = members.keys().cloned().collect();
+        self.batches.insert(
+            header_id,
+            Pending {
+                members,
+                excluded,
+                waiting,
+            },
+        );
+        evicted
+    }
+
+    /// The tx hash an election is held for, unless it is not held (anymore).
+    pub fn held(&self, election_id: &ElectionId) -> Option<&TxHash> {
+        let pending = self.batches.get(self.holders.get(election_id)?)?;
+        match pending.excluded.contains(election_id) {
+            true => None,
+            false => pending.members.get(election_id),
+        }
+    }
+
+    /// Report a held election as ready: it holds a quorum of votes for its held tx hash in round
+    /// 0, or it decided. Its header is queued for its batch commit once it waits for no other.
+    pub fn ready(&mut self, election_id: &ElectionId) {
+        let header_id = match self.holders.get(election_id) {
+            Some(header_id) => header_id,
+            None => return,
+        };
+        if let Some(pending) = self.batches.get_mut(header_id) {
+            if pending.waiting.remove(election_id) && pending.waiting.is_empty() {
+                self.done.push(header_id.clone());
+            }
+        }
+    }
+
+    /// Stop holding an election: the batch commit of its header leaves it out.
+    pub fn exclude(&mut self, election_id: &ElectionId) {
+        if self.held(election_id).is_none() {
+            return;
+        }
+        let batches = &mut self.batches;
+        if let Some(pending) = self
+            .holders
+            .get(election_id)
+            .and_then(|x| batches.get_mut(x))
+        {
+            pending.excluded.insert(election_id.clone());
+            self.fallbacks += 1;
+        }
+        self.ready(election_id);
+    }
+
+    /// Take the batch commits of the headers whose held elections are all ready, along with the
+    /// elections and tx hashes each stands for. Headers whose members all fell back are dropped.
+    pub fn take_ready(&mut self) -> Vec<(BatchCommit, Vec<(ElectionId, TxHash)>)> {
+        let mut batch_commits = Vec::new();
+        for header_id in std::mem::take(&mut self.done) {
+            // The header may have been evicted since.
+            let excluded = match self.batches.get(&header_id) {
+                Some(pending) if pending.waiting.is_empty() => pending.excluded.clone(),
+                _ => continue,
+            };
+            let covered: Vec<_> = self
+                .remove(&header_id)
+                .into_iter()
+                .filter(|(x, _)| !excluded.contains(x))
+                .collect();
+            if covered.is_empty() {
+                continue;
+            }
+            self.committed += 1;
+            self.covered += covered.len() as u64;
+            let header = header_id;
+            batch_commits.push((BatchCommit { header, excluded }, covered));
+        }
+        batch_commits
+    }
+
+    /// Forget a header. Returns its members.
+    fn remove(&mut self, header_id: &Digest) -> BTreeMap<ElectionId, TxHash> {
+        self.order.retain(|x| x != header_id);
+        let pending = match self.batches.remove(header_id) {
+            Some(pending) => pending,
+            None => return BTreeMap::new(),
+        };
+        for election_id in pending.members.keys() {
+            self.holders.remove(election_id);
+        }
+        pending.members
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::info;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use store::Store;
+use tokio::time::Instant;
+
+#[cfg(test)]
+#[path = "tests/compaction_tests.rs"]
+pub mod compaction_tests;
+
+/// Schedules manual compactions of the store during the idle periods of the primary, so that the
+/// write stalls they cause do not land in the middle of an election.
+#[derive(Clone, Debug, Default)]
+pub struct Compactor {
+    /// The minimum delay between the starts of two compactions (in ms). Disabled if zero.
+    interval: u64,
+    /// How long the primary must be idle before a compaction starts (in ms).
+    idle_delay: u64,
+    /// The last time the primary had work to do.
+    last_busy: Option<Instant>,
+    /// The start of the last compaction.
+    last_compaction: Option<Instant>,
+    /// The compactions run so far, updated by the tasks running them.
+    metrics: Arc<Mutex<CompactionMetrics>>,
+}
+
+impl Compactor {
+    pub fn new(interval: u64, idle_delay: u64) -> Self {
+        Self {
+            interval,
+            idle_delay,
+            ..Self::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.interval > 0
+    }
+
+    /// Record whether the primary had work since the last call, and tell whether a compaction is
+    /// due: the primary has been idle for `idle_delay`, the last compaction started at least
+    /// `interval` ago and is over.
+    pub fn due(&mut self, busy: bool, now: Instant) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        if busy {
+            self.last_busy = Some(now);
+            return false;
+        }
+        // The idle period starts with the first call.
+        let last_busy = *self.last_busy.get_or_insert(now);
+        let idle = now - last_busy >= Duration::from_millis(self.idle_delay);
+        let elapsed = match self.last_compaction {
+            Some(x) => now - x >= Duration::from_millis(self.interval),
+            None => true,
+        };
+        idle && elapsed && !self.metrics.lock().unwrap().running
+    }
+
+    /// Compact the store in the background.
+    pub fn compact(&mut self, mut store: Store, now: Instant) {
+        self.last_compaction = Some(now);
+        self.metrics.lock().unwrap().running = true;
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            info!("Compaction started");
+            let duration = store.compact().await;
+            info!("Compaction finished in {} ms", duration.as_millis());
+            metrics.lock().unwrap().record(duration);
+        });
+    }
+
+    pub fn metrics(&self) -> CompactionMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+}
+
+/// The manual compactions of the store, to correlate them with latency spikes.
+#[derive(Clone, Debug, Default)]
+pub struct CompactionMetrics {
+    /// Whether a compaction is running.
+    pub running: bool,
+    /// The number of compactions that completed.
+    pub compactions: u64,
+    /// The duration of the longest compaction.
+    pub longest: Duration,
+    /// The total duration of the compactions.
+    pub total: Duration,
+}
+
+impl CompactionMetrics {
+    pub fn record(&mut self, duration: Duration) {
+        self.running = false;
+        self.compactions += 1;
+        self.longest = self.longest.max(duration);
+        self.total += duration;
+    }
+}
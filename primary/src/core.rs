@@ -1,26 +1,47 @@
-use crate::constants::{QUORUM, SEMI_QUORUM, NUMBER_OF_NODES};
-use crate::election::{self, Election, Tally, ElectionId, Timer};
+use crate::batching::PendingBatches;
+use crate::election::{Election, ElectionId};
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::error::{DagError, DagResult};
-use crate::messages::{Header, Vote};
-use crate::primary::{PrimaryMessage, Round};
+use crate::compaction::Compactor;
+use crate::dedup::{Outcome, SeenHeaders};
+use crate::dissemination::{DisseminationMetrics, Disseminator};
+use crate::error::{DagError, DagResult, PeerErrors};
+use crate::messages::{
+    BatchCommit, CommitCertificate, Hash as _, Header, InlineLimits, Reconfiguration, Vote,
+};
+use crate::pacing::{Pacer, PacingMetrics};
+use crate::parking::ParkedHeaders;
+use crate::payload_receiver::{payload_key, payload_worker};
+use crate::primary::{
+    ElectionOutcome, PrimaryMessage, PrimaryWorkerMessage, Round, StateRequest, Transaction,
+    CHANNEL_CAPACITY,
+};
+use crate::vote_log::VoteLog;
+use crate::window::ElectionWindow;
+use crate::wire;
 use async_recursion::async_recursion;
 use bytes::Bytes;
-use config::Committee;
-use crypto::Hash as _;
+use futures::stream::StreamExt as _;
+use config::{ByzantineStrategy, Committee, Stake, WorkerId};
 use crypto::{Digest, PublicKey as PublicAddress, SignatureService};
 use log::{debug, error, warn, info};
-use network::{CancelHandler, ReliableSender, SimpleSender};
+use network::{
+    decode, encode, CancelHandler, KeepAlive, SenderLimits, SimpleSender,
+};
 use rand::rngs::OsRng;
 use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
 use tokio::time::{sleep, Instant};
-use std::collections::{HashMap, HashSet, BTreeSet};
+use std::collections::{BTreeMap, HashMap, HashSet, BTreeSet, VecDeque};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use store::Store;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_util::time::DelayQueue;
 
 #[cfg(test)]
 #[path = "tests/core_tests.rs"]
@@ -28,8 +49,93 @@ pub mod core_tests;
 
 pub type TxHash = Digest;
 
+/// What a header claimed about an election. The claims of every election are persisted until we
+/// forget it (see `Core::cleanup`), so that conflicts (the same election with different tx
+/// hashes) can be investigated after the fact.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Claim {
+    pub header_id: Digest,
+    pub author: PublicAddress,
+    pub round: Round,
+    pub tx_hash: TxHash,
+}
+
+/// The store key of the claims of an election.
+pub fn claims_key(election_id: &ElectionId) -> Vec<u8> {
+    [b"claims".as_ref(), election_id.as_ref()].concat()
+}
+
+/// Evidence that an author claimed two tx hashes in the same round of an election, in two headers.
+/// It outlives the claims it was found in: `Core::cleanup` never deletes it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Equivocation {
+    pub author: PublicAddress,
+    pub round: Round,
+    pub election_id: ElectionId,
+    /// The header holding the first claim, which is the one we tallied.
+    pub first: Digest,
+    /// The header holding the conflicting claim.
+    pub second: Digest,
+}
+
+/// The store key of the evidence of an equivocation.
+pub fn equivocation_key(election_id: &ElectionId, author: &PublicAddress, round: Round) -> Vec<u8> {
+    [b"equivocation".as_ref(), election_id.as_ref(), author.as_ref(), &round.to_le_bytes()].concat()
+}
+
+/// The store key of the commit certificate of an election.
+pub fn certificate_key(election_id: &ElectionId) -> Vec<u8> {
+    [b"certificate".as_ref(), election_id.as_ref()].concat()
+}
+
+/// The store key of a transaction proposed inline. The application finds the decided inline
+/// transactions there, rather than in the batches of the workers.
+pub fn inline_key(tx_hash: &TxHash) -> Vec<u8> {
+    [b"inline".as_ref(), tx_hash.as_ref()].concat()
+}
+
+/// The store key of the round of our last header.
+pub const ROUND_KEY: &[u8] = b"round";
+
 const TIMER: u64 = 100;
 
+/// The number of times a header-spamming node sends each of its headers.
+const SPAM_COPIES: usize = 10;
+
+/// The number of processed headers we remember to drop their copies.
+const SEEN_HEADERS: usize = 10_000;
+
+/// The number of headers we keep waiting for their parents or batches.
+const PARKED_HEADERS: usize = 1_000;
+
+/// The number of equivocations we keep in memory (the most recent ones). All are persisted.
+const EQUIVOCATIONS: usize = 1_000;
+
+/// The number of headers of the next epoch we keep until we move to it.
+const EARLY_HEADERS: usize = 1_000;
+/// The number of headers whose votes we hold our commits to at once.
+const PENDING_BATCHES: usize = 1_000;
+
+/// A vote for the same round and election as `vote`, but for another tx hash.
+fn equivocate(vote: &Vote) -> Vote {
+    let mut tx_hash = vote.tx_hash.clone();
+    tx_hash.0.iter_mut().for_each(|x| *x = !*x);
+    Vote { tx_hash, ..vote.clone() }
+}
+
+/// Whether `round` is past the last voting round of an election (`max_election_rounds` of them,
+/// unbounded if zero).
+fn out_of_rounds(max_election_rounds: Round, round: Round) -> bool {
+    max_election_rounds > 0 && round >= max_election_rounds
+}
+
+/// Cast one of our votes: tally it, then queue it for our next header. Our headers only go to
+/// the other primaries, never back to us, so this is the one time we tally it.
+fn cast_vote(election: &mut Election, votes: &mut Vec<Vote>, name: PublicAddress, vote: Vote) {
+    election.insert_vote(&vote, name);
+    votes.push(vote);
+}
+
 pub struct Core {
     /// The public key of this primary.
     name: PublicAddress,
@@ -41,15 +147,22 @@ pub struct Core {
     signature_service: SignatureService,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
-    /// The depth of the garbage collector.
+    /// The depth of the garbage collector: the number of decided elections whose tallies we keep.
+    /// Headers (and votes) further behind are dropped.
     gc_depth: Round,
+    /// How far ahead headers (and votes) may be before they are dropped.
+    round_window: Round,
 
     /// Receiver for dag messages (headers, votes, certificates).
     rx_primaries: Receiver<PrimaryMessage>,
+    /// Receives the requests for the state of an election (see `PrimaryMessage::StateRequest`).
+    rx_state_requests: Receiver<StateRequest>,
     /// Receives our newly created headers from the `Proposer`.
     rx_proposer: Receiver<Header>,
-    /// Send valid a quorum of certificates' ids to the `Proposer` (along with their round).
+    /// Sends the digests of a quorum of headers to the `Proposer` (along with their round).
     tx_proposer: Sender<(Vec<TxHash>, Round)>,
+    /// Outputs the outcome of each election (see `ElectionOutcome`).
+    tx_output: Sender<ElectionOutcome>,
 
     /// The last garbage collected round.
     gc_round: Round,
@@ -64,11 +177,80 @@ pub struct Core {
     /// Keeps the cancel handlers of the messages we sent.
     cancel_handlers: HashMap<Round, Vec<CancelHandler>>,
     elections: HashMap<ElectionId, Election>,
+    /// The addresses of the other primaries. Never ours: we do not send ourselves our headers.
     addresses: Vec<SocketAddr>,
-    byzantine: bool,
+    /// How we misbehave, if we are byzantine.
+    byzantine: Option<ByzantineStrategy>,
     payloads: HashMap<ElectionId, BTreeSet<TxHash>>,
     votes: Vec<Vote>,
+    /// The number of pending votes past which we send them, and the most votes a header of ours
+    /// carries.
     header_size: usize,
+    /// The most votes we accept in the headers of others.
+    max_header_entries: usize,
+    /// The decided (or failed) elections whose tallies are still in memory (oldest first).
+    retained: VecDeque<ElectionId>,
+    /// The decided (or failed) elections that have been garbage collected in the last `gc_depth`
+    /// rounds of the DAG. Late votes for them are ignored.
+    decided: ElectionWindow,
+    /// The elections for which we saw conflicting claims (forgotten along with `decided`).
+    conflicts: HashSet<ElectionId>,
+    /// The latest equivocations found in the claims (oldest first).
+    equivocations: VecDeque<Equivocation>,
+    /// How long a voting round waits for more votes once it holds a quorum (in ms).
+    vote_timeout: u64,
+    /// The number of voting rounds an election gets to decide (unbounded if zero).
+    max_election_rounds: Round,
+    /// Wakes us up when the timer of a voting round expires, so that we move to the next round if
+    /// we were only waiting on it.
+    timers: DelayQueue<(ElectionId, Round)>,
+    /// Spreads our outbound votes over time.
+    pacer: Pacer,
+    /// The sizes of our outbound bursts of votes.
+    metrics: PacingMetrics,
+    /// Channel to hand ourselves the paced headers once their delay elapsed.
+    tx_paced: Sender<Header>,
+    /// Receives the paced headers ready to be broadcast.
+    rx_paced: Receiver<Header>,
+    /// The round of the DAG whose parents we are collecting.
+    dag_round: Round,
+    /// The round of the DAG at which we moved to the current epoch: the parents of our headers up
+    /// to it are of the previous epoch.
+    epoch_round: Round,
+    /// The latest header of each author since we last sent parents to the `Proposer`.
+    parents: HashMap<PublicAddress, Digest>,
+    /// The headers waiting for some of their parents or batches.
+    parked: ParkedHeaders,
+    /// Channel to re-inject parked headers once their parents and batches are stored.
+    tx_loopback: Sender<Header>,
+    /// Receives the headers whose parents are now stored.
+    rx_loopback: Receiver<Header>,
+    /// The highest round of the verified headers of each peer that was ahead of us.
+    observed: HashMap<PublicAddress, Round>,
+    /// Hands our headers carrying commits to the `Disseminator`, if acknowledged broadcast is enabled.
+    tx_disseminator: Option<Sender<(Digest, Bytes)>>,
+    /// The outcomes of our acknowledged broadcasts.
+    dissemination: Arc<Mutex<DisseminationMetrics>>,
+    /// The errors the headers of each peer caused.
+    peer_errors: PeerErrors,
+    /// Persists our votes before they are sent, so that we stick to them after a restart.
+    vote_log: VoteLog,
+    /// The headers we already processed.
+    seen: SeenHeaders,
+    /// Compacts the store while we are idle.
+    compactor: Compactor,
+    /// The number of headers processed as of the previous tick of the timer.
+    last_processed: u64,
+    /// The bounds on the inline transactions of the headers.
+    inline_limits: InlineLimits,
+    /// The headers we commit to at once, if batch commits are enabled.
+    batches: Option<PendingBatches>,
+    /// Our batch commits waiting to be sent.
+    batch_commits: BTreeSet<BatchCommit>,
+    /// Hands the committee of each new epoch to the tasks that follow it.
+    tx_reconfigure: Vec<Sender<Committee>>,
+    /// The headers of the next epoch that reached us before its reconfiguration (oldest first).
+    early: VecDeque<Header>,
 }
 
 impl Core {
@@ -80,40 +262,605 @@ impl Core {
         signature_service: SignatureService,
         consensus_round: Arc<AtomicU64>,
         gc_depth: Round,
+        round_window: Round,
         rx_primaries: Receiver<PrimaryMessage>,
+        rx_state_requests: Receiver<StateRequest>,
         rx_proposer: Receiver<Header>,
         tx_proposer: Sender<(Vec<TxHash>, Round)>,
+        tx_output: Sender<ElectionOutcome>,
         addresses: Vec<SocketAddr>,
-        byzantine: bool,
+        byzantine: Option<ByzantineStrategy>,
         header_size: usize,
-    ) {
+        max_header_entries: usize,
+        vote_timeout: u64,
+        max_election_rounds: Round,
+        pacer: Pacer,
+        outbound: SenderLimits,
+        keepalive: KeepAlive,
+        dissemination_timeout: u64,
+        compactor: Compactor,
+        inline_limits: InlineLimits,
+        batch_commits: bool,
+        tx_reconfigure: Vec<Sender<Committee>>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            Self {
+            Self::new(
                 name,
                 committee,
                 store,
                 signature_service,
                 consensus_round,
                 gc_depth,
+                round_window,
                 rx_primaries,
+                rx_state_requests,
                 rx_proposer,
                 tx_proposer,
-                gc_round: 0,
-                last_voted: HashMap::with_capacity(2 * gc_depth as usize),
-                processing: HashMap::with_capacity(2 * gc_depth as usize),
-                current_header: Header::default(),
-                network: SimpleSender::new(),
-                cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
-                elections: HashMap::new(),
+                tx_output,
                 addresses,
                 byzantine,
-                payloads: HashMap::new(),
-                votes: Vec::new(),
                 header_size,
-            }
+                max_header_entries,
+                vote_timeout,
+                max_election_rounds,
+                pacer,
+                outbound,
+                keepalive,
+                dissemination_timeout,
+                compactor,
+                inline_limits,
+                batch_commits,
+                tx_reconfigure,
+            )
             .run()
             .await;
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        name: PublicAddress,
+        committee: Committee,
+        store: Store,
+        signature_service: SignatureService,
+        consensus_round: Arc<AtomicU64>,
+        gc_depth: Round,
+        round_window: Round,
+        rx_primaries: Receiver<PrimaryMessage>,
+        rx_state_requests: Receiver<StateRequest>,
+        rx_proposer: Receiver<Header>,
+        tx_proposer: Sender<(Vec<TxHash>, Round)>,
+        tx_output: Sender<ElectionOutcome>,
+        addresses: Vec<SocketAddr>,
+        byzantine: Option<ByzantineStrategy>,
+        header_size: usize,
+        max_header_entries: usize,
+        vote_timeout: u64,
+        max_election_rounds: Round,
+        pacer: Pacer,
+        outbound: SenderLimits,
+        keepalive: KeepAlive,
+        dissemination_timeout: u64,
+        compactor: Compactor,
+        inline_limits: InlineLimits,
+        batch_commits: bool,
+        tx_reconfigure: Vec<Sender<Committee>>,
+    ) -> Self {
+        let (tx_paced, rx_paced) = channel(CHANNEL_CAPACITY);
+        let (tx_loopback, rx_loopback) = channel(CHANNEL_CAPACITY);
+
+        // The `Disseminator` stops once we drop our end of its channel. It follows the committee
+        // along with the other tasks we reconfigure.
+        let mut tx_reconfigure = tx_reconfigure;
+        let dissemination = Arc::new(Mutex::new(DisseminationMetrics::default()));
+        let tx_disseminator = (dissemination_timeout > 0).then(|| {
+            let (tx_disseminator, rx_disseminator) = channel(CHANNEL_CAPACITY);
+            let (tx_committee, rx_committee) = channel(CHANNEL_CAPACITY);
+            tx_reconfigure.push(tx_committee);
+            Disseminator::spawn(
+                name,
+                &committee,
+                dissemination_timeout,
+                keepalive,
+                rx_disseminator,
+                /* rx_reconfigure */ rx_committee,
+                dissemination.clone(),
+            );
+            tx_disseminator
+        });
+        Self {
+            name,
+            committee,
+            vote_log: VoteLog::new(Some(store.clone())),
+            seen: SeenHeaders::new(SEEN_HEADERS),
+            store,
+            signature_service,
+            consensus_round,
+            gc_depth,
+            round_window,
+            rx_primaries,
+            rx_state_requests,
+            rx_proposer,
+            tx_proposer,
+            tx_output,
+            gc_round: 0,
+            last_voted: HashMap::with_capacity(2 * gc_depth as usize),
+            processing: HashMap::with_capacity(2 * gc_depth as usize),
+            current_header: Header::default(),
+            network: SimpleSender::with_limits(outbound).with_keepalive(keepalive),
+            cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
+            elections: HashMap::new(),
+            addresses,
+            byzantine,
+            payloads: HashMap::new(),
+            votes: Vec::new(),
+            header_size,
+            max_header_entries,
+            retained: VecDeque::with_capacity(gc_depth as usize + 1),
+            decided: ElectionWindow::new(gc_depth),
+            conflicts: HashSet::new(),
+            equivocations: VecDeque::new(),
+            vote_timeout,
+            max_election_rounds,
+            timers: DelayQueue::new(),
+            pacer,
+            metrics: PacingMetrics::default(),
+            tx_paced,
+            rx_paced,
+            dag_round: 1,
+            epoch_round: 0,
+            parents: HashMap::new(),
+            parked: ParkedHeaders::new(PARKED_HEADERS),
+            tx_loopback,
+            rx_loopback,
+            observed: HashMap::new(),
+            tx_disseminator,
+            dissemination,
+            peer_errors: PeerErrors::default(),
+            compactor,
+            last_processed: 0,
+            inline_limits,
+            batches: batch_commits.then(|| PendingBatches::new(PENDING_BATCHES)),
+            batch_commits: BTreeSet::new(),
+            tx_reconfigure,
+            early: VecDeque::new(),
+        }
+    }
+
+    /// Broadcast one of our headers to the other primaries. We tallied its votes already.
+    async fn broadcast(&mut self, header: &Header) {
+        self.metrics.record_burst(header.votes.len());
+        let serialize = |header: &Header| {
+            let bytes = wire::serialize(&PrimaryMessage::Header(header.clone()))
+                .expect("Failed to serialize our own header");
+            Bytes::from(bytes)
+        };
+        match self.byzantine {
+            Some(ByzantineStrategy::Equivocate) => {
+                // Half of the peers get our header, the others get conflicting votes.
+                let votes = header.votes.iter().map(equivocate).collect();
+                let payload = header.payload.clone();
+                let parents = header.parents.clone();
+                let inline = header.inline.clone();
+                let batch_commits = header.batch_commits.clone();
+                let conflicting = Header::new(self.name, header.epoch, header.round, votes, payload, parents, inline, batch_commits, &mut self.signature_service).await;
+                let (left, right) = self.addresses.split_at(self.addresses.len() / 2);
+                let (left, right) = (left.to_vec(), right.to_vec());
+                self.network.broadcast(left, serialize(header)).await;
+                self.network.broadcast(right, serialize(&conflicting)).await;
+            }
+            Some(ByzantineStrategy::SpamHeaders) => {
+                for _ in 0..SPAM_COPIES {
+                    self.network.broadcast(self.addresses.clone(), serialize(header)).await;
+                }
+            }
+            _ => {
+                let data = serialize(header);
+                if header.votes.iter().any(|x| x.commit) || !header.batch_commits.is_empty() {
+                    if let Some(tx_disseminator) = &self.tx_disseminator {
+                        // Never wait for the `Disseminator`: if it lags behind, send without acks.
+                        match tx_disseminator.try_send((header.digest(), data)) {
+                            Ok(()) => return,
+                            Err(TrySendError::Full((_, data)) | TrySendError::Closed((_, data))) => {
+                                return self.network.broadcast(self.addresses.clone(), data).await
+                            }
+                        }
+                    }
+                }
+                self.network.broadcast(self.addresses.clone(), data).await
+            }
+        }
+    }
+
+    /// Send one of our headers now, or once `delay` (in ms) elapsed.
+    async fn send(&mut self, header: Header, delay: u64) {
+        if delay == 0 {
+            return self.broadcast(&header).await;
+        }
+        let tx_paced = self.tx_paced.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(delay)).await;
+            let _ = tx_paced.send(header).await;
+        });
+    }
+
+    /// How long we hold back our outbound headers (in ms).
+    fn holdback(&self) -> u64 {
+        match &self.byzantine {
+            Some(ByzantineStrategy::DelayVotes(delay)) => delay.as_millis() as u64,
+            _ => 0,
+        }
+    }
+
+    /// The vote-flipping version of one of our votes: the minority tx hash of its round, or of the
+    /// latest round before it whose minority is another tx hash (when we vote first in a round, its
+    /// tally only holds our own vote).
+    fn flip(&self, vote: Vote) -> Vote {
+        let minority = self.elections.get(&vote.election_id).and_then(|x| {
+            (0..=vote.round)
+                .rev()
+                .filter_map(|round| x.tallies.get(&round))
+                .filter_map(|tally| tally.minority())
+                .find(|tx_hash| **tx_hash != vote.tx_hash)
         });
+        match minority {
+            Some(tx_hash) if !vote.commit => Vote { tx_hash: tx_hash.clone(), ..vote },
+            _ => vote,
+        }
+    }
+
+    /// Send our pending votes. With pacing, commits leave right away and the other votes follow
+    /// in chunks, each delayed by its slot in the window plus a jitter specific to this node.
+    async fn flush_votes(&mut self) {
+        let votes: Vec<Vote> = self.votes.drain(..).collect();
+        let mut votes = match self.vote_log.cast_all(votes).await {
+            Ok(votes) => votes,
+            Err(e) => {
+                // Better to send no vote than one that may contradict a vote we already cast.
+                warn!("Failed to persist our votes: {}", e);
+                return;
+            }
+        };
+        if self.byzantine == Some(ByzantineStrategy::VoteFlip) {
+            votes = votes.into_iter().map(|x| self.flip(x)).collect();
+        }
+        self.metrics.record_flush(votes.len());
+        let holdback = self.holdback();
+        let batch_commits = std::mem::take(&mut self.batch_commits);
+        let (unpaced, chunks) = self.pacer.split(votes);
+        if !unpaced.is_empty() || !batch_commits.is_empty() {
+            for header in self.make_headers(unpaced, batch_commits).await {
+                self.send(header, holdback).await;
+            }
+        }
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            for header in self.make_headers(chunk, BTreeSet::new()).await {
+                let delay = self.pacer.delay(&self.name, &header.digest(), index);
+                self.send(header, holdback + delay).await;
+            }
+        }
+    }
+
+    /// Make our headers for some votes. They carry the inline transactions the votes are for, over
+    /// as many headers as it takes to keep each within the bounds (and within `header_size` votes).
+    /// The first also carries the batch commits. Each is stored before it goes out, since peers may
+    /// list it as a parent of theirs.
+    async fn make_headers(&mut self, votes: Vec<Vote>, mut batch_commits: BTreeSet<BatchCommit>) -> Vec<Header> {
+        let mut groups = vec![(BTreeSet::new(), BTreeMap::new(), 0)];
+        for vote in votes {
+            if groups.last().unwrap().0.len() >= self.header_size {
+                groups.push((BTreeSet::new(), BTreeMap::new(), 0));
+            }
+            if let Some(transaction) = self.inline_transaction(&vote.tx_hash).await {
+                let size = transaction.data.len();
+                let (votes, _, bytes) = groups.last().unwrap();
+                if !votes.is_empty() && bytes + size > self.inline_limits.header_bytes {
+                    groups.push((BTreeSet::new(), BTreeMap::new(), 0));
+                }
+                let (_, inline, bytes) = groups.last_mut().unwrap();
+                inline.insert(vote.tx_hash.clone(), transaction);
+                *bytes += size;
+            }
+            groups.last_mut().unwrap().0.insert(vote);
+        }
+        let mut headers = Vec::new();
+        for (votes, inline, _) in groups {
+            let payload = self.payload(&votes).await;
+            let batch_commits = std::mem::take(&mut batch_commits);
+            let header = Header::new(self.name, self.committee.epoch, self.dag_round, votes, payload, BTreeSet::new(), inline, batch_commits, &mut self.signature_service).await;
+            let bytes = encode(&header).expect("Failed to serialize our own header");
+            self.store.write(header.digest().to_vec(), bytes).await;
+            headers.push(header);
+        }
+        headers
+    }
+
+    /// The inline transaction with this tx hash, if we saw one.
+    async fn inline_transaction(&mut self, tx_hash: &TxHash) -> Option<Transaction> {
+        match self.store.read(inline_key(tx_hash)).await {
+            Ok(Some(bytes)) => decode(&bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// Move to the next round of an election whose round timer expired, if we were only waiting on it.
+    async fn process_timeout(&mut self, election_id: ElectionId, round: Round) {
+        if round == 0 && self.release_stalled(&election_id, Instant::now()) {
+            self.fall_back(&election_id);
+        }
+        let election = match self.elections.get_mut(&election_id) {
+            Some(election) if !election.decided && !election.failed => election,
+            _ => return,
+        };
+        if election.can_advance(&self.name, round, Instant::now()) {
+            if out_of_rounds(self.max_election_rounds, round + 1) {
+                // The commits of the last round may still decide the election.
+                if election.awaits_commits(round) {
+                    return;
+                }
+                election.failed = true;
+                return self.fail(election_id).await;
+            }
+            let vote = election.next_round_vote(&election_id, round);
+            cast_vote(election, &mut self.votes, self.name, vote);
+        }
+    }
+
+    /// Report an election that ran out of voting rounds (and that we marked as failed). Its
+    /// transactions are dropped: no header of ours votes for them anymore.
+    async fn fail(&mut self, election_id: ElectionId) {
+        info!("Election {} failed after {} rounds", election_id, self.max_election_rounds);
+        if let Some(batches) = &mut self.batches {
+            batches.exclude(&election_id);
+        }
+        self.retained.push_back(election_id.clone());
+        self.tx_output
+            .send(ElectionOutcome::Failed(election_id))
+            .await
+            .expect("Failed to output election outcome");
+        self.cleanup().await;
+    }
+
+    /// Hold our commits to the round-0 votes of a header, to send them at once (see `PendingBatches`)
+    /// if batch commits are enabled. We only hold the elections we did not commit in yet.
+    fn hold_batch(&mut self, header: &Header, header_id: &Digest) {
+        if self.batches.is_none() {
+            return;
+        }
+        let members = header
+            .votes
+            .iter()
+            .filter(|x| x.round == 0 && !x.commit && !self.decided.contains(&x.election_id))
+            .filter(|x| {
+                !self
+                    .elections
+                    .get(&x.election_id)
+                    .is_some_and(|e| e.decided || e.voted_or_committed(&self.name, 1))
+            })
+            .map(|x| (x.election_id.clone(), x.tx_hash.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let held: Vec<_> = members.keys().cloned().collect();
+        let evicted = match &mut self.batches {
+            Some(batches) => batches.insert(header_id.clone(), members),
+            None => return,
+        };
+        for election_id in evicted {
+            self.fall_back(&election_id);
+        }
+        // Some may hold their quorum already.
+        for election_id in &held {
+            self.check_held(election_id);
+        }
+    }
+
+    /// Report a held election to the batch commit of its header once it is ready: it decided, or
+    /// it holds a quorum of votes for its held tx hash in round 0.
+    fn check_held(&mut self, election_id: &ElectionId) {
+        let (batches, election) = match (&mut self.batches, self.elections.get(election_id)) {
+            (Some(batches), Some(election)) => (batches, election),
+            _ => return,
+        };
+        let ready = match batches.held(election_id) {
+            Some(tx_hash) => election.decided || election.has_quorum_of_votes(0, tx_hash),
+            None => return,
+        };
+        if ready {
+            batches.ready(election_id);
+        }
+    }
+
+    /// Stop holding an election for a batch commit if its tx hash stalled in round 0 (see
+    /// `Election::stalled`). Returns whether it did.
+    fn release_stalled(&mut self, election_id: &ElectionId, now: Instant) -> bool {
+        let (batches, election) = match (&mut self.batches, self.elections.get(election_id)) {
+            (Some(batches), Some(election)) => (batches, election),
+            _ => return false,
+        };
+        match batches.held(election_id) {
+            Some(tx_hash) if election.stalled(0, tx_hash, now) => {
+                debug!("Election {} falls back from its batch commit", election_id);
+                batches.exclude(election_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Commit on its own in an election no longer held for a batch commit, if round 0 holds a quorum
+    /// of votes. Otherwise the election goes on as any other.
+    fn fall_back(&mut self, election_id: &ElectionId) {
+        let election = match self.elections.get_mut(election_id) {
+            Some(election) if !election.decided => election,
+            _ => return,
+        };
+        let tx_hash = match election.tallies.get(&0).and_then(|x| x.find_quorum_of_votes()) {
+            Some(tx_hash) => tx_hash.clone(),
+            None => return,
+        };
+        if !election.voted_or_committed(&self.name, 1) {
+            let vote = Vote {
+                round: 1,
+                tx_hash: tx_hash.clone(),
+                election_id: election_id.clone(),
+                commit: true,
+            };
+            election.commit = Some(tx_hash);
+            election.proof_round = Some(0);
+            cast_vote(election, &mut self.votes, self.name, vote);
+        }
+    }
+
+    /// Make the batch commits of the held headers whose elections all hold a quorum of votes for
+    /// their tx hash in round 0 (but those that fell back), and commit to their votes. The
+    /// elections are reported as they get ready (see `check_held`).
+    async fn make_batch_commits(&mut self) -> DagResult<()> {
+        let ready = match &mut self.batches {
+            Some(batches) => batches.take_ready(),
+            None => return Ok(()),
+        };
+        for (mut batch, covered) in ready {
+            let commits = covered
+                .iter()
+                .map(|(election_id, tx_hash)| Vote {
+                    round: 1,
+                    tx_hash: tx_hash.clone(),
+                    election_id: election_id.clone(),
+                    commit: true,
+                })
+                .collect();
+            let commits = self.vote_log.cast_all(commits).await?;
+            for (vote, (election_id, tx_hash)) in commits.into_iter().zip(covered) {
+                let election = match self.elections.get_mut(&election_id) {
+                    Some(election) if !election.decided => election,
+                    _ => continue,
+                };
+                if vote.tx_hash != tx_hash || !vote.commit {
+                    // We cast another vote in this round before restarting: it is sent on its own.
+                    batch.excluded.insert(election_id);
+                    cast_vote(election, &mut self.votes, self.name, vote);
+                    continue;
+                }
+                // The commit leaves in the batch commit rather than in our votes: we tally it
+                // now all the same (see `cast_vote`).
+                election.commit = Some(tx_hash);
+                election.proof_round = Some(0);
+                election.insert_vote(&vote, self.name);
+            }
+            self.batch_commits.insert(batch);
+        }
+        Ok(())
+    }
+
+    /// The commits the batch commits of a header stand for. The headers they refer to are stored
+    /// (see `accept_dependencies`).
+    async fn batch_commit_votes(&mut self, header: &Header) -> DagResult<Vec<Vote>> {
+        let mut votes = Vec::new();
+        for batch in &header.batch_commits {
+            if let Some(bytes) = self.store.read(batch.header.to_vec()).await? {
+                let committed: Header = decode(&bytes)?;
+                votes.extend(batch.commits(&committed));
+            }
+        }
+        Ok(votes)
+    }
+
+    /// Compact the store if we have been idle for a while: no header since the previous tick of
+    /// the timer and no election in its final rounds, which a compaction stall would delay.
+    fn schedule_compaction(&mut self) {
+        if !self.compactor.enabled() {
+            return;
+        }
+        let busy = self.seen.processed != self.last_processed
+            || self.elections.values().any(|x| x.committing());
+        self.last_processed = self.seen.processed;
+        let now = Instant::now();
+        if self.compactor.due(busy, now) {
+            self.compactor.compact(self.store.clone(), now);
+        }
+        let metrics = self.compactor.metrics();
+        debug!(
+            "Compactions: {} done ({} ms longest, {} ms total), {}",
+            metrics.compactions, metrics.longest.as_millis(), metrics.total.as_millis(),
+            if metrics.running { "one running" } else { "none running" },
+        );
+    }
+
+    /// Drop the tallies of all decided (or failed) elections but the `gc_depth` most recent ones. We only
+    /// remember the ids of the pruned elections so that late votes do not re-open them, and only for
+    /// `gc_depth` rounds: by then, the headers of the rounds they were decided in are too old to process.
+    async fn cleanup(&mut self) {
+        while self.retained.len() > self.gc_depth as usize {
+            if let Some(election_id) = self.retained.pop_front() {
+                self.elections.remove(&election_id);
+                self.decided.insert(election_id, self.dag_round);
+            }
+        }
+        // Their claims go with them.
+        for election_id in self.decided.expire(self.dag_round) {
+            self.conflicts.remove(&election_id);
+            self.store.delete(claims_key(&election_id)).await;
+        }
+    }
+
+    /// The latest equivocations found in the claims of the headers (oldest first).
+    pub fn equivocations(&self) -> &VecDeque<Equivocation> {
+        &self.equivocations
+    }
+
+    /// Persist the claim of a header about an election and report the first conflicting claim.
+    async fn record_claim(&mut self, header_id: &Digest, author: PublicAddress, vote: &Vote) -> DagResult<()> {
+        let key = claims_key(&vote.election_id);
+        let mut claims: Vec<Claim> = match self.store.read(key.clone()).await? {
+            Some(bytes) => decode(&bytes)?,
+            None => Vec::new(),
+        };
+        let claim = Claim {
+            header_id: header_id.clone(),
+            author,
+            round: vote.round,
+            tx_hash: vote.tx_hash.clone(),
+        };
+        if claims.contains(&claim) {
+            return Ok(());
+        }
+
+        let equivocation = claims
+            .iter()
+            .find(|x| x.author == claim.author && x.round == claim.round && x.tx_hash != claim.tx_hash);
+        if let Some(other) = equivocation {
+            warn!(
+                "Equivocation by {} in round {} of election {}: {} (header {}) and {} (header {})",
+                claim.author, claim.round, vote.election_id,
+                other.tx_hash, other.header_id, claim.tx_hash, claim.header_id,
+            );
+            let evidence = Equivocation {
+                author: claim.author,
+                round: claim.round,
+                election_id: vote.election_id.clone(),
+                first: other.header_id.clone(),
+                second: claim.header_id.clone(),
+            };
+            let key = equivocation_key(&evidence.election_id, &evidence.author, evidence.round);
+            self.store.write(key, encode(&evidence)?).await;
+            if self.equivocations.len() == EQUIVOCATIONS {
+                self.equivocations.pop_front();
+            }
+            self.equivocations.push_back(evidence);
+        }
+        if let Some(other) = claims.iter().find(|x| x.tx_hash != claim.tx_hash) {
+            if self.conflicts.insert(vote.election_id.clone()) {
+                warn!(
+                    "Conflicting claims for election {}: {} (header {}, author {}, round {}) and {} (header {}, author {}, round {})",
+                    vote.election_id,
+                    other.tx_hash, other.header_id, other.author, other.round,
+                    claim.tx_hash, claim.header_id, claim.author, claim.round,
+                );
+            }
+        }
+
+        claims.push(claim);
+        self.store.write(key, encode(&claims)?).await;
+        Ok(())
     }
 
     #[async_recursion]
@@ -121,7 +868,7 @@ impl Core {
         assert!(header.author == self.name);
         info!("Received own header with {} votes", header.votes.len());
             // broadcast vote
-            let bytes = bincode::serialize(&PrimaryMessage::Header(header.clone()))
+            let bytes = wire::serialize(&PrimaryMessage::Header(header.clone()))
                 .expect("Failed to serialize our own header");
             let handlers = self.network.broadcast(self.addresses.clone(), Bytes::from(bytes)).await;
 
@@ -133,19 +880,19 @@ impl Core {
                 //info!("Received commit {:?} from {}", vote, header.author);
             }
             let (tx_hash, election_id) = (vote.tx_hash.clone(), vote.election_id.clone()); 
-            if !self.byzantine {
+            if self.byzantine != Some(ByzantineStrategy::Silent) {
                 match self.elections.get_mut(&election_id) {
                     Some(election) => {
                         election.insert_vote(&vote, header.author);
                     }
                     None => {
                         // create election
-                        let election = Election::new();
+                        let election = Election::new(&self.committee);
                         self.elections.insert(election_id.clone(), election);
 
                         #[cfg(feature = "benchmark")]
                         // NOTE: This log entry is used to compute performance.
-                        info!("Created {} -> {:?}", vote, election_id);
+                        info!("{}", bench_support::created(&vote, &vote.tx_hash));
                             
                         let mut election = self.elections.get_mut(&election_id).unwrap();
                         // insert vote
@@ -157,22 +904,464 @@ impl Core {
         Ok(())
     }
 
-    #[async_recursion]
+    /// Hand the parents of our next header to the `Proposer`. During a shutdown, it stops before us
+    /// (see `ShutdownStage`): its parents then go nowhere.
+    async fn send_parents(&mut self, parents: Vec<Digest>, round: Round) {
+        if self.tx_proposer.send((parents, round)).await.is_err() {
+            debug!("The proposer stopped, dropping the parents of round {}", round);
+        }
+    }
+
+    /// Resume from the round of our last header, so that a restarted node does not propose from
+    /// round 1 again. The `Proposer` gets the round with no parents, as for its very first header.
+    async fn recover_round(&mut self) -> DagResult<()> {
+        if let Some(bytes) = self.store.read(ROUND_KEY.to_vec()).await? {
+            let round: Round = decode(&bytes)?;
+            info!("Resuming from round {}", round);
+            self.dag_round = round + 1;
+            self.send_parents(Vec::new(), round).await;
+        }
+        Ok(())
+    }
+
+    /// Catch up with peers that are more than a round ahead of us. We adopt the highest round
+    /// reached by verified headers of authors holding f+1 stake: at least one honest author vouches
+    /// for it, so a byzantine header cannot drag us ahead on its own.
+    fn observe_round(&mut self, header: &Header) {
+        if header.round <= self.dag_round + 1 {
+            return;
+        }
+        let round = self.observed.entry(header.author).or_insert(0);
+        *round = (*round).max(header.round);
+
+        let mut rounds: Vec<_> = self
+            .observed
+            .iter()
+            .map(|(author, round)| (*round, self.committee.stake(author)))
+            .collect();
+        rounds.sort_unstable_by_key(|x| std::cmp::Reverse(x.0));
+        let mut stake = 0;
+        for (round, x) in rounds {
+            stake += x;
+            if stake >= self.committee.validity_threshold() {
+                if round > self.dag_round {
+                    info!("Catching up from round {} to round {}", self.dag_round, round);
+                    self.dag_round = round;
+                }
+                return;
+            }
+        }
+    }
+
+    /// Drop the headers of peers more than `gc_depth` rounds behind us or more than `round_window`
+    /// rounds ahead: they would otherwise make us store (and tally) replayed or bogus rounds.
+    fn check_round(&self, header: &Header, header_id: &Digest) -> DagResult<()> {
+        ensure!(
+            header.round + self.gc_depth >= self.dag_round,
+            DagError::TooOld(header_id.clone(), header.round)
+        );
+        ensure!(
+            header.round <= self.dag_round + self.round_window,
+            DagError::TooFar(header_id.clone(), header.round)
+        );
+        Ok(())
+    }
+
+    /// Same as `check_round` for a vote, against the latest round of its election holding a
+    /// quorum, so that no peer can make us hold a tally for every round it makes up.
+    fn check_vote_round(&self, vote: &Vote) -> DagResult<()> {
+        let frontier = self
+            .elections
+            .get(&vote.election_id)
+            .map_or(0, |x| x.frontier());
+        ensure!(
+            vote.round + self.gc_depth >= frontier,
+            DagError::TooOld(vote.digest(), vote.round)
+        );
+        ensure!(
+            vote.round <= frontier + self.round_window,
+            DagError::TooFar(vote.digest(), vote.round)
+        );
+        Ok(())
+    }
+
+    /// Ask the author of a header for its missing parents: it must hold them to reference them.
+    async fn request_parents(&mut self, header: &Header, missing: Vec<Digest>) {
+        let address = match self.committee.primary(&header.author) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => return warn!("Cannot request parents: {}", e),
+        };
+        let message = PrimaryMessage::HeaderRequest(missing, self.name);
+        let bytes = wire::serialize(&message).expect("Failed to serialize header request");
+        self.network.send(address, Bytes::from(bytes)).await;
+    }
+
+    /// The workers of ours holding the batches of some of our votes, for the payload of a header.
+    async fn payload(&mut self, votes: &BTreeSet<Vote>) -> BTreeMap<TxHash, WorkerId> {
+        let mut payload = BTreeMap::new();
+        for vote in votes {
+            if let Ok(Some(value)) = self.store.read(payload_key(&vote.tx_hash)).await {
+                if let Some(worker_id) = payload_worker(&value) {
+                    payload.insert(vote.tx_hash.clone(), worker_id);
+                }
+            }
+        }
+        payload
+    }
+
+    /// Ask our workers to fetch the batches they do not hold from the workers of a header's author.
+    /// Each batch is requested from our worker with the id of the author's worker holding it (as
+    /// it only talks to the workers with its own id), or from all of them if we do not know it.
+    async fn request_payloads(&mut self, header: &Header, missing: Vec<TxHash>) {
+        let mut requests: BTreeMap<Option<WorkerId>, Vec<TxHash>> = BTreeMap::new();
+        for tx_hash in missing {
+            let worker_id = header
+                .payload
+                .get(&tx_hash)
+                .filter(|x| self.committee.worker(&self.name, x).is_ok())
+                .copied();
+            requests.entry(worker_id).or_default().push(tx_hash);
+        }
+        for (worker_id, digests) in requests {
+            let addresses = match worker_id {
+                Some(id) => self.committee.worker(&self.name, &id).map(|x| vec![x]),
+                None => self.committee.our_workers(&self.name),
+            };
+            let addresses = match addresses {
+                Ok(x) => x.into_iter().map(|x| x.primary_to_worker).collect(),
+                Err(e) => return warn!("Cannot request payloads: {}", e),
+            };
+            let message = PrimaryWorkerMessage::Synchronize(digests, header.author);
+            let bytes = encode(&message).expect("Failed to serialize sync request");
+            self.network.broadcast(addresses, Bytes::from(bytes)).await;
+        }
+    }
+
+    /// Store a header and collect it as a parent. Returns false if some of its parents (or of the
+    /// headers its batch commits refer to) are unknown or, for the headers of others, if our workers do not hold some of the batches it votes for:
+    /// the header is then parked until they are stored, so that we never vote on data we never saw.
+    async fn accept_dependencies(&mut self, header: &Header, header_id: &Digest) -> DagResult<bool> {
+        let mut missing_parents = Vec::new();
+        let committed = header.batch_commits.iter().map(|x| &x.header);
+        for parent in header.parents.iter().chain(committed) {
+            if self.store.read(parent.to_vec()).await?.is_none() {
+                missing_parents.push(parent.clone());
+            }
+        }
+        // The inline transactions need no batch: we keep them for our own votes.
+        for (tx_hash, transaction) in &header.inline {
+            self.store.write(inline_key(tx_hash), encode(transaction)?).await;
+        }
+        let mut missing_payloads = Vec::new();
+        if header.author != self.name {
+            for vote in &header.votes {
+                if !header.inline.contains_key(&vote.tx_hash)
+                    && !missing_payloads.contains(&vote.tx_hash)
+                    && self.store.read(payload_key(&vote.tx_hash)).await?.is_none()
+                    && self.store.read(inline_key(&vote.tx_hash)).await?.is_none()
+                {
+                    missing_payloads.push(vote.tx_hash.clone());
+                }
+            }
+        }
+        if !missing_parents.is_empty() || !missing_payloads.is_empty() {
+            if !self.parked.contains(header_id) {
+                debug!(
+                    "Parking header {} until {} parents and {} batches arrive",
+                    header_id, missing_parents.len(), missing_payloads.len(),
+                );
+                let mut missing: Vec<_> = missing_parents.iter().map(|x| x.to_vec()).collect();
+                missing.extend(missing_payloads.iter().map(payload_key));
+                if !missing_parents.is_empty() {
+                    self.request_parents(header, missing_parents).await;
+                }
+                if !missing_payloads.is_empty() {
+                    self.request_payloads(header, missing_payloads).await;
+                }
+                let (tx_cancel, rx_cancel) = oneshot::channel();
+                if let Some(evicted) = self.parked.insert(header_id.clone(), tx_cancel) {
+                    debug!("Evicting parked header {}", evicted);
+                }
+                let mut store = self.store.clone();
+                let tx_loopback = self.tx_loopback.clone();
+                let header = header.clone();
+                tokio::spawn(async move {
+                    let dependencies = async {
+                        for key in missing {
+                            let _ = store.notify_read(key).await;
+                        }
+                    };
+                    tokio::select! {
+                        () = dependencies => {
+                            let _ = tx_loopback.send(header).await;
+                        }
+                        // The header was evicted from the parking.
+                        _ = rx_cancel => (),
+                    }
+                });
+            }
+            return Ok(false);
+        }
+        self.parked.remove(header_id);
+        self.store.write(header_id.to_vec(), encode(header)?).await;
+
+        // Once we hold headers from a quorum of authors, they are the parents of our next header.
+        self.parents.insert(header.author, header_id.clone());
+        let stake: Stake = self.parents.keys().map(|x| self.committee.stake(x)).sum();
+        if stake >= self.committee.quorum_threshold() {
+            let parents = self.parents.drain().map(|(_, x)| x).collect();
+            self.send_parents(parents, self.dag_round).await;
+            self.dag_round += 1;
+        }
+        Ok(true)
+    }
+
+    /// Move to the committee of the next epoch. The elections under way keep the stakes they
+    /// started with; the new ones are run by the new committee.
+    async fn process_reconfiguration(&mut self, reconfiguration: Reconfiguration) -> DagResult<()> {
+        // Drop the copies of the reconfigurations we applied already.
+        if reconfiguration.committee.epoch <= self.committee.epoch {
+            return Ok(());
+        }
+        reconfiguration.verify(&self.committee)?;
+        let committee = reconfiguration.committee.clone();
+        info!(
+            "Moving to epoch {} with {} authorities",
+            committee.epoch,
+            committee.size()
+        );
+
+        // Pass it on to the primaries of both committees, so that the ones that missed it move on
+        // too (and those leaving learn they are out).
+        let addresses: Vec<_> = committee
+            .others_primaries(&self.name)
+            .iter()
+            .map(|(_, x)| x.primary_to_primary)
+            .collect();
+        let mut peers = self.addresses.clone();
+        peers.extend(addresses.iter().filter(|x| !self.addresses.contains(x)));
+        let bytes = wire::serialize(&PrimaryMessage::Reconfigure(reconfiguration))
+            .expect("Failed to serialize a reconfiguration");
+        self.network.broadcast(peers, Bytes::from(bytes)).await;
+
+        // Tell our workers, which exchange batches with the workers of the new committee.
+        match committee.our_workers(&self.name) {
+            Ok(workers) => {
+                let message = PrimaryWorkerMessage::Reconfigure(committee.clone());
+                let bytes = encode(&message).expect("Failed to serialize a reconfiguration");
+                let addresses = workers.into_iter().map(|x| x.primary_to_worker).collect();
+                self.network.broadcast(addresses, Bytes::from(bytes)).await;
+            }
+            Err(e) => warn!("We are out of epoch {}: {}", committee.epoch, e),
+        }
+        for tx_reconfigure in &self.tx_reconfigure {
+            let _ = tx_reconfigure.send(committee.clone()).await;
+        }
+        self.addresses = addresses;
+        self.committee = committee;
+
+        // The headers of the previous epoch are no parents in the new one: the authorities joining
+        // could not fetch them. The `Proposer` gets the round with no parents, as after a restart.
+        self.parents.clear();
+        self.epoch_round = self.dag_round;
+        self.send_parents(Vec::new(), self.dag_round).await;
+        self.dag_round += 1;
+
+        // Replay the headers of the new epoch we kept.
+        let early: Vec<_> = self.early.drain(..).collect();
+        let tx_loopback = self.tx_loopback.clone();
+        tokio::spawn(async move {
+            for header in early {
+                let _ = tx_loopback.send(header).await;
+            }
+        });
+        Ok(())
+    }
+
+    /// One of our headers, signed for the current epoch: the `Proposer` may make one in the
+    /// previous epoch, or with the parents of the previous epoch, before it hears of the
+    /// reconfiguration. Such a header has no parents, as the first one of the epoch.
+    async fn current_epoch(&mut self, header: Header) -> Header {
+        let stale_parents = header.round <= self.epoch_round && !header.parents.is_empty();
+        if header.epoch == self.committee.epoch && !stale_parents {
+            return header;
+        }
+        Header::new(
+            self.name,
+            self.committee.epoch,
+            header.round,
+            header.votes,
+            header.payload,
+            BTreeSet::new(),
+            header.inline,
+            header.batch_commits,
+            &mut self.signature_service,
+        )
+        .await
+    }
+
+    /// Complete the certificate of an election we decided with our own commit, then persist and
+    /// broadcast it so that the peers that missed some commits decide too.
+    async fn certify(&mut self, mut certificate: CommitCertificate) -> DagResult<()> {
+        let election_id = certificate.election_id.clone();
+        let commit = certificate.commit();
+        let committed = self
+            .elections
+            .get(&election_id)
+            .is_some_and(|x| x.committed(&self.name, commit.round, &commit.tx_hash));
+        if committed && !certificate.votes.iter().any(|(x, _)| x == &self.name) {
+            let signature = self.signature_service.request_signature(commit.commit_digest()).await;
+            certificate.votes.push((self.name, signature));
+        }
+
+        let stake: Stake = certificate.votes.iter().map(|(x, _)| self.committee.stake(x)).sum();
+        if stake < self.committee.quorum_threshold() {
+            debug!("Not enough signed commits to certify election {}", election_id);
+            return Ok(());
+        }
+        self.store.write(certificate_key(&election_id), encode(&certificate)?).await;
+        let bytes = wire::serialize(&PrimaryMessage::CommitCertificate(certificate))?;
+        self.network.broadcast(self.addresses.clone(), Bytes::from(bytes)).await;
+        Ok(())
+    }
+
+    /// Store one of our headers received from the network: a peer answers a request for its
+    /// parents, or replays it. Its votes are tallied already and it went out already, so we only
+    /// check we did sign it.
+    async fn process_returned_header(&mut self, header: &Header) -> DagResult<()> {
+        header.verify(&self.committee)?;
+        let header_id = header.digest();
+        self.store.write(header_id.to_vec(), encode(header)?).await;
+        Ok(())
+    }
+
+    /// Decide an election from the certificate of a peer, without waiting for its commits.
+    async fn process_certificate(&mut self, certificate: CommitCertificate) -> DagResult<()> {
+        let election_id = certificate.election_id.clone();
+        let decided = self.decided.contains(&election_id)
+            || self.elections.get(&election_id).is_some_and(|x| x.decided || x.failed);
+        if decided {
+            return Ok(());
+        }
+        certificate.verify(&self.committee)?;
+        self.store.write(certificate_key(&election_id), encode(&certificate)?).await;
+
+        let committee = &self.committee;
+        let election = self
+            .elections
+            .entry(election_id.clone())
+            .or_insert_with(|| Election::new(committee));
+        election.decided = true;
+        self.check_held(&election_id);
+
+        #[cfg(feature = "benchmark")]
+        // NOTE: This log entry is used to compute performance.
+        info!("{}", bench_support::committed(&certificate.commit(), &certificate.tx_hash));
+        #[cfg(feature = "benchmark")]
+        // NOTE: This log entry is used to check conflicting transactions.
+        info!("{}", bench_support::decided(&election_id, &certificate.tx_hash));
+        let decision = (election_id.clone(), certificate.tx_hash, certificate.round);
+        self.retained.push_back(election_id);
+        self.tx_output
+            .send(ElectionOutcome::Decided(decision))
+            .await
+            .expect("Failed to output decision");
+        self.cleanup().await;
+        Ok(())
+    }
+
+    /// Process a header unless we already did: copies of a processed header are dropped. Parked
+    /// headers are not remembered, so that they are processed once they come back from the loopback.
     async fn process_header(&mut self, header: &Header) -> DagResult<()> {
+        let header_id = header.digest();
+        if let Some(outcome) = self.seen.check(&header_id) {
+            debug!("Dropping copy of header {} ({})", header_id, outcome);
+            return Ok(());
+        }
+        self.seen.processed += 1;
+        match self.handle_header(header, &header_id).await {
+            Ok(true) => {
+                self.seen.insert(header_id, Outcome::Accepted);
+                Ok(())
+            }
+            Ok(false) => Ok(()),
+            // Headers too far ahead may be acceptable later, once we caught up.
+            Err(e @ DagError::TooOld(..)) | Err(e @ DagError::HeaderTooLarge(..)) => {
+                self.seen.insert(header_id, Outcome::Rejected(e.to_string()));
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Process a header. Returns false if it is parked until its parents and batches are stored.
+    #[async_recursion]
+    async fn handle_header(&mut self, header: &Header, header_id: &Digest) -> DagResult<bool> {
+        if header.author != self.name {
+            // The headers of the next epoch wait for its reconfiguration to reach us.
+            if header.epoch == self.committee.epoch + 1 {
+                if self.early.len() == EARLY_HEADERS {
+                    self.early.pop_front();
+                }
+                self.early.push_back(header.clone());
+                return Ok(false);
+            }
+            ensure!(
+                header.epoch == self.committee.epoch,
+                DagError::StaleEpoch(header_id.clone(), header.epoch)
+            );
+            // Check the size first: verifying an enormous header is work enough.
+            ensure!(
+                header.votes.len() <= self.max_header_entries,
+                DagError::HeaderTooLarge(header_id.clone(), header.votes.len())
+            );
+            // Only tally votes their author signed: the header signature covers all of them.
+            header.verify(&self.committee)?;
+            self.inline_limits.check(header)?;
+
+            // Far headers still count to catch up: `observe_round` keeps one round per author.
+            self.observe_round(header);
+            self.check_round(header, header_id)?;
+        }
+        if !self.accept_dependencies(header, header_id).await? {
+            return Ok(false);
+        }
 
         if header.author == self.name {
             info!("Received own header with {} votes from {}", header.votes.len(), header.author);
+            // Once out, the header must not be followed by another of the same round after a
+            // restart: its round goes to disk first.
+            self.store.write(ROUND_KEY.to_vec(), encode(&header.round)?).await;
+            self.store.flush().await?;
 
             // broadcast header
-            let bytes = bincode::serialize(&PrimaryMessage::Header(header.clone()))
-                .expect("Failed to serialize our own header");
-            let handlers = self.network.broadcast(self.addresses.clone(), Bytes::from(bytes)).await;
+            self.send(header.clone(), self.holdback()).await;
         }
         else {
             info!("Received header with {} votes from {}", header.votes.len(), header.author);
         }
-  
-        for vote in &header.votes {
+
+        let signatures: BTreeMap<_, _> = header.signed_commits().collect();
+        let batch_commits = self.batch_commit_votes(header).await?;
+        self.hold_batch(header, header_id);
+        let mut certificates = Vec::new();
+        let mut failures = Vec::new();
+        for vote in header.votes.iter().chain(&batch_commits) {
+            if let Err(e) = self.check_vote_round(vote) {
+                debug!("{}", e);
+                continue;
+            }
+            if self.decided.contains(&vote.election_id) {
+                continue;
+            }
+            // The claims of an election stop growing once it is over.
+            let over = self
+                .elections
+                .get(&vote.election_id)
+                .is_some_and(|x| x.decided || x.failed);
+            if !over {
+                self.record_claim(header_id, header.author, vote).await?;
+            }
             if !vote.commit {
                 //info!("Received vote {:?} from {}", vote, header.author);
             }
@@ -180,19 +1369,19 @@ impl Core {
                 //info!("Received commit {:?} from {}", vote, header.author);
             }
             let (tx_hash, election_id) = (vote.tx_hash.clone(), vote.election_id.clone()); 
-            if !self.byzantine {
+            if self.byzantine != Some(ByzantineStrategy::Silent) {
                 match self.elections.get_mut(&election_id) {
                     Some(election) => {
                         election.insert_vote(&vote, header.author);
                     }
                     None => {
                         // create election
-                        let election = Election::new();
+                        let election = Election::new(&self.committee);
                         self.elections.insert(election_id.clone(), election);
 
                         #[cfg(feature = "benchmark")]
                         // NOTE: This log entry is used to compute performance.
-                        info!("Created {} -> {:?}", vote, election_id);
+                        info!("{}", bench_support::created(&vote, &vote.tx_hash));
                             
                         let mut election = self.elections.get_mut(&election_id).unwrap();
                         // insert vote
@@ -200,51 +1389,99 @@ impl Core {
                     }
                 }
 
+                // A contested election no longer waits for the batch commit of its header.
+                if vote.round == 0 {
+                    self.release_stalled(&election_id, Instant::now());
+                }
+
                 // decide vote
                 let election = self.elections.get_mut(&election_id).unwrap();
-                if !election.decided {
+
+                // Only keep valid signatures: a single forged one would void the certificate.
+                if let Some(signature) = signatures.get(vote) {
+                    if signature.verify(&vote.commit_digest(), &header.author).is_ok() {
+                        election.insert_commit_signature(vote, header.author, (*signature).clone());
+                    }
+                }
+
+                // start the round timer on its first vote
+                if let Some(tally) = election.tallies.get_mut(&vote.round) {
+                    let expires_at = Instant::now() + Duration::from_millis(self.vote_timeout);
+                    if tally.arm(expires_at) {
+                        self.timers.insert_at((election_id.clone(), vote.round), expires_at);
+                    }
+                }
+                if !election.decided && !election.failed {
                     if let Some(tally) = election.tallies.get(&vote.round) {
 
                         // reaches quorum of commits in this round
-                        if let Some(_) = election.find_quorum_of_commits() {
+                        if let Some((round, tx_hash)) = election.find_quorum_of_commits() {
                             //#[cfg(not(feature = "benchmark"))]
                             //info!("Committed {}", vote);
                                                     
                             #[cfg(feature = "benchmark")]
                             // NOTE: This log entry is used to compute performance.
-                            info!("Committed {} -> {:?}", vote, election_id);
+                            info!("{}", bench_support::committed(&vote, &tx_hash));
+                            #[cfg(feature = "benchmark")]
+                            // NOTE: This log entry is used to check conflicting transactions.
+                            info!("{}", bench_support::decided(&election_id, &tx_hash));
+                            let decision = (election_id.clone(), tx_hash.clone(), round);
+                            certificates.push(CommitCertificate {
+                                election_id: election_id.clone(),
+                                tx_hash: tx_hash.clone(),
+                                round,
+                                votes: election.commit_signatures(round, tx_hash),
+                            });
                             election.decided = true;
+                            self.retained.push_back(election_id.clone());
+                            self.tx_output
+                                .send(ElectionOutcome::Decided(decision))
+                                .await
+                                .expect("Failed to output decision");
                         }
 
+                            // Out of rounds, we give up on the election rather than vote in the
+                            // next round. The commits of the last round may still decide it though:
+                            // we wait for them until they cannot reach a quorum.
+                            let last_round = !election.decided
+                                && out_of_rounds(self.max_election_rounds, vote.round + 1);
+                            let waiting = last_round && election.awaits_commits(vote.round);
+                            let exhausted = last_round && !waiting;
+
                             // reaches quorum of votes in this round
-                            if let Some(tx_hash) = tally.find_quorum_of_votes() {
-                                if !election.voted_or_committed(&self.name, vote.round+1) {
-                                    election.commit = Some(tx_hash.clone());
-                                    election.proof_round = Some(vote.round);
-                                    let vote = Vote::new(vote.round + 1, tx_hash.clone(), election_id, true).await;
-                                    election.insert_vote(&vote, self.name);
-
-                                    self.votes.push(vote);
+                            if let Some(tx_hash) = tally.find_quorum_of_votes().filter(|_| !waiting) {
+                                // Our commits to the votes of a held header leave in its batch commit.
+                                let held = vote.round == 0
+                                    && self.batches.as_ref().is_some_and(|x| x.held(&election_id).is_some());
+                                if !held && !election.voted_or_committed(&self.name, vote.round+1) {
+                                    if exhausted {
+                                        election.failed = true;
+                                        failures.push(election_id);
+                                    } else {
+                                        election.commit = Some(tx_hash.clone());
+                                        election.proof_round = Some(vote.round);
+                                        let vote = Vote::new(vote.round + 1, tx_hash.clone(), election_id, true).await;
+                                        cast_vote(election, &mut self.votes, self.name, vote);
+                                    }
                                 }
                             }
 
                             // voted in this round already, not voted in the next round
-                            else if election.voted_or_committed(&self.name, vote.round) && ((tally.total_votes() >= QUORUM && *tally.timer.0.lock().unwrap() == Timer::Expired) || tally.total_votes() == NUMBER_OF_NODES)
-                            && !election.voted_or_committed(&self.name, vote.round + 1) {
-                                let mut highest = election.highest.clone().unwrap();
-                                let mut committed = false;
-
-                                    if let Some(commit) = &election.commit {
-                                        highest = commit.clone();
-                                        committed = true;
-                                    }
-                                let vote = Vote::new(vote.round+1, highest, election_id, committed).await;
-                                self.votes.push(vote.clone());
-                                election.insert_vote(&vote, self.name);
+                            else if !waiting && election.can_advance(&self.name, vote.round, Instant::now()) {
+                                if exhausted {
+                                    election.failed = true;
+                                    failures.push(election_id);
+                                } else {
+                                    let vote = election.next_round_vote(&election_id, vote.round);
+                                    cast_vote(election, &mut self.votes, self.name, vote);
+                                }
                             }
 
-                            // not voted in this round yet
-                            else if !election.voted_or_committed(&self.name, vote.round) {
+                            // not voted in this round yet (the votes of others past our last round
+                            // are only counted)
+                            else if !election.voted_or_committed(&self.name, vote.round)
+                                && !out_of_rounds(self.max_election_rounds, vote.round)
+                            {
                                 let mut tx_hash = tx_hash;
                                 if let Some(highest) = &election.highest {
                                     tx_hash = highest.clone();
@@ -253,12 +1490,12 @@ impl Core {
                                     tx_hash = commit.clone();
                                 }
                                 let vote = Vote::new(vote.round, tx_hash, election_id, vote.commit).await;
-                                election.insert_vote(&vote, self.name);
-                                self.votes.push(vote);                         
+                                cast_vote(election, &mut self.votes, self.name, vote);
                             }
                         //}               
                     }
                 }
+                self.check_held(&vote.election_id);
                 //info!("Election of {:?}: {:?}", &election_id, self.elections.get(&election_id).unwrap());
             }
             else {
@@ -291,48 +1528,137 @@ impl Core {
 
             //info!("VOTES: {}", self.votes.len());
         }
-        if self.votes.len() >= self.header_size {
+        self.make_batch_commits().await?;
+        for certificate in certificates {
+            self.certify(certificate).await?;
+        }
+        for election_id in failures {
+            self.fail(election_id).await;
+        }
+        self.cleanup().await;
+
+        if self.votes.len() + self.batch_commits.len() >= self.header_size {
             //for vote in &self.votes {
                 //info!("{} sending vote {:?}", self.name, vote);
             //}
             // broadcast votes
-            let own_header = Header::new(self.name, self.votes.drain(..).collect(), &mut self.signature_service).await;
-            let bytes = bincode::serialize(&PrimaryMessage::Header(own_header.clone()))
-                .expect("Failed to serialize our own header");
-            let handlers = self.network.broadcast(self.addresses.clone(), Bytes::from(bytes)).await;
+            self.flush_votes().await;
         }
         
-        Ok(())
+        Ok(true)
+    }
+
+    /// Process a message of another primary. The errors caused by the header of a peer count
+    /// against it (see `PeerErrors`) and are reported here, along with the peer; only storage
+    /// failures go up.
+    async fn process_message(&mut self, message: PrimaryMessage) -> DagResult<()> {
+        let (author, result) = match message {
+            PrimaryMessage::Header(header) if header.author == self.name => {
+                return self.process_returned_header(&header).await
+            }
+            PrimaryMessage::Header(header) => (header.author, self.process_header(&header).await),
+            PrimaryMessage::CommitCertificate(certificate) => {
+                return self.process_certificate(certificate).await
+            }
+            PrimaryMessage::Reconfigure(reconfiguration) => {
+                return self.process_reconfiguration(reconfiguration).await
+            }
+            _ => panic!("Unexpected core message"),
+        };
+        match result {
+            Err(e @ DagError::StoreError(..)) => Err(e),
+            Err(e) => {
+                self.peer_errors.record(&author, &e);
+                match e {
+                    DagError::TooOld(..) | DagError::TooFar(..) => debug!("{} (from {})", e, author),
+                    _ => warn!("{} (from {})", e, author),
+                }
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
     }
 
     // Main loop listening to incoming messages.
     pub async fn run(&mut self) {
+        if let Err(e) = self.recover_round().await {
+            warn!("Failed to recover our round: {}", e);
+        }
+
         let timer: tokio::time::Sleep = sleep(Duration::from_millis(TIMER));
         tokio::pin!(timer);
 
         loop {
+            // NOTE: `select!` only races the branch futures (channel receives, the timer and the round
+            // timers, which are cancellation safe); the handler of the selected branch then runs to completion. Keep any
+            // state-mutating `await` inside the handlers, never in the branch expressions.
             let result = tokio::select! {
                 // We receive here messages from other primaries.
-                Some(message) = self.rx_primaries.recv() => {
-                    match message {
-                        PrimaryMessage::Header(header) => self.process_header(&header).await,
-                        _ => panic!("Unexpected core message")
-                    }
+                Some(message) = self.rx_primaries.recv() => self.process_message(message).await,
+
+                // A peer (or an operator) asks for the state of an election.
+                Some((election_id, reply)) = self.rx_state_requests.recv() => {
+                    let _ = reply.send(self.elections.get(&election_id).map(Election::state));
+                    Ok(())
                 },
 
                 () = &mut timer => {
                     info!("Votes of {}: {}", self.name, self.votes.len());
 
-                    if self.votes.len() > 0 {
+                    if !self.votes.is_empty() || !self.batch_commits.is_empty() {
                         //for vote in &self.votes {
                             //info!("{} sending vote {:?}", self.name, vote);
                         //}
                         // broadcast votes
                         info!("{} sending header with {} votes", self.name, self.votes.len());
-                        let own_header = Header::new(self.name, self.votes.drain(..).collect(), &mut self.signature_service).await;
-                        let bytes = bincode::serialize(&PrimaryMessage::Header(own_header.clone()))
-                            .expect("Failed to serialize our own header");
-                        let handlers = self.network.broadcast(self.addresses.clone(), Bytes::from(bytes)).await;
+                        self.flush_votes().await;
+                    }
+                    debug!(
+                        "Outbound votes: {} flushes (largest {} votes), {} headers (largest {} votes)",
+                        self.metrics.flushes, self.metrics.largest_flush,
+                        self.metrics.bursts, self.metrics.largest_burst,
+                    );
+                    debug!(
+                        "Headers: {} processed, {} copies dropped",
+                        self.seen.processed, self.seen.duplicates,
+                    );
+                    debug!(
+                        "Parked headers: {} waiting, {} evicted",
+                        self.parked.len(), self.parked.evicted,
+                    );
+                    if let Some(batches) = &self.batches {
+                        debug!(
+                            "Batch commits: {} made (for {} elections), {} elections fell back",
+                            batches.committed, batches.covered, batches.fallbacks,
+                        );
+                    }
+                    self.schedule_compaction();
+                    {
+                        let dissemination = self.dissemination.lock().unwrap();
+                        debug!(
+                            "Acknowledged broadcasts: {} disseminated, {} retransmitted, {} under-delivered",
+                            dissemination.disseminated, dissemination.retransmitted,
+                            dissemination.under_delivered,
+                        );
+                    }
+                    if let Some(latest) = self.equivocations().back() {
+                        debug!(
+                            "Equivocations: {} on record, latest by {} in round {} of election {}",
+                            self.equivocations().len(), latest.author, latest.round, latest.election_id,
+                        );
+                    }
+                    for (peer, errors) in &self.peer_errors.counts {
+                        debug!("Errors caused by {}: {:?}", peer, errors);
+                    }
+                    for (address, stats) in self.network.stats() {
+                        if stats.dropped > 0 {
+                            debug!(
+                                "Outbound messages to {}: {} sent, {} dropped, {} queued ({} B), connected for {:?}, round trip {:?}",
+                                address, stats.sent, stats.dropped,
+                                stats.queued_messages, stats.queued_bytes,
+                                stats.connection_age, stats.round_trip,
+                            );
+                        }
                     }
 
                     let deadline = Instant::now() + Duration::from_millis(TIMER);
@@ -342,7 +1668,32 @@ impl Core {
                 }
 
                 // We also receive here our new headers created by the `Proposer`.
-                Some(header) = self.rx_proposer.recv() => self.process_header(&header).await,
+                Some(header) = self.rx_proposer.recv() => {
+                    let header = self.current_epoch(header).await;
+                    self.process_header(&header).await
+                },
+
+                // The parents and batches of a parked header are now stored.
+                Some(header) = self.rx_loopback.recv() => self.process_header(&header).await,
+
+                // A paced chunk of our votes is due.
+                Some(header) = self.rx_paced.recv() => {
+                    let header = self.current_epoch(header).await;
+                    self.broadcast(&header).await;
+                    Ok(())
+                }
+
+                // The timer of a voting round expired.
+                Some(expired) = self.timers.next() => {
+                    match expired {
+                        Ok(expired) => {
+                            let (election_id, round) = expired.into_inner();
+                            self.process_timeout(election_id, round).await;
+                        }
+                        Err(e) => warn!("Failed to expire a voting round: {}", e),
+                    }
+                    Ok(())
+                }
             };
             match result {
                 Ok(()) => (),
@@ -350,7 +1701,7 @@ impl Core {
                     error!("{}", e);
                     panic!("Storage failure: killing node.");
                 }
-                Err(e @ DagError::TooOld(..)) => debug!("{}", e),
+                Err(e @ DagError::TooOld(..)) | Err(e @ DagError::TooFar(..)) => debug!("{}", e),
                 Err(e) => warn!("{}", e),
             }
         }
@@ -0,0 +1,73 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+#[cfg(test)]
+#[path = "tests/dedup_tests.rs"]
+pub mod dedup_tests;
+
+/// What became of a header once we processed it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Accepted,
+    Rejected(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Accepted => write!(f, "accepted"),
+            Self::Rejected(reason) => write!(f, "rejected ({})", reason),
+        }
+    }
+}
+
+/// The headers we already processed, so that the copies we receive again (through redundant
+/// broadcasts or retransmissions) are dropped before any work. Only the `capacity` most recent
+/// headers are remembered.
+pub struct SeenHeaders {
+    capacity: usize,
+    outcomes: HashMap<Digest, Outcome>,
+    /// The remembered headers, oldest first.
+    order: VecDeque<Digest>,
+    /// The number of headers processed.
+    pub processed: u64,
+    /// The number of copies of already processed headers dropped.
+    pub duplicates: u64,
+}
+
+impl SeenHeaders {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            outcomes: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            processed: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// The outcome of a header we already processed, counting the copy as a duplicate.
+    pub fn check(&mut self, header_id: &Digest) -> Option<&Outcome> {
+        let outcome = self.outcomes.get(header_id);
+        if outcome.is_some() {
+            self.duplicates += 1;
+        }
+        outcome
+    }
+
+    /// Remember the outcome of a header, forgetting the oldest one if we hold too many.
+    pub fn insert(&mut self, header_id: Digest, outcome: Outcome) {
+        if self.capacity == 0 || self.outcomes.contains_key(&header_id) {
+            return;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.outcomes.remove(&oldest);
+            }
+        }
+        self.order.push_back(header_id.clone());
+        self.outcomes.insert(header_id, outcome);
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use config::{Committee, Stake};
+use crypto::{Digest, PublicKey};
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::StreamExt as _;
+use log::{debug, warn};
+use network::{CancelHandler, KeepAlive, ReliableSender};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+#[cfg(test)]
+#[path = "tests/dissemination_tests.rs"]
+pub mod dissemination_tests;
+
+/// The outcomes of our acknowledged broadcasts.
+#[derive(Clone, Debug, Default)]
+pub struct DisseminationMetrics {
+    /// The number of messages acknowledged by a quorum of peers.
+    pub disseminated: u64,
+    /// The number of messages sent a second time because no quorum acknowledged them in time.
+    pub retransmitted: u64,
+    /// The number of messages that no quorum acknowledged, even after their retransmission.
+    pub under_delivered: u64,
+}
+
+/// A peer we broadcast to.
+#[derive(Clone)]
+struct Peer {
+    name: PublicKey,
+    address: SocketAddr,
+    stake: Stake,
+}
+
+/// What became of a broadcast once a quorum acknowledged it or its timeout expired.
+struct Outcome {
+    digest: Digest,
+    data: Bytes,
+    /// The stake that acknowledged the message (including ours).
+    stake: Stake,
+    /// The peers that did not acknowledge the message.
+    missing: Vec<Peer>,
+    /// Whether this was the retransmission of the message.
+    retransmission: bool,
+}
+
+/// Broadcasts consensus-critical messages (our headers carrying commit votes) over reliable
+/// connections and tracks their acknowledgements, so that we know whether they reached a quorum
+/// of peers. The caller hands over the messages and never waits for their delivery.
+pub struct Disseminator {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The stake needed for a message to be disseminated.
+    quorum: Stake,
+    /// Our own stake; we count as having received our messages.
+    stake: Stake,
+    /// The other primaries.
+    peers: Vec<Peer>,
+    /// How long we wait for a quorum of acknowledgements before retransmitting (in ms).
+    timeout: u64,
+    /// Receives the messages to broadcast (along with their digest).
+    rx_message: Receiver<(Digest, Bytes)>,
+    /// Receives the committee of each new epoch.
+    rx_reconfigure: Receiver<Committee>,
+    /// A reliable network sender, returning a handler per peer resolved upon its acknowledgement.
+    network: ReliableSender,
+    /// The outcomes of the broadcasts so far.
+    metrics: Arc<Mutex<DisseminationMetrics>>,
+}
+
+impl Disseminator {
+    pub fn spawn(
+        name: PublicKey,
+        committee: &Committee,
+        timeout: u64,
+        keepalive: KeepAlive,
+        rx_message: Receiver<(Digest, Bytes)>,
+        rx_reconfigure: Receiver<Committee>,
+        metrics: Arc<Mutex<DisseminationMetrics>>,
+    ) -> JoinHandle<()> {
+        let mut disseminator = Self {
+            name,
+            quorum: 0,
+            stake: 0,
+            peers: Vec::new(),
+            timeout,
+            rx_message,
+            rx_reconfigure,
+            network: ReliableSender::new().with_keepalive(keepalive),
+            metrics,
+        };
+        disseminator.reconfigure(committee);
+        tokio::spawn(async move {
+            disseminator.run().await;
+        })
+    }
+
+    /// Broadcast to the primaries of this committee, and count their acknowledgements against its
+    /// quorum. The broadcasts under way keep the quorum they started with.
+    fn reconfigure(&mut self, committee: &Committee) {
+        self.peers = committee
+            .others_primaries(&self.name)
+            .into_iter()
+            .map(|(name, addresses)| Peer {
+                name,
+                address: addresses.primary_to_primary,
+                stake: committee.stake(&name),
+            })
+            .collect();
+        self.quorum = committee.quorum_threshold();
+        self.stake = committee.stake(&self.name);
+    }
+
+    /// Send a message to the specified peers and return a future resolving once a quorum
+    /// acknowledged it or the timeout expired.
+    async fn broadcast(
+        &mut self,
+        digest: Digest,
+        data: Bytes,
+        stake: Stake,
+        peers: Vec<Peer>,
+        retransmission: bool,
+    ) -> impl std::future::Future<Output = Outcome> {
+        // NOTE: We do not use `ReliableSender::broadcast`, which holds the thread for a random delay.
+        let mut handlers = Vec::new();
+        for peer in peers {
+            let handler = self.network.send(peer.address, data.clone()).await;
+            handlers.push((peer, handler));
+        }
+        Self::waiter(
+            digest,
+            data,
+            stake,
+            handlers,
+            self.quorum,
+            self.timeout,
+            retransmission,
+        )
+    }
+
+    /// Helper function. It waits for the acknowledgements of a message until their stake reaches
+    /// `quorum` or `timeout` (in ms) expires. Dropping the handlers of the peers that did not
+    /// acknowledge cancels the delivery to them.
+    async fn waiter(
+        digest: Digest,
+        data: Bytes,
+        mut stake: Stake,
+        handlers: Vec<(Peer, CancelHandler)>,
+        quorum: Stake,
+        timeout: u64,
+        retransmission: bool,
+    ) -> Outcome {
+        let (peers, handlers): (Vec<_>, Vec<_>) = handlers.into_iter().unzip();
+        let mut acked = vec![false; peers.len()];
+        let mut waiting: FuturesUnordered<_> = handlers
+            .into_iter()
+            .enumerate()
+            .map(|(i, handler)| async move { (i, handler.await.is_ok()) })
+            .collect();
+
+        let timer = sleep(Duration::from_millis(timeout));
+        tokio::pin!(timer);
+        while stake < quorum {
+            tokio::select! {
+                Some((i, ok)) = waiting.next() => {
+                    if ok {
+                        acked[i] = true;
+                        stake += peers[i].stake;
+                    }
+                },
+                () = &mut timer => break,
+            }
+        }
+
+        let missing = peers
+            .into_iter()
+            .zip(acked)
+            .filter(|(_, acked)| !acked)
+            .map(|(peer, _)| peer)
+            .collect();
+        Outcome {
+            digest,
+            data,
+            stake,
+            missing,
+            retransmission,
+        }
+    }
+
+    /// Main loop broadcasting messages and accounting for their acknowledgements.
+    async fn run(&mut self) {
+        let mut waiting = FuturesUnordered::new();
+
+        loop {
+            tokio::select! {
+                Some((digest, data)) = self.rx_message.recv() => {
+                    let peers = self.peers.clone();
+                    let fut = self.broadcast(digest, data, self.stake, peers, false).await;
+                    waiting.push(fut);
+                },
+
+                Some(committee) = self.rx_reconfigure.recv() => self.reconfigure(&committee),
+
+                Some(outcome) = waiting.next() => {
+                    let Outcome { digest, data, stake, missing, retransmission } = outcome;
+                    if stake >= self.quorum {
+                        debug!("Header {} disseminated to a quorum", digest);
+                        self.metrics.lock().unwrap().disseminated += 1;
+                    } else if !retransmission {
+                        warn!(
+                            "Header {} possibly under-delivered ({} of {} stake acknowledged): retransmitting to {} peers",
+                            digest, stake, self.quorum, missing.len()
+                        );
+                        self.metrics.lock().unwrap().retransmitted += 1;
+                        let fut = self.broadcast(digest, data, stake, missing, true).await;
+                        waiting.push(fut);
+                    } else {
+                        let names: Vec<_> = missing.iter().map(|x| x.name.to_string()).collect();
+                        warn!(
+                            "Header {} under-delivered ({} of {} stake acknowledged), unacknowledged by {}",
+                            digest, stake, self.quorum, names.join(", ")
+                        );
+                        self.metrics.lock().unwrap().under_delivered += 1;
+                    }
+                },
+
+                else => break,
+            }
+        }
+    }
+}
@@ -1,7 +1,15 @@
-use std::{collections::{BTreeSet, HashMap}, sync::{Arc, Mutex, Condvar}, thread::{self, sleep}, time::Duration};
-use crypto::{PublicKey as PublicAddress, Digest};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use config::{Committee, Stake};
+use crypto::{PublicKey as PublicAddress, Digest, Signature};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 
-use crate::{Round, Header, constants::{QUORUM, SEMI_QUORUM}, core::TxHash, messages::Vote};
+use crate::{Round, core::TxHash, messages::Vote};
+
+#[cfg(test)]
+#[path = "tests/election_tests.rs"]
+pub mod election_tests;
 
 pub type ElectionId = Digest;
 
@@ -10,40 +18,93 @@ pub struct Election {
     //pub round: Round,
     pub tallies: HashMap<Round, Tally>,
     pub decided: bool,
+    /// Whether we gave up on the election, out of voting rounds. We then no longer vote in it.
+    pub failed: bool,
     pub commit: Option<Digest>,
     pub highest: Option<Digest>,
     pub proof_round: Option<Round>,
+    /// The stake behind the same tx hash that forms a quorum of votes (or commits).
+    pub quorum: Stake,
+    /// The stake of each authority.
+    stakes: HashMap<PublicAddress, Stake>,
+    /// The stake of the whole committee.
+    total_stake: Stake,
+    /// The authors that sent two different votes for the same round.
+    equivocators: BTreeSet<PublicAddress>,
     //pub voted: bool,
     //pub committed: bool,
 }
 
 impl Election {
-    pub fn new() -> Self {
+    pub fn new(committee: &Committee) -> Self {
+        let quorum = committee.quorum_threshold();
+        let stakes: HashMap<_, _> = committee
+            .authorities
+            .iter()
+            .map(|(name, authority)| (*name, authority.stake))
+            .collect();
+        let total_stake = stakes.values().sum();
         let mut tallies = HashMap::new();
-        tallies.insert(0, Tally::new());
+        tallies.insert(0, Tally::new(quorum));
         Self {
             //round: 0,
             tallies,
             decided: false,
+            failed: false,
             commit: None,
             highest: None,
             proof_round: None,
+            quorum,
+            stakes,
+            total_stake,
+            equivocators: BTreeSet::new(),
             //voted: false,
             //committed: false,
         }
     }
 
-    pub fn find_quorum_of_commits(&self) -> Option<&TxHash> {
-        for (_, tally) in &self.tallies {
+    /// Returns the committed tx hash along with the round of the tally that holds the quorum.
+    pub fn find_quorum_of_commits(&self) -> Option<(Round, &TxHash)> {
+        for (round, tally) in &self.tallies {
             if let Some(digest) = tally.find_quorum_of_commits() {
-                return Some(digest);
+                return Some((*round, digest));
             }
         }
         None
     }
 
+    /// The highest round whose tally holds a quorum of stake (votes and commits alike). Honest
+    /// authorities only move to the round following such a round.
+    pub fn frontier(&self) -> Round {
+        self.tallies
+            .iter()
+            .filter(|(_, tally)| tally.total_stake() + tally.commit_stake() >= self.quorum)
+            .map(|(round, _)| *round)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether the election is in its final rounds: it holds commits but is not decided yet.
+    pub fn committing(&self) -> bool {
+        !self.decided && self.tallies.values().any(|x| !x.commits.is_empty())
+    }
+
+    /// The authors caught sending two different votes for the same round.
+    pub fn equivocators(&self) -> &BTreeSet<PublicAddress> {
+        &self.equivocators
+    }
+
+    /// Count the vote of an author. Each author has a single vote per round: repeating it is a
+    /// no-op, and a different one is recorded as an equivocation and not counted.
     pub fn insert_vote(&mut self, vote: &Vote, author: PublicAddress) {
         let tx_hash = vote.tx_hash.clone();
+        if let Some((previous, commit)) = self.tallies.get(&vote.round).and_then(|x| x.vote_of(&author)) {
+            if (previous != &tx_hash || commit != vote.commit) && self.equivocators.insert(author) {
+                warn!("{} equivocated in round {} of election {}", author, vote.round, vote.election_id);
+            }
+            return;
+        }
+
         if !vote.commit {
             if let Some(highest) = self.highest.clone() {
                 if tx_hash > highest {
@@ -55,18 +116,50 @@ impl Election {
             }
         }
 
+        // Authorities outside the committee have no stake: their votes never count towards a quorum.
+        let stake = self.stakes.get(&author).cloned().unwrap_or_default();
         match self.tallies.get_mut(&vote.round) {
             Some(tally) => {
-                tally.insert_to_tally(tx_hash, author, vote.commit);
+                tally.insert_to_tally(tx_hash, author, stake, vote.commit);
             }
             None => {
-                let mut tally = Tally::new();
-                Tally::insert_to_tally(&mut tally, tx_hash.clone(), author, vote.commit);
+                let mut tally = Tally::new(self.quorum);
+                Tally::insert_to_tally(&mut tally, tx_hash.clone(), author, stake, vote.commit);
                 self.tallies.insert(vote.round, tally);
             }
         }
     }
 
+    /// Keep the signature of a commit counted in its tally, to certify the decision later on.
+    pub fn insert_commit_signature(&mut self, vote: &Vote, author: PublicAddress, signature: Signature) {
+        if let Some(tally) = self.tallies.get_mut(&vote.round) {
+            if tally.commits.get(&vote.tx_hash).is_some_and(|x| x.contains(&author)) {
+                tally
+                    .signatures
+                    .entry(vote.tx_hash.clone())
+                    .or_default()
+                    .insert(author, signature);
+            }
+        }
+    }
+
+    /// The signed commits to a tx hash in a round.
+    pub fn commit_signatures(&self, round: Round, tx_hash: &TxHash) -> Vec<(PublicAddress, Signature)> {
+        self.tallies
+            .get(&round)
+            .and_then(|x| x.signatures.get(tx_hash))
+            .map(|x| x.iter().map(|(author, signature)| (*author, signature.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether an author committed to a tx hash in a round.
+    pub fn committed(&self, author: &PublicAddress, round: Round, tx_hash: &TxHash) -> bool {
+        self.tallies
+            .get(&round)
+            .and_then(|x| x.commits.get(tx_hash))
+            .is_some_and(|x| x.contains(author))
+    }
+
     pub fn voted_or_committed(&self, pa: &PublicAddress, round: Round) -> bool {
         match self.tallies.get(&round) {
             Some(tally) => {
@@ -79,44 +172,171 @@ impl Election {
             None => return false,
         }
         false
-    }    
+    }
+
+    /// Whether we can move past `round` at `now`: we voted in it but not yet in the next one, and
+    /// the round holds a quorum of votes and either timed out or heard from the whole committee.
+    pub fn can_advance(&self, name: &PublicAddress, round: Round, now: Instant) -> bool {
+        match self.tallies.get(&round) {
+            Some(tally) => {
+                let total = tally.total_stake();
+                self.voted_or_committed(name, round)
+                    && !self.voted_or_committed(name, round + 1)
+                    && ((total >= tally.quorum && tally.expired(now))
+                        || total == self.total_stake)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the commits of `round` may still reach a quorum: the round holds some, and not
+    /// enough stake went to votes or to commits for other tx hashes to stop them.
+    pub fn awaits_commits(&self, round: Round) -> bool {
+        match self.tallies.get(&round) {
+            Some(tally) => {
+                let best = tally.commit_stake.values().max().copied().unwrap_or_default();
+                let others = tally.total_stake() + tally.commit_stake() - best;
+                best > 0 && others <= self.total_stake - self.quorum
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `tx_hash` holds a quorum of votes (not commits) in `round`.
+    pub fn has_quorum_of_votes(&self, round: Round, tx_hash: &TxHash) -> bool {
+        self.tallies
+            .get(&round)
+            .is_some_and(|x| x.vote_stake.get(tx_hash).is_some_and(|x| *x >= self.quorum))
+    }
+
+    /// Whether `tx_hash` stalled in `round` at `now`: the votes for other tx hashes leave it no
+    /// chance of a quorum, or the round timed out holding a quorum of votes but not for it. The
+    /// votes of a minority alone cannot stall a tx hash before the timeout.
+    pub fn stalled(&self, round: Round, tx_hash: &TxHash, now: Instant) -> bool {
+        let tally = match self.tallies.get(&round) {
+            Some(tally) => tally,
+            None => return false,
+        };
+        let stake = tally.vote_stake.get(tx_hash).copied().unwrap_or_default();
+        let others = tally.total_stake() - stake;
+        others > self.total_stake - self.quorum
+            || (stake < self.quorum && tally.total_stake() >= self.quorum && tally.expired(now))
+    }
+
+    /// A snapshot of the election, for debugging.
+    pub fn state(&self) -> ElectionState {
+        let authors = |x: &HashMap<TxHash, BTreeSet<PublicAddress>>| {
+            x.iter().map(|(tx_hash, authors)| (tx_hash.clone(), authors.clone())).collect()
+        };
+        let tallies: BTreeMap<_, _> = self
+            .tallies
+            .iter()
+            .map(|(round, tally)| {
+                let state = TallyState {
+                    votes: authors(&tally.votes),
+                    commits: authors(&tally.commits),
+                };
+                (*round, state)
+            })
+            .collect();
+        ElectionState {
+            round: tallies.keys().next_back().copied().unwrap_or_default(),
+            tallies,
+            highest: self.highest.clone(),
+            commit: self.commit.clone(),
+            decided: self.decided,
+            equivocators: self.equivocators().clone(),
+        }
+    }
+
+    /// Our vote for the round following `round`: the tx hash we committed to if any, or else the
+    /// highest tx hash we have seen. Nodes moving on from a timed-out round have seen a quorum of
+    /// its votes, so they break a split vote alike (or as soon as they hear of the same highest tx
+    /// hash): with f crashed nodes, the next rounds still reach a quorum for a single tx hash.
+    pub fn next_round_vote(&self, election_id: &ElectionId, round: Round) -> Vote {
+        let (tx_hash, commit) = match &self.commit {
+            Some(commit) => (commit.clone(), true),
+            None => (self.highest.clone().expect("We voted without a tx hash"), false),
+        };
+        Vote {
+            round: round + 1,
+            tx_hash,
+            election_id: election_id.clone(),
+            commit,
+        }
+    }
+}
+
+/// A snapshot of an election, as answered to `PrimaryMessage::StateRequest`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElectionState {
+    /// The highest round holding votes or commits.
+    pub round: Round,
+    pub tallies: BTreeMap<Round, TallyState>,
+    pub highest: Option<TxHash>,
+    pub commit: Option<TxHash>,
+    pub decided: bool,
+    /// The authors caught sending two different votes for the same round.
+    pub equivocators: BTreeSet<PublicAddress>,
+}
+
+/// The authors of the votes and of the commits of a round, per tx hash.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TallyState {
+    pub votes: BTreeMap<TxHash, BTreeSet<PublicAddress>>,
+    pub commits: BTreeMap<TxHash, BTreeSet<PublicAddress>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Tally {
     pub votes: HashMap<TxHash, BTreeSet<PublicAddress>>,
     pub commits: HashMap<TxHash, BTreeSet<PublicAddress>>,
-    pub timer: Arc<(Mutex<Timer>, Condvar)>,
+    /// The stake of the authors of `votes`, per tx hash.
+    vote_stake: HashMap<TxHash, Stake>,
+    /// The stake of the authors of `commits`, per tx hash.
+    commit_stake: HashMap<TxHash, Stake>,
+    /// When the round times out. The core sets it when the first vote of the round arrives.
+    pub expires_at: Option<Instant>,
+    /// The signatures of the authors of `commits`, per tx hash (when they were valid).
+    signatures: HashMap<TxHash, BTreeMap<PublicAddress, Signature>>,
+    pub quorum: Stake,
 }
 
 impl Tally {
-    pub fn new() -> Self {
-        let timer = Arc::new((Mutex::new(Timer::Active), Condvar::new())).clone();
-        let timer_clone = Arc::clone(&timer);
-        thread::spawn(move || {
-            sleep(Duration::from_millis(ROUND_TIMER as u64));
-            //debug!("round {} of {:?} expired!", round, id);
-            let &(ref mutex, ref cvar) = &*timer_clone;
-            let mut value = mutex.lock().unwrap();
-            *value = Timer::Expired;
-            cvar.notify_one();
-        });
-
+    pub fn new(quorum: Stake) -> Self {
         Self {
             votes: HashMap::new(),
             commits: HashMap::new(),
-            timer,
+            vote_stake: HashMap::new(),
+            commit_stake: HashMap::new(),
+            signatures: HashMap::new(),
+            expires_at: None,
+            quorum,
+        }
+    }
+
+    /// Start the timer of the round, unless it is already running. Returns whether it started.
+    pub fn arm(&mut self, expires_at: Instant) -> bool {
+        if self.expires_at.is_some() {
+            return false;
         }
+        self.expires_at = Some(expires_at);
+        true
+    }
+
+    /// Whether the round timed out at `now`.
+    pub fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|x| now >= x)
     }
 
     pub fn find_quorum_of_votes(&self) -> Option<&TxHash> {
-        for (tx_hash, vote_set) in &self.votes {
-            if vote_set.len() >= QUORUM {
+        for (tx_hash, stake) in &self.vote_stake {
+            if *stake >= self.quorum {
                 return Some(tx_hash);
             }
         }
         for (tx_hash, commit_set) in &self.commits {
-            if commit_set.len() > 0 {
+            if !commit_set.is_empty() {
                 return Some(tx_hash);
             }
         }
@@ -124,37 +344,55 @@ impl Tally {
     }
 
     pub fn find_quorum_of_commits(&self) -> Option<&TxHash> {
-        for (tx_hash, commit_set) in &self.commits {
-            if commit_set.len() >= QUORUM {
+        for (tx_hash, stake) in &self.commit_stake {
+            if *stake >= self.quorum {
                 return Some(tx_hash);
             }
         }
         None
     }
 
+    /// The tx hash with the least stake behind it in this round (the lowest one on ties).
+    pub fn minority(&self) -> Option<&TxHash> {
+        self.vote_stake
+            .iter()
+            .min_by_key(|(tx_hash, stake)| (**stake, *tx_hash))
+            .map(|(tx_hash, _)| tx_hash)
+    }
+
+    /// The tx hash an author voted (or committed) for in this round, and whether it was a commit.
+    fn vote_of(&self, author: &PublicAddress) -> Option<(&TxHash, bool)> {
+        if let Some((tx_hash, _)) = self.votes.iter().find(|(_, x)| x.contains(author)) {
+            return Some((tx_hash, false));
+        }
+        self.commits
+            .iter()
+            .find(|(_, x)| x.contains(author))
+            .map(|(tx_hash, _)| (tx_hash, true))
+    }
+
     pub fn total_votes(&self) -> usize {
         self.votes.values().map(|vote_set| vote_set.len()).sum()
     }
 
-    fn insert_to_tally(&mut self, tx_hash: Digest, author: PublicAddress, is_commit: bool) {
-        let target = if is_commit { &mut self.commits } else { &mut self.votes };
-        match target.get_mut(&tx_hash) {
-            Some(btreeset) => {
-                btreeset.insert(author);
-            }
-            None => {
-                let mut btreeset = BTreeSet::new();
-                btreeset.insert(author);
-                target.insert(tx_hash, btreeset);
-            }
-        }
+    /// The stake of the authors of all votes (not commits) of this round.
+    pub fn total_stake(&self) -> Stake {
+        self.vote_stake.values().sum()
     }
-}
 
-pub const ROUND_TIMER: usize = 0;
+    /// The stake of the authors of all commits of this round.
+    pub fn commit_stake(&self) -> Stake {
+        self.commit_stake.values().sum()
+    }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Timer {
-    Active,
-    Expired,
-}
\ No newline at end of file
+    fn insert_to_tally(&mut self, tx_hash: Digest, author: PublicAddress, stake: Stake, is_commit: bool) {
+        let (target, weights) = if is_commit {
+            (&mut self.commits, &mut self.commit_stake)
+        } else {
+            (&mut self.votes, &mut self.vote_stake)
+        };
+        if target.entry(tx_hash.clone()).or_insert_with(BTreeSet::new).insert(author) {
+            *weights.entry(tx_hash).or_insert(0) += stake;
+        }
+    }
+}
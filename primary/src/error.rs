@@ -1,7 +1,9 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::primary::Round;
+use config::{Epoch, WorkerId};
 use crypto::{Digest as TxHash, PublicKey as PublicAddress, CryptoError};
 use store::StoreError;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
 #[macro_export]
@@ -24,7 +26,7 @@ pub type DagResult<T> = Result<T, DagError>;
 
 #[derive(Debug, Error)]
 pub enum DagError {
-    #[error("Invalid signature")]
+    #[error("Invalid signature: {0}")]
     InvalidSignature(#[from] CryptoError),
 
     #[error("Storage failure: {0}")]
@@ -33,6 +35,9 @@ pub enum DagError {
     #[error("Serialization error: {0}")]
     SerializationError(#[from] Box<bincode::ErrorKind>),
 
+    #[error("Invalid signature of commit {0}")]
+    InvalidVoteSignature(TxHash),
+
     #[error("Invalid header id")]
     InvalidHeaderId,
 
@@ -42,6 +47,9 @@ pub enum DagError {
     #[error("Received message from unknown authority {0}")]
     UnknownAuthority(PublicAddress),
 
+    #[error("Authority {0} has no worker {1}")]
+    UnknownWorker(PublicAddress, WorkerId),
+
     #[error("Authority {0} appears in quorum more than once")]
     AuthorityReuse(PublicAddress),
 
@@ -56,4 +64,55 @@ pub enum DagError {
 
     #[error("Message {0} (round {1}) too old")]
     TooOld(TxHash, Round),
+
+    #[error("Message {0} (round {1}) too far ahead")]
+    TooFar(TxHash, Round),
+
+    #[error("Message {0} is of epoch {1}, not ours")]
+    StaleEpoch(TxHash, Epoch),
+
+    #[error("Header {0} carries {1} votes, more than we accept")]
+    HeaderTooLarge(TxHash, usize),
+}
+
+impl DagError {
+    /// The name of the variant, to count the errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidSignature(..) => "InvalidSignature",
+            Self::StoreError(..) => "StoreError",
+            Self::SerializationError(..) => "SerializationError",
+            Self::InvalidVoteSignature(..) => "InvalidVoteSignature",
+            Self::InvalidHeaderId => "InvalidHeaderId",
+            Self::MalformedHeader(..) => "MalformedHeader",
+            Self::UnknownAuthority(..) => "UnknownAuthority",
+            Self::UnknownWorker(..) => "UnknownWorker",
+            Self::AuthorityReuse(..) => "AuthorityReuse",
+            Self::UnexpectedVote(..) => "UnexpectedVote",
+            Self::CertificateRequiresQuorum => "CertificateRequiresQuorum",
+            Self::HeaderRequiresQuorum(..) => "HeaderRequiresQuorum",
+            Self::TooOld(..) => "TooOld",
+            Self::TooFar(..) => "TooFar",
+            Self::StaleEpoch(..) => "StaleEpoch",
+            Self::HeaderTooLarge(..) => "HeaderTooLarge",
+        }
+    }
+}
+
+/// The errors the messages of each peer caused, by kind (see `DagError::kind`). A message counts
+/// against the author it claims, even if its signature does not check out.
+#[derive(Default)]
+pub struct PeerErrors {
+    pub counts: HashMap<PublicAddress, BTreeMap<&'static str, u64>>,
+}
+
+impl PeerErrors {
+    pub fn record(&mut self, peer: &PublicAddress, error: &DagError) {
+        *self
+            .counts
+            .entry(*peer)
+            .or_default()
+            .entry(error.kind())
+            .or_insert(0) += 1;
+    }
 }
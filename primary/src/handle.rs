@@ -0,0 +1,191 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::{info, warn};
+use std::collections::HashMap;
+use store::Store;
+use tokio::sync::mpsc::Sender;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{timeout_at, Duration, Instant};
+
+/// How long a consumer of decisions asked to stop gets to reach a clean point.
+const CLEAN_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The stages of a shutdown, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShutdownStage {
+    /// The tasks taking in new work: the client ports, mempools and batch makers.
+    Intake,
+    /// The proposer. Our votes it already cast are persisted before the next stage.
+    Proposer,
+    /// The other tasks of the primaries and workers. Once they stop, no decision comes out anymore.
+    Consensus,
+    /// The consumers of the decisions, which run until they applied the ones already delivered.
+    Drain,
+    /// The stores, flushed and closed.
+    Stores,
+}
+
+impl ShutdownStage {
+    const ALL: [ShutdownStage; 5] = [
+        ShutdownStage::Intake,
+        ShutdownStage::Proposer,
+        ShutdownStage::Consensus,
+        ShutdownStage::Drain,
+        ShutdownStage::Stores,
+    ];
+
+    /// How long a shutdown waits on the stage by default.
+    fn default_timeout(&self) -> Duration {
+        match self {
+            ShutdownStage::Drain => Duration::from_secs(10),
+            ShutdownStage::Stores => Duration::from_secs(5),
+            _ => Duration::from_secs(1),
+        }
+    }
+}
+
+struct Task {
+    stage: ShutdownStage,
+    join: JoinHandle<()>,
+    /// Asks the task to stop at a clean point, if it can.
+    stop: Option<Sender<()>>,
+}
+
+/// The tasks of a running node (a primary, workers, and the consumers of their decisions).
+/// Dropping the handle leaves them running.
+#[derive(Default)]
+pub struct NodeHandle {
+    tasks: Vec<Task>,
+    /// The stores flushed at the end of the shutdown.
+    stores: Vec<Store>,
+    /// The timeouts overriding the default ones.
+    timeouts: HashMap<ShutdownStage, Duration>,
+}
+
+impl NodeHandle {
+    /// Add a task stopped (cancelled) in the specified stage.
+    pub fn push(&mut self, stage: ShutdownStage, join: JoinHandle<()>) {
+        self.tasks.push(Task {
+            stage,
+            join,
+            stop: None,
+        });
+    }
+
+    /// Add a consumer of decisions. It gets the whole `Drain` timeout to finish by itself once
+    /// consensus stopped, and is then asked to stop at a clean point before being cancelled.
+    pub fn push_drain(&mut self, join: JoinHandle<()>, stop: Sender<()>) {
+        self.tasks.push(Task {
+            stage: ShutdownStage::Drain,
+            join,
+            stop: Some(stop),
+        });
+    }
+
+    /// Add a store to flush once all tasks stopped.
+    pub fn push_store(&mut self, store: Store) {
+        self.stores.push(store);
+    }
+
+    /// Override how long a shutdown waits on a stage.
+    pub fn set_timeout(&mut self, stage: ShutdownStage, duration: Duration) {
+        self.timeouts.insert(stage, duration);
+    }
+
+    /// Add the tasks and stores of another handle to this one.
+    pub fn extend(&mut self, other: NodeHandle) {
+        self.tasks.extend(other.tasks);
+        self.stores.extend(other.stores);
+    }
+
+    fn timeout(&self, stage: ShutdownStage) -> Duration {
+        self.timeouts
+            .get(&stage)
+            .copied()
+            .unwrap_or_else(|| stage.default_timeout())
+    }
+
+    /// Stop the node and wait until all its tasks finished, one stage after the other (see
+    /// `ShutdownStage`), logging the progress. Tasks are cancelled at their next `await` point,
+    /// except the consumers of decisions which first get to drain them; the network listeners and
+    /// connections of the node are closed along with the tasks owning them. A stage running past
+    /// its timeout is left behind. Returns the error of the first task that panicked, if any.
+    pub async fn shutdown(mut self) -> Result<(), JoinError> {
+        let mut tasks = std::mem::take(&mut self.tasks);
+        let mut result = Ok(());
+        for stage in ShutdownStage::ALL {
+            let now = Instant::now();
+            let deadline = now + self.timeout(stage);
+            let (current, rest): (Vec<_>, Vec<_>) =
+                tasks.into_iter().partition(|x| x.stage == stage);
+            tasks = rest;
+
+            let mut complete = true;
+            for task in &current {
+                if task.stop.is_none() {
+                    task.join.abort();
+                }
+            }
+            for task in current {
+                match Self::join(task, deadline).await {
+                    Some(outcome) => Self::record(&mut result, outcome),
+                    None => complete = false,
+                }
+            }
+            // Our votes are persisted before anything else stops. The stores close once the last
+            // of their handles (here) is dropped.
+            if stage == ShutdownStage::Proposer || stage == ShutdownStage::Stores {
+                complete &= Self::flush(&self.stores, deadline).await;
+            }
+
+            if complete {
+                info!(
+                    "Shutdown: {:?} stopped in {} ms",
+                    stage,
+                    now.elapsed().as_millis()
+                );
+            } else {
+                warn!(
+                    "Shutdown: {:?} timed out after {} ms",
+                    stage,
+                    now.elapsed().as_millis()
+                );
+            }
+        }
+        result
+    }
+
+    /// Wait for a task until the deadline. A consumer of decisions still running then is asked to
+    /// stop, and cancelled if it does not within `CLEAN_STOP_TIMEOUT`.
+    async fn join(mut task: Task, deadline: Instant) -> Option<Result<(), JoinError>> {
+        if let Ok(outcome) = timeout_at(deadline, &mut task.join).await {
+            return Some(outcome);
+        }
+        let stop = task.stop?;
+        let _ = stop.try_send(());
+        let grace = Instant::now() + CLEAN_STOP_TIMEOUT;
+        if let Ok(outcome) = timeout_at(grace, &mut task.join).await {
+            return Some(outcome);
+        }
+        task.join.abort();
+        None
+    }
+
+    /// Flush the stores until the deadline. Returns false if they did not all make it.
+    async fn flush(stores: &[Store], deadline: Instant) -> bool {
+        for mut store in stores.iter().cloned() {
+            match timeout_at(deadline, store.flush()).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => warn!("Failed to flush store: {}", e),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn record(result: &mut Result<(), JoinError>, outcome: Result<(), JoinError>) {
+        match outcome {
+            Err(e) if e.is_panic() && result.is_ok() => *result = Err(e),
+            _ => (),
+        }
+    }
+}
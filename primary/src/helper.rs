@@ -1,22 +1,30 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Header;
 use crate::primary::PrimaryMessage;
+use crate::wire;
 use bytes::Bytes;
-use config::{Committee, PK};
+use config::Committee;
+use crypto::{Digest, PublicKey};
 use log::{error, warn};
-use mc_account_keys::PublicAddress;
-use mc_crypto_keys::tx_hash::TxHash;
-use network::SimpleSender;
+use network::{decode, SimpleSender};
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
 
-/// A task dedicated to help other authorities by replying to their certificates requests.
+#[cfg(test)]
+#[path = "tests/helper_tests.rs"]
+pub mod helper_tests;
+
+/// A task dedicated to help other authorities by replying to their header requests.
 pub struct Helper {
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
     store: Store,
-    /// Input channel to receive certificates requests.
-    rx_primaries: Receiver<(Vec<TxHash>, PK)>,
+    /// Input channel to receive header requests.
+    rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+    /// Receives the committee of each new epoch.
+    rx_reconfigure: Receiver<Committee>,
     /// A network sender to reply to the sync requests.
     network: SimpleSender,
 }
@@ -25,47 +33,62 @@ impl Helper {
     pub fn spawn(
         committee: Committee,
         store: Store,
-        rx_primaries: Receiver<(Vec<TxHash>, PK)>,
-    ) {
+        rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+        rx_reconfigure: Receiver<Committee>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 committee,
                 store,
                 rx_primaries,
+                rx_reconfigure,
                 network: SimpleSender::new(),
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_primaries.recv().await {
-            // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
+        loop {
+            tokio::select! {
+                Some((digests, origin)) = self.rx_primaries.recv() => self.reply(digests, origin).await,
+                Some(committee) = self.rx_reconfigure.recv() => self.committee = committee,
+                else => break,
+            }
+        }
+    }
 
-            // get the requestors address.
-            let address = match self.committee.primary(&origin) {
-                Ok(x) => x.primary_to_primary,
-                Err(e) => {
-                    warn!("Unexpected certificate request: {}", e);
-                    continue;
-                }
-            };
+    async fn reply(&mut self, digests: Vec<Digest>, origin: PublicKey) {
+        // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
+
+        // get the requestors address.
+        let address = match self.committee.primary(&origin) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => {
+                warn!("Unexpected header request: {}", e);
+                return;
+            }
+        };
 
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        // TODO: Remove this deserialization-serialization in the critical path.
-                        let certificate = bincode::deserialize(&data)
-                            .expect("Failed to deserialize our own certificate");
-                        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
-                            .expect("Failed to serialize our own certificate");
-                        self.network.send(address, Bytes::from(bytes)).await;
-                    }
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
+        // Reply to the request (the best we can).
+        for digest in digests {
+            match self.store.read(digest.to_vec()).await {
+                Ok(Some(data)) => {
+                    // TODO: Remove this deserialization-serialization in the critical path.
+                    let header: Header = match decode(&data) {
+                        Ok(header) => header,
+                        Err(_) => {
+                            warn!("Requested digest {} is not a header", digest);
+                            continue;
+                        }
+                    };
+                    let bytes = wire::serialize(&PrimaryMessage::Header(header))
+                        .expect("Failed to serialize our own header");
+                    self.network.send(address, Bytes::from(bytes)).await;
                 }
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
             }
         }
     }
@@ -3,21 +3,40 @@
 mod error;
 //mod aggregators;
 //mod certificate_waiter;
+mod compaction;
+mod batching;
 mod core;
+mod dedup;
+mod dissemination;
+mod handle;
 //mod garbage_collector;
 //mod header_waiter;
-//mod helper;
+mod helper;
 mod messages;
+mod pacing;
+mod parking;
 mod payload_receiver;
 mod primary;
 mod proposer;
+mod reproposer;
 mod election;
+mod vote_log;
+mod window;
+mod wire;
 //mod synchronizer;
-mod constants;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::messages::{Header, Hash};
-pub use crate::primary::{Primary, PrimaryWorkerMessage, Round, WorkerPrimaryMessage, Transaction};
+pub use crate::election::{ElectionState, TallyState};
+pub use crate::handle::{NodeHandle, ShutdownStage};
+pub use crate::messages::{Header, Hash, Reconfiguration};
+pub use crate::primary::{
+    inspect, reconfigure, unauthenticated_worker_messages, Decision, ElectionOutcome, Primary,
+    PrimaryWorkerMessage, Round, SignedWorkerMessage, Transaction, WorkerPrimaryMessage,
+};
+pub use crate::proposer::unauthenticated_forwards;
+pub use crate::wire::{
+    frame_content, open_frame, seal_frame, unknown_versions, WireError, PROTOCOL_VERSION,
+};
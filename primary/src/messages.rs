@@ -2,8 +2,8 @@ use crate::core::TxHash;
 use crate::election::ElectionId;
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
-use crate::primary::Round;
-use config::{Committee, WorkerId};
+use crate::primary::{Round, Transaction};
+use config::{Committee, Epoch, WorkerId};
 use ed25519_dalek::{Digest as _, Sha512};
 use crypto::{Digest, PublicKey as PublicAddress, Signature, SignatureService};
 use serde::{Deserialize, Serialize};
@@ -11,6 +11,10 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::convert::{TryInto, TryFrom};
 use std::fmt;
 
+#[cfg(test)]
+#[path = "tests/messages_tests.rs"]
+pub mod messages_tests;
+
 /// This trait is implemented by all messages that can be hashed.
 pub trait Hash {
     fn digest(&self) -> TxHash;
@@ -19,15 +23,40 @@ pub trait Hash {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Header {
     pub author: PublicAddress,
+    /// The epoch of the committee the author signed this header in. Headers of other epochs
+    /// are rejected.
+    pub epoch: Epoch,
+    /// The DAG round the author was at when it made this header.
+    pub round: Round,
     pub votes: BTreeSet<Vote>,
+    /// The workers of the author holding the batches it votes for, so that the primaries missing
+    /// some of them know which of their own workers should fetch them.
+    pub payload: BTreeMap<TxHash, WorkerId>,
+    /// The digests of the headers this header causally follows.
+    pub parents: BTreeSet<Digest>,
+    /// The transactions small enough to be proposed inline that the votes are for, by tx hash.
+    /// Voting for them takes no batch.
+    pub inline: BTreeMap<TxHash, Transaction>,
+    /// The commits of the author to whole headers at once, in place of one commit per vote.
+    pub batch_commits: BTreeSet<BatchCommit>,
     pub signature: Signature,
+    /// The signatures of the author over its commits (see `Vote::commit_digest`), in the order of
+    /// `votes`. They let peers prove a decision with a `CommitCertificate`.
+    pub commit_signatures: Vec<Signature>,
     //pub id: Digest,
 }
 
 impl Header {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         author: PublicAddress,
+        epoch: Epoch,
+        round: Round,
         votes: BTreeSet<Vote>,
+        payload: BTreeMap<TxHash, WorkerId>,
+        parents: BTreeSet<Digest>,
+        inline: BTreeMap<TxHash, Transaction>,
+        batch_commits: BTreeSet<BatchCommit>,
         signature_service: &mut SignatureService,
     ) -> Self {
         // Ensure all votes in the same round with the same election_id are unique.
@@ -41,20 +70,39 @@ impl Header {
 
         let header = Self {
             author,
-            votes: BTreeSet::new(),
+            epoch,
+            round,
+            votes,
+            payload,
+            parents,
+            inline,
+            batch_commits,
             signature: Signature::default(),
+            commit_signatures: Vec::new(),
             //id: Digest::default(),
         };
-        let id = header.digest();
-        let signature = signature_service.request_signature(Digest::default()).await;
+        // Sign the header and each of its commits in a single request.
+        let digests = std::iter::once(header.digest())
+            .chain(header.votes.iter().filter(|x| x.commit).map(Vote::commit_digest))
+            .collect();
+        let mut commit_signatures = signature_service.sign_batch(digests).await;
+        let signature = commit_signatures.remove(0);
         Self {
             //id,
-            votes,
             signature,
+            commit_signatures,
             ..header
         }
     }
 
+    /// The commits of this header along with their signatures.
+    pub fn signed_commits(&self) -> impl Iterator<Item = (&Vote, &Signature)> {
+        self.votes
+            .iter()
+            .filter(|x| x.commit)
+            .zip(self.commit_signatures.iter())
+    }
+
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
         // Ensure the header id is well formed.
         //ensure!(self.digest() == self.id, DagError::InvalidHeaderId);
@@ -64,16 +112,35 @@ impl Header {
         ensure!(voting_rights > 0, DagError::UnknownAuthority(self.author.clone()));
 
         // Ensure all worker ids are correct.
-        /*for worker_id in self.payload.values() {
+        for worker_id in self.payload.values() {
             committee
-                .worker(&PK(self.author.to_bytes()), &worker_id)
-                .map_err(|_| DagError::MalformedHeader(self.id.clone()))?;
-        }*/
+                .worker(&self.author, worker_id)
+                .map_err(|_| DagError::MalformedHeader(self.digest()))?;
+        }
 
-        // Check the signature.
-        self.signature
-            .verify(&Digest::default(), &self.author)
-            .map_err(DagError::from)
+        // Ensure the inline transactions are filed under their tx hash.
+        for (tx_hash, transaction) in &self.inline {
+            ensure!(
+                transaction.digest() == *tx_hash,
+                DagError::MalformedHeader(self.digest())
+            );
+        }
+
+        // Ensure every commit is signed.
+        let commits = self.votes.iter().filter(|x| x.commit).count();
+        ensure!(
+            commits == self.commit_signatures.len(),
+            DagError::MalformedHeader(self.digest())
+        );
+
+        // Check the signatures.
+        self.signature.verify(&self.digest(), &self.author)?;
+        for (vote, signature) in self.signed_commits() {
+            signature
+                .verify(&vote.commit_digest(), &self.author)
+                .map_err(|_| DagError::InvalidVoteSignature(vote.digest()))?;
+        }
+        Ok(())
     }
 }
 
@@ -81,14 +148,63 @@ impl Hash for Header {
     fn digest(&self) -> TxHash {
         let mut hasher = Sha512::new();
         hasher.update(self.author);
-        //hasher.update(self.round.to_le_bytes());
+        hasher.update(self.epoch.to_le_bytes());
+        hasher.update(self.round.to_le_bytes());
         for vote in &self.votes {
             hasher.update(vote.digest());
         }
-        //for x in &self.parents {
-            //hasher.update(x);
-        //}
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        for (tx_hash, worker_id) in &self.payload {
+            hasher.update(tx_hash);
+            hasher.update(worker_id.to_le_bytes());
+        }
+        for x in &self.parents {
+            hasher.update(x);
+        }
+        // The inline transactions are covered through their tx hash (see `verify`).
+        for tx_hash in self.inline.keys() {
+            hasher.update(tx_hash);
+        }
+        for batch in &self.batch_commits {
+            hasher.update(&batch.header);
+            hasher.update((batch.excluded.len() as u64).to_le_bytes());
+            for election_id in &batch.excluded {
+                hasher.update(election_id);
+            }
+        }
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
+}
+
+/// The bounds on the transactions proposed inline (see `Header::inline`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InlineLimits {
+    /// The largest inline transaction. Proposing inline is disabled if zero.
+    pub transaction_size: usize,
+    /// The largest total size of the inline transactions of a header.
+    pub header_bytes: usize,
+}
+
+impl InlineLimits {
+    /// Whether a transaction is small enough to be proposed inline.
+    pub fn fits(&self, transaction: &Transaction) -> bool {
+        self.transaction_size > 0 && transaction.data.len() <= self.transaction_size
+    }
+
+    /// Ensure the inline transactions of a header are within the bounds.
+    pub fn check(&self, header: &Header) -> DagResult<()> {
+        let mut bytes = 0;
+        for transaction in header.inline.values() {
+            ensure!(
+                self.fits(transaction),
+                DagError::MalformedHeader(header.digest())
+            );
+            bytes += transaction.data.len();
+        }
+        ensure!(
+            bytes <= self.header_bytes,
+            DagError::MalformedHeader(header.digest())
+        );
+        Ok(())
     }
 }
 
@@ -130,15 +246,30 @@ impl Vote {
             round, tx_hash, election_id, commit,
         }
     }
+
+    /// The digest an author signs to commit to a tx hash in a round of an election. It is kept
+    /// apart from `digest` so that the signature of a commit cannot pass for any other message.
+    pub fn commit_digest(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(b"commit");
+        hasher.update(self.round.to_le_bytes());
+        hasher.update(&self.tx_hash);
+        hasher.update(&self.election_id);
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
 }
 
 impl Hash for Vote {
+    /// Covers every field of the vote, the commit flag included: the signature of a header then
+    /// pins down which of its votes are commits.
     fn digest(&self) -> TxHash {
         let mut hasher = Sha512::new();
+        hasher.update(b"vote");
         hasher.update(self.round.to_le_bytes());
         hasher.update(&self.tx_hash);
         hasher.update(&self.election_id);
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.update([self.commit as u8]);
+        Digest(hasher.finalize()[..32].try_into().unwrap())
     }
 }
 
@@ -161,3 +292,133 @@ impl fmt::Display for Vote {
     }
 }
 
+/// The committee of the next epoch, signed by a quorum of the current one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reconfiguration {
+    pub committee: Committee,
+    pub signatures: Vec<(PublicAddress, Signature)>,
+}
+
+impl Reconfiguration {
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure it moves to the epoch following ours.
+        ensure!(
+            self.committee.epoch == committee.epoch + 1,
+            DagError::StaleEpoch(self.digest(), self.committee.epoch)
+        );
+
+        // Ensure a quorum of the current committee signed it.
+        let mut weight = 0;
+        let mut used = HashSet::new();
+        for (name, _) in &self.signatures {
+            ensure!(!used.contains(name), DagError::AuthorityReuse(*name));
+            let voting_rights = committee.stake(name);
+            ensure!(voting_rights > 0, DagError::UnknownAuthority(*name));
+            used.insert(*name);
+            weight += voting_rights;
+        }
+        ensure!(
+            weight >= committee.quorum_threshold(),
+            DagError::CertificateRequiresQuorum
+        );
+
+        // Check the signatures.
+        Signature::verify_batch(&self.digest(), &self.signatures).map_err(DagError::from)
+    }
+}
+
+impl Hash for Reconfiguration {
+    /// Covers the whole committee, in an order independent of its encoding: the worker
+    /// addresses live in hash maps.
+    fn digest(&self) -> TxHash {
+        let mut hasher = Sha512::new();
+        hasher.update(b"reconfigure");
+        hasher.update(self.committee.epoch.to_le_bytes());
+        for (name, authority) in &self.committee.authorities {
+            hasher.update(name);
+            hasher.update(authority.stake.to_le_bytes());
+            hasher.update(authority.primary.primary_to_primary.to_string());
+            hasher.update(authority.primary.worker_to_primary.to_string());
+            let workers: BTreeMap<_, _> = authority.workers.iter().collect();
+            for (id, addresses) in workers {
+                hasher.update(id.to_le_bytes());
+                hasher.update(addresses.transactions.to_string());
+                hasher.update(addresses.worker_to_worker.to_string());
+                hasher.update(addresses.primary_to_worker.to_string());
+            }
+            hasher.update([authority.byzantine as u8]);
+        }
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
+}
+
+/// The commit of an author to all the round-0 votes of a header but those of the excluded
+/// elections. It stands for the author's commit (in round 1) to each of them, which saves a vote
+/// per election when none of them is contested. Batch commits carry no commit signature, so the
+/// elections they decide are not certified (see `CommitCertificate`).
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BatchCommit {
+    /// The digest of the header whose votes are committed to.
+    pub header: Digest,
+    /// The elections of the header left to be committed on their own.
+    pub excluded: BTreeSet<ElectionId>,
+}
+
+impl BatchCommit {
+    /// The commits the batch commit stands for, given the header it refers to.
+    pub fn commits(&self, header: &Header) -> Vec<Vote> {
+        header
+            .votes
+            .iter()
+            .filter(|x| x.round == 0 && !x.commit && !self.excluded.contains(&x.election_id))
+            .map(|x| Vote {
+                round: 1,
+                tx_hash: x.tx_hash.clone(),
+                election_id: x.election_id.clone(),
+                commit: true,
+            })
+            .collect()
+    }
+}
+
+/// The proof that an election was decided: the signatures of a quorum of authorities over their
+/// commits to the same tx hash in the same round.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommitCertificate {
+    pub election_id: ElectionId,
+    pub tx_hash: TxHash,
+    pub round: Round,
+    pub votes: Vec<(PublicAddress, Signature)>,
+}
+
+impl CommitCertificate {
+    /// The commit signed by every vote of the certificate.
+    pub fn commit(&self) -> Vote {
+        Vote {
+            round: self.round,
+            tx_hash: self.tx_hash.clone(),
+            election_id: self.election_id.clone(),
+            commit: true,
+        }
+    }
+
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure the certificate has a quorum.
+        let mut weight = 0;
+        let mut used = HashSet::new();
+        for (name, _) in &self.votes {
+            ensure!(!used.contains(name), DagError::AuthorityReuse(*name));
+            let voting_rights = committee.stake(name);
+            ensure!(voting_rights > 0, DagError::UnknownAuthority(*name));
+            used.insert(*name);
+            weight += voting_rights;
+        }
+        ensure!(
+            weight >= committee.quorum_threshold(),
+            DagError::CertificateRequiresQuorum
+        );
+
+        // Check the signatures.
+        Signature::verify_batch(&self.commit().commit_digest(), &self.votes).map_err(DagError::from)
+    }
+}
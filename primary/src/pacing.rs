@@ -0,0 +1,82 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Vote;
+use crypto::{Digest, PublicKey};
+use ed25519_dalek::{Digest as _, Sha512};
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/pacing_tests.rs"]
+pub mod pacing_tests;
+
+/// Spreads the outbound votes of a flush over a short window instead of sending them at once,
+/// so that the nodes of the committee do not all burst on the network at the same instant.
+#[derive(Clone, Debug, Default)]
+pub struct Pacer {
+    /// The maximum number of votes sent at once. Pacing is disabled if zero.
+    chunk: usize,
+    /// The delay between two consecutive chunks (in ms).
+    interval: u64,
+}
+
+impl Pacer {
+    pub fn new(chunk: usize, interval: u64) -> Self {
+        Self { chunk, interval }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.chunk > 0
+    }
+
+    /// Split a flush into the votes sent right away and the chunks of votes to pace. Commits are
+    /// never paced. Without pacing, the whole flush is sent right away.
+    pub fn split(&self, votes: Vec<Vote>) -> (Vec<Vote>, Vec<Vec<Vote>>) {
+        if !self.enabled() {
+            return (votes, Vec::new());
+        }
+        let (commits, votes): (Vec<_>, Vec<_>) = votes.into_iter().partition(|x| x.commit);
+        let chunks = votes.chunks(self.chunk).map(|x| x.to_vec()).collect();
+        (commits, chunks)
+    }
+
+    /// The delay before sending the `index`-th chunk of a flush (in ms).
+    pub fn delay(&self, name: &PublicKey, header_id: &Digest, index: usize) -> u64 {
+        index as u64 * self.interval + self.jitter(name, header_id)
+    }
+
+    /// A deterministic jitter in `[0, interval)` so that authorities desynchronize naturally.
+    pub fn jitter(&self, name: &PublicKey, header_id: &Digest) -> u64 {
+        if self.interval == 0 {
+            return 0;
+        }
+        let mut hasher = Sha512::new();
+        hasher.update(name);
+        hasher.update(header_id);
+        let bytes = hasher.finalize()[..8].try_into().unwrap();
+        u64::from_le_bytes(bytes) % self.interval
+    }
+}
+
+/// Outbound burst sizes (in votes) before and after pacing.
+#[derive(Clone, Debug, Default)]
+pub struct PacingMetrics {
+    /// The number of flushes.
+    pub flushes: u64,
+    /// The largest number of votes flushed at once (the burst size without pacing).
+    pub largest_flush: usize,
+    /// The number of headers sent.
+    pub bursts: u64,
+    /// The largest number of votes sent in a single header.
+    pub largest_burst: usize,
+}
+
+impl PacingMetrics {
+    pub fn record_flush(&mut self, votes: usize) {
+        self.flushes += 1;
+        self.largest_flush = self.largest_flush.max(votes);
+    }
+
+    pub fn record_burst(&mut self, votes: usize) {
+        self.bursts += 1;
+        self.largest_burst = self.largest_burst.max(votes);
+    }
+}
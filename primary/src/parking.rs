@@ -0,0 +1,65 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/parking_tests.rs"]
+pub mod parking_tests;
+
+/// The headers waiting for some of their parents or batches. Only the `capacity` most recently
+/// parked headers are kept: a header whose dependencies never arrive is eventually evicted, which
+/// also stops the task waiting for them.
+pub struct ParkedHeaders {
+    capacity: usize,
+    /// Dropping the sender of a parked header cancels the task waiting for its dependencies.
+    cancels: HashMap<Digest, oneshot::Sender<()>>,
+    /// The parked headers, oldest first.
+    order: VecDeque<Digest>,
+    /// The number of headers evicted before their dependencies arrived.
+    pub evicted: u64,
+}
+
+impl ParkedHeaders {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            cancels: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            evicted: 0,
+        }
+    }
+
+    pub fn contains(&self, header_id: &Digest) -> bool {
+        self.cancels.contains_key(header_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cancels.len()
+    }
+
+    /// Park a header, evicting the oldest one if we hold too many. Returns the evicted header.
+    pub fn insert(&mut self, header_id: Digest, cancel: oneshot::Sender<()>) -> Option<Digest> {
+        if self.capacity == 0 || self.contains(&header_id) {
+            return None;
+        }
+        let mut evicted = None;
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.cancels.remove(&oldest);
+                self.evicted += 1;
+                evicted = Some(oldest);
+            }
+        }
+        self.order.push_back(header_id.clone());
+        self.cancels.insert(header_id, cancel);
+        evicted
+    }
+
+    /// Forget a header whose dependencies are now stored.
+    pub fn remove(&mut self, header_id: &Digest) {
+        if self.cancels.remove(header_id).is_some() {
+            self.order.retain(|x| x != header_id);
+        }
+    }
+}
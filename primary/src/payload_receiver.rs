@@ -1,31 +1,48 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use config::WorkerId;
+use std::convert::TryInto as _;
 use crypto::Digest as TxHash;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
 
 use crate::election::{ElectionId, self};
 
-/// Receives batches' digests of other authorities. These are only needed to verify incoming
-/// headers (ie. make sure we have their payload).
+/// The store key marking that our workers hold a batch. Its value is the id of the worker holding it.
+pub fn payload_key(tx_hash: &TxHash) -> Vec<u8> {
+    [b"payload".as_ref(), tx_hash.as_ref()].concat()
+}
+
+/// The id of the worker holding a batch, as stored under its `payload_key`.
+pub fn payload_worker(value: &[u8]) -> Option<WorkerId> {
+    value.try_into().ok().map(WorkerId::from_le_bytes)
+}
+
+/// Receives the digests of the batches our workers hold, ours included. These are only needed to
+/// verify incoming headers (ie. make sure we have their payload) and to tell the other primaries
+/// which of our workers holds the batches we vote for.
 pub struct PayloadReceiver {
     /// The persistent storage.
     store: Store,
     /// Receives batches' digests from the network.
-    rx_workers: Receiver<(TxHash, ElectionId)>,
+    rx_workers: Receiver<(TxHash, ElectionId, WorkerId)>,
 }
 
 impl PayloadReceiver {
-    pub fn spawn(store: Store, rx_workers: Receiver<(TxHash, ElectionId)>) {
+    pub fn spawn(
+        store: Store,
+        rx_workers: Receiver<(TxHash, ElectionId, WorkerId)>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self { store, rx_workers }.run().await;
-        });
+        })
     }
 
     async fn run(&mut self) {
-        while let Some((tx_hash, election_id)) = self.rx_workers.recv().await {
-            //let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-            //self.store.write(key.to_vec(), Vec::default()).await;
+        while let Some((tx_hash, _election_id, worker_id)) = self.rx_workers.recv().await {
+            self.store
+                .write(payload_key(&tx_hash), worker_id.to_le_bytes().to_vec())
+                .await;
         }
     }
 }
@@ -1,25 +1,42 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compaction::Compactor;
 use crate::core::{Core, TxHash};
-use crate::election::{ElectionId, self};
-use crate::error::DagError;
-use crate::messages::{Header, Hash};
+use crate::election::{ElectionId, ElectionState};
+use crate::error::{DagError, DagResult};
+use crate::handle::{NodeHandle, ShutdownStage};
+use crate::helper::Helper;
+use crate::messages::{CommitCertificate, Hash, Header, InlineLimits, Reconfiguration};
+use crate::pacing::Pacer;
 use crate::payload_receiver::PayloadReceiver;
-use crate::proposer::Proposer;
+use crate::proposer::{Proposal, Proposer, SignedProposals};
+use crate::reproposer::Reproposer;
+use crate::wire::{self, WireError};
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, WorkerId};
-use crypto::{Digest, PublicKey, SignatureService, SecretKey};
+use config::{ByzantineStrategy, Committee, Parameters, WorkerId};
+use crypto::{Digest, PublicKey, SecretKey, Signature, SignatureService};
 use ed25519_dalek::{Digest as _, Sha512};
 use futures::sink::SinkExt as _;
-use log::info;
-use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use rand::Rng as _;
+use log::{info, warn};
+use network::{
+    decode, DropPolicy, KeepAlive, MessageHandler, Receiver as NetworkReceiver, ReliableSender,
+    SenderLimits, Writer,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::error::Error;
-use std::sync::atomic::AtomicU64;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/primary_tests.rs"]
+pub mod primary_tests;
 
 /// The default channel capacity for each channel of the primary.
 pub const CHANNEL_CAPACITY: usize = 100_000;
@@ -27,11 +44,39 @@ pub const CHANNEL_CAPACITY: usize = 100_000;
 /// The round number.
 pub type Round = u64;
 
+/// A decided election: its id, the committed tx hash, and the round of the quorum of commits.
+pub type Decision = (ElectionId, TxHash, Round);
+
+/// How an election ended, as output by the primary (once per election).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ElectionOutcome {
+    Decided(Decision),
+    /// The election ran out of voting rounds (see `Parameters::max_election_rounds`). Its
+    /// transactions are dropped. Failing is local: the other primaries may still decide it.
+    Failed(ElectionId),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum PrimaryMessage {
     Header(Header),
+    /// Move to the committee of the next epoch.
+    Reconfigure(Reconfiguration),
+    /// Ask the author of a header for some of its parents, to be sent back to the requestor.
+    HeaderRequest(Vec<Digest>, /* requestor */ PublicKey),
+    /// Proves the decision of an election to the primaries that missed its commits.
+    CommitCertificate(CommitCertificate),
+    /// Ask a primary for its state of an election (see `inspect`). The primary answers on the same
+    /// connection, in place of the acknowledgement.
+    StateRequest(ElectionId),
+    /// The state of an election, unless the primary knows nothing of it (or collected it already).
+    StateResponse(ElectionId, Option<ElectionState>),
+    /// The proposals of a primary for a round led by another (see `ProposalPolicy`).
+    ForwardProposals(SignedProposals),
 }
 
+/// A request for the state of an election, answered by the `Core`.
+pub type StateRequest = (ElectionId, oneshot::Sender<Option<ElectionState>>);
+
 /// The messages sent by the primary to its workers.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum PrimaryWorkerMessage {
@@ -39,37 +84,108 @@ pub enum PrimaryWorkerMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The primary indicates a round update.
     Cleanup(Round),
+    /// The primary moved to the committee of a new epoch.
+    Reconfigure(Committee),
 }
 
 /// The messages sent by the workers to their primary.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum WorkerPrimaryMessage {
     /// The worker indicates it sealed a new batch.
-    OurBatch(TxHash, ElectionId),
+    OurBatch(TxHash, ElectionId, WorkerId),
     /// The worker indicates it received a batch's digest from another authority.
-    OthersBatch(TxHash, ElectionId),
+    OthersBatch(TxHash, ElectionId, WorkerId),
+    /// The worker hands over a client transaction small enough to be proposed inline.
+    Inline(Transaction),
+    /// The worker indicates it sealed several new batches, in the order it sealed them.
+    OurBatches(Vec<(TxHash, ElectionId, WorkerId)>),
+}
+
+impl WorkerPrimaryMessage {
+    /// The workers the message claims to come from.
+    fn worker_ids(&self) -> Vec<WorkerId> {
+        match self {
+            Self::OurBatch(_, _, id) | Self::OthersBatch(_, _, id) => vec![*id],
+            Self::OurBatches(batches) => batches.iter().map(|x| x.2).collect(),
+            Self::Inline(_) => Vec::new(),
+        }
+    }
+}
+
+/// The tag of the signatures of workers' messages, so they cannot pass for headers or receipts.
+const WORKER_MESSAGE_TAG: &[u8] = b"worker to primary";
+
+/// The number of workers' messages dropped because they were not from one of our workers.
+static UNAUTHENTICATED_WORKER_MESSAGES: AtomicU64 = AtomicU64::new(0);
+
+/// The number of workers' messages dropped since start because they were not from one of our
+/// workers: unsigned, signed with another key, or claiming a worker we do not have.
+pub fn unauthenticated_worker_messages() -> u64 {
+    UNAUTHENTICATED_WORKER_MESSAGES.load(Ordering::Relaxed)
+}
+
+/// A serialized `WorkerPrimaryMessage`, signed with the key of the authority of the worker. The
+/// port a primary listens to its workers on is open to anyone.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedWorkerMessage {
+    pub message: Vec<u8>,
+    pub signature: Signature,
+}
+
+impl SignedWorkerMessage {
+    pub async fn new(message: Vec<u8>, signature_service: &mut SignatureService) -> Self {
+        let signature = signature_service
+            .request_signature(Self::digest(&message))
+            .await;
+        Self { message, signature }
+    }
+
+    fn digest(message: &[u8]) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(WORKER_MESSAGE_TAG);
+        hasher.update(message);
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
+
+    /// Check the message was signed with the key of `name`, and open it.
+    pub fn verify(&self, name: &PublicKey) -> DagResult<WorkerPrimaryMessage> {
+        self.signature.verify(&Self::digest(&self.message), name)?;
+        Ok(decode(&self.message)?)
+    }
 }
 
 pub struct Primary;
 
 impl Primary {
+    /// Spawn all the tasks of the primary. The returned handle stops them.
     pub fn spawn(
         name: PublicKey,
         secret: SecretKey,
         committee: Committee,
         parameters: Parameters,
         store: Store,
+        tx_output: Sender<ElectionOutcome>,
+        byzantine: Option<ByzantineStrategy>,
         //tx_consensus: Sender<Certificate>,
         //rx_consensus: Receiver<Certificate>,
-    ) {
-        let (tx_others_digests, rx_others_digests) = channel(CHANNEL_CAPACITY);
+    ) -> NodeHandle {
+        let (tx_payloads, rx_payloads) = channel(CHANNEL_CAPACITY);
         let (tx_our_digests, rx_our_digests) = channel(CHANNEL_CAPACITY);
         let (tx_parents, rx_parents) = channel(CHANNEL_CAPACITY);
         let (tx_headers, rx_headers) = channel(CHANNEL_CAPACITY);
         let (tx_primary_messages, rx_primary_messages) = channel(CHANNEL_CAPACITY);
+        let (tx_proposer_committee, rx_proposer_committee) = channel(CHANNEL_CAPACITY);
+        let (tx_helper_committee, rx_helper_committee) = channel(CHANNEL_CAPACITY);
+        let (tx_header_requests, rx_header_requests) = channel(CHANNEL_CAPACITY);
+        let (tx_state_requests, rx_state_requests) = channel(CHANNEL_CAPACITY);
+        let (tx_forwarded, rx_forwarded) = channel(CHANNEL_CAPACITY);
 
         // Write the parameters to the logs.
         parameters.log();
+        let inline_limits = InlineLimits {
+            transaction_size: parameters.inline_size,
+            header_bytes: parameters.max_inline_bytes,
+        };
 
         // Atomic variable use to synchronizer all tasks with the latest consensus round. This is only
         // used for cleanup. The only tasks that write into this variable is `GarbageCollector`.
@@ -81,32 +197,59 @@ impl Primary {
             .expect("Our public key or worker id is not in the committee")
             .primary_to_primary;
         address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+        let mut node = NodeHandle::default();
+        node.push_store(store.clone());
+        node.push(ShutdownStage::Consensus, NetworkReceiver::spawn(
             address,
             /* handler */
             PrimaryReceiverHandler {
                 tx_primary_messages,
+                tx_header_requests,
+                tx_state_requests,
+                tx_forwarded,
             },
-        );
+        ));
         info!(
             "Primary {} listening to primary messages on {}",
             name, address
         );
 
+        // Unless disabled, we propose the batches of others whose election does not start.
+        let tx_others = match parameters.reproposal_delay {
+            0 => None,
+            delay => {
+                let (tx_others, rx_others) = channel(CHANNEL_CAPACITY);
+                node.push(ShutdownStage::Proposer, Reproposer::spawn(
+                    delay,
+                    store.clone(),
+                    rx_others,
+                    /* tx_proposer */ tx_our_digests.clone(),
+                ));
+                Some(tx_others)
+            }
+        };
+
         // Spawn the network receiver listening to messages from our workers.
         let mut address = committee
             .primary(&name)
             .expect("Our public key or worker id is not in the committee")
             .worker_to_primary;
         address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+        node.push(ShutdownStage::Consensus, NetworkReceiver::spawn(
             address,
             /* handler */
             WorkerReceiverHandler {
+                name,
+                workers: committee
+                    .authorities
+                    .get(&name)
+                    .map(|x| x.workers.keys().copied().collect())
+                    .unwrap_or_default(),
                 tx_our_digests,
-                tx_others_digests,
+                tx_payloads,
+                tx_others,
             },
-        );
+        ));
         info!(
             "Primary {} listening to workers messages on {}",
             name, address
@@ -122,36 +265,74 @@ impl Primary {
             .collect();
 
         // The `Core` receives and handles headers, votes, and certificates from the other primaries.
-        Core::spawn(
+        node.push(ShutdownStage::Consensus, Core::spawn(
             name.clone(),
             committee.clone(),
             store.clone(),
             signature_service.clone(),
             consensus_round.clone(),
             parameters.gc_depth,
+            parameters.round_window,
             /* rx_primaries */ rx_primary_messages,
+            rx_state_requests,
             /* rx_proposer */ rx_headers,
             /* tx_proposer */ tx_parents,
+            tx_output,
             addresses,
-            committee.authorities.get(&name).unwrap().byzantine,
+            byzantine,
             parameters.header_size,
-        );
+            parameters.max_header_entries,
+            parameters.vote_timeout,
+            parameters.max_election_rounds,
+            Pacer::new(parameters.vote_pacing_chunk, parameters.vote_pacing_interval),
+            SenderLimits {
+                max_messages: parameters.outbound_buffer_messages,
+                max_bytes: parameters.outbound_buffer_bytes,
+                policy: DropPolicy::DropOldest,
+            },
+            KeepAlive {
+                interval: parameters.keepalive_interval,
+                timeout: parameters.dead_peer_timeout,
+            },
+            parameters.dissemination_timeout,
+            Compactor::new(parameters.compaction_interval, parameters.compaction_idle_delay),
+            inline_limits,
+            parameters.batch_commits,
+            /* tx_reconfigure */ vec![tx_proposer_committee, tx_helper_committee],
+        ));
+
+        // The `Helper` is dedicated to reply to header requests from other primaries.
+        node.push(ShutdownStage::Consensus, Helper::spawn(
+            committee.clone(),
+            store.clone(),
+            /* rx_primaries */ rx_header_requests,
+            /* rx_reconfigure */ rx_helper_committee,
+        ));
 
-        // Receives batch digests from other workers. They are only used to validate headers.
-        PayloadReceiver::spawn(store.clone(), /* rx_workers */ rx_others_digests);
+        // Receives the digests of all the batches our workers hold. They are only used to validate headers.
+        node.push(ShutdownStage::Consensus, PayloadReceiver::spawn(
+            store.clone(),
+            /* rx_workers */ rx_payloads,
+        ));
 
         // When the `Core` collects enough parent certificates, the `Proposer` generates a new header with new batch
         // digests from our workers and it back to the `Core`.
-        Proposer::spawn(
+        node.push(ShutdownStage::Proposer, Proposer::spawn(
             name.clone(),
             &committee,
+            parameters.proposal_policy,
             signature_service,
             parameters.header_size,
             parameters.max_header_delay,
+            parameters.gc_depth,
             /* rx_core */ rx_parents,
             /* rx_workers */ rx_our_digests,
+            rx_forwarded,
+            /* rx_reconfigure */ rx_proposer_committee,
             /* tx_core */ tx_headers,
-        );
+            Some(store),
+            inline_limits,
+        ));
 
         // NOTE: This log entry is used to compute performance.
         info!(
@@ -163,38 +344,130 @@ impl Primary {
                 .primary_to_primary
                 .ip()
         );
+        node
     }
 }
 
+/// Hand the primary listening on `address` the committee of the next epoch. It passes it on to
+/// the others. Waits until the primary is reachable.
+pub async fn reconfigure(
+    address: SocketAddr,
+    reconfiguration: Reconfiguration,
+) -> Result<(), Box<dyn Error>> {
+    let message = wire::serialize(&PrimaryMessage::Reconfigure(reconfiguration))?;
+    let mut sender = ReliableSender::new();
+    sender.send(address, Bytes::from(message)).await.await?;
+    Ok(())
+}
+
 /// Defines how the network receiver handles incoming primary messages.
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_primary_messages: Sender<PrimaryMessage>,
+    tx_header_requests: Sender<(Vec<Digest>, PublicKey)>,
+    tx_state_requests: Sender<StateRequest>,
+    tx_forwarded: Sender<SignedProposals>,
 }
 
 #[async_trait]
 impl MessageHandler for PrimaryReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Reply with an ACK.
+        // Deserialize and parse the message. Peers running another protocol version are no fault
+        // of theirs: we only drop their messages.
+        let message = wire::deserialize(&serialized);
+
+        // Reply with an ACK, but to state requests: they get the state instead.
+        if let Ok(PrimaryMessage::StateRequest(election_id)) = message {
+            let (sender, receiver) = oneshot::channel();
+            self.tx_state_requests.send((election_id.clone(), sender)).await?;
+            let response = PrimaryMessage::StateResponse(election_id, receiver.await?);
+            let _ = writer.send(Bytes::from(wire::serialize(&response)?)).await;
+            return Ok(());
+        }
         let _ = writer.send(Bytes::from("Ack")).await;
 
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
-            request => self
-                .tx_primary_messages
-                .send(request)
-                .await
-                .expect("Failed to send certificate"),
+        let message = match message {
+            Ok(message) => message,
+            Err(WireError::UnknownVersion(version)) => {
+                warn!(
+                    "Dropping primary message of protocol version {} ({} dropped so far)",
+                    version,
+                    wire::unknown_versions()
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        match message {
+            PrimaryMessage::HeaderRequest(missing, requestor) => {
+                self.tx_header_requests.send((missing, requestor)).await?
+            }
+            PrimaryMessage::StateResponse(..) => warn!("Dropping unsolicited state response"),
+            PrimaryMessage::ForwardProposals(signed) => self.tx_forwarded.send(signed).await?,
+            message => self.tx_primary_messages.send(message).await?,
         }
         Ok(())
     }
 }
 
+/// Ask the primary listening on `address` for its state of an election, to debug it. Waits until
+/// the primary is reachable.
+pub async fn inspect(
+    address: SocketAddr,
+    election_id: ElectionId,
+) -> Result<Option<ElectionState>, Box<dyn Error>> {
+    let request = wire::serialize(&PrimaryMessage::StateRequest(election_id.clone()))?;
+    let mut sender = ReliableSender::new();
+    let reply = sender.send(address, Bytes::from(request)).await.await?;
+    match wire::deserialize(&reply)? {
+        PrimaryMessage::StateResponse(id, state) if id == election_id => Ok(state),
+        _ => Err("Unexpected reply to a state request".into()),
+    }
+}
+
 /// Defines how the network receiver handles incoming workers messages.
 #[derive(Clone)]
 struct WorkerReceiverHandler {
-    tx_our_digests: Sender<(TxHash, ElectionId)>,
-    tx_others_digests: Sender<(TxHash, ElectionId)>,
+    /// Our public key, whose signature the messages of our workers carry.
+    name: PublicKey,
+    /// The ids of our workers.
+    workers: HashSet<WorkerId>,
+    tx_our_digests: Sender<Proposal>,
+    tx_payloads: Sender<(TxHash, ElectionId, WorkerId)>,
+    /// Receives the batches of others, to propose them if their election does not start.
+    tx_others: Option<Sender<(TxHash, ElectionId, WorkerId)>>,
+}
+
+impl WorkerReceiverHandler {
+    /// Open a message, provided it comes from one of our workers.
+    fn authenticate(&self, serialized: &[u8]) -> DagResult<WorkerPrimaryMessage> {
+        let signed: SignedWorkerMessage = decode(serialized)?;
+        let message = signed.verify(&self.name)?;
+        if let Some(id) = message
+            .worker_ids()
+            .into_iter()
+            .find(|id| !self.workers.contains(id))
+        {
+            bail!(DagError::UnknownWorker(self.name, id));
+        }
+        Ok(message)
+    }
+
+    async fn our_batch(
+        &self,
+        digest: TxHash,
+        election_id: ElectionId,
+        worker_id: WorkerId,
+    ) -> Result<(), Box<dyn Error>> {
+        // Other authorities may vote for our batches too.
+        self.tx_payloads
+            .send((digest.clone(), election_id.clone(), worker_id))
+            .await?;
+        self.tx_our_digests
+            .send(Proposal::Digest(digest, election_id, worker_id))
+            .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -204,18 +477,30 @@ impl MessageHandler for WorkerReceiverHandler {
         _writer: &mut Writer,
         serialized: Bytes,
     ) -> Result<(), Box<dyn Error>> {
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
-            WorkerPrimaryMessage::OurBatch(digest, election_id) => self
-                .tx_our_digests
-                .send((digest, election_id))
-                .await
-                .expect("Failed to send workers' digests"),
-            WorkerPrimaryMessage::OthersBatch(digest, election_id) => self
-                .tx_others_digests
-                .send((digest, election_id))
-                .await
-                .expect("Failed to send workers' digests"),
+        // Deserialize and parse the message, dropping those not from our workers.
+        let message = self.authenticate(&serialized).inspect_err(|_| {
+            UNAUTHENTICATED_WORKER_MESSAGES.fetch_add(1, Ordering::Relaxed);
+        })?;
+        match message {
+            WorkerPrimaryMessage::OurBatch(digest, election_id, worker_id) => {
+                self.our_batch(digest, election_id, worker_id).await?
+            }
+            WorkerPrimaryMessage::OurBatches(batches) => {
+                for (digest, election_id, worker_id) in batches {
+                    self.our_batch(digest, election_id, worker_id).await?;
+                }
+            }
+            WorkerPrimaryMessage::OthersBatch(digest, election_id, worker_id) => {
+                if let Some(tx_others) = &self.tx_others {
+                    tx_others
+                        .send((digest.clone(), election_id.clone(), worker_id))
+                        .await?;
+                }
+                self.tx_payloads.send((digest, election_id, worker_id)).await?
+            }
+            WorkerPrimaryMessage::Inline(transaction) => {
+                self.tx_our_digests.send(Proposal::Inline(transaction)).await?
+            }
         }
         Ok(())
     }
@@ -227,6 +512,9 @@ impl MessageHandler for WorkerReceiverHandler {
 pub struct Transaction {
     pub data: Vec<u8>,
     pub id: Vec<u8>,
+    /// The fee paid to get the transaction included. Workers reject transactions paying less
+    /// than their `minimum_fee`.
+    pub fee: u64,
 }
 
 impl Transaction {
@@ -234,8 +522,28 @@ impl Transaction {
         Self {
             data: Vec::new(),
             id: Vec::new(),
+            fee: 0,
         }
     }
+
+    /// A transaction with a random payload of `size` bytes. Transactions with the same `id`
+    /// compete in the same election.
+    pub fn random(size: usize, id: Vec<u8>) -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            data: (0..size).map(|_| rng.gen()).collect(),
+            id,
+            fee: 0,
+        }
+    }
+
+    /// The election of a transaction is identified by its id (padded or truncated to 32 bytes).
+    pub fn election_id(&self) -> ElectionId {
+        let mut array = [0u8; 32];
+        let len = self.id.len().min(32);
+        array[..len].copy_from_slice(&self.id[..len]);
+        Digest(array)
+    }
 }
 
 impl Hash for Transaction {
@@ -243,6 +551,7 @@ impl Hash for Transaction {
         let mut hasher = Sha512::new();
         hasher.update(&self.data);
         hasher.update(&self.id);
+        hasher.update(self.fee.to_le_bytes());
         hasher.finalize().as_slice()[..32].try_into().unwrap()
     }
 }
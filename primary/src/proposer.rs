@@ -1,47 +1,141 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::core::TxHash;
 use crate::election::ElectionId;
-use crate::messages::{Header, Hash, Vote};
-use crate::primary::Round;
-use config::{Committee, WorkerId};
-use crypto::{Digest, PublicKey, SignatureService};
-use log::{debug, info};
+use crate::error::{DagError, DagResult};
+use crate::messages::{Header, Hash, InlineLimits, Vote};
+use crate::primary::{PrimaryMessage, Round, Transaction};
+use crate::vote_log::VoteLog;
+use crate::window::ElectionWindow;
+use crate::wire;
+use bytes::Bytes;
+use config::{Committee, ProposalPolicy, WorkerId};
+use crypto::{Digest, PublicKey, Signature, SignatureService};
+use ed25519_dalek::{Digest as _, Sha512};
+use log::{debug, warn};
+use network::{encode, SimpleSender};
+use serde::{Deserialize, Serialize};
 //#[cfg(feature = "benchmark")]
 //use log::info;
+use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/proposer_tests.rs"]
 pub mod proposer_tests;
 
+/// What our workers (or, under a leader policy, the other primaries) hand us to propose.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Proposal {
+    /// The digest of a batch held by one of our workers, and its election.
+    Digest(TxHash, ElectionId, WorkerId),
+    /// A transaction small enough to be proposed inline (see `Header::inline`).
+    Inline(Transaction),
+}
+
+/// The tag of the signatures of forwarded proposals, so they cannot pass for headers or receipts.
+const FORWARD_TAG: &[u8] = b"forwarded proposals";
+
+/// The number of forwarded proposals dropped because they were not from another primary.
+static UNAUTHENTICATED_FORWARDS: AtomicU64 = AtomicU64::new(0);
+
+/// The number of forwards dropped since start because they were not from another primary of the
+/// committee: unsigned, signed with another key, or from an authority outside the committee (or us).
+pub fn unauthenticated_forwards() -> u64 {
+    UNAUTHENTICATED_FORWARDS.load(Ordering::Relaxed)
+}
+
+/// The proposals a primary forwards to the leader of a round, signed with the key of the primary.
+/// The leader adopts them in its own headers, and its port is open to anyone.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedProposals {
+    pub author: PublicKey,
+    pub proposals: Vec<Proposal>,
+    pub signature: Signature,
+}
+
+impl SignedProposals {
+    pub async fn new(
+        author: PublicKey,
+        proposals: Vec<Proposal>,
+        signature_service: &mut SignatureService,
+    ) -> Self {
+        let digest = Self::digest(&author, &proposals);
+        let signature = signature_service.request_signature(digest).await;
+        Self { author, proposals, signature }
+    }
+
+    fn digest(author: &PublicKey, proposals: &[Proposal]) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(FORWARD_TAG);
+        hasher.update(author);
+        hasher.update(encode(proposals).expect("Failed to serialize proposals"));
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
+
+    /// Check the proposals were signed by another primary of `committee` than `name`, and open them.
+    pub fn verify(self, committee: &Committee, name: &PublicKey) -> DagResult<Vec<Proposal>> {
+        ensure!(
+            self.author != *name && committee.stake(&self.author) > 0,
+            DagError::UnknownAuthority(self.author)
+        );
+        self.signature
+            .verify(&Self::digest(&self.author, &self.proposals), &self.author)?;
+        Ok(self.proposals)
+    }
+}
+
 /// The proposer creates new headers and send them to the core for broadcasting and further processing.
 pub struct Proposer {
     /// The public key of this primary.
     name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// Which primaries propose in each round.
+    policy: ProposalPolicy,
     /// Service to sign headers.
     signature_service: SignatureService,
-    /// The size of the headers' payload.
+    /// The number of votes that triggers a header, and the most a header carries.
     header_size: usize,
     /// The maximum delay to wait for batches' digests.
     max_header_delay: u64,
 
     /// Receives the parents to include in the next header (along with their round number).
     rx_core: Receiver<(Vec<Digest>, Round)>,
-    /// Receives the batches' digests from our workers.
-    rx_workers: Receiver<(TxHash, ElectionId)>,
+    /// Receives the batches' digests and the inline transactions from our workers.
+    rx_workers: Receiver<Proposal>,
+    /// Receives the proposals the other primaries forward to us while we lead.
+    rx_forwarded: Receiver<SignedProposals>,
+    /// Receives the committee of each new epoch.
+    rx_reconfigure: Receiver<Committee>,
     /// Sends newly created headers to the `Core`.
     tx_core: Sender<Header>,
+    /// A network sender to forward our proposals to the leaders.
+    network: SimpleSender,
 
     /// The current round of the dag.
     round: Round,
-    /// Holds the batches' digests waiting to be included in the next header.
-    digests: Vec<(TxHash, ElectionId)>,
-    /// Keeps track of the size (in bytes) of batches' digests that we received so far.
-    payload_size: usize,
+    /// The parents to include in the next header, if we received them already.
+    last_parents: Option<Vec<Digest>>,
     votes: Vec<Vote>,
-    active_elections: Vec<Digest>,
+    /// The worker holding each batch we are about to vote for.
+    workers: HashMap<TxHash, WorkerId>,
+    /// The inline transactions we are about to vote for.
+    inline: HashMap<TxHash, Transaction>,
+    /// The bounds on the inline transactions of our headers.
+    inline_limits: InlineLimits,
+    /// The elections we proposed a vote for in the last `gc_depth` rounds. Repeated digests for them
+    /// are dropped.
+    active_elections: ElectionWindow,
+    /// The elections of the proposals forwarded to us. We propose them even in the rounds we do
+    /// not lead, rather than forwarding them once more.
+    adopted: HashSet<ElectionId>,
+    /// Our votes persisted before a previous crash, if any, take precedence over new ones.
+    vote_log: VoteLog,
 }
 
 impl Proposer {
@@ -49,41 +143,164 @@ impl Proposer {
     pub fn spawn(
         name: PublicKey,
         committee: &Committee,
+        policy: ProposalPolicy,
         signature_service: SignatureService,
         header_size: usize,
         max_header_delay: u64,
+        gc_depth: Round,
         rx_core: Receiver<(Vec<Digest>, Round)>,
-        rx_workers: Receiver<(TxHash, ElectionId)>,
+        rx_workers: Receiver<Proposal>,
+        rx_forwarded: Receiver<SignedProposals>,
+        rx_reconfigure: Receiver<Committee>,
         tx_core: Sender<Header>,
-    ) {
+        store: Option<Store>,
+        inline_limits: InlineLimits,
+    ) -> JoinHandle<()> {
+        let committee = committee.clone();
         tokio::spawn(async move {
             Self {
                 name,
+                committee,
+                policy,
                 signature_service,
                 header_size,
                 max_header_delay,
                 rx_core,
                 rx_workers,
+                rx_forwarded,
+                rx_reconfigure,
                 tx_core,
+                network: SimpleSender::new(),
                 round: 1,
-                digests: Vec::with_capacity(2 * header_size),
-                payload_size: 0,
+                last_parents: Some(Vec::new()), // The first header has no parents.
                 votes: Vec::with_capacity(header_size),
-                active_elections: Vec::new(),
+                workers: HashMap::new(),
+                inline: HashMap::new(),
+                inline_limits,
+                active_elections: ElectionWindow::new(gc_depth),
+                adopted: HashSet::new(),
+                vote_log: VoteLog::new(store),
             }
             .run()
             .await;
-        });
+        })
+    }
+
+    /// Queue a proposal for our next header, unless we already proposed a vote in its election.
+    /// Returns its election if queued.
+    async fn queue(&mut self, proposal: Proposal) -> Option<ElectionId> {
+        let (tx_hash, election_id) = match &proposal {
+            Proposal::Digest(tx_hash, election_id, _) => (tx_hash.clone(), election_id.clone()),
+            Proposal::Inline(tx) => (tx.digest(), tx.election_id()),
+        };
+        if !self.active_elections.insert(election_id.clone(), self.round) {
+            return None;
+        }
+        match proposal {
+            Proposal::Digest(_, _, worker_id) => {
+                self.workers.insert(tx_hash.clone(), worker_id);
+            }
+            Proposal::Inline(tx) => {
+                self.inline.insert(tx_hash.clone(), tx);
+            }
+        }
+        let vote = Vote::new(0, tx_hash, election_id.clone(), false).await;
+        self.votes.push(vote);
+        Some(election_id)
+    }
+
+    /// Forward our queued proposals to the leader of the round of our next header, unless we
+    /// lead it (or every primary proposes). Only the adopted proposals stay in the queue.
+    async fn forward(&mut self) {
+        let leader = match self.committee.proposer(self.policy, self.round) {
+            Some(leader) if leader != self.name => leader,
+            _ => return,
+        };
+        let adopted = &self.adopted;
+        let (kept, forwarded): (Vec<_>, Vec<_>) = self
+            .votes
+            .drain(..)
+            .partition(|x| adopted.contains(&x.election_id));
+        self.votes = kept;
+        if forwarded.is_empty() {
+            return;
+        }
+        let proposals: Vec<_> = forwarded
+            .into_iter()
+            .map(|vote| match self.inline.remove(&vote.tx_hash) {
+                Some(tx) => Proposal::Inline(tx),
+                None => {
+                    let worker_id = self.workers.remove(&vote.tx_hash).unwrap_or_default();
+                    Proposal::Digest(vote.tx_hash, vote.election_id, worker_id)
+                }
+            })
+            .collect();
+        let address = match self.committee.primary(&leader) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => return warn!("Cannot forward our proposals: {}", e),
+        };
+        debug!("Forwarding {} proposals to {}", proposals.len(), leader);
+        let signed = SignedProposals::new(self.name, proposals, &mut self.signature_service).await;
+        let message = PrimaryMessage::ForwardProposals(signed);
+        let bytes = wire::serialize(&message).expect("Failed to serialize our proposals");
+        self.network.send(address, Bytes::from(bytes)).await;
     }
 
     async fn make_header(&mut self) {
-        // Make a new header.
-        let header = Header::new(
-            self.name.clone(),
-            self.votes.drain(..).collect(),
-            &mut self.signature_service,
-        )
-        .await;
+        // Make a new header, with at most one vote per election. The votes past `header_size` (or
+        // for the inline transactions past the bound of the header) wait for the next one.
+        let mut seen = HashSet::new();
+        let mut inline_bytes = 0;
+        let mut votes = Vec::new();
+        let mut deferred = Vec::new();
+        for vote in self.votes.drain(..) {
+            if !seen.insert(vote.election_id.clone()) {
+                continue;
+            }
+            if votes.len() == self.header_size {
+                deferred.push(vote);
+                continue;
+            }
+            match self.inline.get(&vote.tx_hash) {
+                Some(tx) if inline_bytes + tx.data.len() > self.inline_limits.header_bytes => {
+                    deferred.push(vote)
+                }
+                Some(tx) => {
+                    inline_bytes += tx.data.len();
+                    votes.push(vote);
+                }
+                None => votes.push(vote),
+            }
+        }
+        self.votes = deferred;
+        let votes: BTreeSet<_> = match self.vote_log.cast_all(votes.clone()).await {
+            Ok(votes) => votes.into_iter().collect(),
+            Err(e) => {
+                // Better to send no vote than one that may contradict a vote we already cast. The
+                // votes wait for the next header, along with their batches.
+                warn!("Failed to persist our votes: {}", e);
+                votes.append(&mut self.votes);
+                self.votes = votes;
+                BTreeSet::new()
+            }
+        };
+        for vote in &votes {
+            self.adopted.remove(&vote.election_id);
+        }
+        // Tell the other primaries which of our workers holds the batches we vote for.
+        let voted: HashSet<_> = votes.iter().map(|x| &x.tx_hash).collect();
+        let payload: BTreeMap<_, _> = voted
+            .iter()
+            .filter_map(|x| self.workers.remove(*x).map(|worker_id| ((*x).clone(), worker_id)))
+            .collect();
+        let inline = voted
+            .iter()
+            .filter_map(|x| self.inline.remove(*x).map(|tx| ((*x).clone(), tx)))
+            .collect();
+        let parents = self.last_parents.take().unwrap_or_default().into_iter().collect();
+        let header = Header::new(self.name, self.committee.epoch, self.round, votes, payload, parents, inline, BTreeSet::new(), &mut self.signature_service).await;
+        self.round += 1;
+        self.active_elections.expire(self.round);
 
         //info!("Votes: {:?}", header.votes);
         //debug!("Created {:?}", header);
@@ -111,20 +328,19 @@ impl Proposer {
         loop {
             // Check if we can propose a new header. We propose a new header when one of the following
             // conditions is met:
-            // 1. We have a quorum of certificates from the previous round and enough batches' digests;
-            // 2. We have a quorum of certificates from the previous round and the specified maximum
+            // 1. We have the parents from the previous round and enough votes;
+            // 2. We have the parents from the previous round and the specified maximum
             // inter-header delay has passed.
-            //let enough_parents = !self.last_parents.is_empty();
-            //let enough_digests = self.payload_size >= self.header_size;
-            //let enough_digests = self.digests.len() == 1;
+            let enough_parents = self.last_parents.is_some();
             let timer_expired = timer.is_elapsed();
             let enough_votes = self.votes.len() >= self.header_size;
-            //info!("Digests: {:?}", self.digests);
 
-            if enough_votes || timer_expired {
-                // Make a new header.
+            if enough_parents && (enough_votes || timer_expired) {
+                // Make a new header. Under a leader policy, the headers of the rounds we do not
+                // lead only carry the parents (and the adopted proposals): they keep the DAG
+                // moving, so that the leadership moves on even past a crashed leader.
+                self.forward().await;
                 self.make_header().await;
-                //self.payload_size = 0;
 
                 // Reschedule the timer.
                 let deadline = Instant::now() + Duration::from_millis(self.max_header_delay);
@@ -132,14 +348,40 @@ impl Proposer {
             }
 
             tokio::select! {
-                Some((tx_hash, election_id)) = self.rx_workers.recv() => {
-                    if !self.active_elections.contains(&election_id) {
-                        self.active_elections.push(election_id.clone());
-                        let vote = Vote::new(0, tx_hash, election_id, false).await;
-                        self.votes.push(vote);
+                Some((parents, round)) = self.rx_core.recv() => {
+                    // Ignore parents older than the header we are waiting to make.
+                    if round + 1 >= self.round {
+                        self.round = round + 1;
+                        self.last_parents = Some(parents);
                     }
                 }
-                () = &mut timer => {
+                Some(proposal) = self.rx_workers.recv() => {
+                    self.queue(proposal).await;
+                }
+                Some(signed) = self.rx_forwarded.recv() => {
+                    // Only the proposals of the other primaries are ours to adopt.
+                    let proposals = match signed.verify(&self.committee, &self.name) {
+                        Ok(proposals) => proposals,
+                        Err(e) => {
+                            UNAUTHENTICATED_FORWARDS.fetch_add(1, Ordering::Relaxed);
+                            warn!("Dropping forwarded proposals: {}", e);
+                            continue;
+                        }
+                    };
+                    // Only the first proposal of an election to reach us is proposed.
+                    for proposal in proposals {
+                        if let Some(election_id) = self.queue(proposal).await {
+                            self.adopted.insert(election_id);
+                        }
+                    }
+                }
+                Some(committee) = self.rx_reconfigure.recv() => {
+                    // The elections we proposed in the previous epoch carry on: the votes we did
+                    // not send yet go in our next header, the first of the new epoch.
+                    debug!("Proposing in epoch {}", committee.epoch);
+                    self.committee = committee;
+                }
+                () = &mut timer, if !timer_expired => {
                     // Nothing to do.
                 }
             }
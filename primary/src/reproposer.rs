@@ -0,0 +1,102 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::core::{claims_key, TxHash};
+use crate::election::ElectionId;
+use crate::proposer::Proposal;
+use config::WorkerId;
+use log::{debug, warn};
+use std::collections::{HashSet, VecDeque};
+use store::Store;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/reproposer_tests.rs"]
+pub mod reproposer_tests;
+
+/// Proposes the batches of other authorities held by our workers whose election never started:
+/// their proposer may have crashed right after its workers shared them, before any header voted
+/// for them. An election started once we stored a claim about it (see `Claim`). We vote for the
+/// same election id, so the election goes on with our proposal as if it were the original one.
+pub struct Reproposer {
+    /// How long a batch waits for its election to start. Denominated in ms.
+    delay: u64,
+    /// The persistent storage, holding the claims of the elections.
+    store: Store,
+    /// Receives the batches of the other authorities our workers hold.
+    rx_others: Receiver<(TxHash, ElectionId, WorkerId)>,
+    /// Hands the batches to propose to the `Proposer`, as our workers do.
+    tx_proposer: Sender<Proposal>,
+    /// The batches waiting for their election to start, with their deadline (oldest first).
+    waiting: VecDeque<(Instant, TxHash, ElectionId, WorkerId)>,
+    /// The elections of the waiting batches. Only the first batch of an election waits.
+    elections: HashSet<ElectionId>,
+}
+
+impl Reproposer {
+    pub fn spawn(
+        delay: u64,
+        store: Store,
+        rx_others: Receiver<(TxHash, ElectionId, WorkerId)>,
+        tx_proposer: Sender<Proposal>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            Self {
+                delay,
+                store,
+                rx_others,
+                tx_proposer,
+                waiting: VecDeque::new(),
+                elections: HashSet::new(),
+            }
+            .run()
+            .await;
+        })
+    }
+
+    /// Propose a batch unless its election started.
+    async fn repropose(&mut self, tx_hash: TxHash, election_id: ElectionId, worker_id: WorkerId) {
+        match self.store.read(claims_key(&election_id)).await {
+            Ok(None) => {
+                debug!(
+                    "Proposing batch {} of election {}, which did not start",
+                    tx_hash, election_id
+                );
+                self.tx_proposer
+                    .send(Proposal::Digest(tx_hash, election_id, worker_id))
+                    .await
+                    .expect("Failed to send a batch to the proposer");
+            }
+            Ok(Some(_)) => (),
+            Err(e) => warn!(
+                "Failed to read the claims of election {}: {}",
+                election_id, e
+            ),
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            let deadline = self.waiting.front().map(|x| x.0);
+            tokio::select! {
+                batch = self.rx_others.recv() => {
+                    let (tx_hash, election_id, worker_id) = match batch {
+                        Some(batch) => batch,
+                        None => break,
+                    };
+                    if self.elections.insert(election_id.clone()) {
+                        let deadline = Instant::now() + Duration::from_millis(self.delay);
+                        self.waiting.push_back((deadline, tx_hash, election_id, worker_id));
+                    }
+                },
+
+                // The oldest batch waited long enough.
+                () = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    let (_, tx_hash, election_id, worker_id) = self.waiting.pop_front().unwrap();
+                    self.elections.remove(&election_id);
+                    self.repropose(tx_hash, election_id, worker_id).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::time::{Duration, Instant};
+
+fn members(elections: &[u8]) -> BTreeMap<ElectionId, TxHash> {
+    elections
+        .iter()
+        .map(|x| (Digest([*x; 32]), Digest([!*x; 32])))
+        .collect()
+}
+
+#[test]
+fn commit_ready_headers() {
+    let mut batches = PendingBatches::new(10);
+    assert!(batches
+        .insert(Digest([0; 32]), members(&[1, 2, 3]))
+        .is_empty());
+
+    // An election held for another header is not held twice.
+    batches.insert(Digest([4; 32]), members(&[3, 4]));
+    assert_eq!(batches.held(&Digest([3; 32])), Some(&Digest([!3; 32])));
+    assert_eq!(batches.held(&Digest([4; 32])), Some(&Digest([!4; 32])));
+
+    // Nothing is ready until every held election of a header is.
+    batches.ready(&Digest([1; 32]));
+    batches.ready(&Digest([2; 32]));
+    assert!(batches.take_ready().is_empty());
+    batches.ready(&Digest([3; 32]));
+    batches.ready(&Digest([3; 32]));
+    let batch_commits = batches.take_ready();
+    assert_eq!(batch_commits.len(), 1);
+    let (batch, covered) = &batch_commits[0];
+    assert_eq!(batch.header, Digest([0; 32]));
+    assert!(batch.excluded.is_empty());
+    assert_eq!(covered.len(), 3);
+    assert_eq!(batches.held(&Digest([1; 32])), None);
+    assert_eq!(batches.committed, 1);
+    assert_eq!(batches.covered, 3);
+}
+
+#[test]
+fn exclude_fallbacks() {
+    let mut batches = PendingBatches::new(10);
+    batches.insert(Digest([0; 32]), members(&[1, 2]));
+
+    // The excluded election no longer holds the batch commit back, and is left out of it.
+    batches.exclude(&Digest([1; 32]));
+    batches.exclude(&Digest([1; 32]));
+    assert_eq!(batches.held(&Digest([1; 32])), None);
+    assert_eq!(batches.fallbacks, 1);
+    batches.ready(&Digest([2; 32]));
+    let batch_commits = batches.take_ready();
+    let (batch, covered) = &batch_commits[0];
+    assert_eq!(batch.excluded, vec![Digest([1; 32])].into_iter().collect());
+    assert_eq!(covered, &vec![(Digest([2; 32]), Digest([!2; 32]))]);
+
+    // A header whose elections all fell back makes no batch commit.
+    batches.insert(Digest([3; 32]), members(&[3]));
+    batches.exclude(&Digest([3; 32]));
+    assert!(batches.take_ready().is_empty());
+    assert_eq!(batches.committed, 1);
+}
+
+#[test]
+fn evict_oldest() {
+    let mut batches = PendingBatches::new(2);
+    batches.insert(Digest([0; 32]), members(&[1, 2]));
+    batches.exclude(&Digest([2; 32]));
+    batches.insert(Digest([3; 32]), members(&[3]));
+
+    // The elections of the evicted header fall back (but those that already did).
+    let evicted = batches.insert(Digest([4; 32]), members(&[4]));
+    assert_eq!(evicted, vec![Digest([1; 32])]);
+    assert_eq!(batches.held(&Digest([1; 32])), None);
+    assert_eq!(batches.fallbacks, 2);
+    batches.ready(&Digest([3; 32]));
+    batches.ready(&Digest([4; 32]));
+    assert_eq!(batches.take_ready().len(), 2);
+
+    // A header evicted once ready makes no batch commit.
+    batches.insert(Digest([5; 32]), members(&[5]));
+    batches.ready(&Digest([5; 32]));
+    batches.insert(Digest([6; 32]), members(&[6]));
+    batches.insert(Digest([7; 32]), members(&[7]));
+    assert!(batches.take_ready().is_empty());
+}
+
+/// The time it takes to report `votes` elections ready and take the batch commits after each,
+/// held over as many headers of `size` elections as it takes.
+fn time_votes(votes: u32, size: u32) -> Duration {
+    let election = |x: u32| {
+        let mut digest = [0; 32];
+        digest[..4].copy_from_slice(&x.to_le_bytes());
+        Digest(digest)
+    };
+    let headers = votes / size;
+    let mut batches = PendingBatches::new(headers as usize);
+    for header in 0..headers {
+        let members = (0..size)
+            .map(|x| (election(header * size + x), Digest([0; 32])))
+            .collect();
+        batches.insert(election(u32::MAX - header), members);
+    }
+    let start = Instant::now();
+    for x in 0..votes {
+        batches.ready(&election(x));
+        batches.take_ready();
+    }
+    assert_eq!(batches.committed, headers as u64);
+    start.elapsed()
+}
+
+#[test]
+fn per_vote_cost_independent_of_header_size() {
+    // The same number of votes, over headers of 100 and of 10k entries. Rescanning the held
+    // headers on every vote would make the latter two orders of magnitude slower.
+    let small = time_votes(10_000, 100);
+    let large = time_votes(10_000, 10_000);
+    assert!(
+        large < small * 10,
+        "{:?} per vote with 10k entries, {:?} with 100",
+        large / 10_000,
+        small / 10_000
+    );
+}
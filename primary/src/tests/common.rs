@@ -1,13 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::messages::{Certificate, Header, Vote};
+use crate::election::ElectionId;
+use crate::messages::{CommitCertificate, Hash as _, Header, Reconfiguration, Vote};
 use bytes::Bytes;
 use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
-use crypto::Hash as _;
-use crypto::{generate_keypair, PublicKey, SecretKey, Signature};
+use crypto::{generate_keypair, Digest, PublicKey, SecretKey, Signature};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
@@ -15,20 +16,15 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 impl PartialEq for Header {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+        self.author == other.author
+            && self.epoch == other.epoch
+            && self.round == other.round
+            && self.votes == other.votes
+            && self.payload == other.payload
+            && self.parents == other.parents
     }
 }
 
-/*impl PartialEq for Vote {
-    fn eq(&self, other: &Self) -> bool {
-        self.digest() == other.digest()
-    }
-}
-
-impl Eq for Vote {
-    
-}*/
-
 // Fixture
 pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     let mut rng = StdRng::from_seed([0; 32]);
@@ -37,8 +33,16 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 
 // Fixture
 pub fn committee() -> Committee {
+    committee_of_size(4)
+}
+
+// Fixture
+pub fn committee_of_size(size: usize) -> Committee {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let keys: Vec<(PublicKey, SecretKey)> = (0..size).map(|_| generate_keypair(&mut rng)).collect();
     Committee {
-        authorities: keys()
+        epoch: 0,
+        authorities: keys
             .iter()
             .enumerate()
             .map(|(i, (id, _))| {
@@ -46,7 +50,7 @@ pub fn committee() -> Committee {
                     primary_to_primary: format!("127.0.0.1:{}", 100 + i).parse().unwrap(),
                     worker_to_primary: format!("127.0.0.1:{}", 200 + i).parse().unwrap(),
                 };
-                let workers = vec![(
+                let workers = [(
                     0,
                     WorkerAddresses {
                         primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
@@ -63,6 +67,7 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        byzantine: false,
                     },
                 )
             })
@@ -96,77 +101,103 @@ pub fn committee_with_base_port(base_port: u16) -> Committee {
     committee
 }
 
+// Fixture: every authority runs a second worker, with id 1.
+pub fn committee_with_two_workers(base_port: u16) -> Committee {
+    let mut committee = committee_with_base_port(base_port);
+    for (i, authority) in committee.authorities.values_mut().enumerate() {
+        let port = |offset: usize| base_port + (offset + i) as u16;
+        let worker = WorkerAddresses {
+            primary_to_worker: format!("127.0.0.1:{}", port(600)).parse().unwrap(),
+            transactions: format!("127.0.0.1:{}", port(700)).parse().unwrap(),
+            worker_to_worker: format!("127.0.0.1:{}", port(800)).parse().unwrap(),
+        };
+        authority.workers.insert(1, worker);
+    }
+    committee
+}
+
 // Fixture
-pub fn header() -> Header {
-    let (author, secret) = keys().pop().unwrap();
-    let header = Header {
+pub fn election_id(seed: u8) -> ElectionId {
+    Digest([seed; 32])
+}
+
+// Fixture
+pub fn vote(round: u64, tx_hash: Digest, election_id: ElectionId, commit: bool) -> Vote {
+    Vote {
+        round,
+        tx_hash,
+        election_id,
+        commit,
+    }
+}
+
+// Fixture
+pub fn header_with_votes(author: PublicKey, votes: Vec<Vote>) -> Header {
+    sign(Header {
         author,
+        epoch: 0,
         round: 1,
-        parents: Certificate::genesis(&committee())
+        votes: votes.into_iter().collect::<BTreeSet<_>>(),
+        payload: BTreeMap::new(),
+        inline: BTreeMap::new(),
+        batch_commits: BTreeSet::new(),
+        parents: BTreeSet::new(),
+        signature: Signature::default(),
+        commit_signatures: Vec::new(),
+    })
+}
+
+// Sign a header and its commits with the key of its author, again after altering it.
+pub fn sign(mut header: Header) -> Header {
+    if let Some((_, secret)) = keys().into_iter().find(|(x, _)| *x == header.author) {
+        header.signature = Signature::new(&header.digest(), &secret);
+        header.commit_signatures = header
+            .votes
             .iter()
-            .map(|x| x.digest())
-            .collect(),
-        ..Header::default()
-    };
-    Header {
-        id: header.digest(),
-        signature: Signature::new(&header.digest(), &secret),
-        ..header
+            .filter(|x| x.commit)
+            .map(|x| Signature::new(&x.commit_digest(), &secret))
+            .collect();
     }
+    header
 }
 
 // Fixture
-pub fn headers() -> Vec<Header> {
-    keys()
+pub fn reconfiguration(committee: Committee, signers: usize) -> Reconfiguration {
+    let mut reconfiguration = Reconfiguration {
+        committee,
+        signatures: Vec::new(),
+    };
+    let digest = reconfiguration.digest();
+    reconfiguration.signatures = keys()
         .into_iter()
-        .map(|(author, secret)| {
-            let header = Header {
-                author,
-                round: 1,
-                parents: Certificate::genesis(&committee())
-                    .iter()
-                    .map(|x| x.digest())
-                    .collect(),
-                ..Header::default()
-            };
-            Header {
-                id: header.digest(),
-                signature: Signature::new(&header.digest(), &secret),
-                ..header
-            }
-        })
-        .collect()
+        .take(signers)
+        .map(|(name, secret)| (name, Signature::new(&digest, &secret)))
+        .collect();
+    reconfiguration
 }
 
 // Fixture
-pub fn votes(header: &Header) -> Vec<Vote> {
-    keys()
+pub fn commit_certificate(tx_hash: Digest, election_id: ElectionId, signers: usize) -> CommitCertificate {
+    let mut certificate = CommitCertificate {
+        election_id,
+        tx_hash,
+        round: 1,
+        votes: Vec::new(),
+    };
+    let digest = certificate.commit().commit_digest();
+    certificate.votes = keys()
         .into_iter()
-        .map(|(author, secret)| {
-            let vote = Vote {
-                id: header.id.clone(),
-                round: header.round,
-                origin: header.author,
-                author,
-                signature: Signature::default(),
-            };
-            Vote {
-                signature: Signature::new(&vote.digest(), &secret),
-                ..vote
-            }
-        })
-        .collect()
+        .take(signers)
+        .map(|(name, secret)| (name, Signature::new(&digest, &secret)))
+        .collect();
+    certificate
 }
 
 // Fixture
-pub fn certificate(header: &Header) -> Certificate {
-    Certificate {
-        header: header.clone(),
-        votes: votes(&header)
-            .into_iter()
-            .map(|x| (x.author, x.signature))
-            .collect(),
-    }
+pub fn header() -> Header {
+    let (author, _) = keys().pop().unwrap();
+    let tx_hash = Digest([1; 32]);
+    header_with_votes(author, vec![vote(0, tx_hash, election_id(0), false)])
 }
 
 // Fixture
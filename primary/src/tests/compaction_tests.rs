@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fs;
+
+#[test]
+fn compact_once_idle() {
+    let mut compactor = Compactor::new(/* interval */ 1_000, /* idle_delay */ 200);
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+
+    // Work resets the idle period.
+    assert!(!compactor.due(false, at(0)));
+    assert!(!compactor.due(true, at(100)));
+    assert!(!compactor.due(false, at(250)));
+    assert!(compactor.due(false, at(300)));
+
+    // The next compaction waits for the interval, even if the primary stays idle.
+    compactor.last_compaction = Some(at(300));
+    assert!(!compactor.due(false, at(1_200)));
+    assert!(compactor.due(false, at(1_300)));
+}
+
+#[test]
+fn never_overlap_compactions() {
+    let mut compactor = Compactor::new(/* interval */ 1, /* idle_delay */ 0);
+    let now = Instant::now();
+    assert!(compactor.due(false, now));
+
+    compactor.metrics.lock().unwrap().running = true;
+    assert!(!compactor.due(false, now + Duration::from_millis(10)));
+    compactor
+        .metrics
+        .lock()
+        .unwrap()
+        .record(Duration::from_millis(5));
+    assert!(compactor.due(false, now + Duration::from_millis(10)));
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut compactor = Compactor::default();
+    let now = Instant::now();
+    assert!(!compactor.due(false, now));
+    assert!(!compactor.due(false, now + Duration::from_secs(3_600)));
+}
+
+#[tokio::test]
+async fn record_compactions() {
+    let path = ".db_test_record_compactions";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let mut compactor = Compactor::new(/* interval */ 1, /* idle_delay */ 0);
+    compactor.compact(store, Instant::now());
+    assert!(compactor.metrics().running);
+    while compactor.metrics().running {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(compactor.metrics().compactions, 1);
+}
@@ -1,361 +1,1663 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{
-    certificate, committee, committee_with_base_port, header, headers, keys, listener, votes,
+    commit_certificate, committee_with_base_port, committee_with_two_workers, election_id, header,
+    header_with_votes, keys, listener, reconfiguration, sign, vote,
 };
-use futures::future::try_join_all;
+use crate::Decision;
+use crypto::Signature;
+use futures::FutureExt as _;
+use rand::SeedableRng as _;
 use std::fs;
 use tokio::sync::mpsc::channel;
 
-#[tokio::test]
-async fn process_header() {
-    let mut keys = keys();
-    let _ = keys.pop().unwrap(); // Skip the header' author.
-    let (name, secret) = keys.pop().unwrap();
-    let mut signature_service = SignatureService::new(secret);
-
-    let committee = committee_with_base_port(13_000);
+// Fixture
+async fn core(
+    name: PublicAddress,
+    secret: crypto::SecretKey,
+    base_port: u16,
+    gc_depth: Round,
+    vote_timeout: u64,
+) -> (Core, Receiver<ElectionOutcome>) {
+    let committee = committee_with_base_port(base_port);
+    let addresses = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, x)| x.primary_to_primary)
+        .collect();
 
-    let (tx_sync_headers, _rx_sync_headers) = channel(1);
-    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
-    let (tx_primary_messages, rx_primary_messages) = channel(1);
-    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
-    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_state_requests, rx_state_requests) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
-    let (tx_consensus, _rx_consensus) = channel(1);
-    let (tx_parents, _rx_parents) = channel(1);
-
-    // Create a new test store.
-    let path = ".db_test_process_header";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let (tx_parents, mut rx_parents) = channel(1);
+    let (tx_output, rx_output) = channel(10);
 
-    // Make the vote we expect to receive.
-    let expected = Vote::new(&header(), &name, &mut signature_service).await;
+    // Drain the parents sent to the proposer.
+    tokio::spawn(async move { while rx_parents.recv().await.is_some() {} });
 
-    // Spawn a listener to receive the vote.
-    let address = committee
-        .primary(&header().author)
-        .unwrap()
-        .primary_to_primary;
-    let handle = listener(address);
+    // Create a new test store.
+    let path = format!(".db_test_core_{}", base_port);
+    let _ = fs::remove_dir_all(&path);
+    let mut store = Store::new(&path).unwrap();
 
-    // Make a synchronizer for the core.
-    let synchronizer = Synchronizer::new(
-        name,
-        &committee,
-        store.clone(),
-        /* tx_header_waiter */ tx_sync_headers,
-        /* tx_certificate_waiter */ tx_sync_certificates,
-    );
+    // Our workers hold the batches the tests vote for (and their equivocating twins).
+    for seed in [1, 2, !1, !2] {
+        store
+            .write(payload_key(&Digest([seed; 32])), Vec::default())
+            .await;
+    }
 
-    // Spawn the core.
-    Core::spawn(
+    let core = Core::new(
         name,
         committee,
-        store.clone(),
-        synchronizer,
-        signature_service,
+        store,
+        SignatureService::new(secret),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        gc_depth,
+        /* round_window */ 50,
         /* rx_primaries */ rx_primary_messages,
-        /* rx_header_waiter */ rx_headers_loopback,
-        /* rx_certificate_waiter */ rx_certificates_loopback,
+        rx_state_requests,
         /* rx_proposer */ rx_headers,
-        tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_output,
+        addresses,
+        /* byzantine */ None,
+        /* header_size */ 1_000,
+        /* max_header_entries */ 10_000,
+        vote_timeout,
+        /* max_election_rounds */ 0,
+        Pacer::default(),
+        SenderLimits::default(),
+        KeepAlive::default(),
+        /* dissemination_timeout */ 0,
+        Compactor::default(),
+        InlineLimits::default(),
+        /* batch_commits */ false,
+        /* tx_reconfigure */ Vec::new(),
     );
+    (core, rx_output)
+}
 
-    // Send a header to the core.
-    tx_primary_messages
-        .send(PrimaryMessage::Header(header()))
-        .await
-        .unwrap();
-
-    // Ensure the listener correctly received the vote.
-    let received = handle.await.unwrap();
-    match bincode::deserialize(&received).unwrap() {
-        PrimaryMessage::Vote(x) => assert_eq!(x, expected),
-        x => panic!("Unexpected message: {:?}", x),
+// The decision of an election the core decided.
+fn decision(outcome: ElectionOutcome) -> Decision {
+    match outcome {
+        ElectionOutcome::Decided(x) => x,
+        x => panic!("Unexpected outcome {:?}", x),
     }
-
-    // Ensure the header is correctly stored.
-    let stored = store
-        .read(header().id.to_vec())
-        .await
-        .unwrap()
-        .map(|x| bincode::deserialize(&x).unwrap());
-    assert_eq!(stored, Some(header()));
 }
 
 #[tokio::test]
-async fn process_header_missing_parent() {
+async fn process_own_header() {
     let (name, secret) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_000);
     let signature_service = SignatureService::new(secret);
 
-    let (tx_sync_headers, _rx_sync_headers) = channel(1);
-    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
-    let (tx_primary_messages, rx_primary_messages) = channel(1);
-    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
-    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
-    let (_tx_headers, rx_headers) = channel(1);
-    let (tx_consensus, _rx_consensus) = channel(1);
+    let (_tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_state_requests, rx_state_requests) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
     let (tx_parents, _rx_parents) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_header_missing_parent";
+    let path = ".db_test_process_own_header";
     let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let store = Store::new(path).unwrap();
 
-    // Make a synchronizer for the core.
-    let synchronizer = Synchronizer::new(
-        name,
-        &committee(),
-        store.clone(),
-        /* tx_header_waiter */ tx_sync_headers,
-        /* tx_certificate_waiter */ tx_sync_certificates,
-    );
+    // Spawn a listener for each other primary to receive our header.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, addresses)| listener(addresses.primary_to_primary))
+        .collect();
+    let addresses = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, x)| x.primary_to_primary)
+        .collect();
 
     // Spawn the core.
     Core::spawn(
         name,
-        committee(),
-        store.clone(),
-        synchronizer,
+        committee,
+        store,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        /* round_window */ 50,
         /* rx_primaries */ rx_primary_messages,
-        /* rx_header_waiter */ rx_headers_loopback,
-        /* rx_certificate_waiter */ rx_certificates_loopback,
+        rx_state_requests,
         /* rx_proposer */ rx_headers,
-        tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_output,
+        addresses,
+        /* byzantine */ None,
+        /* header_size */ 1_000,
+        /* max_header_entries */ 10_000,
+        /* vote_timeout */ 0,
+        /* max_election_rounds */ 0,
+        Pacer::default(),
+        SenderLimits::default(),
+        KeepAlive::default(),
+        /* dissemination_timeout */ 0,
+        Compactor::default(),
+        InlineLimits::default(),
+        /* batch_commits */ false,
+        /* tx_reconfigure */ Vec::new(),
     );
 
-    // Send a header to the core.
-    let header = Header {
-        parents: [Digest::default()].iter().cloned().collect(),
-        ..header()
+    // Send our own header to the core.
+    let header = header();
+    tx_headers.send(header.clone()).await.unwrap();
+
+    // Ensure all other primaries received it.
+    for handle in handles {
+        let received = handle.await.unwrap();
+        match wire::deserialize(&received).unwrap() {
+            PrimaryMessage::Header(x) => assert_eq!(x, header),
+            x => panic!("Unexpected message {:?}", x),
+        }
+    }
+}
+
+#[tokio::test]
+async fn decide_on_quorum_of_commits() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 13_100, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // Three commits for the same tx hash form a quorum.
+    let tx_hash = Digest([1; 32]);
+    for (author, _) in keys {
+        let header =
+            header_with_votes(author, vec![vote(1, tx_hash.clone(), election_id(0), true)]);
+        core.process_header(&header).await.unwrap();
+    }
+    assert!(core.elections.get(&election_id(0)).unwrap().decided);
+
+    // The decision is output once.
+    let decision = decision(rx_output.try_recv().unwrap());
+    assert_eq!(decision, (election_id(0), tx_hash.clone(), 1));
+
+    // Our own commit for the decided election does not output it again.
+    let header = header_with_votes(name, vec![vote(1, tx_hash, election_id(0), true)]);
+    core.process_header(&header).await.unwrap();
+    assert!(rx_output.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn cleanup_decided_elections() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 13_200, /* gc_depth */ 1, /* vote_timeout */ 0,
+    ).await;
+
+    // Decide three elections, one per round of the dag.
+    let tx_hash = Digest([1; 32]);
+    for seed in 0..3 {
+        for (author, _) in &keys {
+            let mut header = header_with_votes(
+                *author,
+                vec![vote(1, tx_hash.clone(), election_id(seed), true)],
+            );
+            header.round = seed as Round + 1;
+            let header = sign(header);
+            core.process_header(&header).await.unwrap();
+        }
+    }
+
+    // Only the tallies of the last decided election are kept in memory.
+    assert_eq!(core.elections.len(), 1);
+    assert!(core.elections.contains_key(&election_id(2)));
+    assert_eq!(core.decided.len(), 2);
+
+    // A late vote does not re-open a pruned election.
+    let (author, _) = keys[0];
+    let mut header = header_with_votes(author, vec![vote(2, tx_hash, election_id(0), true)]);
+    header.round = core.dag_round;
+    let header = sign(header);
+    core.process_header(&header).await.unwrap();
+    assert!(!core.elections.contains_key(&election_id(0)));
+}
+
+#[tokio::test]
+async fn bound_pruned_elections() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 18_100, /* gc_depth */ 2, /* vote_timeout */ 0,
+    ).await;
+    tokio::spawn(async move { while rx_output.recv().await.is_some() {} });
+
+    // Decide many elections, one per round of the dag.
+    let tx_hash = Digest([1; 32]);
+    for seed in 0..50 {
+        for (author, _) in &keys {
+            let mut header = header_with_votes(
+                *author,
+                vec![vote(1, tx_hash.clone(), election_id(seed), true)],
+            );
+            header.round = seed as Round + 1;
+            let header = sign(header);
+            core.process_header(&header).await.unwrap();
+        }
+    }
+
+    // We only remember the elections pruned in the last `gc_depth` rounds.
+    assert_eq!(core.elections.len(), 2);
+    assert!(core.decided.len() <= 3);
+    assert!(core.decided.contains(&election_id(47)));
+    assert!(!core.decided.contains(&election_id(0)));
+
+    // Their claims go with them.
+    let claims = core.store.read(claims_key(&election_id(0))).await.unwrap();
+    assert!(claims.is_none());
+    let claims = core.store.read(claims_key(&election_id(47))).await.unwrap();
+    assert!(claims.is_some());
+}
+
+#[tokio::test]
+async fn record_equivocation_evidence() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 18_500, /* gc_depth */ 2, /* vote_timeout */ 0,
+    ).await;
+    tokio::spawn(async move { while rx_output.recv().await.is_some() {} });
+
+    // The same author claims two tx hashes in the same round, in two headers of the same round.
+    let author = keys[0].0;
+    let first = Digest([1; 32]);
+    let second = Digest([2; 32]);
+    let header_1 = header_with_votes(author, vec![vote(0, first.clone(), election_id(0), false)]);
+    let header_2 = header_with_votes(author, vec![vote(0, second.clone(), election_id(0), false)]);
+    core.process_header(&header_1).await.unwrap();
+    core.process_header(&header_2).await.unwrap();
+
+    // Only the first vote is tallied.
+    let tally = &core.elections[&election_id(0)].tallies[&0];
+    assert!(tally.votes[&first].contains(&author));
+    assert!(!tally.votes.contains_key(&second));
+
+    // The evidence names both headers.
+    let evidence = Equivocation {
+        author,
+        round: 0,
+        election_id: election_id(0),
+        first: header_1.digest(),
+        second: header_2.digest(),
     };
-    let id = header.id.clone();
-    tx_primary_messages
-        .send(PrimaryMessage::Header(header))
-        .await
-        .unwrap();
+    assert_eq!(core.equivocations().iter().collect::<Vec<_>>(), vec![&evidence]);
 
-    // Ensure the header is not stored.
-    assert!(store.read(id.to_vec()).await.unwrap().is_none());
+    // Decide many elections, so that the claims of the first one are deleted.
+    for seed in 0..10 {
+        for (author, _) in &keys {
+            let mut header = header_with_votes(
+                *author,
+                vec![vote(1, first.clone(), election_id(seed), true)],
+            );
+            header.round = seed as Round + 1;
+            let header = sign(header);
+            core.process_header(&header).await.unwrap();
+        }
+    }
+    let claims = core.store.read(claims_key(&election_id(0))).await.unwrap();
+    assert!(claims.is_none());
+
+    // The evidence survives them.
+    assert_eq!(core.equivocations().iter().collect::<Vec<_>>(), vec![&evidence]);
+    let key = equivocation_key(&election_id(0), &author, 0);
+    let bytes = core.store.read(key).await.unwrap().unwrap();
+    assert_eq!(network::decode::<Equivocation>(&bytes).unwrap(), evidence);
 }
 
 #[tokio::test]
-async fn process_header_missing_payload() {
-    let (name, secret) = keys().pop().unwrap();
-    let signature_service = SignatureService::new(secret);
+async fn stop_claims_once_decided() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 18_200, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
 
-    let (tx_sync_headers, _rx_sync_headers) = channel(1);
-    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
-    let (tx_primary_messages, rx_primary_messages) = channel(1);
-    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
-    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
-    let (_tx_headers, rx_headers) = channel(1);
-    let (tx_consensus, _rx_consensus) = channel(1);
-    let (tx_parents, _rx_parents) = channel(1);
+    // A quorum of commits decides the election.
+    let tx_hash = Digest([1; 32]);
+    for (author, _) in &keys {
+        let votes = vec![vote(1, tx_hash.clone(), election_id(0), true)];
+        core.process_header(&sign(header_with_votes(*author, votes))).await.unwrap();
+    }
+    assert!(core.elections[&election_id(0)].decided);
 
-    // Create a new test store.
-    let path = ".db_test_process_header_missing_payload";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    // Later votes are not recorded, not even conflicting ones.
+    let votes = vec![vote(2, Digest([2; 32]), election_id(0), false)];
+    core.process_header(&sign(header_with_votes(keys[0].0, votes))).await.unwrap();
+    let bytes = core.store.read(claims_key(&election_id(0))).await.unwrap();
+    let claims: Vec<Claim> = network::decode(&bytes.unwrap()).unwrap();
+    assert_eq!(claims.len(), 3);
+    assert!(core.conflicts.is_empty());
+}
 
-    // Make a synchronizer for the core.
-    let synchronizer = Synchronizer::new(
-        name,
-        &committee(),
-        store.clone(),
-        /* tx_header_waiter */ tx_sync_headers,
-        /* tx_certificate_waiter */ tx_sync_certificates,
+#[tokio::test]
+async fn record_conflicting_claims() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 13_300, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // The first author claims one tx hash, the other two claim another.
+    let first = Digest([1; 32]);
+    let second = Digest([2; 32]);
+    let hashes = vec![first.clone(), second.clone(), second.clone()];
+    for ((author, _), tx_hash) in keys.iter().zip(hashes) {
+        let header = header_with_votes(*author, vec![vote(0, tx_hash, election_id(5), false)]);
+        core.process_header(&header).await.unwrap();
+    }
+
+    // The index holds every claim, including both conflicting tx hashes.
+    let bytes = core.store.read(claims_key(&election_id(5))).await.unwrap();
+    let claims: Vec<Claim> = network::decode(&bytes.unwrap()).unwrap();
+    assert_eq!(claims.len(), 3);
+    assert!(claims
+        .iter()
+        .any(|x| x.tx_hash == first && x.author == keys[0].0));
+    assert!(claims.iter().any(|x| x.tx_hash == second));
+
+    // The conflict is only reported once.
+    assert_eq!(core.conflicts.len(), 1);
+    assert!(core.conflicts.contains(&election_id(5)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn advance_round_on_timeout() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(name, secret, 13_400, /* gc_depth */ 50, 50).await;
+    let start = Instant::now();
+
+    // Two nodes vote for different tx hashes; together with our own vote the round holds a
+    // quorum of votes but no quorum for a single tx hash.
+    let (low, high) = (Digest([1; 32]), Digest([2; 32]));
+    for ((author, _), tx_hash) in keys.iter().zip(vec![low.clone(), high.clone()]) {
+        let header = header_with_votes(*author, vec![vote(0, tx_hash, election_id(0), false)]);
+        core.process_header(&header).await.unwrap();
+    }
+    let next_round = |core: &Core| core.votes.iter().any(|x| x.round == 1);
+    assert!(!next_round(&core));
+
+    // The round times out 50 ms after its first vote.
+    let election = core.elections.get(&election_id(0)).unwrap();
+    let deadline = start + Duration::from_millis(50);
+    assert!(!election.can_advance(&name, 0, deadline - Duration::from_millis(1)));
+    assert!(election.can_advance(&name, 0, deadline));
+
+    // Once the round timer expires, we vote for the highest tx hash in the next round.
+    let (id, round) = core.timers.next().await.unwrap().unwrap().into_inner();
+    assert_eq!(Instant::now(), deadline);
+    assert_eq!((id.clone(), round), (election_id(0), 0));
+    core.process_timeout(id, round).await;
+    let expected = vote(1, high, election_id(0), false);
+    assert!(core.votes.contains(&expected));
+}
+
+#[tokio::test]
+async fn pace_outbound_votes() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 13_500, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+    core.pacer = Pacer::new(/* chunk */ 2, /* interval */ 5);
+
+    // Flush one commit and three votes.
+    let tx_hash = Digest([1; 32]);
+    core.votes = (0..4)
+        .map(|seed| vote(0, tx_hash.clone(), election_id(seed), seed == 0))
+        .collect();
+    core.flush_votes().await;
+
+    // The commit leaves right away, the votes follow in two chunks. The first chunk leaves right
+    // away too if its jitter happens to be zero.
+    let immediate = core.metrics.bursts - 1;
+    assert!(immediate <= 1);
+    assert_eq!(core.metrics.largest_flush, 4);
+    for _ in immediate..2 {
+        let header = core.rx_paced.recv().await.unwrap();
+        assert!(header.votes.len() <= 2);
+        assert!(header.votes.iter().all(|x| !x.commit));
+    }
+}
+
+#[tokio::test]
+async fn equivocate_to_peers() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 13_600, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+    core.byzantine = Some(ByzantineStrategy::Equivocate);
+
+    // Spawn a listener for each other primary to receive our header.
+    let handles: Vec<_> = core
+        .addresses
+        .iter()
+        .map(|address| listener(*address))
+        .collect();
+
+    let header = header_with_votes(name, vec![vote(0, Digest([1; 32]), election_id(0), false)]);
+    core.broadcast(&header).await;
+
+    // The peers received conflicting votes for the same round and election.
+    let mut received = Vec::new();
+    for handle in handles {
+        match wire::deserialize(&handle.await.unwrap()).unwrap() {
+            PrimaryMessage::Header(x) => received.extend(x.votes),
+            x => panic!("Unexpected message {:?}", x),
+        }
+    }
+    assert!(received
+        .iter()
+        .all(|x| x.round == 0 && x.election_id == election_id(0)));
+    assert!(received.iter().any(|x| x.tx_hash != received[0].tx_hash));
+}
+
+// Fixture: deliver the headers to all cores, then let 10 ms pass and gather the votes they make
+// (expired round timers included) in new headers, `steps` times. Vote-flipping cores flip them
+// as they would when flushing them.
+async fn exchange_votes(cores: &mut [Core], mut outbound: Vec<Header>, steps: usize) {
+    for _ in 0..steps {
+        for header in outbound.drain(..) {
+            for core in cores.iter_mut() {
+                core.process_header(&header).await.unwrap();
+            }
+        }
+        sleep(Duration::from_millis(10)).await;
+        for core in cores.iter_mut() {
+            while let Some(Some(Ok(expired))) = core.timers.next().now_or_never() {
+                let (id, round) = expired.into_inner();
+                core.process_timeout(id, round).await;
+            }
+            let mut votes: Vec<_> = core.votes.drain(..).collect();
+            if core.byzantine == Some(ByzantineStrategy::VoteFlip) {
+                votes = votes.into_iter().map(|x| core.flip(x)).collect();
+            }
+            if !votes.is_empty() {
+                outbound.push(header_with_votes(core.name, votes));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn decide_despite_equivocation() {
+    let mut keys = keys();
+    let (byzantine, _) = keys.pop().unwrap();
+    let mut cores = Vec::new();
+    let mut outputs = Vec::new();
+    for ((name, secret), base_port) in keys.into_iter().zip(vec![13_700, 13_800, 13_900]) {
+        let (core, rx_output) = core(
+            name, secret, base_port, /* gc_depth */ 50, /* vote_timeout */ 0,
+        ).await;
+        cores.push(core);
+        outputs.push(rx_output);
+    }
+
+    // The equivocator sends one tx hash to the first honest node and another one to the others.
+    let genuine = vote(0, Digest([2; 32]), election_id(0), false);
+    let conflicting = equivocate(&genuine);
+    for (i, core) in cores.iter_mut().enumerate() {
+        let vote = if i == 0 {
+            conflicting.clone()
+        } else {
+            genuine.clone()
+        };
+        let header = header_with_votes(byzantine, vec![vote]);
+        core.process_header(&header).await.unwrap();
+    }
+
+    // Each honest node proposes the tx hash it received and then keeps exchanging its votes.
+    let outbound: Vec<_> = cores
+        .iter()
+        .enumerate()
+        .map(|(i, core)| {
+            let vote = if i == 0 {
+                conflicting.clone()
+            } else {
+                genuine.clone()
+            };
+            header_with_votes(core.name, vec![vote])
+        })
+        .collect();
+    exchange_votes(&mut cores, outbound, /* steps */ 10).await;
+
+    // All honest nodes decide the same tx hash.
+    let decisions: Vec<_> = outputs.iter_mut().map(|x| x.try_recv().unwrap()).collect();
+    assert!(decisions.iter().all(|x| x == &decisions[0]));
+}
+
+#[tokio::test]
+async fn park_header_with_unknown_parents() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 14_000, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // The child follows a parent we did not receive yet.
+    let tx_hash = Digest([1; 32]);
+    let parent = header_with_votes(
+        keys[0].0,
+        vec![vote(0, tx_hash.clone(), election_id(0), false)],
     );
+    let mut child = header_with_votes(keys[1].0, vec![vote(0, tx_hash, election_id(1), false)]);
+    child.parents.insert(parent.digest());
+    let child = sign(child);
+
+    // The child is parked: its votes are not counted.
+    core.process_header(&child).await.unwrap();
+    assert!(!core.elections.contains_key(&election_id(1)));
+
+    // Once the parent is processed, the child comes back to the core.
+    core.process_header(&parent).await.unwrap();
+    let header = core.rx_loopback.recv().await.unwrap();
+    assert_eq!(header, child);
+    core.process_header(&header).await.unwrap();
+    assert!(core.elections.contains_key(&election_id(1)));
+}
+
+#[tokio::test]
+async fn accept_own_vote_headers_as_parents() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 18_000, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // One of our vote headers is stored as we make it.
+    let tx_hash = Digest([1; 32]);
+    let votes = vec![vote(0, tx_hash.clone(), election_id(0), false)];
+    let ours = core.make_headers(votes, BTreeSet::new()).await.pop().unwrap();
+    let stored = core.store.read(ours.digest().to_vec()).await.unwrap();
+    assert_eq!(stored, Some(encode(&ours).unwrap()));
+
+    // A peer header that follows it is accepted right away rather than parked.
+    let mut child = header_with_votes(keys[0].0, vec![vote(0, tx_hash, election_id(1), false)]);
+    child.parents.insert(ours.digest());
+    let child = sign(child);
+    core.process_header(&child).await.unwrap();
+    assert!(!core.parked.contains(&child.digest()));
+    assert!(core.elections.contains_key(&election_id(1)));
+}
+
+#[tokio::test]
+async fn outlive_stopped_proposer() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 18_300, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // The proposer stops first during a shutdown.
+    let (tx_parents, _) = channel(1);
+    core.tx_proposer = tx_parents;
+
+    // A quorum of headers still moves us to the next round.
+    for (author, _) in &keys {
+        let votes = vec![vote(0, Digest([1; 32]), election_id(0), false)];
+        core.process_header(&sign(header_with_votes(*author, votes))).await.unwrap();
+    }
+    assert_eq!(core.dag_round, 2);
+}
+
+#[tokio::test]
+async fn recover_round_after_restart() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let committee = committee_with_base_port(14_100);
+
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_state_requests, rx_state_requests) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_parents, mut rx_parents) = channel(10);
+    let (tx_output, _rx_output) = channel(1);
+
+    // Create a new test store remembering a stale round.
+    let path = ".db_test_recover_round_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store
+        .write(ROUND_KEY.to_vec(), encode(&3u64).unwrap())
+        .await;
 
     // Spawn the core.
     Core::spawn(
         name,
-        committee(),
+        committee.clone(),
         store.clone(),
-        synchronizer,
-        signature_service,
+        SignatureService::new(secret),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        /* round_window */ 50,
         /* rx_primaries */ rx_primary_messages,
-        /* rx_header_waiter */ rx_headers_loopback,
-        /* rx_certificate_waiter */ rx_certificates_loopback,
+        rx_state_requests,
         /* rx_proposer */ rx_headers,
-        tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_output,
+        /* addresses */ Vec::new(),
+        /* byzantine */ None,
+        /* header_size */ 1_000,
+        /* max_header_entries */ 10_000,
+        /* vote_timeout */ 0,
+        /* max_election_rounds */ 0,
+        Pacer::default(),
+        SenderLimits::default(),
+        KeepAlive::default(),
+        /* dissemination_timeout */ 0,
+        Compactor::default(),
+        InlineLimits::default(),
+        /* batch_commits */ false,
+        /* tx_reconfigure */ Vec::new(),
     );
 
-    // Send a header to the core.
-    let header = Header {
-        payload: [(Digest::default(), 0)].iter().cloned().collect(),
-        ..header()
-    };
-    let id = header.id.clone();
-    tx_primary_messages
-        .send(PrimaryMessage::Header(header))
-        .await
-        .unwrap();
+    // The proposer resumes after the persisted round.
+    assert_eq!(rx_parents.recv().await.unwrap(), (Vec::new(), 3));
+
+    // A single byzantine peer claims a far round, two honest peers are at round 40. The far
+    // header is dropped, so the byzantine peer then sends one at round 40 as well.
+    let mut keys: Vec<_> = keys.into_iter().rev().collect();
+    keys.push(crate::common::keys().remove(2));
+    let rounds = vec![10_000, 40, 40, 40];
+    for ((author, secret), round) in keys.into_iter().zip(rounds) {
+        let mut signature_service = SignatureService::new(secret);
+        let header = Header::new(
+            author,
+            /* epoch */ 0,
+            round,
+            BTreeSet::new(),
+            BTreeMap::new(),
+            BTreeSet::new(),
+            BTreeMap::new(),
+            BTreeSet::new(),
+            &mut signature_service,
+        )
+        .await;
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // The core catches up with the honest peers only.
+    let (parents, round) = rx_parents.recv().await.unwrap();
+    assert_eq!(parents.len(), 3);
+    assert_eq!(round, 40);
 
-    // Ensure the header is not stored.
-    assert!(store.read(id.to_vec()).await.unwrap().is_none());
+    // Our next header is persisted for the following restart.
+    let mut header = header();
+    header.author = name;
+    header.round = 41;
+    tx_headers.send(header).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    let stored = store.read(ROUND_KEY.to_vec()).await.unwrap().unwrap();
+    assert_eq!(decode::<Round>(&stored).unwrap(), 41);
+}
+
+// Spawn a core over a store, returning the channel of our headers and the parents it hands the
+// proposer.
+#[allow(clippy::type_complexity)]
+fn spawn_core(
+    name: PublicAddress,
+    secret: crypto::SecretKey,
+    committee: Committee,
+    store: Store,
+) -> (Sender<Header>, Receiver<(Vec<Digest>, Round)>, JoinHandle<()>) {
+    let (_tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_state_requests, rx_state_requests) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_parents, rx_parents) = channel(10);
+    let (tx_output, _rx_output) = channel(1);
+    let handle = Core::spawn(
+        name,
+        committee,
+        store,
+        SignatureService::new(secret),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* round_window */ 50,
+        /* rx_primaries */ rx_primary_messages,
+        rx_state_requests,
+        /* rx_proposer */ rx_headers,
+        /* tx_proposer */ tx_parents,
+        tx_output,
+        /* addresses */ Vec::new(),
+        /* byzantine */ None,
+        /* header_size */ 1_000,
+        /* max_header_entries */ 10_000,
+        /* vote_timeout */ 0,
+        /* max_election_rounds */ 0,
+        Pacer::default(),
+        SenderLimits::default(),
+        KeepAlive::default(),
+        /* dissemination_timeout */ 0,
+        Compactor::default(),
+        InlineLimits::default(),
+        /* batch_commits */ false,
+        /* tx_reconfigure */ Vec::new(),
+    );
+    (tx_headers, rx_parents, handle)
 }
 
 #[tokio::test]
-async fn process_votes() {
+async fn restart_after_own_header() {
     let (name, secret) = keys().pop().unwrap();
-    let signature_service = SignatureService::new(secret);
+    let committee = committee_with_base_port(18_400);
+    let path = ".db_test_restart_after_own_header";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
 
-    let committee = committee_with_base_port(13_100);
+    // The core sends one of our headers, then crashes.
+    let (tx_headers, _rx_parents, handle) =
+        spawn_core(name, secret, committee.clone(), store.clone());
+    let mut header = header();
+    header.author = name;
+    header.round = 7;
+    tx_headers.send(header).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    handle.abort();
+    let _ = handle.await;
 
-    let (tx_sync_headers, _rx_sync_headers) = channel(1);
-    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
-    let (tx_primary_messages, rx_primary_messages) = channel(1);
-    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
-    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
-    let (_tx_headers, rx_headers) = channel(1);
-    let (tx_consensus, _rx_consensus) = channel(1);
-    let (tx_parents, _rx_parents) = channel(1);
+    // Restarted over the same store, the proposer resumes past the round of that header.
+    let (_, secret) = keys().pop().unwrap();
+    let (_tx_headers, mut rx_parents, _handle) = spawn_core(name, secret, committee, store);
+    assert_eq!(rx_parents.recv().await.unwrap(), (Vec::new(), 7));
+}
 
-    // Create a new test store.
-    let path = ".db_test_process_vote";
-    let _ = fs::remove_dir_all(path);
-    let store = Store::new(path).unwrap();
+#[tokio::test]
+async fn drop_far_future_votes() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 14_200, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
 
-    // Make a synchronizer for the core.
-    let synchronizer = Synchronizer::new(
-        name,
-        &committee,
-        store.clone(),
-        /* tx_header_waiter */ tx_sync_headers,
-        /* tx_certificate_waiter */ tx_sync_certificates,
+    // A byzantine peer floods us with votes for rounds nobody reached.
+    let (author, _) = keys[0];
+    let tx_hash = Digest([1; 32]);
+    let header = header_with_votes(
+        author,
+        vec![vote(0, tx_hash.clone(), election_id(0), false)],
     );
+    core.process_header(&header).await.unwrap();
+    for round in 0..1_000 {
+        let votes = vec![vote(
+            1_000_000 + round,
+            tx_hash.clone(),
+            election_id(0),
+            false,
+        )];
+        let header = header_with_votes(author, votes);
+        core.process_header(&header).await.unwrap();
+    }
 
-    // Spawn the core.
-    Core::spawn(
-        name,
-        committee.clone(),
-        store.clone(),
-        synchronizer,
-        signature_service,
-        /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
-        /* rx_primaries */ rx_primary_messages,
-        /* rx_header_waiter */ rx_headers_loopback,
-        /* rx_certificate_waiter */ rx_certificates_loopback,
-        /* rx_proposer */ rx_headers,
-        tx_consensus,
-        /* tx_proposer */ tx_parents,
+    // We only hold the tally of the round it voted in along with ours.
+    let election = core.elections.get(&election_id(0)).unwrap();
+    assert!(election.tallies.len() <= 2);
+    assert!(election.tallies.keys().all(|x| *x <= 50));
+}
+
+#[tokio::test]
+async fn reject_headers_out_of_bounds() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 14_300, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+    let (author, _) = keys[0];
+
+    // A header far ahead of us.
+    let mut header = header_with_votes(
+        author,
+        vec![vote(0, Digest([1; 32]), election_id(0), false)],
+    );
+    header.round = 1_000_000_000;
+    header = sign(header);
+    match core.process_header(&header).await {
+        Err(DagError::TooFar(_, round)) => assert_eq!(round, 1_000_000_000),
+        x => panic!("Unexpected result {:?}", x),
+    }
+    assert!(core.elections.is_empty());
+
+    // A header replaying a round long gone.
+    core.dag_round = 100;
+    header.round = 10;
+    header = sign(header);
+    match core.process_header(&header).await {
+        Err(DagError::TooOld(_, round)) => assert_eq!(round, 10),
+        x => panic!("Unexpected result {:?}", x),
+    }
+    assert!(core.elections.is_empty());
+}
+
+#[tokio::test]
+async fn bound_header_entries() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 16_600, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    core.header_size = 2;
+    core.max_header_entries = 2;
+
+    // A header voting in more elections than we accept is rejected before we look at its votes.
+    let votes = (0..3).map(|x| vote(0, Digest([1; 32]), election_id(x), false)).collect();
+    let header = header_with_votes(keys[0].0, votes);
+    match core.process_header(&header).await {
+        Err(DagError::HeaderTooLarge(_, entries)) => assert_eq!(entries, 3),
+        x => panic!("Unexpected result {:?}", x),
+    }
+    assert!(core.elections.is_empty());
+
+    // Our own votes are spread over headers of at most `header_size` votes.
+    let votes = (0..5).map(|x| vote(0, Digest([1; 32]), election_id(x), false)).collect();
+    let headers = core.make_headers(votes, BTreeSet::new()).await;
+    let sizes: Vec<_> = headers.iter().map(|x| x.votes.len()).collect();
+    assert_eq!(sizes, vec![2, 2, 1]);
+}
+
+#[tokio::test]
+async fn drop_header_copies() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 14_400, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // The first commit arrives five times, interleaved with the other two.
+    let tx_hash = Digest([1; 32]);
+    let headers: Vec<_> = keys
+        .iter()
+        .map(|(author, _)| {
+            header_with_votes(
+                *author,
+                vec![vote(1, tx_hash.clone(), election_id(0), true)],
+            )
+        })
+        .collect();
+    for i in [0, 0, 1, 0, 2, 0, 0] {
+        core.process_header(&headers[i]).await.unwrap();
+    }
+
+    // Each header was processed once, and the election decided once.
+    assert_eq!(core.seen.processed, 3);
+    assert_eq!(core.seen.duplicates, 4);
+    assert!(core.elections.get(&election_id(0)).unwrap().decided);
+    let (decided, committed, _) = decision(rx_output.recv().await.unwrap());
+    assert_eq!(decided, election_id(0));
+    assert_eq!(committed, tx_hash);
+    assert!(rx_output.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn move_to_next_epoch() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 17_500, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    let (tx_reconfigure, mut rx_reconfigure) = channel(1);
+    core.tx_reconfigure = vec![tx_reconfigure];
+    let (author, _) = keys[0];
+    let votes = |x: u8| vec![vote(0, Digest([1; 32]), election_id(x), false)];
+    let of_epoch = |epoch, x| sign(Header { epoch, ..header_with_votes(author, votes(x)) });
+
+    // A header of the next epoch waits for it; those of later epochs are rejected.
+    core.process_message(PrimaryMessage::Header(of_epoch(1, 1))).await.unwrap();
+    core.process_message(PrimaryMessage::Header(of_epoch(2, 2))).await.unwrap();
+    assert!(core.elections.is_empty());
+    assert_eq!(core.peer_errors.counts[&author], BTreeMap::from([("StaleEpoch", 1)]));
+
+    // Without a quorum of the current committee, the reconfiguration is refused.
+    let next = Committee {
+        epoch: 1,
+        ..committee_with_base_port(17_500)
+    };
+    let refused = reconfiguration(next.clone(), 2);
+    assert!(matches!(
+        core.process_reconfiguration(refused).await,
+        Err(DagError::CertificateRequiresQuorum)
+    ));
+    assert_eq!(core.committee.epoch, 0);
+    let round = core.dag_round;
+
+    // Once we move on, the tasks following the committee do too, and the held header comes back.
+    let accepted = reconfiguration(next, 3);
+    core.process_reconfiguration(accepted).await.unwrap();
+    assert_eq!(core.committee.epoch, 1);
+    assert_eq!(core.dag_round, round + 1);
+    assert_eq!(rx_reconfigure.recv().await.unwrap().epoch, 1);
+    let header = core.rx_loopback.recv().await.unwrap();
+    assert_eq!(header.epoch, 1);
+    core.process_header(&header).await.unwrap();
+    assert!(core.elections.contains_key(&election_id(1)));
+
+    // The headers of the previous epoch are now stale.
+    core.process_message(PrimaryMessage::Header(of_epoch(0, 3))).await.unwrap();
+    assert_eq!(core.peer_errors.counts[&author], BTreeMap::from([("StaleEpoch", 2)]));
+
+    // Our own headers made before the `Proposer` heard of it are signed again, without the parents
+    // of the previous epoch.
+    let header = core.current_epoch(header_with_votes(name, votes(4))).await;
+    assert_eq!(header.epoch, 1);
+    assert!(header.verify(&core.committee).is_ok());
+    let old_parents = Header {
+        epoch: 1,
+        parents: BTreeSet::from([Digest([2; 32])]),
+        ..header_with_votes(name, votes(5))
+    };
+    let header = core.current_epoch(old_parents).await;
+    assert!(header.parents.is_empty());
+    assert!(header.verify(&core.committee).is_ok());
+}
+
+#[tokio::test]
+async fn request_unknown_parents() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 14_500, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+
+    // The votes of the child arrive before its parent.
+    let tx_hash = Digest([1; 32]);
+    let parent = header_with_votes(
+        keys[0].0,
+        vec![vote(0, tx_hash.clone(), election_id(0), false)],
+    );
+    let mut child = header_with_votes(keys[1].0, vec![vote(0, tx_hash, election_id(1), false)]);
+    child.parents.insert(parent.digest());
+    let child = sign(child);
+
+    // We ask the author of the child for the parent.
+    let address = committee_with_base_port(14_500)
+        .primary(&keys[1].0)
+        .unwrap()
+        .primary_to_primary;
+    let handle = listener(address);
+    core.process_header(&child).await.unwrap();
+    match wire::deserialize(&handle.await.unwrap()).unwrap() {
+        PrimaryMessage::HeaderRequest(missing, requestor) => {
+            assert_eq!(missing, vec![parent.digest()]);
+            assert_eq!(requestor, name);
+        }
+        x => panic!("Unexpected message {:?}", x),
+    }
+
+    // Once the parent comes back, the votes of the child are tallied.
+    core.process_header(&parent).await.unwrap();
+    let header = core.rx_loopback.recv().await.unwrap();
+    core.process_header(&header).await.unwrap();
+    assert!(core.elections.contains_key(&election_id(1)));
+}
+
+#[tokio::test]
+async fn bound_parked_headers() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 14_600, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+    core.parked = ParkedHeaders::new(2);
+
+    // Five children follow parents that never arrive.
+    let tx_hash = Digest([1; 32]);
+    let parents: Vec<_> = (0..5)
+        .map(|i| {
+            header_with_votes(
+                keys[0].0,
+                vec![vote(0, tx_hash.clone(), election_id(i), false)],
+            )
+        })
+        .collect();
+    for (i, parent) in parents.iter().enumerate() {
+        let mut child = header_with_votes(
+            keys[1].0,
+            vec![vote(0, tx_hash.clone(), election_id(10 + i as u8), false)],
+        );
+        child.parents.insert(parent.digest());
+        let child = sign(child);
+        core.process_header(&child).await.unwrap();
+    }
+
+    // Only the last two stay parked.
+    assert_eq!(core.parked.len(), 2);
+    assert_eq!(core.parked.evicted, 3);
+
+    // An evicted child no longer waits for its parent.
+    core.process_header(&parents[0]).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(core.rx_loopback.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn wait_for_missing_batches() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 14_800, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+
+    // A header votes for a batch our workers never received.
+    let withheld = Digest([3; 32]);
+    let header = header_with_votes(
+        keys[0].0,
+        vec![vote(0, withheld.clone(), election_id(0), false)],
     );
 
-    // Make the certificate we expect to receive.
-    let expected = certificate(&Header::default());
+    // We ask our worker to fetch it from the author, and hold the header meanwhile.
+    let address = committee_with_base_port(14_800)
+        .our_workers(&name)
+        .unwrap()
+        .remove(0)
+        .primary_to_worker;
+    let handle = listener(address);
+    core.process_header(&header).await.unwrap();
+    match decode(&handle.await.unwrap()).unwrap() {
+        PrimaryWorkerMessage::Synchronize(missing, target) => {
+            assert_eq!(missing, vec![withheld.clone()]);
+            assert_eq!(target, keys[0].0);
+        }
+        x => panic!("Unexpected message {:?}", x),
+    }
+    assert!(!core.elections.contains_key(&election_id(0)));
+
+    // Once the worker fetched the batch, the header is voted on.
+    core.store
+        .write(payload_key(&withheld), Vec::default())
+        .await;
+    let header = core.rx_loopback.recv().await.unwrap();
+    core.process_header(&header).await.unwrap();
+    assert!(core.elections.contains_key(&election_id(0)));
+}
 
-    // Spawn all listeners to receive our newly formed certificate.
+#[tokio::test]
+async fn certify_decisions() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 14_900, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    let committee = committee_with_base_port(14_900);
     let handles: Vec<_> = committee
         .others_primaries(&name)
         .iter()
-        .map(|(_, address)| listener(address.primary_to_primary))
+        .map(|(_, addresses)| listener(addresses.primary_to_primary))
         .collect();
 
-    // Send a votes to the core.
-    for vote in votes(&Header::default()) {
-        tx_primary_messages
-            .send(PrimaryMessage::Vote(vote))
-            .await
-            .unwrap();
+    // Three signed commits for the same tx hash decide the election.
+    let tx_hash = Digest([1; 32]);
+    for (author, secret) in keys {
+        let mut signature_service = SignatureService::new(secret);
+        let votes = vec![vote(1, tx_hash.clone(), election_id(0), true)];
+        let votes = votes.into_iter().collect();
+        let header = Header::new(author, 0, 1, votes, BTreeMap::new(), BTreeSet::new(), BTreeMap::new(), BTreeSet::new(), &mut signature_service).await;
+        core.process_header(&header).await.unwrap();
     }
+    assert_eq!(
+        decision(rx_output.try_recv().unwrap()),
+        (election_id(0), tx_hash.clone(), 1)
+    );
 
-    // Ensure all listeners got the certificate.
-    for received in try_join_all(handles).await.unwrap() {
-        match bincode::deserialize(&received).unwrap() {
-            PrimaryMessage::Certificate(x) => assert_eq!(x, expected),
-            x => panic!("Unexpected message: {:?}", x),
+    // Every peer gets a valid certificate of the decision, which we also keep.
+    for handle in handles {
+        match wire::deserialize(&handle.await.unwrap()).unwrap() {
+            PrimaryMessage::CommitCertificate(certificate) => {
+                assert_eq!(certificate.tx_hash, tx_hash);
+                assert!(certificate.verify(&committee).is_ok());
+            }
+            x => panic!("Unexpected message {:?}", x),
         }
     }
+    let key = certificate_key(&election_id(0));
+    assert!(core.store.read(key).await.unwrap().is_some());
 }
 
 #[tokio::test]
-async fn process_certificates() {
+async fn decide_on_commit_certificate() {
     let (name, secret) = keys().pop().unwrap();
-    let signature_service = SignatureService::new(secret);
+    let (mut core, mut rx_output) = core(
+        name, secret, 15_200, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
 
-    let (tx_sync_headers, _rx_sync_headers) = channel(1);
-    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
-    let (tx_primary_messages, rx_primary_messages) = channel(3);
-    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
-    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
-    let (_tx_headers, rx_headers) = channel(1);
-    let (tx_consensus, mut rx_consensus) = channel(3);
-    let (tx_parents, mut rx_parents) = channel(1);
+    // We saw no vote at all: certificates without a quorum or with forged signatures are rejected.
+    let tx_hash = Digest([1; 32]);
+    let certificate = commit_certificate(tx_hash.clone(), election_id(0), 2);
+    assert!(core.process_certificate(certificate).await.is_err());
+    let mut certificate = commit_certificate(tx_hash.clone(), election_id(0), 3);
+    certificate.tx_hash = Digest([2; 32]);
+    assert!(core.process_certificate(certificate).await.is_err());
+    assert!(rx_output.try_recv().is_err());
+    assert!(!core.elections.contains_key(&election_id(0)));
 
-    // Create a new test store.
-    let path = ".db_test_process_certificates";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    // A valid certificate decides the election right away, once.
+    let certificate = commit_certificate(tx_hash.clone(), election_id(0), 3);
+    core.process_certificate(certificate.clone()).await.unwrap();
+    assert_eq!(
+        decision(rx_output.try_recv().unwrap()),
+        (election_id(0), tx_hash, 1)
+    );
+    assert!(core.elections.get(&election_id(0)).unwrap().decided);
+    core.process_certificate(certificate).await.unwrap();
+    assert!(rx_output.try_recv().is_err());
+}
 
-    // Make a synchronizer for the core.
-    let synchronizer = Synchronizer::new(
-        name,
-        &committee(),
-        store.clone(),
-        /* tx_header_waiter */ tx_sync_headers,
-        /* tx_certificate_waiter */ tx_sync_certificates,
+#[tokio::test]
+async fn compact_while_idle() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 15_300, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    core.compactor = Compactor::new(/* interval */ 1, /* idle_delay */ 0);
+
+    // No compaction while an election is in its final rounds.
+    let header = header_with_votes(
+        keys[0].0,
+        vec![vote(1, Digest([1; 32]), election_id(0), true)],
     );
+    core.process_header(&header).await.unwrap();
+    core.schedule_compaction();
+    core.schedule_compaction();
+    assert!(!core.compactor.metrics().running);
 
-    // Spawn the core.
-    Core::spawn(
-        name,
-        committee(),
-        store.clone(),
-        synchronizer,
-        signature_service,
-        /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
-        /* rx_primaries */ rx_primary_messages,
-        /* rx_header_waiter */ rx_headers_loopback,
-        /* rx_certificate_waiter */ rx_certificates_loopback,
-        /* rx_proposer */ rx_headers,
-        tx_consensus,
-        /* tx_proposer */ tx_parents,
+    // Once it is decided and no header shows up for a tick, we compact.
+    for (author, _) in &keys[1..] {
+        let header =
+            header_with_votes(*author, vec![vote(1, Digest([1; 32]), election_id(0), true)]);
+        core.process_header(&header).await.unwrap();
+    }
+    core.schedule_compaction();
+    assert!(!core.compactor.metrics().running);
+    core.schedule_compaction();
+    assert!(core.compactor.metrics().running);
+}
+
+#[tokio::test]
+async fn request_batches_from_their_worker() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 15_400, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    core.committee = committee_with_two_workers(15_400);
+
+    // A header votes for a batch its author's second worker holds, which ours never received.
+    let withheld = Digest([3; 32]);
+    let mut header = header_with_votes(
+        keys[0].0,
+        vec![vote(0, withheld.clone(), election_id(0), false)],
     );
+    header.payload.insert(withheld.clone(), 1);
+    let header = sign(header);
 
-    // Send enough certificates to the core.
-    let certificates: Vec<_> = headers()
+    // Only our second worker is asked to fetch it: it is the one talking to theirs.
+    let address = core.committee.worker(&name, &1).unwrap().primary_to_worker;
+    let handle = listener(address);
+    core.process_header(&header).await.unwrap();
+    match decode(&handle.await.unwrap()).unwrap() {
+        PrimaryWorkerMessage::Synchronize(missing, target) => {
+            assert_eq!(missing, vec![withheld]);
+            assert_eq!(target, keys[0].0);
+        }
+        x => panic!("Unexpected message {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn tally_only_signed_votes() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 15_500, /* gc_depth */ 50, /* vote_timeout */ 0,
+    ).await;
+    let tx_hash = Digest([1; 32]);
+    let (author, _) = keys[0];
+
+    // An unsigned header, and one signed by another authority than its author.
+    let unsigned = Header {
+        signature: Signature::default(),
+        ..header_with_votes(author, vec![vote(0, tx_hash.clone(), election_id(0), false)])
+    };
+    let forged = Header {
+        author,
+        ..header_with_votes(keys[1].0, vec![vote(0, tx_hash.clone(), election_id(0), false)])
+    };
+    for header in [unsigned, forged] {
+        assert!(matches!(
+            core.process_header(&header).await,
+            Err(DagError::InvalidSignature(_))
+        ));
+    }
+    assert!(core.elections.is_empty());
+
+    // The signed votes count, next to our own vote.
+    for (author, _) in &keys {
+        let header = header_with_votes(*author, vec![vote(0, tx_hash.clone(), election_id(0), false)]);
+        core.process_header(&header).await.unwrap();
+    }
+    let tally = core.elections[&election_id(0)].tallies.get(&0).unwrap();
+    let voters: BTreeSet<_> = keys.iter().map(|(x, _)| *x).chain([name]).collect();
+    assert_eq!(tally.votes[&tx_hash], voters);
+}
+
+#[tokio::test]
+async fn accept_inline_transactions() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 15_600, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+
+    // A header carries the transaction it votes for, which our workers never received.
+    let transaction = Transaction::random(10, vec![1]);
+    let tx_hash = transaction.digest();
+    let mut header = header_with_votes(
+        keys[0].0,
+        vec![vote(0, tx_hash.clone(), transaction.election_id(), false)],
+    );
+    header.inline.insert(tx_hash.clone(), transaction.clone());
+    let header = sign(header);
+
+    // We do not take inline transactions unless configured to.
+    assert!(matches!(
+        core.process_header(&header).await,
+        Err(DagError::MalformedHeader(_))
+    ));
+
+    // Then the header is voted on right away, and the transaction kept.
+    core.inline_limits = InlineLimits {
+        transaction_size: 10,
+        header_bytes: 10,
+    };
+    core.process_header(&header).await.unwrap();
+    assert!(core.elections.contains_key(&transaction.election_id()));
+    let stored = core.store.read(inline_key(&tx_hash)).await.unwrap().unwrap();
+    assert_eq!(decode::<Transaction>(&stored).unwrap().data, transaction.data);
+}
+
+// Fixture: the votes of a header proposing one transaction per election, all held by our workers.
+async fn proposals(core: &mut Core, seeds: &[u8]) -> Vec<Vote> {
+    let mut votes = Vec::new();
+    for seed in seeds {
+        let tx_hash = Digest([*seed; 32]);
+        core.store.write(payload_key(&tx_hash), Vec::default()).await;
+        votes.push(vote(0, tx_hash, election_id(*seed), false));
+    }
+    votes
+}
+
+// Fixture: a header of `author` committing to the votes of `header` but the excluded elections.
+fn batch_commit(author: PublicAddress, header: &Header, excluded: &[u8]) -> Header {
+    let mut batch_commit = header_with_votes(author, Vec::new());
+    batch_commit.batch_commits.insert(BatchCommit {
+        header: header.digest(),
+        excluded: excluded.iter().map(|x| election_id(*x)).collect(),
+    });
+    sign(batch_commit)
+}
+
+#[tokio::test]
+async fn batch_commit_uncontested_header() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let seeds: Vec<u8> = (100..110).collect();
+
+    // Without batch commits, a quorum of votes on a header of ten proposals takes ten commits.
+    let (mut baseline, _rx_output) = core(
+        name, crate::common::keys().pop().unwrap().1, 15_700, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    let votes = proposals(&mut baseline, &seeds).await;
+    for (author, _) in &keys[..2] {
+        let header = header_with_votes(*author, votes.clone());
+        baseline.process_header(&header).await.unwrap();
+    }
+    assert_eq!(baseline.votes.iter().filter(|x| x.commit).count(), 10);
+
+    // With batch commits, it takes one.
+    let (mut core, mut rx_output) = core(
+        name, secret, 15_800, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    core.batches = Some(PendingBatches::new(10));
+    let votes = proposals(&mut core, &seeds).await;
+    let proposal = header_with_votes(keys[0].0, votes.clone());
+    core.process_header(&proposal).await.unwrap();
+    let echo = header_with_votes(keys[1].0, votes);
+    core.process_header(&echo).await.unwrap();
+    assert!(core.votes.iter().all(|x| !x.commit));
+    assert_eq!(core.batch_commits.len(), 1);
+    let ours = core.batch_commits.iter().next().unwrap();
+    assert_eq!(ours.header, proposal.digest());
+    assert!(ours.excluded.is_empty());
+
+    // The batch commits of two peers complete a quorum of commits in each election.
+    for (author, _) in &keys[..2] {
+        core.process_header(&batch_commit(*author, &proposal, &[])).await.unwrap();
+    }
+    for seed in &seeds {
+        let decision = decision(rx_output.try_recv().unwrap());
+        assert!(seeds.contains(&decision.0 .0[0]));
+        assert_eq!(decision.1, Digest([decision.0 .0[0]; 32]));
+        assert_eq!(decision.2, 1);
+        assert!(core.elections[&election_id(*seed)].decided);
+    }
+    assert!(rx_output.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn batch_commit_falls_back_on_conflict() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 15_900, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    core.batches = Some(PendingBatches::new(10));
+
+    // Two peers vote for another tx hash in one of the three elections of a header.
+    let votes = proposals(&mut core, &[100, 101, 102]).await;
+    let proposal = header_with_votes(keys[0].0, votes.clone());
+    core.process_header(&proposal).await.unwrap();
+    let mut conflicting = votes;
+    conflicting[2] = proposals(&mut core, &[200]).await.pop().unwrap();
+    conflicting[2].election_id = election_id(102);
+    for (author, _) in &keys[1..] {
+        let header = header_with_votes(*author, conflicting.clone());
+        core.process_header(&header).await.unwrap();
+    }
+
+    // Only the contested election falls back: it moves on to its next round on its own.
+    assert_eq!(core.batch_commits.len(), 1);
+    let ours = core.batch_commits.iter().next().unwrap();
+    assert_eq!(ours.excluded, vec![election_id(102)].into_iter().collect());
+    assert_eq!(core.batches.as_ref().unwrap().fallbacks, 1);
+    assert!(core.votes.iter().all(|x| !x.commit));
+    assert!(core
+        .votes
         .iter()
-        .take(3)
-        .map(|header| certificate(header))
-        .collect();
+        .any(|x| x.election_id == election_id(102) && x.round == 1));
 
-    for x in certificates.clone() {
-        tx_primary_messages
-            .send(PrimaryMessage::Certificate(x))
-            .await
-            .unwrap();
+    // The batch commits decide the other two; the contested one is decided by individual commits.
+    for (author, _) in &keys[..2] {
+        core.process_header(&batch_commit(*author, &proposal, &[102])).await.unwrap();
+    }
+    let mut decided: Vec<_> = vec![
+        decision(rx_output.try_recv().unwrap()),
+        decision(rx_output.try_recv().unwrap()),
+    ];
+    decided.sort();
+    assert_eq!(decided[0].0, election_id(100));
+    assert_eq!(decided[1].0, election_id(101));
+    assert!(!core.elections[&election_id(102)].decided);
+    for (author, _) in &keys {
+        let commit = vote(1, Digest([200; 32]), election_id(102), true);
+        core.process_header(&header_with_votes(*author, vec![commit])).await.unwrap();
+    }
+    assert_eq!(
+        decision(rx_output.try_recv().unwrap()),
+        (election_id(102), Digest([200; 32]), 1)
+    );
+}
+
+#[tokio::test]
+async fn resist_spurious_fallbacks() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 16_000, /* gc_depth */ 50, /* vote_timeout */ 60_000,
+    )
+    .await;
+    core.batches = Some(PendingBatches::new(10));
+    let votes = proposals(&mut core, &[100, 101, 102]).await;
+    let other = proposals(&mut core, &[200]).await.pop().unwrap();
+    let proposal = header_with_votes(keys[0].0, votes.clone());
+    core.process_header(&proposal).await.unwrap();
+
+    // A byzantine peer votes for another tx hash in one election, and equivocates in another.
+    let byzantine = keys[2].0;
+    let mut minority = votes.clone();
+    minority[1] = Vote {
+        election_id: election_id(101),
+        ..other.clone()
+    };
+    core.process_header(&header_with_votes(byzantine, minority)).await.unwrap();
+    let mut equivocation = votes.clone();
+    equivocation[2] = Vote {
+        election_id: election_id(102),
+        ..other
+    };
+    let mut header = header_with_votes(byzantine, equivocation);
+    header.round = 2;
+    core.process_header(&sign(header)).await.unwrap();
+
+    // Neither holds back the batch commit once the honest peer votes.
+    assert!(core.batch_commits.is_empty());
+    core.process_header(&header_with_votes(keys[1].0, votes)).await.unwrap();
+    assert_eq!(core.batch_commits.len(), 1);
+    assert!(core.batch_commits.iter().next().unwrap().excluded.is_empty());
+    assert_eq!(core.batches.as_ref().unwrap().fallbacks, 0);
+    assert!(core.votes.iter().all(|x| !x.commit));
+}
+
+#[tokio::test(start_paused = true)]
+async fn decide_split_vote_with_crashed_node() {
+    let mut keys = keys();
+    let _crashed = keys.pop().unwrap();
+    let mut cores = Vec::new();
+    let mut outputs = Vec::new();
+    for ((name, secret), base_port) in keys.into_iter().zip([16_100, 16_200, 16_300]) {
+        let (core, rx_output) = core(
+            name, secret, base_port, /* gc_depth */ 50, /* vote_timeout */ 50,
+        ).await;
+        cores.push(core);
+        outputs.push(rx_output);
     }
 
-    // Ensure the core sends the parents of the certificates to the proposer.
-    let received = rx_parents.recv().await.unwrap();
-    let parents = certificates.iter().map(|x| x.digest()).collect();
-    assert_eq!(received, (parents, 1));
+    // The live nodes split 2-1 on two conflicting tx hashes: no tx hash can reach a quorum, and
+    // without the crashed node the round never hears from the whole committee.
+    let (low, high) = (Digest([1; 32]), Digest([2; 32]));
+    let mut outbound = Vec::new();
+    for (core, tx_hash) in cores.iter_mut().zip([low.clone(), low, high.clone()]) {
+        let header = header_with_votes(core.name, vec![vote(0, tx_hash, election_id(0), false)]);
+        core.process_header(&header).await.unwrap();
+        outbound.push(header);
+    }
+
+    // Once the round times out, all nodes adopt the highest tx hash and decide it.
+    exchange_votes(&mut cores, outbound, /* steps */ 20).await;
+    for output in outputs.iter_mut() {
+        let (id, tx_hash, _) = decision(output.try_recv().unwrap());
+        assert_eq!((id, tx_hash), (election_id(0), high.clone()));
+    }
+}
+
+#[tokio::test]
+async fn decide_in_last_round() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 18_900, /* gc_depth */ 50, /* vote_timeout */ 60_000,
+    )
+    .await;
+    core.max_election_rounds = 2;
+
+    // The commits of the last round trickle in: we commit along rather than give up.
+    let tx_hash = Digest([1; 32]);
+    for (author, _) in &keys {
+        assert!(!core.elections.get(&election_id(0)).is_some_and(|x| x.failed));
+        let votes = vec![vote(1, tx_hash.clone(), election_id(0), true)];
+        core.process_header(&header_with_votes(*author, votes)).await.unwrap();
+    }
+    assert!(core.votes.iter().any(|x| x.round == 1 && x.commit));
+    assert!(!core.elections[&election_id(0)].failed);
+    let (id, decided, round) = decision(rx_output.try_recv().unwrap());
+    assert_eq!((id, decided, round), (election_id(0), tx_hash, 1));
+    assert!(rx_output.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn fail_out_of_rounds() {
+    let mut cores = Vec::new();
+    let mut outputs = Vec::new();
+    for ((name, secret), base_port) in keys().into_iter().zip([16_700, 18_600, 18_700, 18_800]) {
+        let (mut core, rx_output) = core(
+            name, secret, base_port, /* gc_depth */ 50, /* vote_timeout */ 0,
+        ).await;
+        core.max_election_rounds = 3;
+        cores.push(core);
+        outputs.push(rx_output);
+    }
+    cores[0].byzantine = Some(ByzantineStrategy::VoteFlip);
 
-    // Ensure the core sends the certificates to the consensus.
-    for x in certificates.clone() {
-        let received = rx_consensus.recv().await.unwrap();
-        assert_eq!(received, x);
+    // The committee splits on three tx hashes. Without round timeouts, the nodes move on from a
+    // round as soon as it holds a quorum of votes, and the vote flipper keeps backing the minority
+    // tx hash: the honest nodes only agree in the last round, too late to commit.
+    let tx_hashes = [Digest([1; 32]), Digest([2; 32]), Digest([!1; 32]), Digest([1; 32])];
+    let mut outbound = Vec::new();
+    for (core, tx_hash) in cores.iter_mut().zip(tx_hashes) {
+        let header = header_with_votes(core.name, vec![vote(0, tx_hash, election_id(0), false)]);
+        core.process_header(&header).await.unwrap();
+        outbound.push(header);
     }
+    exchange_votes(&mut cores, outbound, /* steps */ 20).await;
 
-    // Ensure the certificates are stored.
-    for x in &certificates {
-        let stored = store.read(x.digest().to_vec()).await.unwrap();
-        let serialized = bincode::serialize(x).unwrap();
-        assert_eq!(stored, Some(serialized));
+    // Every honest node gives up rather than vote in a fourth round.
+    for (core, output) in cores.iter().zip(outputs.iter_mut()).skip(1) {
+        let election = &core.elections[&election_id(0)];
+        assert!(election.failed);
+        assert!(election.tallies.keys().all(|x| *x < 3));
+        assert_eq!(output.try_recv().unwrap(), ElectionOutcome::Failed(election_id(0)));
+        assert!(output.try_recv().is_err());
     }
 }
+
+#[tokio::test]
+async fn decide_without_loopback() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, mut rx_output) = core(
+        name, secret, 16_900, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    let tx_hash = Digest([1; 32]);
+
+    // Only two peers take part: each quorum needs our own vote, and our headers never come back
+    // to us (nothing listens on our address).
+    let peers = [keys[0].0, keys[1].0];
+    for commit in [false, true] {
+        for author in peers {
+            let vote = vote(commit as Round, tx_hash.clone(), election_id(0), commit);
+            core.process_header(&header_with_votes(author, vec![vote])).await.unwrap();
+        }
+    }
+
+    // We voted and committed, tallying each of our votes as we cast it.
+    let voted: Vec<_> = core.votes.iter().map(|x| (x.round, x.commit)).collect();
+    assert_eq!(voted[..2], [(0, false), (1, true)]);
+    let (id, decided, _) = decision(rx_output.try_recv().unwrap());
+    assert_eq!((id, decided), (election_id(0), tx_hash));
+}
+
+#[tokio::test]
+async fn store_returned_own_headers() {
+    let (name, secret) = keys().pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 17_000, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    let votes = vec![vote(1, Digest([1; 32]), election_id(0), true)];
+
+    // A header in our name we did not sign is rejected.
+    let forged = Header {
+        signature: Signature::default(),
+        ..header_with_votes(name, votes.clone())
+    };
+    assert!(matches!(
+        core.process_returned_header(&forged).await,
+        Err(DagError::InvalidSignature(_))
+    ));
+
+    // One of ours is stored, but neither tallied (again) nor voted upon.
+    let header = header_with_votes(name, votes);
+    core.process_returned_header(&header).await.unwrap();
+    let stored = core.store.read(header.digest().to_vec()).await.unwrap();
+    assert_eq!(stored, Some(encode(&header).unwrap()));
+    assert!(core.elections.is_empty());
+    assert!(core.votes.is_empty());
+}
+
+#[tokio::test]
+async fn count_errors_by_peer() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (mut core, _rx_output) = core(
+        name, secret, 17_100, /* gc_depth */ 50, /* vote_timeout */ 0,
+    )
+    .await;
+    core.max_header_entries = 2;
+    let (author, _) = keys[0];
+    let votes = |x: u8| vec![vote(0, Digest([1; 32]), election_id(x), false)];
+
+    // One header of each malformed kind, each in other elections so that none is a copy.
+    let too_large = header_with_votes(
+        author,
+        (10..13).map(|x| vote(0, Digest([1; 32]), election_id(x), false)).collect(),
+    );
+    let unsigned = Header {
+        signature: Signature::default(),
+        ..header_with_votes(author, votes(1))
+    };
+    let mut forged_commit = header_with_votes(author, vec![vote(1, Digest([1; 32]), election_id(2), true)]);
+    forged_commit.commit_signatures[0] = Signature::default();
+    let transaction = Transaction::random(10, vec![3]);
+    let mut malformed = header_with_votes(author, votes(3));
+    malformed.inline.insert(transaction.digest(), transaction);
+    let malformed = sign(malformed);
+    let mut too_far = header_with_votes(author, votes(4));
+    too_far.round = 1_000_000_000;
+    let too_far = sign(too_far);
+    for header in [too_large, unsigned, forged_commit, malformed, too_far] {
+        core.process_message(PrimaryMessage::Header(header)).await.unwrap();
+    }
+
+    // A header of an authority outside the committee counts against it.
+    let mut rng = rand::rngs::StdRng::from_seed([1; 32]);
+    let (stranger, _) = crypto::generate_keypair(&mut rng);
+    let header = header_with_votes(stranger, votes(5));
+    core.process_message(PrimaryMessage::Header(header)).await.unwrap();
+
+    let expected = BTreeMap::from([
+        ("HeaderTooLarge", 1),
+        ("InvalidSignature", 1),
+        ("InvalidVoteSignature", 1),
+        ("MalformedHeader", 1),
+        ("TooFar", 1),
+    ]);
+    assert_eq!(core.peer_errors.counts[&author], expected);
+    assert_eq!(core.peer_errors.counts[&stranger], BTreeMap::from([("UnknownAuthority", 1)]));
+    assert!(core.elections.is_empty());
+
+    // The valid headers of a peer cause no error.
+    let header = header_with_votes(keys[1].0, votes(6));
+    core.process_message(PrimaryMessage::Header(header)).await.unwrap();
+    assert!(!core.peer_errors.counts.contains_key(&keys[1].0));
+}
@@ -0,0 +1,30 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn count_duplicates() {
+    let mut seen = SeenHeaders::new(10);
+    assert!(seen.check(&Digest([1; 32])).is_none());
+    seen.insert(Digest([1; 32]), Outcome::Accepted);
+    let reason = "Message too old".to_string();
+    seen.insert(Digest([2; 32]), Outcome::Rejected(reason.clone()));
+
+    assert_eq!(seen.check(&Digest([1; 32])), Some(&Outcome::Accepted));
+    assert_eq!(
+        seen.check(&Digest([2; 32])),
+        Some(&Outcome::Rejected(reason))
+    );
+    assert!(seen.check(&Digest([3; 32])).is_none());
+    assert_eq!(seen.duplicates, 2);
+}
+
+#[test]
+fn evict_oldest() {
+    let mut seen = SeenHeaders::new(2);
+    for i in 0..3 {
+        seen.insert(Digest([i; 32]), Outcome::Accepted);
+    }
+    assert!(seen.check(&Digest([0; 32])).is_none());
+    assert!(seen.check(&Digest([1; 32])).is_some());
+    assert!(seen.check(&Digest([2; 32])).is_some());
+}
@@ -0,0 +1,125 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_of_size, keys, listener};
+use tokio::sync::mpsc::channel;
+
+// Fixture
+fn committee_with_base_port(base_port: u16) -> Committee {
+    let mut committee = committee_of_size(7);
+    for authority in committee.authorities.values_mut() {
+        let primary = &mut authority.primary;
+        let port = primary.primary_to_primary.port();
+        primary.primary_to_primary.set_port(base_port + port);
+    }
+    committee
+}
+
+/// Broadcast a header to a committee of seven where `unreachable` peers never acknowledge it.
+async fn disseminate(base_port: u16, unreachable: usize) -> DisseminationMetrics {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(base_port);
+
+    // Spawn the listeners of the reachable peers.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .skip(unreachable)
+        .map(|(_, x)| listener(x.primary_to_primary))
+        .collect();
+    sleep(Duration::from_millis(50)).await;
+
+    // Spawn the disseminator and hand it a header.
+    let metrics = Arc::new(Mutex::new(DisseminationMetrics::default()));
+    let (tx_message, rx_message) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    Disseminator::spawn(
+        name,
+        &committee,
+        /* timeout */ 200,
+        KeepAlive::default(),
+        rx_message,
+        rx_reconfigure,
+        metrics.clone(),
+    );
+    let message = Bytes::from("header");
+    tx_message
+        .send((Digest([0; 32]), message.clone()))
+        .await
+        .unwrap();
+
+    // Ensure all reachable peers got the header, then wait for its retransmission (if any).
+    for handle in handles {
+        assert_eq!(handle.await.unwrap(), message);
+    }
+    sleep(Duration::from_millis(600)).await;
+    let metrics = metrics.lock().unwrap().clone();
+    metrics
+}
+
+#[tokio::test]
+async fn disseminate_despite_two_unreachable_peers() {
+    let metrics = disseminate(15_000, 2).await;
+    assert_eq!(metrics.disseminated, 1);
+    assert_eq!(metrics.retransmitted, 0);
+    assert_eq!(metrics.under_delivered, 0);
+}
+
+#[tokio::test]
+async fn retransmit_to_three_unreachable_peers() {
+    let metrics = disseminate(15_100, 3).await;
+    assert_eq!(metrics.disseminated, 0);
+    assert_eq!(metrics.retransmitted, 1);
+    assert_eq!(metrics.under_delivered, 1);
+}
+
+#[tokio::test]
+async fn disseminate_to_new_committee() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(17_800);
+
+    // Three of our six peers leave in the next epoch; only the three staying are reachable.
+    let mut next = Committee {
+        epoch: 1,
+        ..committee.clone()
+    };
+    for (leaving, _) in committee.others_primaries(&name).iter().take(3) {
+        next.authorities.remove(leaving);
+    }
+    let handles: Vec<_> = next
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, x)| listener(x.primary_to_primary))
+        .collect();
+    sleep(Duration::from_millis(50)).await;
+
+    // Spawn the disseminator and move it to the next epoch.
+    let metrics = Arc::new(Mutex::new(DisseminationMetrics::default()));
+    let (tx_message, rx_message) = channel(1);
+    let (tx_reconfigure, rx_reconfigure) = channel(1);
+    Disseminator::spawn(
+        name,
+        &committee,
+        /* timeout */ 200,
+        KeepAlive::default(),
+        rx_message,
+        rx_reconfigure,
+        metrics.clone(),
+    );
+    tx_reconfigure.send(next).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    // The acknowledgements of the three staying peers are a quorum of the new committee, not of
+    // the old one.
+    let message = Bytes::from("header");
+    tx_message
+        .send((Digest([0; 32]), message.clone()))
+        .await
+        .unwrap();
+    for handle in handles {
+        assert_eq!(handle.await.unwrap(), message);
+    }
+    sleep(Duration::from_millis(600)).await;
+    let metrics = metrics.lock().unwrap().clone();
+    assert_eq!(metrics.disseminated, 1);
+    assert_eq!(metrics.retransmitted, 0);
+}
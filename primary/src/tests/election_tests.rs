@@ -0,0 +1,214 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_of_size, election_id, vote};
+use std::time::Duration;
+
+// Insert a commit (or vote) for the same tx hash from each of the first `count` authorities.
+fn insert_votes(election: &mut Election, committee: &Committee, count: usize, commit: bool) {
+    let tx_hash = Digest([1; 32]);
+    for author in committee.authorities.keys().take(count) {
+        election.insert_vote(&vote(0, tx_hash.clone(), election_id(0), commit), *author);
+    }
+}
+
+fn check_quorum(size: usize, expected: usize) {
+    let quorum = expected as Stake;
+    let committee = committee_of_size(size);
+    let election = Election::new(&committee);
+    assert_eq!(election.quorum, quorum);
+
+    // One vote short of a quorum.
+    let mut election = Election::new(&committee);
+    insert_votes(&mut election, &committee, expected - 1, false);
+    assert!(election
+        .tallies
+        .get(&0)
+        .unwrap()
+        .find_quorum_of_votes()
+        .is_none());
+    insert_votes(&mut election, &committee, expected, false);
+    assert!(election
+        .tallies
+        .get(&0)
+        .unwrap()
+        .find_quorum_of_votes()
+        .is_some());
+
+    // One commit short of a quorum.
+    let mut election = Election::new(&committee);
+    insert_votes(&mut election, &committee, expected - 1, true);
+    assert!(election.find_quorum_of_commits().is_none());
+    insert_votes(&mut election, &committee, expected, true);
+    assert!(election.find_quorum_of_commits().is_some());
+}
+
+#[test]
+fn quorum_of_4_nodes() {
+    check_quorum(4, 3);
+}
+
+#[test]
+fn quorum_of_7_nodes() {
+    check_quorum(7, 5);
+}
+
+#[test]
+fn quorum_of_10_nodes() {
+    check_quorum(10, 7);
+}
+
+#[test]
+fn advance_after_timeout() {
+    let committee = committee_of_size(4);
+    let names: Vec<_> = committee.authorities.keys().cloned().collect();
+    let mut election = Election::new(&committee);
+
+    // Three different votes (including ours) form a quorum of votes but not for a single tx hash.
+    for (i, author) in names.iter().take(3).enumerate() {
+        let tx_hash = Digest([i as u8; 32]);
+        election.insert_vote(&vote(0, tx_hash, election_id(0), false), *author);
+    }
+    let now = Instant::now();
+    assert!(!election.can_advance(&names[0], 0, now));
+
+    // The round timed out.
+    election.tallies.get_mut(&0).unwrap().arm(now);
+    assert!(election.can_advance(&names[0], 0, now));
+    let next = election.next_round_vote(&election_id(0), 0);
+    assert!(next == vote(1, Digest([2; 32]), election_id(0), false));
+
+    // A node that did not vote in the round cannot advance.
+    assert!(!election.can_advance(&names[3], 0, now));
+}
+
+#[test]
+fn expire_on_deadline() {
+    let mut tally = Tally::new(3);
+    let now = Instant::now();
+    let deadline = now + Duration::from_millis(50);
+    assert!(!tally.expired(deadline));
+
+    // The timer only starts once, and the round times out right on the deadline.
+    assert!(tally.arm(deadline));
+    assert!(!tally.arm(deadline + Duration::from_millis(50)));
+    assert!(!tally.expired(deadline - Duration::from_millis(1)));
+    assert!(tally.expired(deadline));
+}
+
+#[test]
+fn minority_tx_hash() {
+    let committee = committee_of_size(4);
+    let mut election = Election::new(&committee);
+    let authors: Vec<_> = committee.authorities.keys().cloned().collect();
+    let hashes = vec![Digest([2; 32]), Digest([2; 32]), Digest([3; 32])];
+    for (author, tx_hash) in authors.iter().zip(hashes) {
+        election.insert_vote(&vote(0, tx_hash, election_id(0), false), *author);
+    }
+    let tally = election.tallies.get(&0).unwrap();
+    assert_eq!(tally.minority(), Some(&Digest([3; 32])));
+
+    // Ties go to the lowest tx hash.
+    election.insert_vote(&vote(0, Digest([1; 32]), election_id(0), false), authors[3]);
+    let tally = election.tallies.get(&0).unwrap();
+    assert_eq!(tally.minority(), Some(&Digest([1; 32])));
+}
+
+#[test]
+fn ignore_duplicate_votes() {
+    let committee = committee_of_size(4);
+    let author = *committee.authorities.keys().next().unwrap();
+    let mut election = Election::new(&committee);
+
+    // The same vote sent many times counts once and is not an equivocation.
+    for _ in 0..3 {
+        election.insert_vote(&vote(0, Digest([1; 32]), election_id(0), false), author);
+    }
+    assert_eq!(election.tallies.get(&0).unwrap().total_votes(), 1);
+    assert!(election.equivocators().is_empty());
+}
+
+#[test]
+fn detect_equivocation() {
+    let committee = committee_of_size(4);
+    let authors: Vec<_> = committee.authorities.keys().cloned().collect();
+    let mut election = Election::new(&committee);
+
+    // The first author votes for two tx hashes, then commits in the same round.
+    election.insert_vote(&vote(0, Digest([1; 32]), election_id(0), false), authors[0]);
+    election.insert_vote(&vote(0, Digest([2; 32]), election_id(0), false), authors[0]);
+    election.insert_vote(&vote(0, Digest([1; 32]), election_id(0), true), authors[0]);
+    assert!(election.equivocators().contains(&authors[0]));
+    assert_eq!(election.equivocators().len(), 1);
+
+    // Operators inspecting the election see it too.
+    assert_eq!(&election.state().equivocators, election.equivocators());
+
+    // Only its first vote counts.
+    let tally = election.tallies.get(&0).unwrap();
+    assert_eq!(tally.total_votes(), 1);
+    assert!(tally.commits.is_empty());
+
+    // Its second vote does not help the other tx hash reach a quorum.
+    for author in &authors[1..3] {
+        election.insert_vote(&vote(0, Digest([2; 32]), election_id(0), false), *author);
+    }
+    let tally = election.tallies.get(&0).unwrap();
+    assert!(tally.find_quorum_of_votes().is_none());
+    election.insert_vote(&vote(0, Digest([2; 32]), election_id(0), false), authors[3]);
+    let tally = election.tallies.get(&0).unwrap();
+    assert_eq!(tally.find_quorum_of_votes(), Some(&Digest([2; 32])));
+}
+
+#[test]
+fn stake_weighted_quorum() {
+    // The first authority holds half of the stake: 3 out of 6, so a quorum needs 5.
+    let mut committee = committee_of_size(4);
+    let names: Vec<_> = committee.authorities.keys().cloned().collect();
+    committee.authorities.get_mut(&names[0]).unwrap().stake = 3;
+    let tx_hash = Digest([1; 32]);
+
+    // The three small authorities are a majority of nodes but not of stake.
+    let mut election = Election::new(&committee);
+    assert_eq!(election.quorum, 5);
+    for author in &names[1..] {
+        election.insert_vote(&vote(0, tx_hash.clone(), election_id(0), false), *author);
+        election.insert_vote(&vote(1, tx_hash.clone(), election_id(0), true), *author);
+    }
+    assert!(election.tallies[&0].find_quorum_of_votes().is_none());
+    assert!(election.find_quorum_of_commits().is_none());
+
+    // Neither is the large authority with a single other one.
+    let mut election = Election::new(&committee);
+    for author in &names[..2] {
+        election.insert_vote(&vote(0, tx_hash.clone(), election_id(0), false), *author);
+    }
+    assert!(election.tallies[&0].find_quorum_of_votes().is_none());
+
+    // Adding a second small authority is.
+    election.insert_vote(&vote(0, tx_hash.clone(), election_id(0), false), names[2]);
+    assert_eq!(election.tallies[&0].find_quorum_of_votes(), Some(&tx_hash));
+    assert_eq!(election.tallies[&0].total_stake(), 5);
+}
+
+#[test]
+fn frontier_follows_quorum() {
+    let committee = committee_of_size(4);
+    let names: Vec<_> = committee.authorities.keys().cloned().collect();
+    let tx_hash = Digest([1; 32]);
+
+    // No round holds a quorum yet.
+    let mut election = Election::new(&committee);
+    assert_eq!(election.frontier(), 0);
+
+    // A quorum of votes and commits, mixed, in round 2.
+    for author in &names[..2] {
+        election.insert_vote(&vote(2, tx_hash.clone(), election_id(0), false), *author);
+    }
+    assert_eq!(election.frontier(), 0);
+    election.insert_vote(&vote(2, tx_hash.clone(), election_id(0), true), names[2]);
+    assert_eq!(election.frontier(), 2);
+
+    // A lone vote far ahead does not move it.
+    election.insert_vote(&vote(1_000, tx_hash, election_id(0), false), names[3]);
+    assert_eq!(election.frontier(), 2);
+}
@@ -0,0 +1,72 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, header, keys, listener};
+use crate::messages::Hash as _;
+use network::encode;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn reply_to_header_requests() {
+    let (requestor, _) = keys().remove(0);
+    let committee = committee_with_base_port(14_700);
+    let (tx_requests, rx_requests) = channel(1);
+
+    // Create a new test store holding a header.
+    let path = ".db_test_reply_to_header_requests";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let header = header();
+    store
+        .write(header.digest().to_vec(), encode(&header).unwrap())
+        .await;
+
+    // Spawn the helper.
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    Helper::spawn(committee.clone(), store, rx_requests, rx_reconfigure);
+
+    // Request the header along with one we do not have: only the first comes back.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener(address);
+    let missing = vec![header.digest(), Digest([9; 32])];
+    tx_requests.send((missing, requestor)).await.unwrap();
+    match wire::deserialize(&handle.await.unwrap()).unwrap() {
+        PrimaryMessage::Header(received) => assert_eq!(received, header),
+        x => panic!("Unexpected message {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn reply_at_new_addresses() {
+    let (requestor, _) = keys().remove(0);
+    let (tx_requests, rx_requests) = channel(1);
+    let (tx_reconfigure, rx_reconfigure) = channel(1);
+
+    // Create a new test store holding a header.
+    let path = ".db_test_reply_at_new_addresses";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let header = header();
+    store
+        .write(header.digest().to_vec(), encode(&header).unwrap())
+        .await;
+
+    // Spawn the helper, then move the requestor to other addresses in a new epoch.
+    Helper::spawn(committee_with_base_port(17_600), store, rx_requests, rx_reconfigure);
+    let committee = Committee {
+        epoch: 1,
+        ..committee_with_base_port(17_700)
+    };
+    tx_reconfigure.send(committee.clone()).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    // The reply goes to the address of the new committee.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener(address);
+    tx_requests.send((vec![header.digest()], requestor)).await.unwrap();
+    match wire::deserialize(&handle.await.unwrap()).unwrap() {
+        PrimaryMessage::Header(received) => assert_eq!(received, header),
+        x => panic!("Unexpected message {:?}", x),
+    }
+}
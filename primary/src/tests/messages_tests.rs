@@ -0,0 +1,281 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{
+    commit_certificate, committee, election_id, header_with_votes, keys, reconfiguration, vote,
+};
+use crate::primary::{PrimaryMessage, WorkerPrimaryMessage};
+use crate::wire::{self, PROTOCOL_VERSION};
+use network::{decode, encode};
+
+#[test]
+fn vote_encoding() {
+    let vote = vote(1, Digest([1; 32]), election_id(2), true);
+
+    // Round as a little-endian u64, both digests as raw bytes, and the commit flag as one byte.
+    let mut expected = 1u64.to_le_bytes().to_vec();
+    expected.extend_from_slice(&[1; 32]);
+    expected.extend_from_slice(&[2; 32]);
+    expected.push(1);
+    assert_eq!(encode(&vote).unwrap(), expected);
+    assert!(decode::<Vote>(&expected).unwrap() == vote);
+}
+
+#[test]
+fn vote_digest() {
+    let commit = vote(1, Digest([1; 32]), election_id(2), true);
+    let mut other = commit.clone();
+    other.commit = false;
+
+    // The digest covers the commit flag, and survives the encoding of the vote.
+    assert_ne!(commit.digest(), other.digest());
+    let decoded: Vote = decode(&encode(&commit).unwrap()).unwrap();
+    assert_eq!(decoded.digest(), commit.digest());
+
+    // A vote digest never passes for the digest signed by a commit.
+    assert_ne!(commit.digest(), commit.commit_digest());
+}
+
+#[test]
+fn header_message_encoding() {
+    let (author, _) = keys().pop().unwrap();
+    let vote = vote(0, Digest([1; 32]), election_id(0), false);
+    let mut header = header_with_votes(author, vec![vote.clone()]);
+    header.payload.insert(Digest([1; 32]), 4);
+    header.parents.insert(Digest([3; 32]));
+    header.signature = Signature::default();
+    let message = PrimaryMessage::Header(header.clone());
+
+    // Protocol version and message type, author (base64 string), epoch, round, votes, payload, parents,
+    // inline transactions and batch commits (length-prefixed), signature, and commit signatures (a
+    // length-prefixed list).
+    let author_string = author.encode_base64();
+    let mut expected = vec![PROTOCOL_VERSION, 0];
+    expected.extend_from_slice(&(author_string.len() as u64).to_le_bytes());
+    expected.extend_from_slice(author_string.as_bytes());
+    expected.extend_from_slice(&0u64.to_le_bytes());
+    expected.extend_from_slice(&1u64.to_le_bytes());
+    expected.extend_from_slice(&1u64.to_le_bytes());
+    expected.extend_from_slice(&encode(&vote).unwrap());
+    expected.extend_from_slice(&1u64.to_le_bytes());
+    expected.extend_from_slice(&[1; 32]);
+    expected.extend_from_slice(&4u32.to_le_bytes());
+    expected.extend_from_slice(&1u64.to_le_bytes());
+    expected.extend_from_slice(&[3; 32]);
+    expected.extend_from_slice(&0u64.to_le_bytes());
+    expected.extend_from_slice(&0u64.to_le_bytes());
+    expected.extend_from_slice(&[0; 64]);
+    expected.extend_from_slice(&0u64.to_le_bytes());
+    assert_eq!(wire::serialize(&message).unwrap(), expected);
+
+    match wire::deserialize(&expected).unwrap() {
+        PrimaryMessage::Header(x) => assert_eq!(x, header),
+        x => panic!("Unexpected message {:?}", x),
+    }
+}
+
+#[test]
+fn worker_message_encoding() {
+    let message = WorkerPrimaryMessage::OurBatch(Digest([1; 32]), election_id(2), 3);
+
+    let mut expected = 0u32.to_le_bytes().to_vec();
+    expected.extend_from_slice(&[1; 32]);
+    expected.extend_from_slice(&[2; 32]);
+    expected.extend_from_slice(&3u32.to_le_bytes());
+    assert_eq!(encode(&message).unwrap(), expected);
+}
+
+#[test]
+fn invalid_signature_keeps_cause() {
+    let (author, _) = keys().pop().unwrap();
+    let header = Header {
+        signature: Signature::default(),
+        ..header_with_votes(author, Vec::new())
+    };
+
+    // The header is not signed: the verification error must name the signature failure.
+    match header.verify(&committee()) {
+        Err(DagError::InvalidSignature(e)) => assert_eq!(
+            header.verify(&committee()).unwrap_err().to_string(),
+            format!("Invalid signature: {}", e)
+        ),
+        x => panic!("Unexpected verification result: {:?}", x),
+    }
+}
+
+#[test]
+fn verify_reconfigurations() {
+    let next = Committee {
+        epoch: 1,
+        ..committee()
+    };
+    assert!(reconfiguration(next.clone(), 3).verify(&committee()).is_ok());
+
+    // Two signatures out of four are not a quorum.
+    assert!(matches!(
+        reconfiguration(next.clone(), 2).verify(&committee()),
+        Err(DagError::CertificateRequiresQuorum)
+    ));
+
+    // The signatures cover the whole committee.
+    let mut forged = reconfiguration(next.clone(), 3);
+    forged.committee.authorities.values_mut().next().unwrap().stake = 10;
+    assert!(matches!(
+        forged.verify(&committee()),
+        Err(DagError::InvalidSignature(_))
+    ));
+
+    // Only the epoch following ours is accepted.
+    let skipped = Committee { epoch: 2, ..next };
+    assert!(matches!(
+        reconfiguration(skipped, 3).verify(&committee()),
+        Err(DagError::StaleEpoch(_, 2))
+    ));
+}
+
+#[tokio::test]
+async fn verify_signed_commits() {
+    let (author, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let votes = vec![
+        vote(1, Digest([1; 32]), election_id(0), true),
+        vote(1, Digest([1; 32]), election_id(1), false),
+    ];
+    let votes = votes.into_iter().collect();
+    let mut header = Header::new(author, 0, 1, votes, BTreeMap::new(), BTreeSet::new(), BTreeMap::new(), BTreeSet::new(), &mut signature_service).await;
+    assert_eq!(header.commit_signatures.len(), 1);
+    assert!(header.verify(&committee()).is_ok());
+
+    // A signature over anything but the commit does not sign it.
+    header.commit_signatures[0] = Signature::new(&Digest([0; 32]), &keys().pop().unwrap().1);
+    let commit = vote(1, Digest([1; 32]), election_id(0), true).digest();
+    assert!(matches!(
+        header.verify(&committee()),
+        Err(DagError::InvalidVoteSignature(x)) if x == commit
+    ));
+
+    // Nor can a commit go unsigned.
+    header.commit_signatures.clear();
+    assert!(matches!(header.verify(&committee()), Err(DagError::MalformedHeader(_))));
+}
+
+#[test]
+fn verify_commit_certificate() {
+    let certificate = commit_certificate(Digest([1; 32]), election_id(0), 3);
+    assert!(certificate.verify(&committee()).is_ok());
+}
+
+#[test]
+fn reject_certificate_without_quorum() {
+    // Two signatures out of four are not a quorum, even repeated.
+    let mut certificate = commit_certificate(Digest([1; 32]), election_id(0), 2);
+    assert!(matches!(
+        certificate.verify(&committee()),
+        Err(DagError::CertificateRequiresQuorum)
+    ));
+    certificate.votes.push(certificate.votes[0].clone());
+    assert!(matches!(
+        certificate.verify(&committee()),
+        Err(DagError::AuthorityReuse(_))
+    ));
+}
+
+#[test]
+fn reject_forged_certificate() {
+    // The signatures are over a commit to another tx hash.
+    let mut certificate = commit_certificate(Digest([1; 32]), election_id(0), 3);
+    certificate.tx_hash = Digest([2; 32]);
+    assert!(matches!(
+        certificate.verify(&committee()),
+        Err(DagError::InvalidSignature(_))
+    ));
+}
+
+#[tokio::test]
+async fn verify_inline_transactions() {
+    let (author, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let transaction = Transaction::random(10, vec![1]);
+    let tx_hash = transaction.digest();
+    let votes = vec![vote(1, tx_hash.clone(), transaction.election_id(), false)];
+    let inline: BTreeMap<_, _> = vec![(tx_hash, transaction.clone())].into_iter().collect();
+    let header = Header::new(
+        author,
+        0,
+        1,
+        votes.into_iter().collect(),
+        BTreeMap::new(),
+        BTreeSet::new(),
+        inline,
+        BTreeSet::new(),
+        &mut signature_service,
+    )
+    .await;
+    assert!(header.verify(&committee()).is_ok());
+
+    // An inline transaction filed under another tx hash is rejected.
+    let mut forged = header.clone();
+    forged.inline = vec![(Digest([1; 32]), transaction)].into_iter().collect();
+    let forged = crate::common::sign(forged);
+    assert!(matches!(forged.verify(&committee()), Err(DagError::MalformedHeader(_))));
+}
+
+#[test]
+fn bound_inline_transactions() {
+    let (author, _) = keys().pop().unwrap();
+    let mut header = header_with_votes(author, Vec::new());
+    header.inline = (0..3u8)
+        .map(|i| Transaction::random(10, vec![i]))
+        .map(|x| (x.digest(), x))
+        .collect();
+
+    let limits = InlineLimits {
+        transaction_size: 10,
+        header_bytes: 30,
+    };
+    assert!(limits.check(&header).is_ok());
+
+    // Too large a transaction, too many bytes in the header, or inlining disabled altogether.
+    for limits in &[
+        InlineLimits {
+            transaction_size: 9,
+            ..limits
+        },
+        InlineLimits {
+            header_bytes: 29,
+            ..limits
+        },
+        InlineLimits::default(),
+    ] {
+        assert!(matches!(limits.check(&header), Err(DagError::MalformedHeader(_))));
+    }
+    assert!(InlineLimits::default().check(&header_with_votes(author, Vec::new())).is_ok());
+}
+
+#[test]
+fn batch_commit_stands_for_commits() {
+    let (author, _) = keys().pop().unwrap();
+    let votes = vec![
+        vote(0, Digest([1; 32]), election_id(0), false),
+        vote(0, Digest([2; 32]), election_id(1), false),
+        vote(1, Digest([3; 32]), election_id(2), true),
+    ];
+    let header = header_with_votes(author, votes);
+
+    // The round-0 votes of the header but the excluded ones, as commits of the next round.
+    let mut batch = BatchCommit {
+        header: header.digest(),
+        excluded: vec![election_id(1)].into_iter().collect(),
+    };
+    let commits = batch.commits(&header);
+    assert_eq!(commits, vec![vote(1, Digest([1; 32]), election_id(0), true)]);
+
+    // The header carrying the batch commit signs it.
+    let mut carrier = header_with_votes(author, Vec::new());
+    carrier.batch_commits.insert(batch.clone());
+    let carrier = crate::common::sign(carrier);
+    assert!(carrier.verify(&committee()).is_ok());
+    let mut forged = carrier.clone();
+    batch.excluded.clear();
+    forged.batch_commits = vec![batch].into_iter().collect();
+    assert!(matches!(forged.verify(&committee()), Err(DagError::InvalidSignature(_))));
+}
@@ -0,0 +1,101 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{header_with_votes, keys, vote};
+use crate::messages::Hash as _;
+use std::collections::BTreeMap;
+
+fn votes(count: u64, commit: bool) -> Vec<Vote> {
+    (0..count)
+        .map(|i| {
+            let mut election_id = [0; 32];
+            election_id[..8].copy_from_slice(&i.to_le_bytes());
+            vote(0, Digest([1; 32]), Digest(election_id), commit)
+        })
+        .collect()
+}
+
+// A bottleneck link forwarding at most `capacity` votes per ms. The headers that do not fit are
+// dropped and retransmitted `rto` ms later. Returns the number of retransmissions and the p99
+// delivery time of the votes (in ms).
+fn simulate(headers: Vec<(u64, usize)>, capacity: usize, rto: u64) -> (usize, u64) {
+    let mut pending: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+    for (time, size) in headers {
+        pending.entry(time).or_default().push(size);
+    }
+    let mut retransmissions = 0;
+    let mut delivered = Vec::new();
+    while let Some(&time) = pending.keys().next() {
+        let mut load = 0;
+        for size in pending.remove(&time).unwrap() {
+            if load + size <= capacity {
+                load += size;
+                delivered.resize(delivered.len() + size, time);
+            } else {
+                retransmissions += 1;
+                pending.entry(time + rto).or_default().push(size);
+            }
+        }
+    }
+    delivered.sort_unstable();
+    (retransmissions, delivered[delivered.len() * 99 / 100])
+}
+
+// The headers (send time and size) of every authority flushing the same number of votes.
+fn flushes(pacer: &Pacer, count: u64) -> Vec<(u64, usize)> {
+    let mut headers = Vec::new();
+    for (name, _) in keys() {
+        let (unpaced, chunks) = pacer.split(votes(count, false));
+        if !unpaced.is_empty() {
+            headers.push((0, unpaced.len()));
+        }
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let header_id = header_with_votes(name, chunk.clone()).digest();
+            headers.push((pacer.delay(&name, &header_id, index), chunk.len()));
+        }
+    }
+    headers
+}
+
+#[test]
+fn commits_are_not_paced() {
+    let mut flush = votes(3, false);
+    flush.extend(votes(2, true));
+
+    let (unpaced, chunks) = Pacer::new(2, 5).split(flush.clone());
+    assert!(unpaced.iter().all(|x| x.commit));
+    assert_eq!(unpaced.len(), 2);
+    let sizes: Vec<_> = chunks.iter().map(|x| x.len()).collect();
+    assert_eq!(sizes, vec![2, 1]);
+
+    // Without pacing, the whole flush leaves at once.
+    let (unpaced, chunks) = Pacer::default().split(flush);
+    assert_eq!(unpaced.len(), 5);
+    assert!(chunks.is_empty());
+}
+
+#[test]
+fn deterministic_jitter() {
+    let pacer = Pacer::new(10, 5);
+    let header_id = Digest([7; 32]);
+    let jitters: Vec<_> = keys()
+        .iter()
+        .map(|(name, _)| pacer.jitter(name, &header_id))
+        .collect();
+    assert!(jitters.iter().all(|x| *x < 5));
+
+    let (name, _) = keys().pop().unwrap();
+    assert_eq!(pacer.jitter(&name, &header_id), jitters[3]);
+    assert_eq!(pacer.delay(&name, &header_id, 2), 10 + jitters[3]);
+}
+
+#[test]
+fn pacing_under_packet_loss() {
+    let (capacity, rto) = (1_500, 10);
+    let unpaced = simulate(flushes(&Pacer::default(), 1_000), capacity, rto);
+    let paced = simulate(flushes(&Pacer::new(100, 2), 1_000), capacity, rto);
+
+    // Synchronized bursts overflow the link while paced chunks fit.
+    assert!(unpaced.0 > 0);
+    assert!(paced.0 < unpaced.0);
+    assert!(paced.1 <= unpaced.1);
+}
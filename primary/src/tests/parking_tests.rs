@@ -0,0 +1,28 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::sync::oneshot::error::TryRecvError;
+
+#[test]
+fn evict_oldest() {
+    let mut parked = ParkedHeaders::new(2);
+    let mut cancels = Vec::new();
+    for i in 0..3 {
+        let (tx_cancel, rx_cancel) = oneshot::channel();
+        cancels.push(rx_cancel);
+        let evicted = parked.insert(Digest([i; 32]), tx_cancel);
+        assert_eq!(evicted, (i == 2).then_some(Digest([0; 32])));
+    }
+    assert_eq!(parked.len(), 2);
+    assert_eq!(parked.evicted, 1);
+    assert!(!parked.contains(&Digest([0; 32])));
+
+    // The waiter of the evicted header is cancelled, the others keep waiting.
+    assert_eq!(cancels[0].try_recv(), Err(TryRecvError::Closed));
+    assert_eq!(cancels[1].try_recv(), Err(TryRecvError::Empty));
+
+    // Headers leaving the parking free their slot.
+    parked.remove(&Digest([1; 32]));
+    let (tx_cancel, _rx_cancel) = oneshot::channel();
+    assert!(parked.insert(Digest([3; 32]), tx_cancel).is_none());
+    assert_eq!(parked.evicted, 1);
+}
@@ -0,0 +1,175 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, election_id, header_with_votes, keys, vote};
+use crate::election::TallyState;
+use crate::payload_receiver::payload_key;
+use network::encode;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[tokio::test]
+async fn inspect_election() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let committee = committee_with_base_port(16_400);
+    let address = committee.primary(&name).unwrap().primary_to_primary;
+
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (tx_header_requests, _rx_header_requests) = channel(1);
+    let (tx_state_requests, rx_state_requests) = channel(1);
+    let (tx_forwarded, _rx_forwarded) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_parents, _rx_parents) = channel(10);
+    let (tx_output, _rx_output) = channel(1);
+
+    // Create a new test store holding the batches voted for.
+    let path = ".db_test_inspect_election";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let (low, high) = (Digest([1; 32]), Digest([2; 32]));
+    for tx_hash in [&low, &high] {
+        store.write(payload_key(tx_hash), Vec::default()).await;
+    }
+
+    // Spawn the core and the receiver it answers state requests through.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store,
+        SignatureService::new(secret),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* round_window */ 50,
+        /* rx_primaries */ rx_primary_messages,
+        rx_state_requests,
+        /* rx_proposer */ rx_headers,
+        /* tx_proposer */ tx_parents,
+        tx_output,
+        /* addresses */ Vec::new(),
+        /* byzantine */ None,
+        /* header_size */ 1_000,
+        /* max_header_entries */ 10_000,
+        /* vote_timeout */ 60_000,
+        /* max_election_rounds */ 0,
+        Pacer::default(),
+        SenderLimits::default(),
+        KeepAlive::default(),
+        /* dissemination_timeout */ 0,
+        Compactor::default(),
+        InlineLimits::default(),
+        /* batch_commits */ false,
+        /* tx_reconfigure */ Vec::new(),
+    );
+    NetworkReceiver::spawn(
+        address,
+        PrimaryReceiverHandler {
+            tx_primary_messages,
+            tx_header_requests,
+            tx_state_requests,
+            tx_forwarded,
+        },
+    );
+
+    // Two peers vote for different tx hashes: we join the first one, and the round then holds a
+    // quorum of votes but none for a single tx hash. It waits for its timer.
+    let (first, second) = (keys[0].0, keys[1].0);
+    for (author, tx_hash) in [(first, &low), (second, &high)] {
+        let header = header_with_votes(author, vec![vote(0, tx_hash.clone(), election_id(0), false)]);
+        let frame = wire::serialize(&PrimaryMessage::Header(header)).unwrap();
+        let mut sender = ReliableSender::new();
+        sender.send(address, Bytes::from(frame)).await.await.unwrap();
+    }
+    let mut votes = BTreeMap::new();
+    votes.insert(low, vec![first, name].into_iter().collect());
+    votes.insert(high.clone(), vec![second].into_iter().collect());
+    let mut tallies = BTreeMap::new();
+    tallies.insert(0, TallyState { votes, commits: BTreeMap::new() });
+    let expected = ElectionState {
+        round: 0,
+        tallies,
+        highest: Some(high),
+        commit: None,
+        decided: false,
+        equivocators: BTreeSet::new(),
+    };
+
+    // The core may still be processing the second header when the first request comes in.
+    let mut state = None;
+    for _ in 0..50 {
+        state = inspect(address, election_id(0)).await.unwrap();
+        if state.as_ref() == Some(&expected) {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(state, Some(expected));
+
+    // We know nothing of other elections.
+    assert_eq!(inspect(address, election_id(1)).await.unwrap(), None);
+}
+
+fn our_batch(i: u8, worker_id: WorkerId) -> Vec<u8> {
+    let message = WorkerPrimaryMessage::OurBatch(Digest([i; 32]), election_id(i), worker_id);
+    encode(&message).unwrap()
+}
+
+async fn signed(message: Vec<u8>, signature_service: &mut SignatureService) -> Vec<u8> {
+    encode(&SignedWorkerMessage::new(message, signature_service).await).unwrap()
+}
+
+#[tokio::test]
+async fn reject_unauthenticated_worker_messages() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (_, other_secret) = keys.pop().unwrap();
+    let committee = committee_with_base_port(16_800);
+    let address = committee.primary(&name).unwrap().worker_to_primary;
+
+    let (tx_our_digests, mut rx_our_digests) = channel(10);
+    let (tx_payloads, mut rx_payloads) = channel(10);
+    NetworkReceiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            name,
+            workers: vec![0].into_iter().collect(),
+            tx_our_digests,
+            tx_payloads,
+            tx_others: None,
+        },
+    );
+
+    let mut ours = SignatureService::new(secret);
+    let mut others = SignatureService::new(other_secret);
+    let frames = vec![
+        // Unsigned.
+        our_batch(1, 0),
+        // Signed with the key of another authority.
+        signed(our_batch(2, 0), &mut others).await,
+        // Signed with our key, but claiming a worker we do not have.
+        signed(our_batch(3, 7), &mut ours).await,
+        // From our worker.
+        signed(our_batch(4, 0), &mut ours).await,
+    ];
+
+    // A rogue client injects its digests before the genuine one, on the same connection.
+    sleep(Duration::from_millis(50)).await;
+    let before = unauthenticated_worker_messages();
+    let socket = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    for frame in frames {
+        transport.send(Bytes::from(frame)).await.unwrap();
+    }
+
+    // Only the genuine digest reaches the proposer (and so our headers) or the payloads.
+    match rx_our_digests.recv().await {
+        Some(Proposal::Digest(digest, ..)) => assert_eq!(digest, Digest([4; 32])),
+        x => panic!("Unexpected proposal {:?}", x),
+    }
+    assert_eq!(rx_payloads.recv().await.unwrap().0, Digest([4; 32]));
+    assert!(rx_our_digests.try_recv().is_err());
+    assert!(rx_payloads.try_recv().is_err());
+    assert_eq!(unauthenticated_worker_messages() - before, 3);
+}
@@ -1,7 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{committee, keys};
+use crate::common::{committee, committee_with_base_port, committee_with_two_workers, keys, listener};
+use crate::error::DagError;
+use crate::vote_log::vote_key;
+use config::ProposalPolicy;
+use crypto::SecretKey;
+use rand::SeedableRng as _;
+use std::fs;
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn propose_empty() {
@@ -11,26 +18,80 @@ async fn propose_empty() {
     let (_tx_parents, rx_parents) = channel(1);
     let (_tx_our_digests, rx_our_digests) = channel(1);
     let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
 
     // Spawn the proposer.
     Proposer::spawn(
         name,
         &committee(),
+        ProposalPolicy::AllPropose,
         signature_service,
         /* header_size */ 1_000,
         /* max_header_delay */ 20,
+        /* gc_depth */ 50,
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
         /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
     );
 
     // Ensure the proposer makes a correct empty header.
     let header = rx_headers.recv().await.unwrap();
     assert_eq!(header.round, 1);
-    assert!(header.payload.is_empty());
+    assert!(header.votes.is_empty());
     assert!(header.verify(&committee()).is_ok());
 }
 
+#[tokio::test]
+async fn propose_in_new_epoch() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (tx_reconfigure, rx_reconfigure) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.epoch, 0);
+
+    // Once the committee moves on, so do our headers. The `Core` starts the epoch with no parents.
+    let committee = Committee {
+        epoch: 1,
+        ..committee()
+    };
+    tx_reconfigure.send(committee.clone()).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    tx_parents.send((Vec::new(), 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.epoch, 1);
+    assert_eq!(header.round, 2);
+    assert!(header.parents.is_empty());
+    assert!(header.verify(&committee).is_ok());
+}
+
 #[tokio::test]
 async fn propose_payload() {
     let (name, secret) = keys().pop().unwrap();
@@ -39,30 +100,665 @@ async fn propose_payload() {
     let (_tx_parents, rx_parents) = channel(1);
     let (tx_our_digests, rx_our_digests) = channel(1);
     let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
 
     // Spawn the proposer.
     Proposer::spawn(
         name,
         &committee(),
+        ProposalPolicy::AllPropose,
         signature_service,
-        /* header_size */ 32,
+        /* header_size */ 1,
         /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
         /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
     );
 
     // Send enough digests for the header payload.
     let digest = Digest(name.0);
-    let worker_id = 0;
+    let election_id = Digest([0; 32]);
     tx_our_digests
-        .send((digest.clone(), worker_id))
+        .send(Proposal::Digest(digest.clone(), election_id.clone(), 0))
         .await
         .unwrap();
 
     // Ensure the proposer makes a correct header from the provided payload.
     let header = rx_headers.recv().await.unwrap();
-    assert_eq!(header.round, 1);
-    assert_eq!(header.payload.get(&digest), Some(&worker_id));
+    let vote = Vote::new(0, digest, election_id, false).await;
+    assert!(header.votes.contains(&vote));
     assert!(header.verify(&committee()).is_ok());
 }
+
+#[tokio::test]
+async fn deduplicate_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(10);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 50,
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+
+    // Send the same digest many times.
+    let digest = Digest(name.0);
+    let election_id = Digest([0; 32]);
+    for _ in 0..100 {
+        tx_our_digests
+            .send(Proposal::Digest(digest.clone(), election_id.clone(), 0))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the election appears in exactly one header, with a single vote.
+    let mut count = 0;
+    for round in 1..=5 {
+        let header = rx_headers.recv().await.unwrap();
+        tx_parents
+            .send((vec![header.digest()], round))
+            .await
+            .unwrap();
+        count += header
+            .votes
+            .iter()
+            .filter(|x| x.election_id == election_id)
+            .count();
+    }
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn bound_header_size() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(20);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 2,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+
+    // Send ten times as many proposals as fit in a header.
+    for i in 0..20 {
+        let digest = Digest([i; 32]);
+        tx_our_digests
+            .send(Proposal::Digest(digest.clone(), digest, 0))
+            .await
+            .unwrap();
+    }
+
+    // They are spread over ten headers.
+    let mut voted = HashSet::new();
+    for round in 1..=10 {
+        let header = rx_headers.recv().await.unwrap();
+        assert!(header.votes.len() <= 2);
+        voted.extend(header.votes.iter().map(|x| x.election_id.clone()));
+        tx_parents
+            .send((vec![header.digest()], round))
+            .await
+            .unwrap();
+    }
+    assert_eq!(voted.len(), 20);
+}
+
+#[tokio::test]
+async fn wait_for_parents() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+
+    // The first header has no parents; the next one waits for them.
+    let first = rx_headers.recv().await.unwrap();
+    assert!(first.parents.is_empty());
+    let next = timeout(Duration::from_millis(100), rx_headers.recv()).await;
+    assert!(next.is_err());
+
+    // The next header links to the parents it received.
+    let parents = vec![first.digest(), Digest([1; 32])];
+    tx_parents.send((parents.clone(), 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.parents, parents.into_iter().collect());
+    assert_eq!(header.round, 2);
+    assert!(header.verify(&committee()).is_ok());
+}
+
+#[tokio::test]
+async fn restart_keeps_votes() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_proposer_restart";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let election_id = Digest([0; 32]);
+    let first = Digest([1; 32]);
+    let second = Digest([2; 32]);
+
+    // Spawn the proposer and make it vote for the first digest.
+    let (_tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let handle = Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service.clone(),
+        /* header_size */ 1,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ Some(store.clone()),
+        InlineLimits::default(),
+    );
+    tx_our_digests
+        .send(Proposal::Digest(first.clone(), election_id.clone(), 0))
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert!(header.votes.contains(&Vote::new(0, first.clone(), election_id.clone(), false).await));
+
+    // Kill the proposer and restart it over the same store.
+    handle.abort();
+    let _ = handle.await;
+    let (_tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 1,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ Some(store),
+        InlineLimits::default(),
+    );
+
+    // Ensure the restarted proposer sticks to its earlier vote instead of voting for the second digest.
+    tx_our_digests
+        .send(Proposal::Digest(second.clone(), election_id.clone(), 0))
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert!(header.votes.contains(&Vote::new(0, first, election_id.clone(), false).await));
+    assert!(!header.votes.contains(&Vote::new(0, second, election_id, false).await));
+    assert!(header.verify(&committee()).is_ok());
+}
+
+#[tokio::test]
+async fn keep_votes_that_fail_to_persist() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let two_workers = committee_with_two_workers(0);
+
+    // Create a new test store, whose entry for our vote cannot be read.
+    let path = ".db_test_proposer_failing_store";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let (digest, election_id) = (Digest([1; 32]), Digest([0; 32]));
+    let key = vote_key(&election_id, 0);
+    store.write(key.clone(), vec![0xff]).await;
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &two_workers,
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 1,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ Some(store.clone()),
+        InlineLimits::default(),
+    );
+
+    // The vote fails to persist: it is left out of the header.
+    tx_our_digests
+        .send(Proposal::Digest(digest.clone(), election_id.clone(), 1))
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert!(header.votes.is_empty());
+
+    // Once the store recovers, the next header votes for the batch and names its worker.
+    store.delete(key).await;
+    tx_parents.send((Vec::new(), 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert!(header.votes.contains(&Vote::new(0, digest.clone(), election_id, false).await));
+    assert_eq!(header.payload, vec![(digest, 1)].into_iter().collect());
+    assert!(header.verify(&two_workers).is_ok());
+}
+
+#[tokio::test]
+async fn propose_batches_of_all_workers() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let two_workers = committee_with_two_workers(0);
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(2);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &two_workers,
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 2,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+
+    // Both our workers seal a batch.
+    let (first, second) = (Digest([1; 32]), Digest([2; 32]));
+    tx_our_digests
+        .send(Proposal::Digest(first.clone(), Digest([0; 32]), 0))
+        .await
+        .unwrap();
+    tx_our_digests
+        .send(Proposal::Digest(second.clone(), Digest([1; 32]), 1))
+        .await
+        .unwrap();
+
+    // Ensure the header votes for both, and tells which worker holds each.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.votes.len(), 2);
+    let expected = vec![(first, 0), (second, 1)].into_iter().collect();
+    assert_eq!(header.payload, expected);
+    assert!(header.verify(&two_workers).is_ok());
+
+    // The header is malformed for a committee in which we run a single worker.
+    assert!(matches!(
+        header.verify(&committee()),
+        Err(DagError::MalformedHeader(_))
+    ));
+}
+
+#[tokio::test]
+async fn name_workers_of_deferred_votes() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let two_workers = committee_with_two_workers(0);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(3);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &two_workers,
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 1,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+
+    // The first batch goes in the first header, the two others wait for its parents.
+    let batches: Vec<_> = (1..=3).map(|i| Digest([i; 32])).collect();
+    for (i, batch) in batches.iter().enumerate() {
+        tx_our_digests
+            .send(Proposal::Digest(batch.clone(), Digest([i as u8; 32]), 1))
+            .await
+            .unwrap();
+        if i == 0 {
+            rx_headers.recv().await.unwrap();
+        }
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // The last vote overflows into a third header, which still names its worker.
+    for (round, batch) in batches.into_iter().enumerate().skip(1) {
+        tx_parents
+            .send((Vec::new(), round as Round))
+            .await
+            .unwrap();
+        let header = rx_headers.recv().await.unwrap();
+        let expected = vec![(batch, 1)].into_iter().collect();
+        assert_eq!(header.payload, expected);
+        assert!(header.verify(&two_workers).is_ok());
+    }
+}
+
+#[tokio::test]
+async fn propose_inline_transactions() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(3);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let (_tx_forwarded, rx_forwarded) = channel(1);
+
+    // Spawn the proposer, with room for one inline transaction per header.
+    let limits = InlineLimits {
+        transaction_size: 10,
+        header_bytes: 15,
+    };
+    Proposer::spawn(
+        name,
+        &committee(),
+        ProposalPolicy::AllPropose,
+        signature_service,
+        /* header_size */ 2,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        limits,
+    );
+    let first = Transaction::random(10, vec![1]);
+    let second = Transaction::random(10, vec![2]);
+    for tx in &[&first, &second] {
+        tx_our_digests.send(Proposal::Inline((*tx).clone())).await.unwrap();
+    }
+
+    // The header carries the transaction it votes for; the vote for the other waits.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.votes.len(), 1);
+    assert_eq!(header.inline.len(), 1);
+    let (tx_hash, _) = header.inline.iter().next().unwrap();
+    assert!(header.votes.iter().all(|x| x.tx_hash == *tx_hash));
+    assert!(header.payload.is_empty());
+    assert!(limits.check(&header).is_ok());
+    assert!(header.verify(&committee()).is_ok());
+
+    // The next header carries the other transaction.
+    let digest = Digest([3; 32]);
+    tx_our_digests
+        .send(Proposal::Digest(digest.clone(), Digest([3; 32]), 0))
+        .await
+        .unwrap();
+    tx_parents.send((vec![header.digest()], 1)).await.unwrap();
+    let next = rx_headers.recv().await.unwrap();
+    let inline: HashSet<_> = header.inline.keys().chain(next.inline.keys()).collect();
+    assert_eq!(inline, [first.digest(), second.digest()].iter().collect());
+    assert!(next.votes.iter().any(|x| x.tx_hash == digest));
+    assert!(next.verify(&committee()).is_ok());
+}
+
+// Fixture: spawn the proposer of `name` under the leader policy, making a header per
+// `header_size` votes.
+#[allow(clippy::type_complexity)]
+fn spawn_leader_policy(
+    name: PublicKey,
+    secret: SecretKey,
+    committee: &Committee,
+    header_size: usize,
+) -> (
+    Sender<(Vec<Digest>, Round)>,
+    Sender<Proposal>,
+    Receiver<Header>,
+    Sender<SignedProposals>,
+) {
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_forwarded, rx_forwarded) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    Proposer::spawn(
+        name,
+        committee,
+        ProposalPolicy::Leader,
+        SignatureService::new(secret),
+        header_size,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* gc_depth */ 50,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_forwarded,
+        rx_reconfigure,
+        /* tx_core */ tx_headers,
+        /* store */ None,
+        InlineLimits::default(),
+    );
+    (tx_parents, tx_our_digests, rx_headers, tx_forwarded)
+}
+
+// Fixture: the proposals of `author`, signed with `secret`.
+async fn signed_proposals(
+    author: PublicKey,
+    secret: SecretKey,
+    proposals: Vec<Proposal>,
+) -> SignedProposals {
+    SignedProposals::new(author, proposals, &mut SignatureService::new(secret)).await
+}
+
+#[tokio::test]
+async fn forward_to_leader() {
+    let committee = committee_with_base_port(16_500);
+    let leader = committee.leader(1);
+    let (name, secret) = keys().into_iter().find(|(x, _)| *x != leader).unwrap();
+    let address = committee.primary(&leader).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Spawn the proposer of a primary that does not lead round 1.
+    let (tx_parents, tx_our_digests, mut rx_headers, tx_forwarded) =
+        spawn_leader_policy(name, secret, &committee, /* header_size */ 1);
+    let (digest, election_id) = (Digest([1; 32]), Digest([0; 32]));
+    tx_our_digests
+        .send(Proposal::Digest(digest.clone(), election_id.clone(), 0))
+        .await
+        .unwrap();
+
+    // Our header only carries the parents: the leader gets our proposal instead, signed.
+    let header = rx_headers.recv().await.unwrap();
+    assert!(header.votes.is_empty());
+    let received = handle.await.unwrap();
+    match wire::deserialize(&received).unwrap() {
+        PrimaryMessage::ForwardProposals(signed) => {
+            assert_eq!(signed.author, name);
+            let proposals = signed.verify(&committee, &leader).unwrap();
+            assert!(matches!(
+                proposals.as_slice(),
+                [Proposal::Digest(x, y, 0)] if *x == digest && *y == election_id
+            ));
+        }
+        message => panic!("Unexpected message {:?}", message),
+    }
+
+    // The proposals forwarded to us are ours to propose, whoever leads the round.
+    let forwarded = Digest([2; 32]);
+    let (author, secret) = keys().into_iter().find(|(x, _)| *x != name).unwrap();
+    let proposals = vec![Proposal::Digest(forwarded.clone(), Digest([2; 32]), 0)];
+    tx_forwarded
+        .send(signed_proposals(author, secret, proposals).await)
+        .await
+        .unwrap();
+    tx_parents.send((vec![header.digest()], 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    let vote = Vote::new(0, forwarded, Digest([2; 32]), false).await;
+    assert_eq!(header.votes, vec![vote].into_iter().collect());
+}
+
+#[tokio::test]
+async fn leader_proposes_forwarded() {
+    let committee = committee();
+    let leader = committee.leader(1);
+    let (name, secret) = keys().into_iter().find(|(x, _)| *x == leader).unwrap();
+
+    // Spawn the proposer of the leader of round 1.
+    let (_tx_parents, _tx_our_digests, mut rx_headers, tx_forwarded) =
+        spawn_leader_policy(name, secret, &committee, /* header_size */ 2);
+
+    // Two primaries forward a proposal each, one of them in an election already proposed.
+    let (first, second, third) = (Digest([1; 32]), Digest([2; 32]), Digest([3; 32]));
+    let forwarded = vec![
+        vec![Proposal::Digest(first.clone(), Digest([0; 32]), 0)],
+        vec![
+            Proposal::Digest(second, Digest([0; 32]), 0),
+            Proposal::Digest(third.clone(), Digest([1; 32]), 0),
+        ],
+    ];
+    let others = keys().into_iter().filter(|(x, _)| *x != name);
+    for ((author, secret), proposals) in others.zip(forwarded) {
+        tx_forwarded
+            .send(signed_proposals(author, secret, proposals).await)
+            .await
+            .unwrap();
+    }
+
+    // The header votes for the first proposal of each election.
+    let header = rx_headers.recv().await.unwrap();
+    let voted: HashSet<_> = header.votes.iter().map(|x| x.tx_hash.clone()).collect();
+    assert_eq!(voted, [first.clone(), third.clone()].iter().cloned().collect());
+    let expected = vec![(first, 0), (third, 0)].into_iter().collect();
+    assert_eq!(header.payload, expected);
+    assert!(header.verify(&committee).is_ok());
+}
+
+#[tokio::test]
+async fn drop_unauthenticated_forwards() {
+    let committee = committee();
+    let leader = committee.leader(1);
+    let (name, secret) = keys().into_iter().find(|(x, _)| *x == leader).unwrap();
+    let (_tx_parents, _tx_our_digests, mut rx_headers, tx_forwarded) =
+        spawn_leader_policy(name, secret, &committee, /* header_size */ 1);
+    let proposal = |seed| vec![Proposal::Digest(Digest([seed; 32]), Digest([seed; 32]), 0)];
+
+    // A stranger, a primary signing for another and one posing as us all forward proposals.
+    let mut rng = rand::rngs::StdRng::from_seed([1; 32]);
+    let (stranger, stranger_secret) = crypto::generate_keypair(&mut rng);
+    let mut others = keys().into_iter().filter(|(x, _)| *x != name);
+    let (author, _) = others.next().unwrap();
+    let (_, forger_secret) = others.next().unwrap();
+    let (_, our_secret) = keys().into_iter().find(|(x, _)| *x == name).unwrap();
+    let before = unauthenticated_forwards();
+    let rogue = vec![
+        signed_proposals(stranger, stranger_secret, proposal(1)).await,
+        signed_proposals(author, forger_secret, proposal(2)).await,
+        signed_proposals(name, our_secret, proposal(3)).await,
+    ];
+    for signed in rogue {
+        tx_forwarded.send(signed).await.unwrap();
+    }
+
+    // They are dropped and counted: our header only votes for the genuine forward that follows.
+    let (_, author_secret) = keys().into_iter().find(|(x, _)| *x == author).unwrap();
+    tx_forwarded
+        .send(signed_proposals(author, author_secret, proposal(4)).await)
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    let voted: Vec<_> = header.votes.iter().map(|x| x.tx_hash.clone()).collect();
+    assert_eq!(voted, vec![Digest([4; 32])]);
+    assert_eq!(unauthenticated_forwards() - before, 3);
+}
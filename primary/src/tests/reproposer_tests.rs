@@ -0,0 +1,43 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::election_id;
+use crypto::Digest;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn repropose_batches_of_unstarted_elections() {
+    // Create a new test store, holding a claim about election 2.
+    let path = ".db_test_repropose_batches";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(claims_key(&election_id(2)), Vec::new()).await;
+
+    let (tx_others, rx_others) = channel(10);
+    let (tx_proposer, mut rx_proposer) = channel(10);
+    Reproposer::spawn(/* delay */ 50, store, rx_others, tx_proposer);
+    let start = Instant::now();
+    for (seed, election) in [(1, 1), (2, 2), (3, 1)] {
+        let batch = (
+            Digest([seed; 32]),
+            election_id(election),
+            /* worker_id */ 0,
+        );
+        tx_others.send(batch).await.unwrap();
+    }
+
+    // Once the delay passed, we propose the first batch of the election that did not start.
+    match rx_proposer.recv().await.unwrap() {
+        Proposal::Digest(tx_hash, id, 0) => {
+            assert_eq!((tx_hash, id), (Digest([1; 32]), election_id(1)))
+        }
+        x => panic!("Unexpected proposal {:?}", x),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // Nothing else: election 2 started, and the twin waited on the same election.
+    sleep(Duration::from_millis(100)).await;
+    drop(tx_others);
+    assert!(rx_proposer.recv().await.is_none());
+}
@@ -0,0 +1,22 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::election_id;
+
+#[test]
+fn forget_old_elections() {
+    let mut window = ElectionWindow::new(2);
+    for round in 1..=3 {
+        assert!(window.insert(election_id(round as u8), round));
+    }
+    assert!(!window.insert(election_id(3), 3));
+
+    // Only the elections of the last `depth` rounds stay.
+    assert_eq!(window.expire(4), vec![election_id(1)]);
+    assert_eq!(window.len(), 2);
+    assert!(!window.contains(&election_id(1)));
+    assert!(window.contains(&election_id(2)));
+
+    // A forgotten election may come back.
+    assert!(window.insert(election_id(1), 4));
+    assert!(window.expire(4).is_empty());
+}
@@ -0,0 +1,57 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{commit_certificate, committee, election_id, header, keys, reconfiguration};
+use crate::election::Election;
+use crate::messages::Hash as _;
+use crate::proposer::{Proposal, SignedProposals};
+use config::Committee;
+use crypto::{Digest, Signature};
+
+#[test]
+fn round_trip() {
+    let (requestor, _) = keys().pop().unwrap();
+    let messages = vec![
+        PrimaryMessage::Header(header()),
+        PrimaryMessage::HeaderRequest(vec![header().digest()], requestor),
+        PrimaryMessage::CommitCertificate(commit_certificate(Digest([1; 32]), election_id(0), 3)),
+        PrimaryMessage::Reconfigure(reconfiguration(Committee { epoch: 1, ..committee() }, 3)),
+        PrimaryMessage::StateRequest(election_id(0)),
+        PrimaryMessage::StateResponse(election_id(0), Some(Election::new(&committee()).state())),
+        PrimaryMessage::ForwardProposals(SignedProposals {
+            author: requestor,
+            proposals: vec![Proposal::Digest(Digest([1; 32]), election_id(0), 0)],
+            signature: Signature::default(),
+        }),
+    ];
+    for (tag, message) in messages.into_iter().enumerate() {
+        let frame = serialize(&message).unwrap();
+        assert_eq!(frame[..2], [PROTOCOL_VERSION, tag as u8]);
+        assert_eq!(serialize(&deserialize(&frame).unwrap()).unwrap(), frame);
+    }
+}
+
+#[test]
+fn drop_unknown_version() {
+    let mut frame = serialize(&PrimaryMessage::Header(header())).unwrap();
+    frame[0] = PROTOCOL_VERSION + 1;
+    let dropped = unknown_versions();
+    assert!(matches!(
+        deserialize(&frame),
+        Err(WireError::UnknownVersion(x)) if x == PROTOCOL_VERSION + 1
+    ));
+    assert!(unknown_versions() > dropped);
+}
+
+#[test]
+fn reject_unknown_type() {
+    let mut frame = serialize(&PrimaryMessage::Header(header())).unwrap();
+    frame[1] = 9;
+    assert!(matches!(
+        deserialize(&frame),
+        Err(WireError::UnknownType(9))
+    ));
+    assert!(matches!(
+        deserialize(&[PROTOCOL_VERSION]),
+        Err(WireError::Truncated)
+    ));
+}
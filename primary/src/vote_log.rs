@@ -0,0 +1,61 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::election::ElectionId;
+use crate::error::DagResult;
+use crate::messages::Vote;
+use crate::primary::Round;
+use log::warn;
+use network::{decode, encode};
+use store::Store;
+
+/// The store key of our vote in a round of an election.
+pub fn vote_key(election_id: &ElectionId, round: Round) -> Vec<u8> {
+    [b"vote".as_ref(), election_id.as_ref(), &round.to_le_bytes()].concat()
+}
+
+/// Persists our own votes before they are sent. A primary restarting mid-run consults it so that
+/// it never casts a different vote in a round of an election it already voted in.
+#[derive(Clone)]
+pub struct VoteLog {
+    /// The persistent storage; without it, votes are not persisted.
+    store: Option<Store>,
+}
+
+impl VoteLog {
+    pub fn new(store: Option<Store>) -> Self {
+        Self { store }
+    }
+
+    /// Persist our votes, unless we already cast one in the same election and round: that earlier
+    /// vote is then returned in its place. A single flush puts all of them on disk before this
+    /// returns.
+    pub async fn cast_all(&mut self, votes: Vec<Vote>) -> DagResult<Vec<Vote>> {
+        let store = match &mut self.store {
+            Some(store) => store,
+            None => return Ok(votes),
+        };
+        let mut cast = Vec::with_capacity(votes.len());
+        let mut written = false;
+        for vote in votes {
+            let key = vote_key(&vote.election_id, vote.round);
+            match store.read(key.clone()).await? {
+                Some(bytes) => {
+                    let earlier: Vote = decode(&bytes)?;
+                    if earlier != vote {
+                        warn!("Replacing vote {:?} with our earlier vote {:?}", vote, earlier);
+                    }
+                    cast.push(earlier);
+                }
+                None => {
+                    store.write(key, encode(&vote)?).await;
+                    written = true;
+                    cast.push(vote);
+                }
+            }
+        }
+        // Our votes must survive a crash once they are out.
+        if written {
+            store.flush().await?;
+        }
+        Ok(cast)
+    }
+}
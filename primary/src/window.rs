@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::election::ElectionId;
+use crate::primary::Round;
+use std::collections::{BTreeMap, HashSet};
+
+#[cfg(test)]
+#[path = "tests/window_tests.rs"]
+pub mod window_tests;
+
+/// The elections we must recognize for a while, e.g. to ignore the late votes of the elections
+/// we garbage collected. Each one is forgotten `depth` rounds after we inserted it, so that the
+/// set stays bounded however many elections go by.
+pub struct ElectionWindow {
+    depth: Round,
+    elections: HashSet<ElectionId>,
+    /// The elections inserted in each round.
+    rounds: BTreeMap<Round, Vec<ElectionId>>,
+}
+
+impl ElectionWindow {
+    pub fn new(depth: Round) -> Self {
+        Self {
+            depth,
+            elections: HashSet::new(),
+            rounds: BTreeMap::new(),
+        }
+    }
+
+    pub fn contains(&self, election_id: &ElectionId) -> bool {
+        self.elections.contains(election_id)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.elections.len()
+    }
+
+    /// Insert an election in this round. Returns false if we hold it already.
+    pub fn insert(&mut self, election_id: ElectionId, round: Round) -> bool {
+        if !self.elections.insert(election_id.clone()) {
+            return false;
+        }
+        self.rounds.entry(round).or_default().push(election_id);
+        true
+    }
+
+    /// Forget the elections inserted more than `depth` rounds before this one. Returns them.
+    pub fn expire(&mut self, round: Round) -> Vec<ElectionId> {
+        let kept = self.rounds.split_off(&round.saturating_sub(self.depth));
+        let expired: Vec<_> = std::mem::replace(&mut self.rounds, kept)
+            .into_values()
+            .flatten()
+            .collect();
+        for election_id in &expired {
+            self.elections.remove(election_id);
+        }
+        expired
+    }
+}
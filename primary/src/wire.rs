@@ -0,0 +1,134 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The wire format of the messages between primaries: a frame holds the protocol version, the type
+//! of the message, and then the bincode encoding of its content. Tagging the types explicitly (rather
+//! than through the variant index bincode gives each enum variant) keeps the format stable when the
+//! enums change, and the version lets a node drop the frames of a peer running another one.
+use crate::primary::PrimaryMessage;
+use network::{decode, encode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(test)]
+#[path = "tests/wire_tests.rs"]
+pub mod wire_tests;
+
+/// The version of the wire format we speak. Frames of any other version are dropped. Version 2
+/// changed the digest of votes, and with it the digest (and signature) of headers. Version 3 signs
+/// the forwarded proposals.
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// The types of the primary messages.
+const HEADER: u8 = 0;
+const HEADER_REQUEST: u8 = 1;
+const COMMIT_CERTIFICATE: u8 = 2;
+const RECONFIGURE: u8 = 3;
+const STATE_REQUEST: u8 = 4;
+const STATE_RESPONSE: u8 = 5;
+const FORWARD_PROPOSALS: u8 = 6;
+
+/// The number of frames dropped because of their protocol version.
+static UNKNOWN_VERSIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Why a frame could not be read.
+#[derive(Debug)]
+pub enum WireError {
+    /// The frame is of a protocol version we do not speak.
+    UnknownVersion(u8),
+    /// The frame holds a message type we do not know.
+    UnknownType(u8),
+    /// The frame is too short to hold its header.
+    Truncated,
+    /// The content of the message does not match its type.
+    Malformed(Box<bincode::ErrorKind>),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownVersion(version) => write!(f, "Unknown protocol version {}", version),
+            Self::UnknownType(tag) => write!(f, "Unknown message type {}", tag),
+            Self::Truncated => write!(f, "Truncated frame"),
+            Self::Malformed(e) => write!(f, "Malformed message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl From<Box<bincode::ErrorKind>> for WireError {
+    fn from(e: Box<bincode::ErrorKind>) -> Self {
+        Self::Malformed(e)
+    }
+}
+
+/// Frame the content of a message of the specified type.
+pub fn seal_frame<T: Serialize + ?Sized>(tag: u8, content: &T) -> bincode::Result<Vec<u8>> {
+    let content = encode(content)?;
+    let mut frame = Vec::with_capacity(2 + content.len());
+    frame.push(PROTOCOL_VERSION);
+    frame.push(tag);
+    frame.extend(content);
+    Ok(frame)
+}
+
+/// Split a frame into the type and the content of its message. Frames of another protocol version
+/// are counted (see `unknown_versions`).
+pub fn open_frame(frame: &[u8]) -> Result<(u8, &[u8]), WireError> {
+    match frame {
+        [PROTOCOL_VERSION, tag, content @ ..] => Ok((*tag, content)),
+        [version, _, ..] => {
+            UNKNOWN_VERSIONS.fetch_add(1, Ordering::Relaxed);
+            Err(WireError::UnknownVersion(*version))
+        }
+        _ => Err(WireError::Truncated),
+    }
+}
+
+/// Decode the content of a frame opened by `open_frame`.
+pub fn frame_content<'a, T: Deserialize<'a>>(content: &'a [u8]) -> Result<T, WireError> {
+    Ok(decode(content)?)
+}
+
+/// The number of frames dropped because of their protocol version since we started.
+pub fn unknown_versions() -> u64 {
+    UNKNOWN_VERSIONS.load(Ordering::Relaxed)
+}
+
+pub fn serialize(message: &PrimaryMessage) -> bincode::Result<Vec<u8>> {
+    match message {
+        PrimaryMessage::Header(header) => seal_frame(HEADER, header),
+        PrimaryMessage::HeaderRequest(missing, requestor) => {
+            seal_frame(HEADER_REQUEST, &(missing, requestor))
+        }
+        PrimaryMessage::CommitCertificate(certificate) => {
+            seal_frame(COMMIT_CERTIFICATE, certificate)
+        }
+        PrimaryMessage::Reconfigure(reconfiguration) => seal_frame(RECONFIGURE, reconfiguration),
+        PrimaryMessage::StateRequest(election_id) => seal_frame(STATE_REQUEST, election_id),
+        PrimaryMessage::StateResponse(election_id, state) => {
+            seal_frame(STATE_RESPONSE, &(election_id, state))
+        }
+        PrimaryMessage::ForwardProposals(proposals) => seal_frame(FORWARD_PROPOSALS, proposals),
+    }
+}
+
+pub fn deserialize(frame: &[u8]) -> Result<PrimaryMessage, WireError> {
+    let (tag, bytes) = open_frame(frame)?;
+    match tag {
+        HEADER => Ok(PrimaryMessage::Header(frame_content(bytes)?)),
+        HEADER_REQUEST => {
+            let (missing, requestor) = frame_content(bytes)?;
+            Ok(PrimaryMessage::HeaderRequest(missing, requestor))
+        }
+        COMMIT_CERTIFICATE => Ok(PrimaryMessage::CommitCertificate(frame_content(bytes)?)),
+        RECONFIGURE => Ok(PrimaryMessage::Reconfigure(frame_content(bytes)?)),
+        STATE_REQUEST => Ok(PrimaryMessage::StateRequest(frame_content(bytes)?)),
+        STATE_RESPONSE => {
+            let (election_id, state) = frame_content(bytes)?;
+            Ok(PrimaryMessage::StateResponse(election_id, state))
+        }
+        FORWARD_PROPOSALS => Ok(PrimaryMessage::ForwardProposals(frame_content(bytes)?)),
+        tag => Err(WireError::UnknownType(tag)),
+    }
+}
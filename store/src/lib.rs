@@ -1,5 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{channel, Sender};
 use tokio::sync::oneshot;
 
@@ -15,8 +18,21 @@ type Value = Vec<u8>;
 
 pub enum StoreCommand {
     Write(Key, Value),
+    /// Remove a key (if present).
+    Delete(Key),
     Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
     NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
+    /// Compact the whole key range, replying with how long it took.
+    Compact(oneshot::Sender<Duration>),
+    /// Flush the writes received so far to disk.
+    Flush(oneshot::Sender<StoreResult<()>>),
+}
+
+/// The tuning of the underlying database.
+#[derive(Clone, Debug, Default)]
+pub struct StoreOptions {
+    /// The bandwidth of the background compactions and flushes, in B/s. Unlimited if zero.
+    pub rate_limit: u64,
 }
 
 #[derive(Clone)]
@@ -26,7 +42,17 @@ pub struct Store {
 
 impl Store {
     pub fn new(path: &str) -> StoreResult<Self> {
-        let db = rocksdb::DB::open_default(path)?;
+        Self::with_options(path, StoreOptions::default())
+    }
+
+    pub fn with_options(path: &str, options: StoreOptions) -> StoreResult<Self> {
+        let mut db_options = rocksdb::Options::default();
+        db_options.create_if_missing(true);
+        if options.rate_limit > 0 {
+            // Refill every 100 ms, with the default fairness.
+            db_options.set_ratelimiter(options.rate_limit as i64, 100_000, 10);
+        }
+        let db = Arc::new(rocksdb::DB::open(&db_options, path)?);
         let mut obligations = HashMap::<_, VecDeque<oneshot::Sender<_>>>::new();
         let (tx, mut rx) = channel(100);
         tokio::spawn(async move {
@@ -40,6 +66,9 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::Delete(key) => {
+                        let _ = db.delete(&key);
+                    }
                     StoreCommand::Read(key, sender) => {
                         let response = db.get(&key);
                         let _ = sender.send(response);
@@ -56,6 +85,18 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::Compact(sender) => {
+                        // Compact on a thread of its own so that reads and writes go on meanwhile.
+                        let db = db.clone();
+                        thread::spawn(move || {
+                            let now = Instant::now();
+                            db.compact_range::<&[u8], &[u8]>(None, None);
+                            let _ = sender.send(now.elapsed());
+                        });
+                    }
+                    StoreCommand::Flush(sender) => {
+                        let _ = sender.send(db.flush());
+                    }
                 }
             }
         });
@@ -68,6 +109,12 @@ impl Store {
         }
     }
 
+    pub async fn delete(&mut self, key: Key) {
+        if let Err(e) = self.channel.send(StoreCommand::Delete(key)).await {
+            panic!("Failed to send Delete command to store: {}", e);
+        }
+    }
+
     pub async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
@@ -91,4 +138,25 @@ impl Store {
             .await
             .expect("Failed to receive reply to NotifyRead command from store")
     }
+
+    pub async fn compact(&mut self) -> Duration {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Compact(sender)).await {
+            panic!("Failed to send Compact command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to Compact command from store")
+    }
+
+    /// Wait until all the writes sent before are on disk.
+    pub async fn flush(&mut self) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Flush(sender)).await {
+            panic!("Failed to send Flush command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to Flush command from store")
+    }
 }
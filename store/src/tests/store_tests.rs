@@ -45,6 +45,20 @@ async fn read_unknown_key() {
     assert!(result.unwrap().is_none());
 }
 
+#[tokio::test]
+async fn delete_value() {
+    // Create new store.
+    let path = ".db_test_delete_value";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // A deleted key reads as unknown.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    store.write(key.clone(), vec![4u8, 5u8, 6u8, 7u8]).await;
+    store.delete(key.clone()).await;
+    assert_eq!(store.read(key).await.unwrap(), None);
+}
+
 #[tokio::test]
 async fn read_notify() {
     // Create new store.
@@ -72,3 +86,42 @@ async fn read_notify() {
     store.write(key, value).await;
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn compact_store() {
+    // Create new store, with throttled background compactions.
+    let path = ".db_test_compact_store";
+    let _ = fs::remove_dir_all(path);
+    let options = StoreOptions {
+        rate_limit: 1024 * 1024,
+    };
+    let mut store = Store::with_options(path, options).unwrap();
+
+    // Overwrite the same keys a few times, then compact them.
+    for i in 0..3u8 {
+        for key in 0..100u8 {
+            store.write(vec![key], vec![i; 1024]).await;
+        }
+    }
+    store.compact().await;
+
+    // The latest values survive the compaction.
+    for key in 0..100u8 {
+        assert_eq!(store.read(vec![key]).await.unwrap(), Some(vec![2; 1024]));
+    }
+}
+
+#[tokio::test]
+async fn flush_store() {
+    // Create new store.
+    let path = ".db_test_flush_store";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // The writes sent before the flush are all there once it returns.
+    for key in 0..10u8 {
+        store.write(vec![key], vec![key]).await;
+    }
+    assert!(store.flush().await.is_ok());
+    assert_eq!(store.read(vec![9]).await.unwrap(), Some(vec![9]));
+}
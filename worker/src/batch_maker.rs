@@ -1,21 +1,19 @@
 use crate::Block;
-use crate::processor::SerializedBatchMessage;
 // Copyright(C) Facebook, Inc. and its affiliates.
+#[cfg(feature = "benchmark")]
+use crate::processor::batch_digest;
 use crate::quorum_waiter::QuorumWaiterMessage;
-use crate::worker::WorkerMessage;
+use crate::wire;
+use crate::worker::{SerializedBatchDigestMessage, WorkerMessage};
 use bytes::Bytes;
-//#[cfg(feature = "benchmark")]
-use crypto::{PublicKey, Digest};
-#[cfg(feature = "benchmark")]
-use ed25519_dalek::{Digest as _, Sha512};
+use crypto::PublicKey;
 //#[cfg(feature = "benchmark")]
 use log::info;
-use network::ReliableSender;
-use primary::Transaction;
-#[cfg(feature = "benchmark")]
-use std::convert::TryInto as _;
+use network::{encode, encoded_size, ReliableSender, MAX_MESSAGE_SIZE};
+use primary::{Transaction, WorkerPrimaryMessage};
 use std::net::SocketAddr;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
@@ -29,6 +27,8 @@ pub type Batch = Vec<Transaction>;
 pub struct BatchMaker {
     /// The preferred batch size (in bytes).
     batch_size: usize,
+    /// The size past which no transaction joins the batch (in bytes).
+    max_batch_bytes: usize,
     /// The maximum delay after which to seal the batch (in ms).
     max_batch_delay: u64,
     /// Channel to receive transactions from the network.
@@ -37,44 +37,52 @@ pub struct BatchMaker {
     tx_message: Sender<QuorumWaiterMessage>,
     /// The network addresses of the other workers that share our worker id.
     workers_addresses: Vec<(PublicKey, SocketAddr)>,
+    /// Receives the addresses of the other workers sharing our id in each new epoch.
+    rx_reconfigure: Receiver<Vec<(PublicKey, SocketAddr)>>,
     /// Holds the current batch.
     current_batch: Batch,
-    /// Holds the size of the current batch (in bytes).
+    /// Holds the serialized size of the current batch (in bytes).
     current_batch_size: usize,
     /// A network sender to broadcast the batches to the other workers.
     network: ReliableSender,
-    /// The primary network address.
-    primary_address: SocketAddr,
-    /// Channel to deliver batches for which we have enough acknowledgements.
-    tx_batch: Sender<(SerializedBatchMessage, Digest)>,
+    /// The size up to which transactions are handed to our primary to be proposed inline, rather
+    /// than batched (in bytes). Disabled if zero.
+    inline_size: usize,
+    /// Output channel to hand the inline transactions to the `PrimaryConnector`.
+    tx_inline: Sender<SerializedBatchDigestMessage>,
 }
 
 impl BatchMaker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         batch_size: usize,
+        max_batch_bytes: usize,
         max_batch_delay: u64,
         rx_transaction: Receiver<Transaction>,
         tx_message: Sender<QuorumWaiterMessage>,
         workers_addresses: Vec<(PublicKey, SocketAddr)>,
-        primary_address: SocketAddr,
-        tx_batch: Sender<(SerializedBatchMessage, Digest)>,
-    ) {
+        rx_reconfigure: Receiver<Vec<(PublicKey, SocketAddr)>>,
+        inline_size: usize,
+        tx_inline: Sender<SerializedBatchDigestMessage>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 batch_size,
+                max_batch_bytes,
                 max_batch_delay,
                 rx_transaction,
                 tx_message,
                 workers_addresses,
+                rx_reconfigure,
                 current_batch: Batch::with_capacity(batch_size * 2),
-                current_batch_size: 0,
+                current_batch_size: empty_batch_size(),
                 network: ReliableSender::new(),
-                primary_address,
-                tx_batch,
+                inline_size,
+                tx_inline,
             }
             .run()
             .await;
-        });
+        })
     }
 
     /// Main loop receiving incoming transactions and creating batches.
@@ -86,15 +94,37 @@ impl BatchMaker {
             tokio::select! {
                 // Assemble client transactions into batches of preset size.
                 Some(transaction) = self.rx_transaction.recv() => {
-                    self.current_batch_size += transaction.data.len() + 32;
-                    //info!("tx: {:?}", transaction);
-                    self.current_batch.push(transaction);
-                    if self.current_batch_size >= self.batch_size {
-                        self.seal().await;
-                        timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                    // Small transactions travel in the headers of our primary instead.
+                    if self.inline_size > 0 && transaction.data.len() <= self.inline_size {
+                        let message = encode(&WorkerPrimaryMessage::Inline(transaction))
+                            .expect("Failed to serialize our own worker-primary message");
+                        self.tx_inline
+                            .send(message)
+                            .await
+                            .expect("Failed to deliver inline transaction");
+                    } else {
+                        let size = transaction_size(&transaction);
+                        if !self.current_batch.is_empty()
+                            && self.current_batch_size + size > self.max_batch_bytes
+                        {
+                            self.seal().await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                        }
+                        self.current_batch_size += size;
+                        //info!("tx: {:?}", transaction);
+                        self.current_batch.push(transaction);
+                        if self.current_batch_size >= self.batch_size {
+                            self.seal().await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                        }
                     }
                 },
 
+                // The batches sealed from now on go to the workers of the new epoch.
+                Some(workers_addresses) = self.rx_reconfigure.recv() => {
+                    self.workers_addresses = workers_addresses;
+                },
+
                 // If the timer triggers, seal the batch even if it contains few transactions.
                 () = &mut timer => {
                     if !self.current_batch.is_empty() {
@@ -114,21 +144,20 @@ impl BatchMaker {
         //info!("Current batch: {:?}", self.current_batch);
 
         #[cfg(feature = "benchmark")]
-        let size = self.current_batch_size;
+        let (size, txs) = (self.current_batch_size, self.current_batch.len());
 
-        // Look for sample txs (they all start with 0) and gather their txs id (the next 8 bytes).
+        // Look for sample txs and gather their counters.
         #[cfg(feature = "benchmark")]
         let tx_ids: Vec<_> = self
             .current_batch
             .iter()
-            .filter(|tx| tx.id[0] == 0u8 && tx.id.len() > 8)
-            .filter_map(|tx| tx.id[1..9].try_into().ok())
+            .filter_map(|tx| bench_support::sample_tx_counter(&tx.id))
             .collect();
 
         //info!("tx_ids: {:?}", tx_ids);
 
         // Serialize the batch.
-        self.current_batch_size = 0;
+        self.current_batch_size = empty_batch_size();
         let batch: Vec<Transaction> = self.current_batch.drain(..).collect();
 
         // create range proofs
@@ -163,66 +192,51 @@ impl BatchMaker {
             //range_proof_bytes: range_proof.to_bytes(),
             //commitments,
         };
+        let election_id = block.election_id();
         let message = WorkerMessage::Batch(block);
-        let serialized = bincode::serialize(&message).expect("Failed to serialize our own batch");
-
-        let mut array: [u8; 32] = [0; 32];
-
-        let vec = batch[0].id.clone();
-    
-        let vec_len = vec.len();
-        
-        if vec_len < 32 {
-            array[..vec_len].clone_from_slice(&vec[..vec_len]);
-        } else {
-            array.clone_from_slice(&vec[..32]);
-        }
+        let serialized = wire::serialize(&message).expect("Failed to serialize our own batch");
 
 
         //info!("serialized: {:?}", serialized);
 
         #[cfg(feature = "benchmark")]
         {
-            // NOTE: This is one extra hash that is only needed to print the following log entries.
-            let digest = Digest(
-                Sha512::digest(&serialized).as_slice()[..32]
-                    .try_into()
-                    .unwrap(),
-            );
-
+            let digest = batch_digest(&serialized);
             for id in tx_ids {
                 // NOTE: This log entry is used to compute performance.
-                info!(
-                    "Batch {:?} contains sample tx {}",
-                    //Digest(array),
-                    Digest(array),
-                    u64::from_be_bytes(id)
-                );
+                info!("{}", bench_support::batch_contains_sample(&digest, id));
             }
 
             // NOTE: This log entry is used to compute performance.
-            info!("Batch {:?} contains {} B",  Digest(array), size);
+            info!("{}", bench_support::batch_contains_bytes(&digest, size, txs));
         }
 
         // Broadcast the batch through the network.
-        //let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
-        //let bytes = Bytes::from(serialized.clone());
-        //let handlers = self.network.broadcast(addresses, bytes).await;
-
-        //info!("id: {:?}", array);
-
-        self.tx_batch
-            .send((serialized, Digest(array)))
-            .await
-            .expect("Failed to deliver batch");
+        let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
+        let bytes = Bytes::from(serialized.clone());
+        let handlers = self.network.broadcast(addresses, bytes).await;
 
         // Send the batch through the deliver channel for further processing.
-        /*self.tx_message
+        self.tx_message
             .send(QuorumWaiterMessage {
                 batch: serialized,
+                election_id,
                 handlers: names.into_iter().zip(handlers.into_iter()).collect(),
             })
             .await
-            .expect("Failed to deliver batch");*/
+            .expect("Failed to deliver batch");
     }
 }
+
+/// The size of the serialized batch holding no transaction.
+fn empty_batch_size() -> usize {
+    wire::serialize(&WorkerMessage::Batch(Block::default()))
+        .expect("Failed to serialize an empty batch")
+        .len()
+}
+
+/// The size a transaction adds to a serialized batch. Transactions too large to be sent count as
+/// the largest message, so that they always go alone.
+fn transaction_size(transaction: &Transaction) -> usize {
+    encoded_size(transaction).unwrap_or(MAX_MESSAGE_SIZE) as usize
+}
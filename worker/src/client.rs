@@ -0,0 +1,143 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receipt::{verify_receipt, Receipt, ReceiptRequest};
+use bytes::Bytes;
+use config::Committee;
+use crypto::Digest;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use network::{decode, encode};
+use primary::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/client_tests.rs"]
+pub mod client_tests;
+
+/// Why a worker refused a transaction.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TransactionValidationError {
+    /// The message is not a serialized transaction.
+    Malformed(String),
+    /// The transaction pays less than the minimum fee of the worker.
+    TxFeeError { fee: u64, minimum: u64 },
+    /// The transaction carries no payload.
+    EmptyPayload,
+    /// The transaction has no id, so it belongs to no election.
+    MissingId,
+    /// The id of the transaction is longer than an election id: transactions whose ids only differ
+    /// past it would compete in the same election.
+    IdTooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for TransactionValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "Malformed transaction: {}", e),
+            Self::TxFeeError { fee, minimum } => write!(
+                f,
+                "Transaction fee {} is below the minimum fee {}",
+                fee, minimum
+            ),
+            Self::EmptyPayload => write!(f, "Transaction has no payload"),
+            Self::MissingId => write!(f, "Transaction has no id"),
+            Self::IdTooLong { len, max } => write!(
+                f,
+                "Transaction id of {} B is longer than {} B",
+                len, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransactionValidationError {}
+
+impl From<Box<bincode::ErrorKind>> for TransactionValidationError {
+    fn from(e: Box<bincode::ErrorKind>) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+/// The reply of a worker to each transaction it receives from a client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SubmissionStatus {
+    /// The transaction was handed to the batch maker.
+    Accepted {
+        tx_hash: Digest,
+        /// Only if the client asked for one and the worker signs receipts.
+        receipt: Option<Receipt>,
+    },
+    Rejected {
+        reason: TransactionValidationError,
+    },
+}
+
+/// A connection to the transactions endpoint of a worker.
+pub struct Client {
+    transport: Framed<TcpStream, LengthDelimitedCodec>,
+    /// The verified receipts of our submissions.
+    receipts: Vec<Receipt>,
+}
+
+impl Client {
+    pub async fn connect(address: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Self {
+            transport: Framed::new(stream, LengthDelimitedCodec::new()),
+            receipts: Vec::new(),
+        })
+    }
+
+    /// The receipts collected by `submit_with_receipt`.
+    pub fn receipts(&self) -> &[Receipt] {
+        &self.receipts
+    }
+
+    /// Submit a transaction and wait for the worker to acknowledge it.
+    pub async fn submit_transaction(&mut self, tx: &Transaction) -> io::Result<SubmissionStatus> {
+        let message = encode(tx).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.submit(Bytes::from(message)).await
+    }
+
+    /// Submit a transaction and ask the worker for a signed receipt. A receipt that does not
+    /// verify against the committee is an error; a valid one is kept (see `receipts`).
+    pub async fn submit_with_receipt(
+        &mut self,
+        tx: &Transaction,
+        idempotency_key: Vec<u8>,
+        committee: &Committee,
+    ) -> io::Result<SubmissionStatus> {
+        let request = ReceiptRequest {
+            transaction: tx.clone(),
+            idempotency_key,
+        };
+        let message =
+            encode(&request).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let status = self.submit(Bytes::from(message)).await?;
+        if let SubmissionStatus::Accepted {
+            receipt: Some(receipt),
+            ..
+        } = &status
+        {
+            verify_receipt(receipt, committee)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.receipts.push(receipt.clone());
+        }
+        Ok(status)
+    }
+
+    /// Submit an already serialized transaction and wait for the worker to acknowledge it.
+    pub async fn submit(&mut self, message: Bytes) -> io::Result<SubmissionStatus> {
+        self.transport.send(message).await?;
+        match self.transport.next().await {
+            Some(reply) => {
+                decode(&reply?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
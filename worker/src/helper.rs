@@ -6,6 +6,7 @@ use log::{error, warn};
 use network::SimpleSender;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/helper_tests.rs"]
@@ -21,6 +22,8 @@ pub struct Helper {
     store: Store,
     /// Input channel to receive batch requests.
     rx_request: Receiver<(Vec<Digest>, PublicKey)>,
+    /// Receives the committee of each new epoch.
+    rx_reconfigure: Receiver<Committee>,
     /// A network sender to send the batches to the other workers.
     network: SimpleSender,
 }
@@ -31,40 +34,50 @@ impl Helper {
         committee: Committee,
         store: Store,
         rx_request: Receiver<(Vec<Digest>, PublicKey)>,
-    ) {
+        rx_reconfigure: Receiver<Committee>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 id,
                 committee,
                 store,
                 rx_request,
+                rx_reconfigure,
                 network: SimpleSender::new(),
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_request.recv().await {
-            // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
+        loop {
+            tokio::select! {
+                Some((digests, origin)) = self.rx_request.recv() => self.reply(digests, origin).await,
+                Some(committee) = self.rx_reconfigure.recv() => self.committee = committee,
+                else => break,
+            }
+        }
+    }
 
-            // get the requestors address.
-            let address = match self.committee.worker(&origin, &self.id) {
-                Ok(x) => x.worker_to_worker,
-                Err(e) => {
-                    warn!("Unexpected batch request: {}", e);
-                    continue;
-                }
-            };
+    async fn reply(&mut self, digests: Vec<Digest>, origin: PublicKey) {
+        // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
+
+        // get the requestors address.
+        let address = match self.committee.worker(&origin, &self.id) {
+            Ok(x) => x.worker_to_worker,
+            Err(e) => {
+                warn!("Unexpected batch request: {}", e);
+                return;
+            }
+        };
 
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => self.network.send(address, Bytes::from(data)).await,
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
-                }
+        // Reply to the request (the best we can).
+        for digest in digests {
+            match self.store.read(digest.to_vec()).await {
+                Ok(Some(data)) => self.network.send(address, Bytes::from(data)).await,
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
             }
         }
     }
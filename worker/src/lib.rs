@@ -1,14 +1,22 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 mod batch_maker;
+mod client;
 mod helper;
+mod mempool;
 mod primary_connector;
 mod processor;
 mod quorum_waiter;
+mod receipt;
 mod synchronizer;
+mod validation;
+mod wire;
 mod worker;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
+pub use crate::client::{Client, SubmissionStatus, TransactionValidationError};
+pub use crate::receipt::{verify_receipt, Receipt, ReceiptError, ReceiptRequest, ReceiptSigner};
+pub use crate::validation::{validate_transaction, TransactionValidationResult};
 pub use crate::worker::{Worker, Block};
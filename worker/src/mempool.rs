@@ -0,0 +1,169 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use log::debug;
+use primary::{Hash as _, Transaction};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/mempool_tests.rs"]
+pub mod mempool_tests;
+
+/// The number of forwarded transactions we remember to drop their copies.
+const FORWARDED: usize = 100_000;
+
+/// The delay between two reports of the mempool metrics (in ms).
+const REPORT_INTERVAL: u64 = 1_000;
+
+/// The place of a transaction in the mempool: its fee first, then its arrival (earliest first).
+type Priority = (u64, Reverse<u64>);
+
+/// Buffers the client transactions until the `BatchMaker` takes them, the highest fees first (in
+/// arrival order otherwise). Copies of the transactions we hold or recently forwarded are dropped:
+/// transactions sharing an id but not a digest compete in the same election and are all kept.
+/// Past `capacity` bytes, the lowest-fee transactions are evicted, the oldest first.
+pub struct Mempool {
+    /// The maximum size of the transactions we hold (in bytes).
+    capacity: usize,
+    /// The transactions we hold, by priority.
+    transactions: BTreeMap<Priority, Transaction>,
+    /// The priority of the transactions we hold, by digest.
+    priorities: HashMap<Digest, Priority>,
+    /// The digests of the transactions recently handed to the `BatchMaker`.
+    forwarded: HashSet<Digest>,
+    /// The same digests, oldest first.
+    forwarded_order: VecDeque<Digest>,
+    /// The arrival counter of the transactions.
+    next: u64,
+    /// The size of the transactions we hold (in bytes).
+    bytes: usize,
+    /// The number of copies of transactions dropped.
+    pub duplicates: u64,
+    /// The number of transactions evicted to stay within capacity.
+    pub evicted: u64,
+}
+
+impl Mempool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            transactions: BTreeMap::new(),
+            priorities: HashMap::new(),
+            forwarded: HashSet::new(),
+            forwarded_order: VecDeque::new(),
+            next: 0,
+            bytes: 0,
+            duplicates: 0,
+            evicted: 0,
+        }
+    }
+
+    pub fn spawn(
+        capacity: usize,
+        mut rx_transaction: Receiver<Transaction>,
+        tx_batch_maker: Sender<Transaction>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut mempool = Self::new(capacity);
+            let timer = sleep(Duration::from_millis(REPORT_INTERVAL));
+            tokio::pin!(timer);
+
+            loop {
+                tokio::select! {
+                    // Buffer the transactions of the clients.
+                    Some(transaction) = rx_transaction.recv() => {
+                        mempool.insert(transaction);
+                    },
+
+                    // Hand our best transaction to the `BatchMaker` once it is ready to take it.
+                    Ok(permit) = tx_batch_maker.reserve(), if !mempool.is_empty() => {
+                        if let Some(transaction) = mempool.pop() {
+                            permit.send(transaction);
+                        }
+                    },
+
+                    () = &mut timer => {
+                        debug!(
+                            "Mempool: {} transactions ({} B), {} copies dropped, {} evicted",
+                            mempool.len(), mempool.bytes(), mempool.duplicates, mempool.evicted,
+                        );
+                        timer.as_mut().reset(Instant::now() + Duration::from_millis(REPORT_INTERVAL));
+                    }
+                }
+            }
+        })
+    }
+
+    /// The size a transaction takes in the mempool (in bytes).
+    fn size(transaction: &Transaction) -> usize {
+        transaction.data.len() + transaction.id.len() + 8
+    }
+
+    /// Add a transaction, evicting the lowest-fee ones if we hold too many. Returns false if it is
+    /// a copy of a transaction we hold or recently forwarded.
+    pub fn insert(&mut self, transaction: Transaction) -> bool {
+        let digest = transaction.digest();
+        if self.priorities.contains_key(&digest) || self.forwarded.contains(&digest) {
+            self.duplicates += 1;
+            return false;
+        }
+        let priority = (transaction.fee, Reverse(self.next));
+        self.next += 1;
+        self.bytes += Self::size(&transaction);
+        self.priorities.insert(digest, priority);
+        self.transactions.insert(priority, transaction);
+
+        while self.bytes > self.capacity {
+            let lowest = match self.transactions.keys().next() {
+                Some((fee, _)) => *fee,
+                None => break,
+            };
+            // Within a fee, the oldest transaction comes last.
+            let oldest = self
+                .transactions
+                .range((lowest, Reverse(u64::MAX))..=(lowest, Reverse(0)))
+                .next_back()
+                .map(|(x, _)| *x);
+            if let Some(transaction) = oldest.and_then(|x| self.transactions.remove(&x)) {
+                self.bytes -= Self::size(&transaction);
+                self.priorities.remove(&transaction.digest());
+                self.evicted += 1;
+            }
+        }
+        true
+    }
+
+    /// Take the transaction with the highest fee (the oldest one on ties).
+    pub fn pop(&mut self) -> Option<Transaction> {
+        let priority = *self.transactions.keys().next_back()?;
+        let transaction = self.transactions.remove(&priority)?;
+        let digest = transaction.digest();
+        self.bytes -= Self::size(&transaction);
+        self.priorities.remove(&digest);
+
+        if self.forwarded_order.len() == FORWARDED {
+            if let Some(oldest) = self.forwarded_order.pop_front() {
+                self.forwarded.remove(&oldest);
+            }
+        }
+        self.forwarded.insert(digest.clone());
+        self.forwarded_order.push_back(digest);
+        Some(transaction)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// The size of the transactions we hold (in bytes).
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
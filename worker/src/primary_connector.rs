@@ -1,39 +1,163 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::worker::SerializedBatchDigestMessage;
 use bytes::Bytes;
-use network::SimpleSender;
+use config::WorkerId;
+use crypto::{Digest, SignatureService};
+use log::debug;
+use network::{decode, encode, SimpleSender};
+use primary::{SignedWorkerMessage, WorkerPrimaryMessage};
 use std::net::SocketAddr;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/primary_connector_tests.rs"]
+pub mod primary_connector_tests;
+
+/// The messages sent to the primary, and the digests of our batches they carried.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectorMetrics {
+    pub messages: u64,
+    pub digests: u64,
+}
 
 // Send batches' digests to the primary.
 pub struct PrimaryConnector {
     /// The primary network address.
     primary_address: SocketAddr,
+    /// Signs our messages with the key of our authority, so the primary knows they are ours.
+    signature_service: SignatureService,
+    /// The most digests of our batches sent to the primary in a single message.
+    digests_batch_size: usize,
+    /// The delay after which the digests we hold leave, even if fewer than `digests_batch_size`.
+    max_digests_delay: u64,
     /// Input channel to receive the digests to send to the primary.
     rx_digest: Receiver<SerializedBatchDigestMessage>,
     /// A network sender to send the baches' digests to the primary.
     network: SimpleSender,
+    /// The digests of our batches waiting to be sent, in the order they were sealed.
+    pending: Vec<(Digest, /* election_id */ Digest, WorkerId)>,
+    metrics: ConnectorMetrics,
 }
 
 impl PrimaryConnector {
-    pub fn spawn(primary_address: SocketAddr, rx_digest: Receiver<SerializedBatchDigestMessage>) {
+    pub fn spawn(
+        primary_address: SocketAddr,
+        signature_service: SignatureService,
+        digests_batch_size: usize,
+        max_digests_delay: u64,
+        rx_digest: Receiver<SerializedBatchDigestMessage>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            Self {
+            Self::new(
                 primary_address,
+                signature_service,
+                digests_batch_size,
+                max_digests_delay,
                 rx_digest,
-                network: SimpleSender::new(),
-            }
+            )
             .run()
             .await;
-        });
+        })
+    }
+
+    fn new(
+        primary_address: SocketAddr,
+        signature_service: SignatureService,
+        digests_batch_size: usize,
+        max_digests_delay: u64,
+        rx_digest: Receiver<SerializedBatchDigestMessage>,
+    ) -> Self {
+        Self {
+            primary_address,
+            signature_service,
+            digests_batch_size,
+            max_digests_delay,
+            rx_digest,
+            network: SimpleSender::new(),
+            pending: Vec::with_capacity(digests_batch_size),
+            metrics: ConnectorMetrics::default(),
+        }
     }
 
     async fn run(&mut self) {
-        while let Some(digest) = self.rx_digest.recv().await {
-            // Send the digest through the network.
-            self.network
-                .send(self.primary_address, Bytes::from(digest))
-                .await;
+        let timer = sleep(Duration::from_millis(self.max_digests_delay));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                message = self.rx_digest.recv() => {
+                    let message = match message {
+                        Some(message) => message,
+                        None => break,
+                    };
+                    match decode(&message) {
+                        Ok(WorkerPrimaryMessage::OurBatch(digest, election_id, worker_id))
+                            if self.digests_batch_size > 1 =>
+                        {
+                            // The first digest held starts the delay.
+                            if self.pending.is_empty() {
+                                let deadline =
+                                    Instant::now() + Duration::from_millis(self.max_digests_delay);
+                                timer.as_mut().reset(deadline);
+                            }
+                            self.pending.push((digest, election_id, worker_id));
+                            if self.pending.len() >= self.digests_batch_size {
+                                self.flush().await;
+                            }
+                        }
+                        decoded => {
+                            // The other messages leave after the digests of the batches sealed
+                            // before them.
+                            self.flush().await;
+                            let digests = match decoded {
+                                Ok(WorkerPrimaryMessage::OurBatch(..)) => 1,
+                                _ => 0,
+                            };
+                            self.send(message, digests).await;
+                        }
+                    }
+                },
+
+                // Send the digests we hold even if they are few.
+                () = &mut timer, if !self.pending.is_empty() => self.flush().await,
+            }
         }
+        self.flush().await;
+        debug!(
+            "Sent {} digests of our batches to the primary in {} messages",
+            self.metrics.digests, self.metrics.messages
+        );
+    }
+
+    /// Send the digests we hold in a single message.
+    async fn flush(&mut self) {
+        let message = match self.pending.len() {
+            0 => return,
+            1 => {
+                let (digest, election_id, worker_id) = self.pending.pop().unwrap();
+                WorkerPrimaryMessage::OurBatch(digest, election_id, worker_id)
+            }
+            _ => WorkerPrimaryMessage::OurBatches(self.pending.drain(..).collect()),
+        };
+        let digests = match &message {
+            WorkerPrimaryMessage::OurBatches(x) => x.len() as u64,
+            _ => 1,
+        };
+        let message = encode(&message).expect("Failed to serialize our own worker-primary message");
+        self.send(message, digests).await;
+    }
+
+    async fn send(&mut self, message: SerializedBatchDigestMessage, digests: u64) {
+        self.metrics.messages += 1;
+        self.metrics.digests += digests;
+
+        // Sign the message and send it through the network.
+        let message = SignedWorkerMessage::new(message, &mut self.signature_service).await;
+        let message = encode(&message).expect("Failed to serialize our own signed message");
+        self.network
+            .send(self.primary_address, Bytes::from(message))
+            .await;
     }
 }
@@ -5,10 +5,12 @@ use crypto::Digest;
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use log::info;
+use network::encode;
 use primary::WorkerPrimaryMessage;
 use std::convert::TryInto;
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/processor_tests.rs"]
@@ -17,6 +19,12 @@ pub mod processor_tests;
 /// Indicates a serialized `WorkerMessage::Batch` message.
 pub type SerializedBatchMessage = Vec<u8>;
 
+/// The digest of a serialized `WorkerMessage::Batch`. It identifies the batch everywhere: in the
+/// store, in the messages to the primary, and in the benchmark logs.
+pub fn batch_digest(batch: &[u8]) -> Digest {
+    Digest(Sha512::digest(batch)[..32].try_into().unwrap())
+}
+
 /// Hashes and stores batches, it then outputs the batch's digest.
 pub struct Processor;
 
@@ -32,7 +40,7 @@ impl Processor {
         tx_digest: Sender<SerializedBatchDigestMessage>,
         // Whether we are processing our own batches or the batches of other nodes.
         own_digest: bool,
-    ) {
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             while let Some((batch, election_id)) = rx_batch.recv().await {
                 //info!("id: {:?}", election_id);
@@ -40,23 +48,23 @@ impl Processor {
                 //let txs: Vec<Transaction> = bincode::deserialize(&batch).unwrap();
 
                 // Hash the batch.
-                let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
+                let digest = batch_digest(&batch);
 
                 // Store the batch.
-                //store.write(digest.to_vec(), batch).await;
+                store.write(digest.to_vec(), batch).await;
 
                 // Deliver the batch's digest.
                 let message = match own_digest {
-                    true => WorkerPrimaryMessage::OurBatch(digest, election_id),
-                    false => WorkerPrimaryMessage::OthersBatch(digest, election_id),
+                    true => WorkerPrimaryMessage::OurBatch(digest, election_id, id),
+                    false => WorkerPrimaryMessage::OthersBatch(digest, election_id, id),
                 };
-                let message = bincode::serialize(&message)
+                let message = encode(&message)
                     .expect("Failed to serialize our own worker-primary message");
                 tx_digest
                     .send(message)
                     .await
                     .expect("Failed to send digest");
             }
-        });
+        })
     }
 }
@@ -1,11 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::processor::SerializedBatchMessage;
 use config::{Committee, Stake};
-use crypto::PublicKey;
+use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use network::CancelHandler;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
@@ -15,6 +16,8 @@ pub mod quorum_waiter_tests;
 pub struct QuorumWaiterMessage {
     /// A serialized `WorkerMessage::Batch` message.
     pub batch: SerializedBatchMessage,
+    /// The election the batch belongs to.
+    pub election_id: Digest,
     /// The cancel handlers to receive the acknowledgements of our broadcast.
     pub handlers: Vec<(PublicKey, CancelHandler)>,
 }
@@ -27,8 +30,10 @@ pub struct QuorumWaiter {
     stake: Stake,
     /// Input Channel to receive commands.
     rx_message: Receiver<QuorumWaiterMessage>,
+    /// Receives the committee of each new epoch, along with our stake in it.
+    rx_reconfigure: Receiver<(Committee, Stake)>,
     /// Channel to deliver batches for which we have enough acknowledgements.
-    tx_batch: Sender<SerializedBatchMessage>,
+    tx_batch: Sender<(SerializedBatchMessage, Digest)>,
 }
 
 impl QuorumWaiter {
@@ -37,18 +42,20 @@ impl QuorumWaiter {
         committee: Committee,
         stake: Stake,
         rx_message: Receiver<QuorumWaiterMessage>,
-        tx_batch: Sender<Vec<u8>>,
-    ) {
+        rx_reconfigure: Receiver<(Committee, Stake)>,
+        tx_batch: Sender<(SerializedBatchMessage, Digest)>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 committee,
                 stake,
                 rx_message,
+                rx_reconfigure,
                 tx_batch,
             }
             .run()
             .await;
-        });
+        })
     }
 
     /// Helper function. It waits for a future to complete and then delivers a value.
@@ -59,28 +66,45 @@ impl QuorumWaiter {
 
     /// Main loop.
     async fn run(&mut self) {
-        while let Some(QuorumWaiterMessage { batch, handlers }) = self.rx_message.recv().await {
-            let mut wait_for_quorum: FuturesUnordered<_> = handlers
-                .into_iter()
-                .map(|(name, handler)| {
-                    let stake = self.committee.stake(&name);
-                    Self::waiter(handler, stake)
-                })
-                .collect();
-
-            // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
-            // delivered and we send its digest to the primary (that will include it into
-            // the dag). This should reduce the amount of synching.
-            let mut total_stake = self.stake;
-            while let Some(stake) = wait_for_quorum.next().await {
-                total_stake += stake;
-                if total_stake >= self.committee.quorum_threshold() {
-                    self.tx_batch
-                        .send(batch)
-                        .await
-                        .expect("Failed to deliver batch");
-                    break;
+        loop {
+            tokio::select! {
+                Some(message) = self.rx_message.recv() => self.wait_for_quorum(message).await,
+                Some((committee, stake)) = self.rx_reconfigure.recv() => {
+                    self.committee = committee;
+                    self.stake = stake;
                 }
+                else => break,
+            }
+        }
+    }
+
+    /// Deliver a batch once a quorum of the committee acknowledged it.
+    async fn wait_for_quorum(&mut self, message: QuorumWaiterMessage) {
+        let QuorumWaiterMessage {
+            batch,
+            election_id,
+            handlers,
+        } = message;
+        let mut wait_for_quorum: FuturesUnordered<_> = handlers
+            .into_iter()
+            .map(|(name, handler)| {
+                let stake = self.committee.stake(&name);
+                Self::waiter(handler, stake)
+            })
+            .collect();
+
+        // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
+        // delivered and we send its digest to the primary (that will include it into
+        // the dag). This should reduce the amount of synching.
+        let mut total_stake = self.stake;
+        while let Some(stake) = wait_for_quorum.next().await {
+            total_stake += stake;
+            if total_stake >= self.committee.quorum_threshold() {
+                self.tx_batch
+                    .send((batch, election_id))
+                    .await
+                    .expect("Failed to deliver batch");
+                break;
             }
         }
     }
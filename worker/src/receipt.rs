@@ -0,0 +1,156 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::Committee;
+use crypto::{CryptoError, Digest, PublicKey, Signature, SignatureService};
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use network::{decode, encode};
+use primary::Transaction;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+#[path = "tests/receipt_tests.rs"]
+pub mod receipt_tests;
+
+/// The prefix of every message signed for a receipt. No other message signed with the authority
+/// key starts with it, so a receipt signature cannot pass for a header or a vote.
+const RECEIPT_TAG: &[u8] = b"submission receipt";
+
+/// Why a receipt was not signed or does not verify.
+#[derive(Debug, PartialEq)]
+pub enum ReceiptError {
+    /// The signer was asked to sign a message that is not a receipt of its authority.
+    NotAReceipt,
+    /// The authority of the receipt is not in the committee.
+    UnknownAuthority(PublicKey),
+    /// The signature does not match the receipt.
+    InvalidSignature(String),
+}
+
+impl fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAReceipt => write!(f, "Refusing to sign a message that is not a receipt"),
+            Self::UnknownAuthority(name) => {
+                write!(f, "Receipt signed by unknown authority {}", name)
+            }
+            Self::InvalidSignature(e) => write!(f, "Invalid receipt signature: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReceiptError {}
+
+impl From<CryptoError> for ReceiptError {
+    fn from(e: CryptoError) -> Self {
+        Self::InvalidSignature(e.to_string())
+    }
+}
+
+/// A client submission asking the worker for a signed receipt. A bare `Transaction` asks for none.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiptRequest {
+    pub transaction: Transaction,
+    /// Chosen by the client to tell its submissions apart; the worker signs it back.
+    pub idempotency_key: Vec<u8>,
+}
+
+/// The proof, signed by an authority, that one of its workers received a transaction at a given
+/// time (see `verify_receipt`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The digest of the transaction.
+    pub tx_hash: Digest,
+    /// The authority whose worker received the transaction.
+    pub authority: PublicKey,
+    /// When the worker received the transaction, in ms since the Unix epoch.
+    pub timestamp: u64,
+    pub idempotency_key: Vec<u8>,
+    pub signature: Signature,
+}
+
+/// The fields of a receipt covered by its signature.
+type ReceiptBody = (Digest, PublicKey, u64, Vec<u8>);
+
+impl Receipt {
+    /// The message signed by the authority of the receipt.
+    pub fn signed_message(&self) -> Vec<u8> {
+        let body: ReceiptBody = (
+            self.tx_hash.clone(),
+            self.authority,
+            self.timestamp,
+            self.idempotency_key.clone(),
+        );
+        let body = encode(&body).expect("Failed to serialize receipt");
+        [RECEIPT_TAG, &body].concat()
+    }
+}
+
+/// Check a receipt against the committee it claims to come from. It needs nothing else, so a third
+/// party can check it offline.
+pub fn verify_receipt(receipt: &Receipt, committee: &Committee) -> Result<(), ReceiptError> {
+    if committee.stake(&receipt.authority) == 0 {
+        return Err(ReceiptError::UnknownAuthority(receipt.authority));
+    }
+    let digest = message_digest(&receipt.signed_message());
+    receipt.signature.verify(&digest, &receipt.authority)?;
+    Ok(())
+}
+
+fn message_digest(message: &[u8]) -> Digest {
+    Digest(Sha512::digest(message)[..32].try_into().unwrap())
+}
+
+/// Lends the authority key to a worker for receipts only: it signs the messages that are receipts
+/// of its own authority, and refuses everything else.
+#[derive(Clone)]
+pub struct ReceiptSigner {
+    /// The authority whose key signs the receipts.
+    name: PublicKey,
+    signature_service: SignatureService,
+}
+
+impl ReceiptSigner {
+    pub fn new(name: PublicKey, signature_service: SignatureService) -> Self {
+        Self {
+            name,
+            signature_service,
+        }
+    }
+
+    /// Sign a message, provided it is the `signed_message` of a receipt of our authority.
+    pub async fn sign(&mut self, message: &[u8]) -> Result<Signature, ReceiptError> {
+        let body = message
+            .strip_prefix(RECEIPT_TAG)
+            .and_then(|x| decode::<ReceiptBody>(x).ok())
+            .ok_or(ReceiptError::NotAReceipt)?;
+        if body.1 != self.name {
+            return Err(ReceiptError::NotAReceipt);
+        }
+        let digest = message_digest(message);
+        Ok(self.signature_service.request_signature(digest).await)
+    }
+
+    /// Make the receipt of a transaction received now.
+    pub async fn receipt(
+        &mut self,
+        tx_hash: Digest,
+        idempotency_key: Vec<u8>,
+    ) -> Result<Receipt, ReceiptError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to measure time")
+            .as_millis() as u64;
+        let mut receipt = Receipt {
+            tx_hash,
+            authority: self.name,
+            timestamp,
+            idempotency_key,
+            signature: Signature::default(),
+        };
+        receipt.signature = self.sign(&receipt.signed_message()).await?;
+        Ok(receipt)
+    }
+}
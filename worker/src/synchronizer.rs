@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::wire;
 use crate::worker::{Round, WorkerMessage};
 use bytes::Bytes;
 use config::{Committee, WorkerId};
@@ -12,6 +13,7 @@ use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 use store::{Store, StoreError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
@@ -61,7 +63,7 @@ impl Synchronizer {
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
         rx_message: Receiver<PrimaryWorkerMessage>,
-    ) {
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 name,
@@ -78,7 +80,7 @@ impl Synchronizer {
             }
             .run()
             .await;
-        });
+        })
     }
 
     /// Helper function. It waits for a batch to become available in the storage
@@ -154,7 +156,7 @@ impl Synchronizer {
                             }
                         };
                         let message = WorkerMessage::BatchRequest(missing, self.name.clone());
-                        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+                        let serialized = wire::serialize(&message).expect("Failed to serialize our own message");
                         self.network.send(address, Bytes::from(serialized)).await;
                     },
                     PrimaryWorkerMessage::Cleanup(round) => {
@@ -174,6 +176,7 @@ impl Synchronizer {
                         }
                         self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
                     }
+                    PrimaryWorkerMessage::Reconfigure(committee) => self.committee = committee,
                 },
 
                 // Stream out the futures of the `FuturesUnordered` that completed.
@@ -211,7 +214,7 @@ impl Synchronizer {
                             .iter().map(|(_, address)| address.worker_to_worker)
                             .collect();
                         let message = WorkerMessage::BatchRequest(retry, self.name.clone());
-                        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+                        let serialized = wire::serialize(&message).expect("Failed to serialize our own message");
                         self.network
                             .lucky_broadcast(addresses, Bytes::from(serialized), self.sync_retry_nodes)
                             .await;
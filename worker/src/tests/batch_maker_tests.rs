@@ -1,59 +1,195 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::transaction;
+use crate::common::{batch_digest, block, other_transaction, transaction};
+use crate::processor;
+use network::decode;
 use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
 
 #[tokio::test]
 async fn make_batch() {
     let (tx_transaction, rx_transaction) = channel(1);
     let (tx_message, mut rx_message) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let (tx_inline, _rx_inline) = channel(1);
     let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
         /* max_batch_size */ 200,
+        /* max_batch_bytes */ 1_000,
         /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
         rx_transaction,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        rx_reconfigure,
+        /* inline_size */ 0,
+        tx_inline,
     );
 
     // Send enough transactions to seal a batch.
-    //tx_transaction.send(transaction()).await.unwrap();
-    //tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(other_transaction()).await.unwrap();
 
     // Ensure the batch is as expected.
-    let expected_batch = vec![transaction(), transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
-    //match bincode::deserialize(&batch).unwrap() {
-        //WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
-        //_ => panic!("Unexpected message"),
-    //}
+    let QuorumWaiterMessage {
+        batch,
+        election_id,
+        handlers: _,
+    } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, wire::serialize(&WorkerMessage::Batch(block())).unwrap());
+    assert_eq!(processor::batch_digest(&batch), batch_digest());
+    assert_eq!(election_id, block().election_id());
 }
 
 #[tokio::test]
 async fn batch_timeout() {
     let (tx_transaction, rx_transaction) = channel(1);
     let (tx_message, mut rx_message) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let (tx_inline, _rx_inline) = channel(1);
     let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
         /* max_batch_size */ 200,
+        /* max_batch_bytes */ 1_000,
         /* max_batch_delay */ 50, // Ensure the timer is triggered.
         rx_transaction,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        rx_reconfigure,
+        /* inline_size */ 0,
+        tx_inline,
     );
 
     // Do not send enough transactions to seal a batch..
-    //tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
 
     // Ensure the batch is as expected.
-    let expected_batch = vec![transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
-    //match bincode::deserialize(&batch).unwrap() {
-        //WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
-        //_ => panic!("Unexpected message"),
-    //}
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    match wire::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(block) => assert_eq!(block.txs.len(), 1),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn broadcast_to_workers_of_new_epoch() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let (tx_inline, _rx_inline) = channel(1);
+    let (tx_reconfigure, rx_reconfigure) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 200,
+        /* max_batch_bytes */ 1_000,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses.clone(),
+        rx_reconfigure,
+        /* inline_size */ 0,
+        tx_inline,
+    );
+
+    // The workers of the new epoch get the batches sealed after it starts.
+    let workers_addresses = vec![dummy_addresses[0], dummy_addresses[0]];
+    tx_reconfigure.send(workers_addresses).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    let QuorumWaiterMessage { handlers, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(handlers.len(), 2);
+}
+
+#[tokio::test]
+async fn hand_small_transactions_to_primary() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let (tx_inline, mut rx_inline) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance inlining transactions of up to 10 bytes.
+    BatchMaker::spawn(
+        /* max_batch_size */ 50,
+        /* max_batch_bytes */ 1_000,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        rx_reconfigure,
+        /* inline_size */ 10,
+        tx_inline,
+    );
+
+    // A small transaction goes to our primary as is.
+    let small = Transaction {
+        data: vec![0; 10],
+        ..transaction()
+    };
+    tx_transaction.send(small.clone()).await.unwrap();
+    match decode(&rx_inline.recv().await.unwrap()).unwrap() {
+        WorkerPrimaryMessage::Inline(x) => assert_eq!(x.data, small.data),
+        x => panic!("Unexpected message {:?}", x),
+    }
+
+    // A larger one is batched.
+    tx_transaction.send(transaction()).await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    match wire::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(block) => assert_eq!(block.txs.len(), 1),
+        _ => panic!("Unexpected message"),
+    }
+    assert!(rx_inline.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn bound_batch_bytes() {
+    let (tx_transaction, rx_transaction) = channel(20);
+    let (tx_message, mut rx_message) = channel(20);
+    let (tx_inline, _rx_inline) = channel(1);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance whose batches hold two of our transactions at most.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000,
+        /* max_batch_bytes */ 250,
+        /* max_batch_delay */ 50,
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        rx_reconfigure,
+        /* inline_size */ 0,
+        tx_inline,
+    );
+
+    // A burst of transactions, then one larger than a batch.
+    for _ in 0..9 {
+        tx_transaction.send(transaction()).await.unwrap();
+    }
+    let large = Transaction {
+        data: vec![0; 300],
+        ..transaction()
+    };
+    tx_transaction.send(large).await.unwrap();
+
+    // The burst is spread over batches within the bound; the large transaction goes alone.
+    let mut sizes = Vec::new();
+    while sizes.iter().sum::<usize>() < 10 {
+        let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+        let txs = match wire::deserialize(&batch).unwrap() {
+            WorkerMessage::Batch(block) => block.txs,
+            _ => panic!("Unexpected message"),
+        };
+        if txs.len() > 1 || txs[0].data.len() < 300 {
+            assert!(batch.len() <= 250);
+        }
+        sizes.push(txs.len());
+    }
+    assert_eq!(sizes, vec![2, 2, 2, 2, 1, 1]);
 }
@@ -0,0 +1,192 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, transaction};
+use crate::receipt::ReceiptSigner;
+use crate::worker::Worker;
+use config::Parameters;
+use crypto::SignatureService;
+use network::{decode, encode};
+use primary::Hash as _;
+use std::fs;
+use store::Store;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn submit_and_acknowledge() {
+    let (name, secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_300);
+
+    // Create a new test store.
+    let path = ".db_test_submit_and_acknowledge";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    let mut client = Client::connect(address).await.unwrap();
+
+    // A valid transaction is accepted.
+    let tx_hash = transaction().digest();
+    let status = client.submit_transaction(&transaction()).await.unwrap();
+    assert_eq!(
+        status,
+        SubmissionStatus::Accepted {
+            tx_hash,
+            receipt: None
+        }
+    );
+
+    // Garbage is rejected and the connection remains usable.
+    let status = client.submit(Bytes::from(vec![0xff; 3])).await.unwrap();
+    assert!(matches!(
+        status,
+        SubmissionStatus::Rejected {
+            reason: TransactionValidationError::Malformed(_)
+        }
+    ));
+    let status = client.submit_transaction(&transaction()).await.unwrap();
+    assert!(matches!(status, SubmissionStatus::Accepted { .. }));
+
+    // The rejection of a truncated transaction carries the decoding failure.
+    let mut truncated = encode(&transaction()).unwrap();
+    truncated.pop();
+    let expected = decode::<Transaction>(&truncated).unwrap_err();
+    let status = client.submit(Bytes::from(truncated)).await.unwrap();
+    assert_eq!(
+        status,
+        SubmissionStatus::Rejected {
+            reason: TransactionValidationError::from(expected)
+        }
+    );
+}
+
+#[tokio::test]
+async fn enforce_minimum_fee() {
+    let (name, secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_600);
+    let parameters = Parameters {
+        minimum_fee: 10,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_enforce_minimum_fee";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    let mut client = Client::connect(address).await.unwrap();
+
+    // A transaction paying less than the minimum fee is rejected.
+    let tx = Transaction {
+        fee: 9,
+        ..transaction()
+    };
+    let status = client.submit_transaction(&tx).await.unwrap();
+    assert_eq!(
+        status,
+        SubmissionStatus::Rejected {
+            reason: TransactionValidationError::TxFeeError {
+                fee: 9,
+                minimum: 10
+            }
+        }
+    );
+
+    // Transactions paying at least the minimum fee are accepted.
+    for fee in [10, 11] {
+        let tx = Transaction {
+            fee,
+            ..transaction()
+        };
+        let tx_hash = tx.digest();
+        let status = client.submit_transaction(&tx).await.unwrap();
+        assert_eq!(
+            status,
+            SubmissionStatus::Accepted {
+                tx_hash,
+                receipt: None
+            }
+        );
+    }
+}
+
+#[tokio::test]
+async fn collect_receipts() {
+    let (name, secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_700);
+
+    // Create a new test store.
+    let path = ".db_test_collect_receipts";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance signing receipts.
+    let signature_service = SignatureService::new(secret);
+    let signer = ReceiptSigner::new(name, signature_service.clone());
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        signature_service,
+        Some(signer),
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    let mut client = Client::connect(address).await.unwrap();
+
+    // Without asking, we get no receipt.
+    let status = client.submit_transaction(&transaction()).await.unwrap();
+    assert!(matches!(
+        status,
+        SubmissionStatus::Accepted { receipt: None, .. }
+    ));
+
+    // When asked, the worker signs one and we keep it.
+    let tx_hash = transaction().digest();
+    let status = client
+        .submit_with_receipt(&transaction(), b"key".to_vec(), &committee)
+        .await
+        .unwrap();
+    match status {
+        SubmissionStatus::Accepted {
+            receipt: Some(receipt),
+            ..
+        } => {
+            assert_eq!(receipt.tx_hash, tx_hash);
+            assert_eq!(receipt.authority, name);
+            assert_eq!(receipt.idempotency_key, b"key".to_vec());
+        }
+        x => panic!("Unexpected status {:?}", x),
+    }
+    assert_eq!(client.receipts().len(), 1);
+}
@@ -1,19 +1,21 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::batch_maker::Batch;
-use crate::worker::WorkerMessage;
+use crate::worker::{Block, WorkerMessage};
 use bytes::Bytes;
 use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
-use crypto::Transaction;
-use crypto::{generate_keypair, Digest, PublicKey, SecretKey};
+use crypto::{generate_keypair, Digest, PublicKey, SecretKey, SignatureService};
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
+use network::encode;
+use primary::{SignedWorkerMessage, Transaction, WorkerPrimaryMessage};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
@@ -23,9 +25,18 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     (0..4).map(|_| generate_keypair(&mut rng)).collect()
 }
 
+// Fixture. A message to the primary as sent by a worker of the last authority of `keys()`.
+pub async fn signed(message: &WorkerPrimaryMessage) -> Bytes {
+    let (_, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let message = SignedWorkerMessage::new(encode(message).unwrap(), &mut signature_service).await;
+    Bytes::from(encode(&message).unwrap())
+}
+
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .enumerate()
@@ -34,7 +45,7 @@ pub fn committee() -> Committee {
                     primary_to_primary: format!("127.0.0.1:{}", 100 + i).parse().unwrap(),
                     worker_to_primary: format!("127.0.0.1:{}", 200 + i).parse().unwrap(),
                 };
-                let workers = vec![(
+                let workers = [(
                     0,
                     WorkerAddresses {
                         primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
@@ -51,6 +62,7 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        byzantine: false,
                     },
                 )
             })
@@ -85,26 +97,42 @@ pub fn committee_with_base_port(base_port: u16) -> Committee {
 }
 
 // Fixture
-pub fn transaction() -> Vec<u8> {
-    vec![0; 100]
+pub fn transaction() -> Transaction {
+    Transaction {
+        data: vec![0; 68],
+        id: vec![1; 8],
+        fee: 0,
+    }
+}
+
+// Fixture: a transaction competing with `transaction()` in the same election.
+pub fn other_transaction() -> Transaction {
+    Transaction {
+        data: vec![1; 68],
+        ..transaction()
+    }
 }
 
 // Fixture
 pub fn batch() -> Batch {
-    vec![]
+    vec![transaction(), other_transaction()]
+}
+
+// Fixture
+pub fn block() -> Block {
+    Block { txs: batch() }
 }
 
 // Fixture
 pub fn serialized_batch() -> Vec<u8> {
-    //let message = WorkerMessage::Batch(batch());
-    //bincode::serialize(&message).unwrap()
-    Vec::new()
+    let message = WorkerMessage::Batch(block());
+    crate::wire::serialize(&message).unwrap()
 }
 
 // Fixture
 pub fn batch_digest() -> Digest {
     Digest(
-        Sha512::digest(&serialized_batch()).as_slice()[..32]
+        Sha512::digest(&serialized_batch())[..32]
             .try_into()
             .unwrap(),
     )
@@ -128,3 +156,20 @@ pub fn listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<()>
         }
     })
 }
+
+// Fixture. A listener that only acknowledges the message once `ack` fires.
+pub fn delayed_listener(address: SocketAddr, ack: oneshot::Receiver<()>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let (mut writer, mut reader) = transport.split();
+        match reader.next().await {
+            Some(Ok(_)) => {
+                ack.await.unwrap();
+                writer.send(Bytes::from("Ack")).await.unwrap();
+            }
+            _ => panic!("Failed to receive network message"),
+        }
+    })
+}
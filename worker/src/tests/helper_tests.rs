@@ -22,7 +22,8 @@ async fn batch_reply() {
         .await;
 
     // Spawn an `Helper` instance.
-    Helper::spawn(id, committee.clone(), store, rx_request);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    Helper::spawn(id, committee.clone(), store, rx_request, rx_reconfigure);
 
     // Spawn a listener to receive the batch reply.
     let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
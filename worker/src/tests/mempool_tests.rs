@@ -0,0 +1,81 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+use tokio::sync::mpsc::channel;
+
+// Fixture
+fn transaction_with(data: u8, fee: u64) -> Transaction {
+    Transaction {
+        data: vec![data; 68],
+        fee,
+        ..transaction()
+    }
+}
+
+#[test]
+fn drop_copies() {
+    let mut mempool = Mempool::new(1_000);
+    assert!(mempool.insert(transaction()));
+    assert!(!mempool.insert(transaction()));
+    assert_eq!(mempool.len(), 1);
+
+    // Copies of forwarded transactions are dropped too.
+    assert!(mempool.pop().is_some());
+    assert!(!mempool.insert(transaction()));
+    assert!(mempool.is_empty());
+    assert_eq!(mempool.duplicates, 2);
+
+    // Transactions for the same election are no copies.
+    assert!(mempool.insert(transaction_with(2, 0)));
+}
+
+#[test]
+fn evict_at_capacity() {
+    // Each transaction takes 68 + 8 + 8 = 84 B: there is room for three.
+    let mut mempool = Mempool::new(3 * 84);
+    mempool.insert(transaction_with(0, 5));
+    mempool.insert(transaction_with(1, 1));
+    mempool.insert(transaction_with(2, 1));
+    assert_eq!(mempool.bytes(), 3 * 84);
+
+    // The oldest of the lowest-fee transactions makes room.
+    mempool.insert(transaction_with(3, 9));
+    assert_eq!(mempool.len(), 3);
+    assert_eq!(mempool.evicted, 1);
+    let data: Vec<_> = std::iter::from_fn(|| mempool.pop())
+        .map(|x| x.data[0])
+        .collect();
+    assert_eq!(data, vec![3, 0, 2]);
+    assert_eq!(mempool.bytes(), 0);
+}
+
+#[test]
+fn pop_by_fee_then_arrival() {
+    let mut mempool = Mempool::new(10_000);
+    for (data, fee) in [(0, 0), (1, 7), (2, 0), (3, 7), (4, 3)] {
+        mempool.insert(transaction_with(data, fee));
+    }
+    let data: Vec<_> = std::iter::from_fn(|| mempool.pop())
+        .map(|x| x.data[0])
+        .collect();
+    assert_eq!(data, vec![1, 3, 4, 0, 2]);
+}
+
+#[tokio::test]
+async fn forward_resubmitted_transaction_once() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    Mempool::spawn(1_000, rx_transaction, tx_batch_maker);
+
+    // A client resubmits the same transaction.
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction_with(2, 0)).await.unwrap();
+
+    assert_eq!(
+        rx_batch_maker.recv().await.unwrap().data,
+        transaction().data
+    );
+    assert_eq!(rx_batch_maker.recv().await.unwrap().data, vec![2; 68]);
+    assert!(rx_batch_maker.try_recv().is_err());
+}
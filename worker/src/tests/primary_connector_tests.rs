@@ -0,0 +1,130 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::keys;
+use futures::stream::StreamExt as _;
+use primary::Transaction;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Fixture. A primary forwarding the messages it receives from its worker, once checked.
+fn primary(address: SocketAddr) -> Receiver<WorkerPrimaryMessage> {
+    let (name, _) = keys().pop().unwrap();
+    let (tx_messages, rx_messages) = channel(100);
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(Ok(frame)) = transport.next().await {
+            let signed: SignedWorkerMessage = decode(&frame).unwrap();
+            tx_messages.send(signed.verify(&name).unwrap()).await.unwrap();
+        }
+    });
+    rx_messages
+}
+
+// Fixture. Signs with the key of the primary above.
+fn signature_service() -> SignatureService {
+    let (_, secret) = keys().pop().unwrap();
+    SignatureService::new(secret)
+}
+
+fn our_batch(i: u8) -> WorkerPrimaryMessage {
+    WorkerPrimaryMessage::OurBatch(Digest([i; 32]), Digest([!i; 32]), 0)
+}
+
+async fn send(tx_digest: &Sender<SerializedBatchDigestMessage>, message: &WorkerPrimaryMessage) {
+    tx_digest.send(encode(message).unwrap()).await.unwrap();
+}
+
+// The digests of our batches the primary received, in order.
+async fn received(rx_messages: &mut Receiver<WorkerPrimaryMessage>, count: usize) -> Vec<Digest> {
+    let mut digests = Vec::new();
+    while digests.len() < count {
+        match rx_messages.recv().await.unwrap() {
+            WorkerPrimaryMessage::OurBatch(digest, ..) => digests.push(digest),
+            WorkerPrimaryMessage::OurBatches(x) => digests.extend(x.into_iter().map(|x| x.0)),
+            x => panic!("Unexpected message {:?}", x),
+        }
+    }
+    digests
+}
+
+#[tokio::test]
+async fn coalesce_digests() {
+    let expected: Vec<_> = (0..100).map(|i| Digest([i; 32])).collect();
+    let mut metrics = Vec::new();
+    for (port, digests_batch_size) in [(12_500, 1), (12_501, 10)] {
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        let mut rx_messages = primary(address);
+        let (tx_digest, rx_digest) = channel(100);
+        let mut connector = PrimaryConnector::new(
+            address,
+            signature_service(),
+            digests_batch_size,
+            /* delay */ 60_000,
+            rx_digest,
+        );
+        for i in 0..100 {
+            send(&tx_digest, &our_batch(i)).await;
+        }
+        drop(tx_digest);
+        connector.run().await;
+
+        // The primary gets all the digests, in the order the batches were sealed.
+        assert_eq!(received(&mut rx_messages, 100).await, expected);
+        metrics.push(connector.metrics);
+    }
+
+    // One message per digest before, one per ten after.
+    assert_eq!((metrics[0].messages, metrics[0].digests), (100, 100));
+    assert_eq!((metrics[1].messages, metrics[1].digests), (10, 100));
+}
+
+#[tokio::test]
+async fn keep_order_of_other_messages() {
+    let address = SocketAddr::from(([127, 0, 0, 1], 12_502));
+    let mut rx_messages = primary(address);
+    let (tx_digest, rx_digest) = channel(100);
+    let mut connector =
+        PrimaryConnector::new(address, signature_service(), 10, /* delay */ 60_000, rx_digest);
+
+    // Another message leaves after the digests held before it.
+    for i in 0..3 {
+        send(&tx_digest, &our_batch(i)).await;
+    }
+    send(
+        &tx_digest,
+        &WorkerPrimaryMessage::Inline(Transaction::new()),
+    )
+    .await;
+    send(&tx_digest, &our_batch(3)).await;
+    drop(tx_digest);
+    connector.run().await;
+
+    match rx_messages.recv().await.unwrap() {
+        WorkerPrimaryMessage::OurBatches(x) => assert_eq!(x.len(), 3),
+        x => panic!("Unexpected message {:?}", x),
+    }
+    assert!(matches!(
+        rx_messages.recv().await,
+        Some(WorkerPrimaryMessage::Inline(_))
+    ));
+    assert_eq!(received(&mut rx_messages, 1).await, vec![Digest([3; 32])]);
+    assert_eq!(connector.metrics.messages, 3);
+}
+
+#[tokio::test]
+async fn send_digests_after_delay() {
+    let address = SocketAddr::from(([127, 0, 0, 1], 12_503));
+    let mut rx_messages = primary(address);
+    let (tx_digest, rx_digest) = channel(100);
+    PrimaryConnector::spawn(address, signature_service(), 10, /* delay */ 50, rx_digest);
+
+    // Fewer digests than a full message still reach the primary, while the worker keeps running.
+    for i in 0..3 {
+        send(&tx_digest, &our_batch(i)).await;
+    }
+    let digests = received(&mut rx_messages, 3).await;
+    assert_eq!(digests, (0..3).map(|i| Digest([i; 32])).collect::<Vec<_>>());
+}
@@ -1,6 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::batch;
+use crate::common::block;
 use crate::worker::WorkerMessage;
 use std::fs;
 use tokio::sync::mpsc::channel;
@@ -26,9 +26,13 @@ async fn hash_and_store() {
     );
 
     // Send a batch to the `Processor`.
-    let message = WorkerMessage::Batch(batch());
-    let serialized = bincode::serialize(&message).unwrap();
-    tx_batch.send(serialized.clone()).await.unwrap();
+    let message = WorkerMessage::Batch(block());
+    let serialized = crate::wire::serialize(&message).unwrap();
+    let election_id = block().election_id();
+    tx_batch
+        .send((serialized.clone(), election_id.clone()))
+        .await
+        .unwrap();
 
     // Ensure the `Processor` outputs the batch's digest.
     let output = rx_digest.recv().await.unwrap();
@@ -37,7 +41,7 @@ async fn hash_and_store() {
             .try_into()
             .unwrap(),
     );
-    let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(digest.clone(), id)).unwrap();
+    let expected = encode(&WorkerPrimaryMessage::OurBatch(digest.clone(), election_id, id)).unwrap();
     assert_eq!(output, expected);
 
     // Ensure the `Processor` correctly stored the batch.
@@ -1,6 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch, committee_with_base_port, keys, listener};
+use crate::common::{block, committee_with_base_port, keys, listener};
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
 use futures::future::try_join_all;
@@ -15,11 +15,18 @@ async fn wait_for_quorum() {
     let committee = committee_with_base_port(7_000);
 
     // Spawn a `QuorumWaiter` instance.
-    QuorumWaiter::spawn(committee.clone(), /* stake */ 1, rx_message, tx_batch);
+    let (_tx_reconfigure, rx_reconfigure) = channel(1);
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        rx_message,
+        rx_reconfigure,
+        tx_batch,
+    );
 
     // Make a batch.
-    let message = WorkerMessage::Batch(batch());
-    let serialized = bincode::serialize(&message).unwrap();
+    let message = WorkerMessage::Batch(block());
+    let serialized = crate::wire::serialize(&message).unwrap();
     let expected = Bytes::from(serialized.clone());
 
     // Spawn enough listeners to acknowledge our batches.
@@ -41,13 +48,14 @@ async fn wait_for_quorum() {
     // Forward the batch along with the handlers to the `QuorumWaiter`.
     let message = QuorumWaiterMessage {
         batch: serialized.clone(),
+        election_id: block().election_id(),
         handlers: names.into_iter().zip(handlers.into_iter()).collect(),
     };
     tx_message.send(message).await.unwrap();
 
     // Wait for the `QuorumWaiter` to gather enough acknowledgements and output the batch.
     let output = rx_batch.recv().await.unwrap();
-    assert_eq!(output, serialized);
+    assert_eq!(output, (serialized, block().election_id()));
 
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
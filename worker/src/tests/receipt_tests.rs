@@ -0,0 +1,62 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+
+// Fixture
+async fn receipt() -> Receipt {
+    let (name, secret) = keys().pop().unwrap();
+    let mut signer = ReceiptSigner::new(name, SignatureService::new(secret));
+    signer
+        .receipt(Digest([1; 32]), b"key".to_vec())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn verify_against_committee() {
+    let receipt = receipt().await;
+    assert!(verify_receipt(&receipt, &committee()).is_ok());
+
+    // Any change to the receipt voids its signature.
+    let tampered = Receipt {
+        timestamp: receipt.timestamp + 1,
+        ..receipt
+    };
+    assert!(matches!(
+        verify_receipt(&tampered, &committee()),
+        Err(ReceiptError::InvalidSignature(_))
+    ));
+}
+
+#[tokio::test]
+async fn reject_other_committee() {
+    let receipt = receipt().await;
+    let mut other = committee();
+    other.authorities.remove(&receipt.authority);
+    assert_eq!(
+        verify_receipt(&receipt, &other),
+        Err(ReceiptError::UnknownAuthority(receipt.authority))
+    );
+}
+
+#[tokio::test]
+async fn refuse_to_sign_other_messages() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let mut signer = ReceiptSigner::new(name, SignatureService::new(secret));
+
+    // A bare digest, such as that of a header, is no receipt.
+    let digest = Digest([1; 32]);
+    assert_eq!(
+        signer.sign(digest.as_ref()).await,
+        Err(ReceiptError::NotAReceipt)
+    );
+
+    // Nor is the receipt of another authority.
+    let mut receipt = receipt().await;
+    receipt.authority = keys.pop().unwrap().0;
+    assert_eq!(
+        signer.sign(&receipt.signed_message()).await,
+        Err(ReceiptError::NotAReceipt)
+    );
+}
@@ -35,7 +35,7 @@ async fn synchronize() {
     let address = committee.worker(&target, &id).unwrap().worker_to_worker;
     let missing = vec![batch_digest()];
     let message = WorkerMessage::BatchRequest(missing.clone(), name);
-    let serialized = bincode::serialize(&message).unwrap();
+    let serialized = wire::serialize(&message).unwrap();
     let handle = listener(address, Some(Bytes::from(serialized)));
 
     // Send a sync request.
@@ -0,0 +1,49 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+
+#[test]
+fn accept_valid_transaction() {
+    assert!(validate_transaction(&transaction(), 0).is_ok());
+
+    // An id as long as an election id is fine.
+    let mut tx = transaction();
+    tx.id = vec![1; MAX_ID_SIZE];
+    assert!(validate_transaction(&tx, 0).is_ok());
+}
+
+// A way to break a transaction, the minimum fee to check it against, and the expected error.
+type Case = (fn(&mut Transaction), u64, TransactionValidationError);
+
+#[test]
+fn reject_invalid_transactions() {
+    let invalid: Vec<Case> = vec![
+        (
+            |tx| tx.data.clear(),
+            0,
+            TransactionValidationError::EmptyPayload,
+        ),
+        (|tx| tx.id.clear(), 0, TransactionValidationError::MissingId),
+        (
+            |tx| tx.id = vec![1; MAX_ID_SIZE + 1],
+            0,
+            TransactionValidationError::IdTooLong {
+                len: MAX_ID_SIZE + 1,
+                max: MAX_ID_SIZE,
+            },
+        ),
+        (
+            |tx| tx.fee = 9,
+            10,
+            TransactionValidationError::TxFeeError {
+                fee: 9,
+                minimum: 10,
+            },
+        ),
+    ];
+    for (corrupt, minimum_fee, expected) in invalid {
+        let mut tx = transaction();
+        corrupt(&mut tx);
+        assert_eq!(validate_transaction(&tx, minimum_fee), Err(expected));
+    }
+}
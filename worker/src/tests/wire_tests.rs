@@ -0,0 +1,28 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch_digest, block, keys};
+use primary::PROTOCOL_VERSION;
+
+#[test]
+fn round_trip() {
+    let (requestor, _) = keys().pop().unwrap();
+    let messages = vec![
+        WorkerMessage::Batch(block()),
+        WorkerMessage::BatchRequest(vec![batch_digest()], requestor),
+    ];
+    for (tag, message) in messages.into_iter().enumerate() {
+        let frame = serialize(&message).unwrap();
+        assert_eq!(frame[..2], [PROTOCOL_VERSION, tag as u8]);
+        assert_eq!(serialize(&deserialize(&frame).unwrap()).unwrap(), frame);
+    }
+}
+
+#[test]
+fn reject_unknown_type() {
+    let mut frame = serialize(&WorkerMessage::Batch(block())).unwrap();
+    frame[1] = 9;
+    assert!(matches!(
+        deserialize(&frame),
+        Err(WireError::UnknownType(9))
+    ));
+}
@@ -1,13 +1,19 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener, transaction};
-use network::SimpleSender;
-use primary::WorkerPrimaryMessage;
+use crate::common::{
+    batch, batch_digest, block, committee_with_base_port, delayed_listener, keys, listener,
+    serialized_batch, signed,
+};
+use network::{encode, ReliableSender, SimpleSender, MAX_FAULTS};
+use primary::{Primary, WorkerPrimaryMessage};
 use std::fs;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
 
 #[tokio::test]
 async fn handle_clients_transactions() {
-    let (name, _) = keys().pop().unwrap();
+    let (name, secret) = keys().pop().unwrap();
     let id = 0;
     let committee = committee_with_base_port(11_000);
     let parameters = Parameters {
@@ -21,25 +27,340 @@ async fn handle_clients_transactions() {
     let store = Store::new(path).unwrap();
 
     // Spawn a `Worker` instance.
-    Worker::spawn(name, id, committee.clone(), parameters, store);
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
 
     // Spawn a network listener to receive our batch's digest.
     let primary_address = committee.primary(&name).unwrap().worker_to_primary;
-    let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(batch_digest(), id)).unwrap();
-    let handle = listener(primary_address, Some(Bytes::from(expected)));
+    let message = WorkerPrimaryMessage::OurBatch(batch_digest(), block().election_id(), id);
+    let handle = listener(primary_address, Some(signed(&message).await));
 
     // Spawn enough workers' listeners to acknowledge our batches.
     for (_, addresses) in committee.others_workers(&name, &id) {
         let address = addresses.worker_to_worker;
-        let _ = listener(address, /* expected */ None);
+        let _listener = listener(address, /* expected */ None);
     }
 
     // Send enough transactions to create a batch.
     let mut network = SimpleSender::new();
     let address = committee.worker(&name, &id).unwrap().transactions;
-    network.send(address, Bytes::from(transaction())).await;
-    network.send(address, Bytes::from(transaction())).await;
+    for transaction in batch() {
+        let transaction = Bytes::from(encode(&transaction).unwrap());
+        network.send(address, transaction).await;
+    }
 
     // Ensure the primary received the batch's digest (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn hand_inline_transactions_to_primary() {
+    let (name, secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_900);
+    let parameters = Parameters {
+        inline_size: 100,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_hand_inline_transactions_to_primary";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
+
+    // Spawn a network listener to receive the transaction, without batching or acks.
+    let transaction = batch().pop().unwrap();
+    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let message = WorkerPrimaryMessage::Inline(transaction.clone());
+    let handle = listener(primary_address, Some(signed(&message).await));
+
+    // Send a transaction small enough to be proposed inline.
+    let mut network = SimpleSender::new();
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    network
+        .send(address, Bytes::from(encode(&transaction).unwrap()))
+        .await;
+
+    // Ensure the primary received it as is.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn wait_for_acks_before_reporting_digest() {
+    let (name, secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_100);
+    let parameters = Parameters {
+        batch_size: 200, // Two transactions.
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_wait_for_acks_before_reporting_digest";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
+
+    // Spawn a network listener to receive our batch's digest.
+    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let message = WorkerPrimaryMessage::OurBatch(batch_digest(), block().election_id(), id);
+    let mut handle = listener(primary_address, Some(signed(&message).await));
+
+    // Spawn the other workers' listeners; they only acknowledge our batch when told to.
+    let mut acks = Vec::new();
+    for (_, addresses) in committee.others_workers(&name, &id) {
+        let (tx_ack, rx_ack) = oneshot::channel();
+        let _listener = delayed_listener(addresses.worker_to_worker, rx_ack);
+        acks.push(tx_ack);
+    }
+
+    // Send enough transactions to create a batch.
+    let mut network = SimpleSender::new();
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    for transaction in batch() {
+        let transaction = Bytes::from(encode(&transaction).unwrap());
+        network.send(address, transaction).await;
+    }
+
+    // With a single ack, only two of the four workers hold the batch: the primary hears nothing.
+    acks.pop().unwrap().send(()).unwrap();
+    let delay = Duration::from_millis(500);
+    assert!(timeout(delay, &mut handle).await.is_err());
+
+    // The second ack completes a quorum of three workers and the digest is delivered.
+    acks.pop().unwrap().send(()).unwrap();
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn recover_missing_batch() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (requestor, requestor_secret) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_200);
+
+    // Spawn a `Worker` holding the batch.
+    let path = ".db_test_recover_missing_batch_0";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
+
+    // Spawn a `Worker` that missed the batch.
+    let path = ".db_test_recover_missing_batch_1";
+    let _ = fs::remove_dir_all(path);
+    let mut requestor_store = Store::new(path).unwrap();
+    let store = requestor_store.clone();
+    Worker::spawn(
+        requestor,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        SignatureService::new(requestor_secret),
+        /* signer */ None,
+    );
+
+    // Ask the first worker for the missing batch.
+    let address = committee.worker(&name, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchRequest(vec![batch_digest()], requestor);
+    let serialized = Bytes::from(wire::serialize(&message).unwrap());
+    let mut network = ReliableSender::new();
+    let _ = network.send(address, serialized).await.await;
+
+    // Ensure the requestor receives and stores the batch.
+    let stored = timeout(
+        Duration::from_secs(5),
+        requestor_store.notify_read(batch_digest().to_vec()),
+    )
+    .await
+    .expect("The batch was not recovered")
+    .unwrap();
+    assert_eq!(stored, serialized_batch());
+}
+
+#[tokio::test]
+async fn survive_malformed_transactions() {
+    let (name, secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_400);
+    let parameters = Parameters {
+        batch_size: 200, // Two transactions.
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_survive_malformed_transactions";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        SignatureService::new(secret),
+        /* signer */ None,
+    );
+
+    // Spawn a network listener to receive our batch's digest.
+    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let message = WorkerPrimaryMessage::OurBatch(batch_digest(), block().election_id(), id);
+    let handle = listener(primary_address, Some(signed(&message).await));
+
+    // Spawn enough workers' listeners to acknowledge our batches.
+    for (_, addresses) in committee.others_workers(&name, &id) {
+        let address = addresses.worker_to_worker;
+        let _listener = listener(address, /* expected */ None);
+    }
+
+    // Send random bytes, then enough transactions to create a batch.
+    let mut network = SimpleSender::new();
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    for _ in 0..MAX_FAULTS - 1 {
+        let garbage: [u8; 32] = rand::random();
+        network.send(address, Bytes::from(garbage.to_vec())).await;
+    }
+    for transaction in batch() {
+        let transaction = Bytes::from(encode(&transaction).unwrap());
+        network.send(address, transaction).await;
+    }
+
+    // Ensure the primary received the batch's digest.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn shutdown_primary_and_worker() {
+    let (name, secret) = keys().pop().unwrap();
+    let (_, worker_secret) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_500);
+    let parameters = Parameters {
+        batch_size: 200, // Two transactions.
+        ..Parameters::default()
+    };
+
+    // Create new test stores.
+    let path = ".db_test_shutdown_primary";
+    let _ = fs::remove_dir_all(path);
+    let primary_store = Store::new(path).unwrap();
+    let path = ".db_test_shutdown_worker";
+    let _ = fs::remove_dir_all(path);
+    let worker_store = Store::new(path).unwrap();
+
+    // Spawn a `Primary` and its `Worker`.
+    let (tx_output, _rx_output) = channel(1);
+    let mut node = Primary::spawn(
+        name,
+        secret,
+        committee.clone(),
+        parameters.clone(),
+        primary_store,
+        tx_output,
+        /* byzantine */ None,
+    );
+    node.extend(Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        worker_store,
+        SignatureService::new(worker_secret),
+        /* signer */ None,
+    ));
+
+    // Send enough transactions to create a batch.
+    let mut network = SimpleSender::new();
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    for transaction in batch() {
+        let transaction = Bytes::from(encode(&transaction).unwrap());
+        network.send(address, transaction).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Ensure all tasks stop without panicking.
+    let result = timeout(Duration::from_secs(1), node.shutdown()).await;
+    assert!(matches!(result, Ok(Ok(()))));
+
+    // Ensure the listeners are closed.
+    let mut address = address;
+    address.set_ip("0.0.0.0".parse().unwrap());
+    assert!(TcpListener::bind(address).await.is_ok());
+}
+
+#[tokio::test]
+async fn survive_unknown_protocol_version() {
+    let (requestor, _) = keys().pop().unwrap();
+    let (tx_helper, mut rx_helper) = channel(1);
+    let (tx_processor, _rx_processor) = channel(1);
+
+    // Spawn a receiver for the messages of the other workers.
+    let address = "127.0.0.1:11800".parse().unwrap();
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+        },
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // A peer running another protocol version sends a request, then one running ours.
+    let message = WorkerMessage::BatchRequest(vec![batch_digest()], requestor);
+    let mut frame = wire::serialize(&message).unwrap();
+    frame[0] = primary::PROTOCOL_VERSION + 1;
+    let mut network = ReliableSender::new();
+    let dropped = unknown_versions();
+    let _ = network.send(address, Bytes::from(frame)).await.await;
+    let serialized = Bytes::from(wire::serialize(&message).unwrap());
+    let _ = network.send(address, serialized).await.await;
+
+    // Only the second request goes through, and the first is counted.
+    let (missing, origin) = rx_helper.recv().await.unwrap();
+    assert_eq!(missing, vec![batch_digest()]);
+    assert_eq!(origin, requestor);
+    assert!(rx_helper.try_recv().is_err());
+    assert!(unknown_versions() > dropped);
+}
@@ -0,0 +1,37 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::client::TransactionValidationError;
+use primary::Transaction;
+
+#[cfg(test)]
+#[path = "tests/validation_tests.rs"]
+pub mod validation_tests;
+
+/// The size of an election id, the longest transaction id we accept (in bytes).
+pub const MAX_ID_SIZE: usize = 32;
+
+pub type TransactionValidationResult<T> = Result<T, TransactionValidationError>;
+
+/// The checks a worker runs on every transaction before handing it to its batch maker. They
+/// are structural only: transactions are opaque payloads here, so there are no inputs, rings or
+/// key images to check.
+pub fn validate_transaction(tx: &Transaction, minimum_fee: u64) -> TransactionValidationResult<()> {
+    if tx.data.is_empty() {
+        return Err(TransactionValidationError::EmptyPayload);
+    }
+    if tx.id.is_empty() {
+        return Err(TransactionValidationError::MissingId);
+    }
+    if tx.id.len() > MAX_ID_SIZE {
+        return Err(TransactionValidationError::IdTooLong {
+            len: tx.id.len(),
+            max: MAX_ID_SIZE,
+        });
+    }
+    if tx.fee < minimum_fee {
+        return Err(TransactionValidationError::TxFeeError {
+            fee: tx.fee,
+            minimum: minimum_fee,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! The wire format of the messages between workers, framed like those between primaries (see
+//! `primary::seal_frame`). The digest of a batch covers its whole frame.
+use crate::worker::WorkerMessage;
+use primary::{frame_content, open_frame, seal_frame, WireError};
+
+#[cfg(test)]
+#[path = "tests/wire_tests.rs"]
+pub mod wire_tests;
+
+/// The types of the worker messages.
+const BATCH: u8 = 0;
+const BATCH_REQUEST: u8 = 1;
+
+pub fn serialize(message: &WorkerMessage) -> bincode::Result<Vec<u8>> {
+    match message {
+        WorkerMessage::Batch(block) => seal_frame(BATCH, block),
+        WorkerMessage::BatchRequest(missing, origin) => {
+            seal_frame(BATCH_REQUEST, &(missing, origin))
+        }
+    }
+}
+
+pub fn deserialize(frame: &[u8]) -> Result<WorkerMessage, WireError> {
+    let (tag, bytes) = open_frame(frame)?;
+    match tag {
+        BATCH => Ok(WorkerMessage::Batch(frame_content(bytes)?)),
+        BATCH_REQUEST => {
+            let (missing, origin) = frame_content(bytes)?;
+            Ok(WorkerMessage::BatchRequest(missing, origin))
+        }
+        tag => Err(WireError::UnknownType(tag)),
+    }
+}
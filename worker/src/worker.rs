@@ -1,23 +1,32 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::batch_maker::{Batch, BatchMaker};
+use crate::client::{SubmissionStatus, TransactionValidationError};
 use crate::helper::Helper;
+use crate::mempool::Mempool;
 use crate::primary_connector::PrimaryConnector;
 use crate::processor::{Processor, SerializedBatchMessage};
 use crate::quorum_waiter::QuorumWaiter;
+use crate::receipt::{ReceiptRequest, ReceiptSigner};
 use crate::synchronizer::Synchronizer;
+use crate::validation::validate_transaction;
+use crate::wire;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, WorkerId};
-use crypto::{Digest, PublicKey};
+use config::{Committee, Parameters, Stake, WorkerId};
+use crypto::{Digest, PublicKey, SignatureService};
 use futures::sink::SinkExt as _;
-use log::{error, info, warn};
-use network::{MessageHandler, Receiver, Writer};
-use primary::{PrimaryWorkerMessage, Transaction};
+use log::{debug, info, warn};
+use network::{decode, encode, MessageHandler, Receiver, Writer};
+use primary::{
+    unknown_versions, Hash as _, NodeHandle, PrimaryWorkerMessage, ShutdownStage, Transaction,
+    WireError,
+};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::net::SocketAddr;
 use store::Store;
-use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::mpsc::{channel, Receiver as ChannelReceiver, Sender};
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/worker_tests.rs"]
@@ -51,16 +60,23 @@ pub struct Worker {
     parameters: Parameters,
     /// The persistent storage.
     store: Store,
+    /// Signs our messages to the primary with the key of our authority.
+    signature_service: SignatureService,
+    /// Signs the receipts the clients ask for, if we hand them out.
+    signer: Option<ReceiptSigner>,
 }
 
 impl Worker {
+    /// Spawn all the tasks of the worker. The returned handle stops them.
     pub fn spawn(
         name: PublicKey,
         id: WorkerId,
         committee: Committee,
         parameters: Parameters,
         store: Store,
-    ) {
+        signature_service: SignatureService,
+        signer: Option<ReceiptSigner>,
+    ) -> NodeHandle {
         // Define a worker instance.
         let worker = Self {
             name,
@@ -68,6 +84,8 @@ impl Worker {
             committee,
             parameters,
             store,
+            signature_service,
+            signer,
         };
 
         let primary_address = worker
@@ -76,21 +94,33 @@ impl Worker {
             .expect("Our public key is not in the committee")
             .worker_to_primary;
 
-        // Spawn all worker tasks.
+        // Spawn all worker tasks. Those following the committee get it from our primary, in each
+        // new epoch.
         let (tx_primary, rx_primary) = channel(CHANNEL_CAPACITY);
-        worker.handle_primary_messages();
-        worker.handle_clients_transactions(tx_primary.clone(), primary_address);
-        worker.handle_workers_messages(tx_primary);
+        let (tx_batch_maker, rx_batch_maker) = channel(CHANNEL_CAPACITY);
+        let (tx_quorum_waiter, rx_quorum_waiter) = channel(CHANNEL_CAPACITY);
+        let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
+        let mut node = NodeHandle::default();
+        node.push_store(worker.store.clone());
+        for (stage, join) in
+            worker.handle_clients_transactions(tx_primary.clone(), rx_batch_maker, rx_quorum_waiter)
+        {
+            node.push(stage, join);
+        }
+        let mut joins = worker.handle_primary_messages(tx_batch_maker, tx_quorum_waiter, tx_helper);
+        joins.extend(worker.handle_workers_messages(tx_primary, rx_helper));
 
         // The `PrimaryConnector` allows the worker to send messages to its primary.
-        PrimaryConnector::spawn(
-            worker
-                .committee
-                .primary(&worker.name)
-                .expect("Our public key is not in the committee")
-                .worker_to_primary,
+        joins.push(PrimaryConnector::spawn(
+            primary_address,
+            worker.signature_service.clone(),
+            worker.parameters.digests_batch_size,
+            worker.parameters.max_digests_delay,
             rx_primary,
-        );
+        ));
+        for join in joins {
+            node.push(ShutdownStage::Consensus, join);
+        }
 
         // NOTE: This log entry is used to compute performance.
         info!(
@@ -103,10 +133,16 @@ impl Worker {
                 .transactions
                 .ip()
         );
+        node
     }
 
     /// Spawn all tasks responsible to handle messages from our primary.
-    fn handle_primary_messages(&self) {
+    fn handle_primary_messages(
+        &self,
+        tx_batch_maker: Sender<Vec<(PublicKey, SocketAddr)>>,
+        tx_quorum_waiter: Sender<(Committee, Stake)>,
+        tx_helper: Sender<Committee>,
+    ) -> Vec<JoinHandle<()>> {
         let (tx_synchronizer, rx_synchronizer) = channel(CHANNEL_CAPACITY);
 
         // Receive incoming messages from our primary.
@@ -116,15 +152,22 @@ impl Worker {
             .expect("Our public key or worker id is not in the committee")
             .primary_to_worker;
         address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        let receiver = Receiver::spawn(
             address,
             /* handler */
-            PrimaryReceiverHandler { tx_synchronizer },
+            PrimaryReceiverHandler {
+                name: self.name,
+                id: self.id,
+                tx_synchronizer,
+                tx_batch_maker,
+                tx_quorum_waiter,
+                tx_helper,
+            },
         );
 
         // The `Synchronizer` is responsible to keep the worker in sync with the others. It handles the commands
         // it receives from the primary (which are mainly notifications that we are out of sync).
-        Synchronizer::spawn(
+        let synchronizer = Synchronizer::spawn(
             self.name.clone(),
             self.id,
             self.committee.clone(),
@@ -139,11 +182,20 @@ impl Worker {
             "Worker {} listening to primary messages on {}",
             self.id, address
         );
+        vec![receiver, synchronizer]
     }
 
-    /// Spawn all tasks responsible to handle clients transactions.
-    fn handle_clients_transactions(&self, tx_primary: Sender<SerializedBatchDigestMessage>, primary_address: SocketAddr) {
-        let (tx_batch_maker, rx_batch_maker) = channel(CHANNEL_CAPACITY);
+    /// Spawn all tasks responsible to handle clients transactions, along with the shutdown stage
+    /// of each: the ones taking in transactions stop first.
+    fn handle_clients_transactions(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+        rx_batch_maker_committee: ChannelReceiver<Vec<(PublicKey, SocketAddr)>>,
+        rx_quorum_waiter_committee: ChannelReceiver<(Committee, Stake)>,
+    ) -> Vec<(ShutdownStage, JoinHandle<()>)> {
+        let (tx_mempool, rx_mempool) = channel(CHANNEL_CAPACITY);
+        // The transactions wait in the `Mempool` (not in this channel) until they are batched.
+        let (tx_batch_maker, rx_batch_maker) = channel(1);
         let (tx_quorum_waiter, rx_quorum_waiter) = channel(CHANNEL_CAPACITY);
         let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
 
@@ -154,41 +206,51 @@ impl Worker {
             .expect("Our public key or worker id is not in the committee")
             .transactions;
         address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        let receiver = Receiver::spawn(
             address,
-            /* handler */ TxReceiverHandler { tx_batch_maker },
+            /* handler */
+            TxReceiverHandler {
+                tx_mempool,
+                minimum_fee: self.parameters.minimum_fee,
+                signer: self.signer.clone(),
+            },
+        );
+
+        // The `Mempool` drops the copies of the transactions and hands them to the `BatchMaker` by fee.
+        let mempool = Mempool::spawn(
+            self.parameters.mempool_size,
+            /* rx_transaction */ rx_mempool,
+            tx_batch_maker,
         );
 
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
         // (in a reliable manner) the batches to all other workers that share the same `id` as us. Finally, it
         // gathers the 'cancel handlers' of the messages and send them to the `QuorumWaiter`.
-        BatchMaker::spawn(
+        let batch_maker = BatchMaker::spawn(
             self.parameters.batch_size,
+            self.parameters.max_batch_bytes,
             self.parameters.max_batch_delay,
             /* rx_transaction */ rx_batch_maker,
             /* tx_message */ tx_quorum_waiter,
-            /* workers_addresses */
-            self.committee
-                .others_workers(&self.name, &self.id)
-                .iter()
-                .map(|(name, addresses)| (*name, addresses.worker_to_worker))
-                .collect(),
-            primary_address,
-            tx_processor
+            workers_addresses(&self.committee, &self.name, &self.id),
+            /* rx_reconfigure */ rx_batch_maker_committee,
+            self.parameters.inline_size,
+            /* tx_inline */ tx_primary.clone(),
         );
 
         // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
         // the batch to the `Processor`.
-        /*QuorumWaiter::spawn(
+        let quorum_waiter = QuorumWaiter::spawn(
             self.committee.clone(),
-            /* stake */ self.committee.stake(&PK(self.name.to_bytes())),
+            /* stake */ self.committee.stake(&self.name),
             /* rx_message */ rx_quorum_waiter,
+            /* rx_reconfigure */ rx_quorum_waiter_committee,
             /* tx_batch */ tx_processor,
-        );*/
+        );
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the `PrimaryConnector`
         // that will send it to our primary machine.
-        Processor::spawn(
+        let processor = Processor::spawn(
             self.id,
             self.store.clone(),
             /* rx_batch */ rx_processor,
@@ -200,11 +262,22 @@ impl Worker {
             "Worker {} listening to client transactions on {}",
             self.id, address
         );
+        vec![
+            (ShutdownStage::Intake, receiver),
+            (ShutdownStage::Intake, mempool),
+            (ShutdownStage::Intake, batch_maker),
+            (ShutdownStage::Consensus, quorum_waiter),
+            (ShutdownStage::Consensus, processor),
+        ]
     }
 
     /// Spawn all tasks responsible to handle messages from other workers.
-    fn handle_workers_messages(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
-       /*let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
+    fn handle_workers_messages(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+        rx_helper_committee: ChannelReceiver<Committee>,
+    ) -> Vec<JoinHandle<()>> {
+        let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
         let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
 
         // Receive incoming messages from other workers.
@@ -214,7 +287,7 @@ impl Worker {
             .expect("Our public key or worker id is not in the committee")
             .worker_to_worker;
         address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        let receiver = Receiver::spawn(
             address,
             /* handler */
             WorkerReceiverHandler {
@@ -224,16 +297,17 @@ impl Worker {
         );
 
         // The `Helper` is dedicated to reply to batch requests from other workers.
-        Helper::spawn(
+        let helper = Helper::spawn(
             self.id,
             self.committee.clone(),
             self.store.clone(),
             /* rx_request */ rx_helper,
+            /* rx_reconfigure */ rx_helper_committee,
         );
 
         // This `Processor` hashes and stores the batches we receive from the other workers. It then forwards the
         // batch's digest to the `PrimaryConnector` that will send it to our primary.
-        Processor::spawnx(
+        let processor = Processor::spawn(
             self.id,
             self.store.clone(),
             /* rx_batch */ rx_processor,
@@ -244,14 +318,19 @@ impl Worker {
         info!(
             "Worker {} listening to worker messages on {}",
             self.id, address
-        );*/
+        );
+        vec![receiver, helper, processor]
     }
 }
 
 /// Defines how the network receiver handles incoming transactions.
 #[derive(Clone)]
 struct TxReceiverHandler {
-    tx_batch_maker: Sender<Transaction>,
+    tx_mempool: Sender<Transaction>,
+    /// The smallest fee we accept.
+    minimum_fee: u64,
+    /// Signs the receipts the clients ask for, if we hand them out.
+    signer: Option<ReceiptSigner>,
 }
 
 #[derive(Default, Clone, Deserialize, Serialize, Debug)]
@@ -261,12 +340,51 @@ pub struct Block {
     //pub commitments: Vec<CompressedRistretto>,
 }
 
+impl Block {
+    /// The election of a block is the election of its first transaction.
+    pub fn election_id(&self) -> Digest {
+        match self.txs.first() {
+            Some(tx) => tx.election_id(),
+            None => Digest([0u8; 32]),
+        }
+    }
+}
+
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
         //info!("TX received: {:?}", message);
         //let txs: Vec<Transaction> = bincode::deserialize(&message).unwrap();
-        let tx: Transaction = bincode::deserialize(&message).unwrap();
+        // A bare transaction asks for no receipt.
+        let (tx, idempotency_key) = match decode::<Transaction>(&message) {
+            Ok(tx) => (tx, None),
+            Err(e) => match decode::<ReceiptRequest>(&message) {
+                Ok(request) => (request.transaction, Some(request.idempotency_key)),
+                Err(_) => {
+                    // Tell the client and let the network receiver count the fault against it.
+                    let reason = TransactionValidationError::from(e);
+                    let status = SubmissionStatus::Rejected {
+                        reason: reason.clone(),
+                    };
+                    let _ = writer.send(Bytes::from(encode(&status)?)).await;
+                    return Err(Box::new(reason));
+                }
+            },
+        };
+        let tx_hash = tx.digest();
+
+        // Refuse the invalid transactions. Those that only pay too little are well-formed, so
+        // they are no fault.
+        if let Err(reason) = validate_transaction(&tx, self.minimum_fee) {
+            let status = SubmissionStatus::Rejected {
+                reason: reason.clone(),
+            };
+            let _ = writer.send(Bytes::from(encode(&status)?)).await;
+            return match reason {
+                TransactionValidationError::TxFeeError { .. } => Ok(()),
+                reason => Err(Box::new(reason)),
+            };
+        }
 
         //let start2 = Instant::now();
 
@@ -282,12 +400,23 @@ impl MessageHandler for TxReceiverHandler {
                 //}
 
         //for tx in txs {
-            self.tx_batch_maker
-                .send(tx)
-                .await
-                .expect("Failed to send transaction");
+            self.tx_mempool.send(tx).await?;
         //}
 
+        // Acknowledge the transaction, with a receipt if the client asked for one.
+        let receipt = match (self.signer.clone(), idempotency_key) {
+            (Some(mut signer), Some(key)) => match signer.receipt(tx_hash.clone(), key).await {
+                Ok(receipt) => Some(receipt),
+                Err(e) => {
+                    warn!("Failed to sign receipt: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        let status = SubmissionStatus::Accepted { tx_hash, receipt };
+        let _ = writer.send(Bytes::from(encode(&status)?)).await;
+
         // Give the change to schedule other tasks.
         tokio::task::yield_now().await;
         Ok(())
@@ -295,7 +424,7 @@ impl MessageHandler for TxReceiverHandler {
 }
 
 /// Defines how the network receiver handles incoming workers messages.
-/*#[derive(Clone)]
+#[derive(Clone)]
 struct WorkerReceiverHandler {
     tx_helper: Sender<(Vec<Digest>, PublicKey)>,
     tx_processor: Sender<(SerializedBatchMessage, Digest)>,
@@ -307,32 +436,58 @@ impl MessageHandler for WorkerReceiverHandler {
         // Reply with an ACK.
         let _ = writer.send(Bytes::from("Ack")).await;
 
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized) {
-            Ok(WorkerMessage::Batch(block)) => { 
-                info!("Received block: {:?}", block);
-
-                self
-                    .tx_processor
-                    .send(serialized.to_vec())
-                    .await
-                    .expect("Failed to send batch")
+        // Deserialize and parse the message. Peers running another protocol version are no fault
+        // of theirs: we only drop their messages.
+        let message = match wire::deserialize(&serialized) {
+            Ok(message) => message,
+            Err(WireError::UnknownVersion(version)) => {
+                warn!(
+                    "Dropping worker message of protocol version {} ({} dropped so far)",
+                    version,
+                    unknown_versions()
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        match message {
+            WorkerMessage::Batch(block) => {
+                debug!("Received block: {:?}", block);
+                self.tx_processor
+                    .send((serialized.to_vec(), block.election_id()))
+                    .await?
+            }
+            WorkerMessage::BatchRequest(missing, requestor) => {
+                self.tx_helper.send((missing, requestor)).await?
             }
-            Ok(WorkerMessage::BatchRequest(missing, requestor)) => self
-                .tx_helper
-                .send((missing, requestor))
-                .await
-                .expect("Failed to send batch request"),
-            Err(e) => warn!("Serialization error: {}", e),
         }
         Ok(())
     }
-}*/
+}
+
+/// The network addresses of the other workers with our id, to broadcast our batches to.
+fn workers_addresses(
+    committee: &Committee,
+    name: &PublicKey,
+    id: &WorkerId,
+) -> Vec<(PublicKey, SocketAddr)> {
+    committee
+        .others_workers(name, id)
+        .iter()
+        .map(|(name, addresses)| (*name, addresses.worker_to_worker))
+        .collect()
+}
 
 /// Defines how the network receiver handles incoming primary messages.
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
+    name: PublicKey,
+    id: WorkerId,
     tx_synchronizer: Sender<PrimaryWorkerMessage>,
+    /// The tasks following the committee, along with the `Synchronizer`.
+    tx_batch_maker: Sender<Vec<(PublicKey, SocketAddr)>>,
+    tx_quorum_waiter: Sender<(Committee, Stake)>,
+    tx_helper: Sender<Committee>,
 }
 
 #[async_trait]
@@ -343,14 +498,16 @@ impl MessageHandler for PrimaryReceiverHandler {
         serialized: Bytes,
     ) -> Result<(), Box<dyn Error>> {
         // Deserialize the message and send it to the synchronizer.
-        match bincode::deserialize(&serialized) {
-            Err(e) => error!("Failed to deserialize primary message: {}", e),
-            Ok(message) => self
-                .tx_synchronizer
-                .send(message)
-                .await
-                .expect("Failed to send transaction"),
+        let message = decode(&serialized)?;
+        if let PrimaryWorkerMessage::Reconfigure(committee) = &message {
+            debug!("Moving to epoch {}", committee.epoch);
+            let addresses = workers_addresses(committee, &self.name, &self.id);
+            self.tx_batch_maker.send(addresses).await?;
+            let stake = committee.stake(&self.name);
+            self.tx_quorum_waiter.send((committee.clone(), stake)).await?;
+            self.tx_helper.send(committee.clone()).await?;
         }
+        self.tx_synchronizer.send(message).await?;
         Ok(())
     }
 }